@@ -5,6 +5,7 @@ const http = require("node:http");
 const { spawn } = require("node:child_process");
 const { Builder, Capabilities, until, By } = require("selenium-webdriver");
 const { ensureDriversOnPath, resolveTauriDriverPath } = require("./driver_paths");
+const { APP_IDENTIFIER } = require("../../../packages/cli/src/paths.js");
 
 const repoRoot = path.join(__dirname, "..", "..", "..");
 process.env.NAGOMI_ENABLE_TEST_ENDPOINTS =
@@ -50,7 +51,7 @@ async function waitForDriver(port, timeoutMs) {
 
 function settingsJsonPath() {
   const base = process.env.APPDATA || "";
-  return path.join(base, "com.kitfactory.nagomi", "settings.json");
+  return path.join(base, APP_IDENTIFIER, "settings.json");
 }
 
 function readUtf8(filePath) {
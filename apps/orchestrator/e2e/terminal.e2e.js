@@ -4,7 +4,8 @@ const { spawn, spawnSync } = require("node:child_process");
 const http = require("node:http");
 const { Builder, By, Capabilities, until } = require("selenium-webdriver");
 const { ensureDriversOnPath } = require("./driver_paths");
-const { openAndSwitchToTerminalWindow } = require("./terminal_window_helper");
+const { controlTokenHeaders, openAndSwitchToTerminalWindow } = require("./terminal_window_helper");
+const { APP_IDENTIFIER } = require("../../../packages/cli/src/paths.js");
 
 const repoRoot = path.join(__dirname, "..", "..", "..");
 process.env.NAGOMI_ENABLE_TEST_ENDPOINTS =
@@ -49,7 +50,7 @@ function workerLogTail(lineCount = 40) {
   if (!appData) {
     return "";
   }
-  const logPath = path.join(appData, "com.kitfactory.nagomi", "worker_smoke.log");
+  const logPath = path.join(appData, APP_IDENTIFIER, "worker_smoke.log");
   try {
     const contents = fs.readFileSync(logPath, "utf8");
     const lines = contents.trimEnd().split(/\r?\n/);
@@ -137,7 +138,7 @@ async function httpGetBody(pathname, timeoutMs = 5000) {
   const port = resolveHealthPort();
   return await new Promise((resolve, reject) => {
     const req = http.get(
-      { host: "127.0.0.1", port, path: pathname, agent: false },
+      { host: "127.0.0.1", port, path: pathname, agent: false, headers: controlTokenHeaders() },
       (res) => {
       const chunks = [];
       res.on("data", (chunk) => chunks.push(chunk));
@@ -1,4 +1,10 @@
+const fs = require("node:fs");
 const http = require("node:http");
+const path = require("node:path");
+const {
+  CONTROL_TOKEN_FILE,
+  resolveAppConfigDir,
+} = require("../../../packages/cli/src/paths.js");
 
 function resolveHealthPort() {
   const raw = process.env.NAGOMI_ORCH_HEALTH_PORT;
@@ -7,11 +13,21 @@ function resolveHealthPort() {
   return Number.isFinite(port) && port > 0 ? port : 17707;
 }
 
+// /health 以外は制御トークンが要る / Every endpoint except /health needs the control token.
+function controlTokenHeaders() {
+  try {
+    const token = fs.readFileSync(path.join(resolveAppConfigDir(), CONTROL_TOKEN_FILE), "utf8").trim();
+    return token ? { "X-Nagomi-Token": token } : {};
+  } catch {
+    return {};
+  }
+}
+
 async function httpGetBody(pathname, timeoutMs = 5000) {
   const port = resolveHealthPort();
   return await new Promise((resolve, reject) => {
     const req = http.get(
-      { host: "127.0.0.1", port, path: pathname, agent: false },
+      { host: "127.0.0.1", port, path: pathname, agent: false, headers: controlTokenHeaders() },
       (res) => {
         const chunks = [];
         res.on("data", (chunk) => chunks.push(chunk));
@@ -99,6 +115,7 @@ async function openAndSwitchToTerminalWindow(client, sessionId = "", timeoutMs =
 
 module.exports = {
   resolveHealthPort,
+  controlTokenHeaders,
   httpGetBody,
   openTerminalViaHealth,
   waitForTerminalWindow,
@@ -2,10 +2,12 @@ const test = require("node:test");
 const assert = require("node:assert/strict");
 const path = require("node:path");
 const fs = require("node:fs");
+const http = require("node:http");
 const os = require("node:os");
 const { spawn, spawnSync } = require("node:child_process");
 const readline = require("node:readline");
 const { parseLine, serializeMessage } = require("../../packages/protocol/src/index.js");
+const { APP_IDENTIFIER } = require("../../packages/cli/src/paths.js");
 
 const appRoot = __dirname;
 const repoRoot = path.join(appRoot, "..", "..");
@@ -375,7 +377,7 @@ test("project_prompt_history_logging", () => {
 test("project_prompt_history_export_cli", () => {
   const tempRoot = fs.mkdtempSync(path.join(os.tmpdir(), "nagomi-prompt-history-"));
   const appData = path.join(tempRoot, "AppData", "Roaming");
-  const configDir = path.join(appData, "com.kitfactory.nagomi");
+  const configDir = path.join(appData, APP_IDENTIFIER);
   const historyDir = path.join(configDir, "project-prompt-history");
   fs.mkdirSync(historyDir, { recursive: true });
 
@@ -459,6 +461,51 @@ test("project_prompt_history_export_cli", () => {
   assert.equal(exportedLines[0].source_file, filePath);
 });
 
+test("workspace_save_cli_posts_with_control_token", async () => {
+  const tempRoot = fs.mkdtempSync(path.join(os.tmpdir(), "nagomi-workspace-save-"));
+  const configDir = path.join(tempRoot, "config");
+  fs.mkdirSync(configDir, { recursive: true });
+  fs.writeFileSync(path.join(configDir, "control_token"), "secret-token\n", "utf8");
+
+  const requests = [];
+  const server = http.createServer((req, res) => {
+    requests.push({ method: req.method, url: req.url, token: req.headers["x-nagomi-token"] });
+    req.resume();
+    req.on("end", () => {
+      res.writeHead(200, { "Content-Type": "application/json" });
+      res.end('{"status":"ok"}');
+    });
+  });
+  await new Promise((resolve) => server.listen(0, "127.0.0.1", resolve));
+  const port = server.address().port;
+  const cliPath = path.join(repoRoot, "packages", "cli", "src", "index.js");
+  const env = {
+    ...process.env,
+    NAGOMI_APP_CONFIG_DIR: configDir,
+    NAGOMI_ORCH_HEALTH_PORT: String(port),
+  };
+  try {
+    for (const args of [["save", "daily"], ["open", "daily"], ["list"]]) {
+      const result = await new Promise((resolve) => {
+        const child = spawn(process.execPath, [cliPath, "workspace", ...args], { cwd: repoRoot, env });
+        let stderr = "";
+        child.stderr.on("data", (chunk) => {
+          stderr += chunk;
+        });
+        child.on("close", (code) => resolve({ code, stderr }));
+      });
+      assert.equal(result.code, 0, result.stderr);
+    }
+  } finally {
+    server.close();
+  }
+  assert.deepStrictEqual(requests, [
+    { method: "POST", url: "/save-workspace?name=daily", token: "secret-token" },
+    { method: "GET", url: "/open-workspace?name=daily", token: "secret-token" },
+    { method: "GET", url: "/list-workspaces", token: "secret-token" },
+  ]);
+});
+
 test("subworker_ui_and_settings", () => {
   const htmlPath = path.join(appRoot, "src", "index.html");
   const html = fs.readFileSync(htmlPath, "utf8");
@@ -1,50 +1,81 @@
-﻿use crate::completion_hook::{hooks_base_dir, CompletionHookManager, HookEvent, HookEventKind};
-use anyhow::Result;
+﻿use anyhow::Result;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
 use base64::Engine;
+use nagomi_core::completion_hook::{hooks_base_dir, CompletionHookManager, HookEvent};
+use nagomi_core::control::{
+    parse_query_pairs, url_encode, ControlBackend, ControlError, ControlRoute, SessionInfo,
+};
+use nagomi_core::history::append_jsonl_entry;
+use nagomi_core::hook_setup::{
+    codex_config_path, ensure_codex_config, ensure_codex_hook_files, hook_install_status,
+    plan_claude_hooks, plan_opencode_plugin, resolve_cli_path, ConfigEdit, HookInstallAction,
+};
+use nagomi_core::launch::{
+    build_terminal_launch_plan, generate_terminal_session_id, launch_options,
+    resolve_terminal_cwd, terminal_window_title, workspace_launch_options, TerminalLaunchOptions,
+};
+#[cfg(not(windows))]
+use nagomi_core::launch::{read_etc_shells, resolve_unix_shell_path};
+#[cfg(windows)]
+use nagomi_core::launch::is_windows_command_available;
+use nagomi_core::layout::{self, LayoutEngine, LayoutItem, WindowRect};
+use nagomi_core::paths::sanitize_asset_component;
+use nagomi_core::session::{
+    dispatch_hook_event, HookStatePayload, OutputCoalescer, SessionRegistry, SmokeWaiters, TerminalErrorPayload,
+    TerminalExitPayload, TerminalOutputPayload,
+};
+use nagomi_core::settings::{
+    aggregate_policy, default_character_3d_scale, default_character_3d_yaw_deg,
+    default_character_renderer, enabled_hook_sources, normalize_character_3d_scale,
+    normalize_character_3d_yaw_deg, normalize_character_motion_state, normalize_character_renderer,
+    normalize_settings, normalize_terminal_theme_mode, normalize_terminal_theme_palette,
+    read_settings, write_settings, Settings,
+};
+#[cfg(windows)]
+use nagomi_core::settings::{
+    TERMINAL_SHELL_CMD, TERMINAL_SHELL_POWERSHELL, TERMINAL_SHELL_POWERSHELL7, TERMINAL_SHELL_WSL,
+};
+#[cfg(not(windows))]
+use nagomi_core::settings::{TERMINAL_SHELL_LOGIN, UNIX_TERMINAL_SHELL_KINDS};
+use nagomi_core::sink::EventSink;
+use nagomi_core::state::{aggregate_group, ObservedStates, StateChange, StateTrigger, TerminalState};
+use nagomi_core::subworker::{run_tool_subworker_decide, SubworkerLlmDecision, SubworkerToolRunOutput};
+use nagomi_core::terminal_input::{builtin_command_output, TerminalBuiltinInvocation};
+use nagomi_core::workspace::{
+    self, Workspace, WorkspaceRect, WorkspaceTerminal, WORKSPACE_FILE_VERSION,
+};
+use nagomi_core::{control, tray, worker};
 use nagomi_protocol::Message;
 use serde::{Deserialize, Serialize};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
-#[cfg(windows)]
-use std::ffi::OsStr;
 use std::fs;
-use std::hash::{Hash, Hasher};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::net::{TcpListener, TcpStream};
 #[cfg(windows)]
-use std::os::windows::ffi::OsStrExt;
-#[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::path::{Path, PathBuf};
+#[cfg(windows)]
 use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tauri::{
-    menu::{Menu, MenuItem, PredefinedMenuItem},
+    menu::{Menu, MenuItem, PredefinedMenuItem, Submenu},
     tray::TrayIconBuilder,
     AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize, Position, Runtime, Size,
     WebviewUrl, WebviewWindowBuilder,
 };
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
 #[cfg(windows)]
 use windows_sys::Win32::Graphics::Dwm::{
     DwmSetWindowAttribute, DWMNCRP_DISABLED, DWMWA_BORDER_COLOR, DWMWA_COLOR_NONE,
     DWMWA_NCRENDERING_POLICY, DWMWA_WINDOW_CORNER_PREFERENCE, DWMWCP_DONOTROUND,
 };
-#[cfg(windows)]
-use windows_sys::Win32::System::Environment::ExpandEnvironmentStringsW;
-#[cfg(windows)]
-use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_READ, REG_EXPAND_SZ, REG_SZ};
-#[cfg(windows)]
-use winreg::RegKey;
 
-mod completion_hook;
 mod ipc_session;
 mod notify;
-mod worker;
 
 const WINDOW_CHAT: &str = "chat";
 const WINDOW_RUN: &str = "run";
@@ -57,82 +88,11 @@ const WATCHER_WINDOW_MARGIN: i32 = 16;
 const WATCHER_DEBUG_WINDOW_WIDTH: u32 = 480;
 const WATCHER_DEBUG_WINDOW_HEIGHT: u32 = 960;
 const WATCHER_DEBUG_WINDOW_MARGIN: i32 = 20;
-const TERMINAL_SHELL_CMD: &str = "cmd";
-const TERMINAL_SHELL_POWERSHELL: &str = "powershell";
-const TERMINAL_SHELL_POWERSHELL7: &str = "pwsh";
-const TERMINAL_SHELL_WSL: &str = "wsl";
-const TERMINAL_KEYBIND_ARRANGE_DEFAULT: &str = "Ctrl+Shift+Y";
-const TERMINAL_KEYBIND_FOCUS_NEXT_DEFAULT: &str = "Ctrl+Shift+J";
-const TERMINAL_KEYBIND_FOCUS_PREV_DEFAULT: &str = "Ctrl+Shift+K";
-const SUBWORKER_ENABLED_DEFAULT: bool = true;
-const SUBWORKER_DEBUG_ENABLED_DEFAULT: bool = false;
-const SUBWORKER_MODE_CAREFUL: &str = "careful";
-const SUBWORKER_CONFIDENCE_THRESHOLD_DEFAULT: f32 = 0.8;
-const STATUS_DEBUG_ENABLED_DEFAULT: bool = false;
-const CHARACTER_RENDERER_DEFAULT: &str = "3d";
-const CHARACTER_3D_SCALE_DEFAULT: f32 = 1.0;
-const CHARACTER_3D_YAW_DEG_DEFAULT: f32 = 0.0;
+const TRAY_PROFILE_ID_PREFIX: &str = "open_profile:";
+const TRAY_WORKSPACE_ID_PREFIX: &str = "open_workspace:";
+const TRAY_TERMINAL_ID_PREFIX: &str = "pickup_terminal:";
 const CHARACTER_ASSET_MAX_BYTES: usize = 64 * 1024 * 1024;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(default)]
-struct Settings {
-    notifications_enabled: bool,
-    audio_enabled: bool,
-    volume: f32,
-    llm_tool: String,
-    #[serde(default = "default_subworker_enabled")]
-    subworker_enabled: bool,
-    #[serde(default = "default_subworker_debug_enabled")]
-    subworker_debug_enabled: bool,
-    #[serde(default = "default_subworker_mode")]
-    subworker_mode: String,
-    #[serde(default = "default_subworker_confidence_threshold")]
-    subworker_confidence_threshold: f32,
-    // NOTE: Backward compatible alias for the previous display-template setting key.
-    // NOTE: 莉･蜑阪・陦ｨ遉ｺ逕ｨ繝・Φ繝励Ξ險ｭ螳壹く繝ｼ・・ubworker_advice_template_markdown・峨°繧峨・遘ｻ陦後ｒ蜿励￠繧九・    #[serde(default, alias = "subworker_advice_template_markdown")]
-    subworker_prompt_template_markdown: String,
-    #[serde(default = "default_status_debug_enabled")]
-    status_debug_enabled: bool,
-    character_id: String,
-    #[serde(default = "default_character_renderer")]
-    character_renderer: String,
-    #[serde(default)]
-    character_3d_vrm_path: String,
-    #[serde(default = "default_character_3d_scale")]
-    character_3d_scale: f32,
-    #[serde(default = "default_character_3d_yaw_deg")]
-    character_3d_yaw_deg: f32,
-    #[serde(default)]
-    character_motion_default_paths: HashMap<String, String>,
-    log_retention_lines: u32,
-    terminal_watcher_enabled: bool,
-    #[serde(default)]
-    terminal_font_family: String,
-    #[serde(default)]
-    terminal_font_size: u16,
-    #[serde(default)]
-    terminal_theme: String,
-    #[serde(default = "default_terminal_theme_palette")]
-    terminal_theme_palette: String,
-    #[serde(default)]
-    terminal_scrollback_lines: u32,
-    #[serde(default)]
-    terminal_copy_on_select: bool,
-    #[serde(default = "default_terminal_internal_commands_enabled")]
-    terminal_internal_commands_enabled: bool,
-    #[serde(default = "default_terminal_shell_kind")]
-    terminal_shell_kind: String,
-    #[serde(default)]
-    terminal_wsl_distro: String,
-    #[serde(default = "default_terminal_keybind_arrange")]
-    terminal_keybind_arrange: String,
-    #[serde(default = "default_terminal_keybind_focus_next")]
-    terminal_keybind_focus_next: String,
-    #[serde(default = "default_terminal_keybind_focus_prev")]
-    terminal_keybind_focus_prev: String,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 struct CharacterPackManifest {
@@ -166,90 +126,6 @@ impl Default for CharacterPackManifest {
     }
 }
 
-fn default_terminal_shell_kind() -> String {
-    TERMINAL_SHELL_CMD.to_string()
-}
-
-fn default_terminal_internal_commands_enabled() -> bool {
-    true
-}
-
-fn default_terminal_theme_palette() -> String {
-    "dark-ink".to_string()
-}
-
-fn default_terminal_keybind_arrange() -> String {
-    TERMINAL_KEYBIND_ARRANGE_DEFAULT.to_string()
-}
-
-fn default_terminal_keybind_focus_next() -> String {
-    TERMINAL_KEYBIND_FOCUS_NEXT_DEFAULT.to_string()
-}
-
-fn default_terminal_keybind_focus_prev() -> String {
-    TERMINAL_KEYBIND_FOCUS_PREV_DEFAULT.to_string()
-}
-
-fn default_subworker_mode() -> String {
-    SUBWORKER_MODE_CAREFUL.to_string()
-}
-
-fn default_subworker_enabled() -> bool {
-    SUBWORKER_ENABLED_DEFAULT
-}
-
-fn default_subworker_debug_enabled() -> bool {
-    SUBWORKER_DEBUG_ENABLED_DEFAULT
-}
-
-fn default_subworker_confidence_threshold() -> f32 {
-    SUBWORKER_CONFIDENCE_THRESHOLD_DEFAULT
-}
-
-fn default_status_debug_enabled() -> bool {
-    STATUS_DEBUG_ENABLED_DEFAULT
-}
-
-fn default_character_renderer() -> String {
-    CHARACTER_RENDERER_DEFAULT.to_string()
-}
-
-fn default_character_3d_scale() -> f32 {
-    CHARACTER_3D_SCALE_DEFAULT
-}
-
-fn default_character_3d_yaw_deg() -> f32 {
-    CHARACTER_3D_YAW_DEG_DEFAULT
-}
-
-#[derive(Debug, Clone, Serialize)]
-struct TerminalOutputPayload {
-    session_id: String,
-    chunk: String,
-    stream: String,
-}
-
-#[derive(Debug, Clone, Serialize)]
-struct TerminalExitPayload {
-    session_id: String,
-    exit_code: i32,
-}
-
-#[derive(Debug, Clone, Serialize)]
-struct TerminalErrorPayload {
-    session_id: String,
-    message: String,
-}
-
-#[derive(Debug, Clone, Serialize)]
-struct HookStatePayload {
-    source: String,
-    kind: String,
-    source_session_id: Option<String>,
-    state: String,
-    summary: Option<String>,
-}
-
 #[derive(Debug, Clone, Serialize)]
 struct TerminalFocusTransitionPayload {
     token: u64,
@@ -261,6 +137,15 @@ struct AggregateStatePayload {
     state: String,
 }
 
+// 状態機械の遷移 1 回分。全 window へ流す / One state machine transition, sent to every window.
+#[derive(Debug, Clone, Serialize)]
+struct TerminalStatePayload {
+    session_id: String,
+    state: String,
+    previous: Option<String>,
+    trigger: String,
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct CharacterMotionDebugPayload {
     base_state: String,
@@ -272,17 +157,16 @@ struct CodexHookSetupResult {
     status: String,
     message: String,
     config_path: String,
-    script_path: String,
+    cli_path: String,
     hook_path: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SubworkerLlmDecision {
-    action: String,
-    confidence: f32,
-    input: String,
-    advice_markdown: String,
-    reason: String,
+#[derive(Debug, Clone, Serialize)]
+struct AgentHookSetupResult {
+    status: String,
+    config_path: String,
+    backup_path: Option<String>,
+    diff: String,
 }
 
 struct WorkerState {
@@ -293,78 +177,171 @@ struct TerminalWorkerBus {
     tx: std::sync::mpsc::Sender<Message>,
 }
 
-struct TerminalWorkerState {
-    processes: Mutex<HashMap<String, worker::WorkerProcess>>,
-}
-
 struct SessionState {
     current: Mutex<Option<String>>,
 }
 
-struct TerminalAggregateState {
-    per_session: Mutex<HashMap<String, String>>,
-    last_state: Mutex<String>,
-}
-
-impl Default for TerminalAggregateState {
-    fn default() -> Self {
-        Self {
-            per_session: Mutex::new(HashMap::new()),
-            last_state: Mutex::new("idle".to_string()),
-        }
-    }
-}
-
 struct SelectionState {
     current: Mutex<Option<String>>,
 }
 
+// session の台帳は nagomi-core の SessionRegistry。ここは window の対応だけ
+// Session bookkeeping lives in nagomi-core's SessionRegistry; this only maps sessions to windows.
 struct TerminalSessionState {
-    active: Mutex<HashSet<String>>,
     labels: Mutex<HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-enum TerminalInputCaptureMode {
-    #[default]
-    Unknown,
-    SuppressCandidate,
-    PassThrough,
+// watcher が追う集約グループ（空なら全体）/ Aggregate group the watcher follows; empty means every terminal.
+#[derive(Default)]
+struct WatcherAggregateGroup {
+    name: Mutex<String>,
 }
 
-#[derive(Debug, Clone, Default)]
-struct TerminalInputCaptureState {
-    pending: String,
-    mode: TerminalInputCaptureMode,
-    skip_next_lf: bool,
-    echoed_display: String,
+// 状態ジャンプ用に登録した global shortcut（他の登録には触れない）
+// Global shortcuts registered for state jumps, so re-registering leaves any others alone.
+#[derive(Default)]
+struct TerminalStateShortcuts {
+    registered: Mutex<Vec<String>>,
 }
 
+// tray メニューに出している terminal 項目と、設定から写したプロファイル名
+// What the tray menu lists: its terminal entries and the profile names copied from settings.
 #[derive(Default)]
-struct TerminalBuiltinCommandState {
-    captures: Mutex<HashMap<String, TerminalInputCaptureState>>,
+struct TrayMenuState {
+    terminals: Mutex<Vec<(String, String)>>,
+    profiles: Mutex<Vec<String>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum TerminalBuiltinCommand {
-    Ping,
-    Usage,
-    Unknown(String),
+// nagomi-core のイベントを Tauri の window と制御 API へ流す
+// Routes nagomi-core events to Tauri windows and the control API.
+struct TauriSink<R: Runtime> {
+    app: AppHandle<R>,
+    debug_io: bool,
+    broadcast_output: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct TerminalBuiltinInvocation {
-    command: TerminalBuiltinCommand,
-    line: String,
-}
+impl<R: Runtime> TauriSink<R> {
+    fn new(app: &AppHandle<R>) -> Self {
+        Self {
+            app: app.clone(),
+            debug_io: std::env::var_os("NAGOMI_DEBUG_WORKER_IO").is_some(),
+            broadcast_output: std::env::var_os("NAGOMI_ENABLE_TERMINAL_OUTPUT_BROADCAST").is_some(),
+        }
+    }
 
-struct TerminalSmokeWaiter {
-    token: String,
-    sender: std::sync::mpsc::Sender<Result<(), String>>,
+    fn emit_to_session<S: Serialize + Clone>(&self, session_id: &str, event: &str, payload: S) {
+        let label = self
+            .app
+            .try_state::<TerminalSessionState>()
+            .and_then(|state| state.labels.lock().ok()?.get(session_id).cloned());
+        if let Some(window) = label.and_then(|label| self.app.get_webview_window(&label)) {
+            let _ = window.emit(event, payload);
+        }
+    }
 }
 
-struct TerminalSmokeState {
-    waiters: Mutex<HashMap<String, TerminalSmokeWaiter>>,
+impl<R: Runtime> EventSink for TauriSink<R> {
+    fn bus(&self) -> Option<&control::EventBus> {
+        self.app
+            .try_state::<control::EventBus>()
+            .map(|state| state.inner())
+    }
+
+    fn log(&self, message: &str) {
+        let _ = log_worker_event(&self.app, message);
+    }
+
+    fn session_output(&self, session_id: &str, stream: &str, chunk: &str) {
+        if self.debug_io {
+            self.log(&format!(
+                "terminal output flushed: session={session_id} stream={stream} size={}",
+                chunk.len()
+            ));
+        }
+        let payload = TerminalOutputPayload {
+            session_id: session_id.to_string(),
+            chunk: chunk.to_string(),
+            stream: stream.to_string(),
+        };
+        if self.broadcast_output {
+            let _ = self.app.emit("terminal-output-broadcast", payload.clone());
+        }
+        self.emit_to_session(session_id, "terminal-output", payload);
+    }
+
+    fn session_exit(&self, session_id: &str, exit_code: i32) {
+        println!("[terminal-worker] exit {session_id}: {exit_code}");
+        let _ = self.app.state::<ObservedStates>().apply(session_id, StateTrigger::Exit(exit_code), self);
+        let payload = TerminalExitPayload {
+            session_id: session_id.to_string(),
+            exit_code,
+        };
+        self.emit_to_session(session_id, "terminal-exit", payload);
+    }
+
+    fn session_error(&self, session_id: &str, message: &str, recoverable: bool) {
+        println!("[terminal-worker] error {session_id}: {message}");
+        if !recoverable {
+            let _ = self.app.state::<ObservedStates>().apply(session_id, StateTrigger::WorkerFailed, self);
+        }
+        let payload = TerminalErrorPayload {
+            session_id: session_id.to_string(),
+            message: message.to_string(),
+        };
+        self.emit_to_session(session_id, "terminal-error", payload);
+    }
+
+    fn session_closed(&self, _session_id: &str) {
+        close_character_windows_if_all_terminals_closed(&self.app);
+    }
+
+    fn state_changed(&self, session_id: &str, change: &StateChange) {
+        let payload = TerminalStatePayload {
+            session_id: session_id.to_string(),
+            state: change.state.as_str().to_string(),
+            previous: change.previous.map(|state| state.as_str().to_string()),
+            trigger: change.trigger.to_string(),
+        };
+        let _ = self.app.emit("terminal-state", payload);
+        if change.entered(TerminalState::NeedInput) {
+            auto_pickup_need_input_terminal(&self.app, session_id);
+        }
+        refresh_tray_terminals(&self.app);
+    }
+
+    fn aggregate_changed(&self, state: &str) {
+        refresh_tray_status(&self.app);
+        if watcher_aggregate_group(&self.app).is_empty() {
+            emit_terminal_aggregate_state(&self.app, state);
+        }
+    }
+
+    fn group_aggregate_changed(&self, group: &str, state: &str) {
+        let watched = self.app.try_state::<ObservedStates>().and_then(|observed| {
+            observed.group_key(&watcher_aggregate_group(&self.app))
+        });
+        if watched.as_deref() == Some(group) {
+            emit_terminal_aggregate_state(&self.app, state);
+        }
+    }
+
+    // 既知の terminal 宛てはその window だけへ、それ以外は従来どおり全体へ
+    // Events for a known terminal go to its window only; the rest are broadcast as before.
+    fn hook_state(&self, payload: &HookStatePayload) {
+        match payload.session_id.as_deref() {
+            Some(session_id) => {
+                let _ = self.app.state::<ObservedStates>().apply(
+                    session_id,
+                    StateTrigger::Hook(payload.event_kind),
+                    self,
+                );
+                self.emit_to_session(session_id, "completion-hook-state", payload.clone())
+            }
+            None => {
+                let _ = self.app.emit("completion-hook-state", payload.clone());
+            }
+        }
+    }
 }
 
 struct CompletionHookState {
@@ -376,14 +353,6 @@ struct SubworkerCodexSessionState {
     by_ipc_session: Mutex<HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct WindowRect {
-    x: i32,
-    y: i32,
-    width: u32,
-    height: u32,
-}
-
 const CONTEXT_NEW_TERMINAL_OFFSET_X: i32 = 48;
 const CONTEXT_NEW_TERMINAL_OFFSET_Y: i32 = 48;
 
@@ -407,132 +376,6 @@ struct CharacterDebugWindowControlState {
     worker_active: Mutex<bool>,
 }
 
-impl Default for Settings {
-    fn default() -> Self {
-        Self {
-            notifications_enabled: true,
-            audio_enabled: true,
-            volume: 0.8,
-            llm_tool: "codex".to_string(),
-            subworker_enabled: default_subworker_enabled(),
-            subworker_debug_enabled: default_subworker_debug_enabled(),
-            subworker_mode: default_subworker_mode(),
-            subworker_confidence_threshold: default_subworker_confidence_threshold(),
-            subworker_prompt_template_markdown: String::new(),
-            status_debug_enabled: default_status_debug_enabled(),
-            character_id: "default".to_string(),
-            character_renderer: default_character_renderer(),
-            character_3d_vrm_path: String::new(),
-            character_3d_scale: default_character_3d_scale(),
-            character_3d_yaw_deg: default_character_3d_yaw_deg(),
-            character_motion_default_paths: HashMap::new(),
-            log_retention_lines: 20_000,
-            terminal_watcher_enabled: true,
-            terminal_font_family: "ui-monospace, 'Cascadia Mono', Consolas, 'SFMono-Regular', Menlo, Monaco, 'Liberation Mono', 'DejaVu Sans Mono', monospace".to_string(),
-            terminal_font_size: 18,
-            terminal_theme: "dark".to_string(),
-            terminal_theme_palette: default_terminal_theme_palette(),
-            terminal_scrollback_lines: 5000,
-            terminal_copy_on_select: true,
-            terminal_internal_commands_enabled: default_terminal_internal_commands_enabled(),
-            terminal_shell_kind: default_terminal_shell_kind(),
-            terminal_wsl_distro: String::new(),
-            terminal_keybind_arrange: default_terminal_keybind_arrange(),
-            terminal_keybind_focus_next: default_terminal_keybind_focus_next(),
-            terminal_keybind_focus_prev: default_terminal_keybind_focus_prev(),
-        }
-    }
-}
-
-fn normalize_terminal_shell_kind(kind: &str) -> &'static str {
-    match kind.trim().to_ascii_lowercase().as_str() {
-        TERMINAL_SHELL_POWERSHELL => TERMINAL_SHELL_POWERSHELL,
-        TERMINAL_SHELL_POWERSHELL7 => TERMINAL_SHELL_POWERSHELL7,
-        TERMINAL_SHELL_WSL => TERMINAL_SHELL_WSL,
-        _ => TERMINAL_SHELL_CMD,
-    }
-}
-
-#[cfg(windows)]
-fn is_windows_command_available(command_name: &str) -> bool {
-    const CREATE_NO_WINDOW_FLAG: u32 = 0x08000000;
-    let mut command = Command::new("where.exe");
-    command
-        .arg(command_name)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .creation_flags(CREATE_NO_WINDOW_FLAG);
-    command
-        .status()
-        .map(|status| status.success())
-        .unwrap_or(false)
-}
-
-#[cfg(windows)]
-fn build_windows_terminal_command_with_pwsh(settings: &Settings, pwsh_available: bool) -> String {
-    match normalize_terminal_shell_kind(&settings.terminal_shell_kind) {
-        TERMINAL_SHELL_POWERSHELL => "powershell.exe".to_string(),
-        TERMINAL_SHELL_POWERSHELL7 => {
-            if pwsh_available {
-                "pwsh.exe".to_string()
-            } else {
-                "powershell.exe".to_string()
-            }
-        }
-        TERMINAL_SHELL_WSL => {
-            let distro = settings.terminal_wsl_distro.trim();
-            if distro.is_empty() {
-                "wsl.exe".to_string()
-            } else {
-                let escaped = distro.replace('"', "\\\"");
-                format!("wsl.exe -d \"{escaped}\"")
-            }
-        }
-        _ => "cmd.exe".to_string(),
-    }
-}
-
-fn normalize_terminal_theme_mode(mode: &str) -> &'static str {
-    if mode.trim().eq_ignore_ascii_case("light") {
-        "light"
-    } else {
-        "dark"
-    }
-}
-
-fn default_terminal_theme_palette_for_mode(mode: &str) -> &'static str {
-    if mode == "light" {
-        "light-sand"
-    } else {
-        "dark-ink"
-    }
-}
-
-fn normalize_terminal_theme_palette(mode: &str, palette: &str) -> String {
-    let value = palette.trim().to_ascii_lowercase();
-    let valid = if mode == "light" {
-        matches!(
-            value.as_str(),
-            "light-sand" | "light-sage" | "light-sky" | "light-mono"
-        )
-    } else {
-        matches!(
-            value.as_str(),
-            "dark-ink" | "dark-ocean" | "dark-ember" | "dark-mono"
-        )
-    };
-    if valid {
-        value
-    } else {
-        default_terminal_theme_palette_for_mode(mode).to_string()
-    }
-}
-
-#[cfg(windows)]
-fn build_windows_terminal_command(settings: &Settings) -> String {
-    build_windows_terminal_command_with_pwsh(settings, is_windows_command_available("pwsh.exe"))
-}
-
 fn app_config_dir<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
     if let Ok(value) = std::env::var("NAGOMI_APP_CONFIG_DIR") {
         let trimmed = value.trim();
@@ -563,23 +406,6 @@ fn character_pack_manifest_path<R: Runtime>(app: &AppHandle<R>, pack_id: &str) -
     character_packs_dir(app).join(pack_id).join("pack.json")
 }
 
-fn sanitize_asset_component(raw: &str) -> String {
-    let mut out = String::with_capacity(raw.len());
-    for ch in raw.chars() {
-        if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' {
-            out.push(ch);
-        } else {
-            out.push('_');
-        }
-    }
-    let trimmed = out.trim_matches('_').trim_matches('.');
-    if trimmed.is_empty() {
-        "asset".to_string()
-    } else {
-        trimmed.to_string()
-    }
-}
-
 fn sanitize_pack_id(raw: &str) -> String {
     let mut out = String::with_capacity(raw.len());
     for ch in raw.trim().chars() {
@@ -663,32 +489,6 @@ fn normalize_character_pack_manifest(manifest: &mut CharacterPackManifest) -> Re
     Ok(())
 }
 
-fn normalize_character_motion_state(raw: &str) -> Option<&'static str> {
-    let value = raw.trim().to_ascii_lowercase();
-    match value.as_str() {
-        "neutral" => Some("neutral"),
-        "processing" => Some("processing"),
-        "waiting" => Some("waiting"),
-        "need-user" | "need_user" => Some("need-user"),
-        "completion" => Some("completion"),
-        "error-alert" | "error_alert" => Some("error-alert"),
-        _ => None,
-    }
-}
-
-fn normalize_character_motion_path_map(raw: &HashMap<String, String>) -> HashMap<String, String> {
-    raw.iter()
-        .filter_map(|(key, value)| {
-            let state = normalize_character_motion_state(key.as_str())?;
-            let path = value.trim().to_string();
-            if path.is_empty() {
-                return None;
-            }
-            Some((state.to_string(), path))
-        })
-        .collect()
-}
-
 fn write_atomic_bytes(path: &Path, bytes: &[u8]) -> Result<(), String> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).map_err(|err| err.to_string())?;
@@ -720,24 +520,6 @@ fn read_character_pack_manifest(path: &Path) -> Result<CharacterPackManifest> {
     Ok(manifest)
 }
 
-fn normalize_character_renderer(_raw: &str) -> &'static str {
-    "3d"
-}
-
-fn normalize_character_3d_scale(raw: f32) -> f32 {
-    if !raw.is_finite() {
-        return CHARACTER_3D_SCALE_DEFAULT;
-    }
-    raw.clamp(0.2, 3.0)
-}
-
-fn normalize_character_3d_yaw_deg(raw: f32) -> f32 {
-    if !raw.is_finite() {
-        return CHARACTER_3D_YAW_DEG_DEFAULT;
-    }
-    raw.clamp(-180.0, 180.0)
-}
-
 fn worker_log_path<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
     app_config_dir(app).join("worker_smoke.log")
 }
@@ -758,146 +540,6 @@ fn project_prompt_history_dir<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
     app_config_dir(app).join("project-prompt-history")
 }
 
-fn project_prompt_history_path<R: Runtime>(app: &AppHandle<R>, project_key: &str) -> PathBuf {
-    project_prompt_history_dir(app).join(format!("{project_key}.jsonl"))
-}
-
-fn append_jsonl_entry(path: &Path, payload: serde_json::Value) -> Result<(), String> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
-    }
-    let now_ms = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis();
-    let mut entry = payload;
-    if let Some(obj) = entry.as_object_mut() {
-        let stamped = u64::try_from(now_ms).unwrap_or(u64::MAX);
-        obj.insert(
-            "ts_ms".to_string(),
-            serde_json::Value::Number(stamped.into()),
-        );
-    }
-    let raw = serde_json::to_string(&entry).map_err(|err| err.to_string())?;
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)
-        .map_err(|err| err.to_string())?;
-    writeln!(file, "{}", raw).map_err(|err| err.to_string())?;
-    Ok(())
-}
-
-fn codex_config_path() -> Option<PathBuf> {
-    let home = std::env::var_os("USERPROFILE").or_else(|| std::env::var_os("HOME"))?;
-    Some(PathBuf::from(home).join(".codex").join("config.toml"))
-}
-
-fn codex_notify_script_body() -> String {
-    [
-        "const fs = require(\"node:fs\");",
-        "const os = require(\"node:os\");",
-        "const path = require(\"node:path\");",
-        "",
-        "function hooksDir() {",
-        "  const base = process.env.NAGOMI_HOOKS_DIR;",
-        "  if (base) return base;",
-        "  return path.join(os.homedir(), \".nagomi\", \"hooks\");",
-        "}",
-        "",
-        "function parseEvent(raw) {",
-        "  if (!raw) return null;",
-        "  try {",
-        "    return JSON.parse(raw);",
-        "  } catch {",
-        "    return { raw };",
-        "  }",
-        "}",
-        "",
-        "function main() {",
-        "  const raw = process.argv[2];",
-        "  if (!raw) return;",
-        "  const event = parseEvent(raw);",
-        "  if (!event) return;",
-        "  const sourceSessionId = process.env.NAGOMI_SESSION_ID;",
-        "  const payload = {",
-        "    source: \"codex\",",
-        "    event,",
-        "    ts_ms: Date.now(),",
-        "  };",
-        "  if (sourceSessionId) {",
-        "    payload.source_session_id = sourceSessionId;",
-        "  }",
-        "  const base = hooksDir();",
-        "  fs.mkdirSync(base, { recursive: true });",
-        "  const filePath = path.join(base, \"codex.jsonl\");",
-        "  fs.appendFileSync(filePath, JSON.stringify(payload) + \"\\n\", \"utf8\");",
-        "}",
-        "",
-        "try {",
-        "  main();",
-        "} catch (err) {",
-        "  console.error(err);",
-        "}",
-        "",
-    ]
-    .join("\n")
-}
-
-fn toml_escape_path(path: &Path) -> String {
-    path.to_string_lossy()
-        .replace('\\', "/")
-        .replace('"', "\\\"")
-}
-
-fn url_decode(input: &str) -> String {
-    let bytes = input.as_bytes();
-    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'+' => {
-                out.push(b' ');
-                i += 1;
-            }
-            b'%' if i + 2 < bytes.len() => {
-                let hex = &bytes[i + 1..i + 3];
-                let hi = (hex[0] as char).to_digit(16);
-                let lo = (hex[1] as char).to_digit(16);
-                if let (Some(hi), Some(lo)) = (hi, lo) {
-                    out.push(((hi << 4) + lo) as u8);
-                    i += 3;
-                } else {
-                    out.push(bytes[i]);
-                    i += 1;
-                }
-            }
-            byte => {
-                out.push(byte);
-                i += 1;
-            }
-        }
-    }
-    String::from_utf8_lossy(&out).to_string()
-}
-
-fn parse_query_pairs(query: &str) -> HashMap<String, String> {
-    let mut pairs = HashMap::new();
-    for part in query.split('&') {
-        if part.is_empty() {
-            continue;
-        }
-        let mut iter = part.splitn(2, '=');
-        let key = iter.next().unwrap_or_default();
-        let value = iter.next().unwrap_or_default();
-        if key.is_empty() {
-            continue;
-        }
-        pairs.insert(url_decode(key), url_decode(value));
-    }
-    pairs
-}
-
 fn test_endpoints_enabled() -> bool {
     matches!(
         std::env::var("NAGOMI_ENABLE_TEST_ENDPOINTS")
@@ -907,379 +549,20 @@ fn test_endpoints_enabled() -> bool {
     )
 }
 
-#[cfg(windows)]
-fn expand_environment_strings(value: &str) -> String {
-    let wide: Vec<u16> = OsStr::new(value).encode_wide().chain(Some(0)).collect();
-    let mut buffer: Vec<u16> = vec![0; 32768];
-    unsafe {
-        let mut len =
-            ExpandEnvironmentStringsW(wide.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32);
-        if len == 0 {
-            return value.to_string();
-        }
-        if len as usize > buffer.len() {
-            buffer.resize(len as usize, 0);
-            len =
-                ExpandEnvironmentStringsW(wide.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32);
-            if len == 0 {
-                return value.to_string();
-            }
-        }
-        let slice_len = len.saturating_sub(1) as usize;
-        String::from_utf16_lossy(&buffer[..slice_len])
-    }
-}
-
-#[cfg(windows)]
-fn decode_reg_value(value: &winreg::RegValue) -> Option<String> {
-    if value.vtype != REG_SZ && value.vtype != REG_EXPAND_SZ {
-        return None;
-    }
-    let mut units: Vec<u16> = Vec::with_capacity(value.bytes.len() / 2);
-    for chunk in value.bytes.chunks(2) {
-        if chunk.len() == 2 {
-            units.push(u16::from_le_bytes([chunk[0], chunk[1]]));
-        }
-    }
-    let len = units
-        .iter()
-        .position(|&unit| unit == 0)
-        .unwrap_or(units.len());
-    let mut text = String::from_utf16_lossy(&units[..len]);
-    if value.vtype == REG_EXPAND_SZ {
-        text = expand_environment_strings(&text);
-    }
-    Some(text)
-}
-
-#[cfg(windows)]
-fn read_registry_env(root: RegKey, path: &str) -> HashMap<String, String> {
-    let mut env = HashMap::new();
-    let Ok(key) = root.open_subkey_with_flags(path, KEY_READ) else {
-        return env;
-    };
-    for item in key.enum_values() {
-        let Ok((name, value)) = item else {
-            continue;
-        };
-        let Some(text) = decode_reg_value(&value) else {
-            continue;
-        };
-        if name.is_empty() {
-            continue;
-        }
-        env.insert(name, text);
-    }
-    env
-}
-
-#[cfg(windows)]
-fn normalize_env_key(key: &str) -> String {
-    key.to_ascii_uppercase()
-}
-
-#[cfg(windows)]
-struct EnvCollector {
-    map: HashMap<String, (String, String)>,
-}
-
-#[cfg(windows)]
-impl EnvCollector {
-    fn new() -> Self {
-        Self {
-            map: HashMap::new(),
-        }
-    }
-
-    fn set_value(&mut self, key: &str, value: &str) {
-        let normalized = normalize_env_key(key);
-        if let Some((existing_key, existing_value)) = self.map.get_mut(&normalized) {
-            *existing_value = value.to_string();
-            if existing_key.is_empty() {
-                *existing_key = key.to_string();
-            }
-        } else {
-            self.map
-                .insert(normalized, (key.to_string(), value.to_string()));
-        }
-    }
-
-    fn insert_if_missing(&mut self, key: &str, value: &str) {
-        let normalized = normalize_env_key(key);
-        if !self.map.contains_key(&normalized) {
-            self.map
-                .insert(normalized, (key.to_string(), value.to_string()));
-        }
-    }
-
-    fn get(&self, key: &str) -> Option<&String> {
-        let normalized = normalize_env_key(key);
-        self.map.get(&normalized).map(|(_, value)| value)
-    }
-
-    fn into_map(self) -> HashMap<String, String> {
-        self.map
-            .into_iter()
-            .map(|(_, (key, value))| (key, value))
-            .collect()
-    }
-}
-
-#[cfg(windows)]
-fn get_case_insensitive(map: &HashMap<String, String>, key: &str) -> Option<String> {
-    map.iter()
-        .find(|(name, _)| name.eq_ignore_ascii_case(key))
-        .map(|(_, value)| value.clone())
-}
-
-#[cfg(windows)]
-fn normalize_path_entry(entry: &str) -> String {
-    entry
-        .trim()
-        .replace('/', "\\")
-        .trim_end_matches('\\')
-        .to_ascii_lowercase()
-}
-
-#[cfg(windows)]
-fn merge_paths(base: &str, extra: &str) -> String {
-    let mut merged: Vec<String> = Vec::new();
-    let mut seen: HashSet<String> = HashSet::new();
-    for part in base.split(';') {
-        let trimmed = part.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        let key = normalize_path_entry(trimmed);
-        if seen.insert(key) {
-            merged.push(trimmed.to_string());
-        }
-    }
-    for part in extra.split(';') {
-        let trimmed = part.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        let key = normalize_path_entry(trimmed);
-        if seen.insert(key) {
-            merged.push(trimmed.to_string());
-        }
-    }
-    merged.join(";")
-}
-
-#[cfg(windows)]
-fn build_windows_terminal_env(session_id: &str) -> HashMap<String, String> {
-    let mut collector = EnvCollector::new();
-    for (key, value) in std::env::vars() {
-        collector.set_value(&key, &value);
-    }
-
-    let system_env = read_registry_env(
-        RegKey::predef(HKEY_LOCAL_MACHINE),
-        "SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Environment",
-    );
-    let user_env = read_registry_env(RegKey::predef(HKEY_CURRENT_USER), "Environment");
-
-    for (key, value) in &system_env {
-        collector.insert_if_missing(key, value);
-    }
-    for (key, value) in &user_env {
-        if key.eq_ignore_ascii_case("PATH") {
-            continue;
-        }
-        collector.insert_if_missing(key, value);
-    }
-
-    let system_path = get_case_insensitive(&system_env, "PATH");
-    let user_path = get_case_insensitive(&user_env, "PATH");
-    let registry_path = match (system_path, user_path) {
-        (Some(system), Some(user)) if !user.is_empty() => Some(format!("{system};{user}")),
-        (Some(system), _) => Some(system),
-        (_, Some(user)) => Some(user),
-        _ => None,
-    };
-
-    if let Some(registry_path) = registry_path {
-        let current_path = collector.get("PATH").cloned().unwrap_or_default();
-        let merged_path = merge_paths(&current_path, &registry_path);
-        if !merged_path.is_empty() {
-            collector.set_value("Path", &merged_path);
-        }
-    }
-
-    collector.set_value("NAGOMI_SESSION_ID", session_id);
-    collector.into_map()
-}
-
-#[cfg(not(windows))]
-fn build_windows_terminal_env(_session_id: &str) -> HashMap<String, String> {
-    HashMap::new()
-}
-
-fn has_header_terminator(buffer: &[u8]) -> bool {
-    buffer.windows(4).any(|slice| slice == b"\r\n\r\n")
-        || buffer.windows(2).any(|slice| slice == b"\n\n")
-}
-
-fn ensure_codex_hook_files(base_dir: &Path) -> Result<(PathBuf, PathBuf, PathBuf), String> {
-    fs::create_dir_all(base_dir).map_err(|err| err.to_string())?;
-    let hook_path = base_dir.join("codex.jsonl");
-    if !hook_path.exists() {
-        fs::write(&hook_path, "").map_err(|err| err.to_string())?;
-    }
-    let script_path = base_dir.join("nagomi_codex_notify.js");
-    let body = codex_notify_script_body();
-    let needs_script_update = match fs::read_to_string(&script_path) {
-        Ok(existing) => existing != body,
-        Err(_) => true,
-    };
-    if needs_script_update {
-        fs::write(&script_path, body).map_err(|err| err.to_string())?;
-    }
-    let legacy_py = base_dir.join("nagomi_codex_notify.py");
-    Ok((script_path, hook_path, legacy_py))
-}
-
-fn send_terminal_input_for_session<R: Runtime>(
-    app: &AppHandle<R>,
-    session_id: &str,
-    text: &str,
-) -> Result<(), String> {
-    let workers = app.state::<TerminalWorkerState>();
-    let mut guard = workers
-        .processes
-        .lock()
-        .map_err(|_| "terminal worker lock".to_string())?;
-    let Some(process) = guard.get_mut(session_id) else {
-        return Err("terminal session not started".to_string());
-    };
-    process
-        .send_input(nagomi_protocol::SendInput {
-            session_id: session_id.to_string(),
-            text: text.to_string(),
-        })
-        .map_err(|err| err.to_string())
-}
-
-fn ensure_codex_config(script_path: &Path, legacy_py: &Path) -> Result<(String, String), String> {
-    let config_path = codex_config_path().ok_or_else(|| "codex config path missing".to_string())?;
-    if let Some(parent) = config_path.parent() {
-        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
-    }
-    let script_marker = script_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("nagomi_codex_notify.js");
-    let legacy_marker = legacy_py
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("nagomi_codex_notify.py");
-    let script_toml = toml_escape_path(script_path);
-    let legacy_toml = toml_escape_path(legacy_py);
-    let mut current = String::new();
-    if config_path.exists() {
-        let mut file = fs::File::open(&config_path).map_err(|err| err.to_string())?;
-        file.read_to_string(&mut current)
-            .map_err(|err| err.to_string())?;
-    }
-    let mut has_top_level_notify = false;
-    let mut has_legacy = false;
-    let mut has_script = false;
-    let mut has_command = false;
-    let mut seen_table = false;
-    for line in current.lines() {
-        let trimmed = line.trim_start();
-        if trimmed.starts_with('[') {
-            seen_table = true;
-        }
-        if trimmed.starts_with("notify") {
-            if !seen_table {
-                has_top_level_notify = true;
-            }
-            if line.contains("nagomi-codex-notify") {
-                has_command = true;
-            }
-            if line.contains(legacy_marker) || line.contains(&legacy_toml) {
-                has_legacy = true;
-            }
-            if line.contains(script_marker) || line.contains(&script_toml) {
-                has_script = true;
-            }
-        }
-    }
-    if has_top_level_notify && (has_command || has_script) {
-        return Ok((
-            "already_installed".to_string(),
-            "codex notify already configured".to_string(),
-        ));
-    }
-    let notify_line = format!("notify = [\"node\", {}]", script_toml);
-    if has_top_level_notify && !has_legacy {
-        return Ok((
-            "skipped_existing_notify".to_string(),
-            "notify already present; skipped updating config".to_string(),
-        ));
-    }
-    let mut next_lines: Vec<String> = Vec::new();
-    if has_legacy || has_script {
-        for line in current.lines() {
-            if line.trim_start().starts_with("notify") {
-                next_lines.push(notify_line.clone());
-            } else {
-                next_lines.push(line.to_string());
-            }
-        }
-        let next = next_lines.join("\n");
-        fs::write(&config_path, format!("{next}\n")).map_err(|err| err.to_string())?;
-        return Ok((
-            "migrated_py_to_js".to_string(),
-            "codex notify updated".to_string(),
-        ));
-    }
-    let next = rewrite_codex_notify_config_text(&current, &notify_line);
-    fs::write(&config_path, format!("{next}\n")).map_err(|err| err.to_string())?;
-    Ok((
-        "installed".to_string(),
-        "codex notify configured".to_string(),
-    ))
-}
-
-fn rewrite_codex_notify_config_text(current: &str, notify_line: &str) -> String {
-    let mut filtered_lines: Vec<String> = Vec::new();
-    let mut insert_at = None;
-    for line in current.lines() {
-        let trimmed = line.trim_start();
-        if insert_at.is_none() && trimmed.starts_with('[') {
-            insert_at = Some(filtered_lines.len());
-        }
-        if trimmed == "# added by nagomi" || trimmed.starts_with("notify") {
-            continue;
-        }
-        filtered_lines.push(line.to_string());
-    }
-    let insert_at = insert_at.unwrap_or(filtered_lines.len());
-    let mut next_lines: Vec<String> = Vec::new();
-    next_lines.extend(filtered_lines[..insert_at].iter().cloned());
-    if !next_lines.is_empty() && !next_lines.last().map(|line| line.is_empty()).unwrap_or(false) {
-        next_lines.push(String::new());
-    }
-    // notify はトップレベルに置く / Keep notify at TOML top-level so Codex can read it.
-    next_lines.push("# added by nagomi".to_string());
-    next_lines.push(notify_line.to_string());
-    if insert_at < filtered_lines.len() {
-        next_lines.push(String::new());
-    }
-    next_lines.extend(filtered_lines[insert_at..].iter().cloned());
-    next_lines.join("\n")
-}
-
 fn start_health_server<R: Runtime>(app: AppHandle<R>) {
     let port = std::env::var("NAGOMI_ORCH_HEALTH_PORT")
         .ok()
         .and_then(|value| value.parse::<u16>().ok())
         .unwrap_or(17707);
     thread::spawn(move || {
+        // トークンが用意できなければ制御口は開かない / Fail closed when the token cannot be prepared.
+        let token = match control::load_or_create_token(&app_config_dir(&app)) {
+            Ok(token) => token,
+            Err(err) => {
+                let _ = log_worker_event(&app, &format!("control token unavailable: {err}"));
+                return;
+            }
+        };
         let addr = format!("127.0.0.1:{port}");
         let sock_addr = match addr.parse::<std::net::SocketAddr>() {
             Ok(value) => value,
@@ -1325,12 +608,27 @@ fn start_health_server<R: Runtime>(app: AppHandle<R>) {
             }
         };
         let _ = log_worker_event(&app, &format!("health listening: {addr}"));
+        // 読み取りと認証も別 thread で行い、遅い client が `/health` を塞がないようにする
+        // Reads and auth run off this thread too, so a slow client can't stall `/health`.
+        let pending = control::PendingLimit::new(control::MAX_PENDING_CONNECTIONS);
         for stream in listener.incoming() {
-            let stream = match stream {
+            let mut stream = match stream {
                 Ok(stream) => stream,
                 Err(_) => continue,
             };
-            handle_health_connection(stream, &app);
+            let Some(permit) = pending.try_acquire() else {
+                control::refuse_busy(&mut stream);
+                continue;
+            };
+            let app = app.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                let request = accept_health_request(&mut stream, &app, &token);
+                drop(permit);
+                if let Some(request) = request {
+                    handle_health_connection(stream, &app, request);
+                }
+            });
         }
     });
 }
@@ -1348,16 +646,6 @@ struct OrchestratorRuntimeFlags {
     exit_on_last_terminal: bool,
 }
 
-fn generate_terminal_session_id() -> String {
-    static SEQ: AtomicU64 = AtomicU64::new(0);
-    let nonce = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_millis(0))
-        .as_millis();
-    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
-    format!("terminal-{nonce}-{seq}")
-}
-
 fn terminal_window_label(session_id: &str) -> String {
     let safe_id = session_id
         .chars()
@@ -1366,198 +654,6 @@ fn terminal_window_label(session_id: &str) -> String {
     format!("terminal-{safe_id}")
 }
 
-fn parse_terminal_builtin_command(line: &str) -> Option<TerminalBuiltinCommand> {
-    let cleaned = strip_ansi_control_sequences(line);
-    let trimmed = cleaned.trim();
-    let rest = trimmed.strip_prefix(":ng")?;
-    let args = rest.trim();
-    if args.is_empty() {
-        return Some(TerminalBuiltinCommand::Usage);
-    }
-    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
-        return Some(TerminalBuiltinCommand::Unknown(args.to_string()));
-    }
-    let mut parts = args.split_whitespace();
-    let command = parts.next().unwrap_or_default().to_ascii_lowercase();
-    if command == "ping" && parts.next().is_none() {
-        return Some(TerminalBuiltinCommand::Ping);
-    }
-    Some(TerminalBuiltinCommand::Unknown(args.to_string()))
-}
-
-fn builtin_local_display_text(pending: &str) -> Option<String> {
-    let cleaned = strip_ansi_control_sequences(pending);
-    if cleaned.trim_start().starts_with(":ng") {
-        return Some(cleaned);
-    }
-    None
-}
-
-fn append_local_echo_delta(
-    capture: &mut TerminalInputCaptureState,
-    next_text: Option<String>,
-    out: &mut String,
-) {
-    let previous: Vec<char> = capture.echoed_display.chars().collect();
-    let next = next_text.unwrap_or_default();
-    let next_chars: Vec<char> = next.chars().collect();
-    let mut common = 0usize;
-    while common < previous.len()
-        && common < next_chars.len()
-        && previous[common] == next_chars[common]
-    {
-        common += 1;
-    }
-    for _ in common..previous.len() {
-        out.push('\u{0008}');
-        out.push(' ');
-        out.push('\u{0008}');
-    }
-    for ch in next_chars.iter().skip(common) {
-        out.push(*ch);
-    }
-    capture.echoed_display = next;
-}
-
-fn strip_ansi_control_sequences(input: &str) -> String {
-    let chars: Vec<char> = input.chars().collect();
-    let mut out = String::new();
-    let mut i = 0usize;
-    while i < chars.len() {
-        let ch = chars[i];
-        if ch == '\u{001b}' {
-            i += 1;
-            if i >= chars.len() {
-                break;
-            }
-            let next = chars[i];
-            if next == '[' {
-                i += 1;
-                while i < chars.len() {
-                    let code = chars[i] as u32;
-                    i += 1;
-                    if (0x40..=0x7e).contains(&code) {
-                        break;
-                    }
-                }
-                continue;
-            }
-            if next == ']' {
-                i += 1;
-                while i < chars.len() {
-                    if chars[i] == '\u{0007}' {
-                        i += 1;
-                        break;
-                    }
-                    if chars[i] == '\u{001b}' && i + 1 < chars.len() && chars[i + 1] == '\\' {
-                        i += 2;
-                        break;
-                    }
-                    i += 1;
-                }
-                continue;
-            }
-            // Other ESC-prefixed controls are ignored as one sequence head.
-            i += 1;
-            continue;
-        }
-        if ch.is_control() && !ch.is_whitespace() {
-            i += 1;
-            continue;
-        }
-        out.push(ch);
-        i += 1;
-    }
-    out
-}
-
-fn process_terminal_input_chunk(
-    capture: &mut TerminalInputCaptureState,
-    chunk: &str,
-) -> (String, Vec<TerminalBuiltinInvocation>, String) {
-    let mut forward = String::new();
-    let mut builtins = Vec::new();
-    let mut local_echo = String::new();
-
-    for ch in chunk.chars() {
-        if capture.skip_next_lf {
-            capture.skip_next_lf = false;
-            if ch == '\n' {
-                continue;
-            }
-        }
-        if ch == '\r' || ch == '\n' {
-            let builtin = parse_terminal_builtin_command(&capture.pending);
-            if let Some(command) = builtin {
-                let display_line = strip_ansi_control_sequences(&capture.pending);
-                if capture.echoed_display.is_empty() {
-                    append_local_echo_delta(
-                        capture,
-                        builtin_local_display_text(&capture.pending),
-                        &mut local_echo,
-                    );
-                }
-                local_echo.push('\r');
-                local_echo.push('\n');
-                builtins.push(TerminalBuiltinInvocation {
-                    command,
-                    line: display_line.trim().to_string(),
-                });
-                if ch == '\r' {
-                    capture.skip_next_lf = true;
-                }
-            } else {
-                forward.push_str(&capture.pending);
-                forward.push(ch);
-            }
-            capture.pending.clear();
-            capture.mode = TerminalInputCaptureMode::Unknown;
-            capture.echoed_display.clear();
-            continue;
-        }
-
-        if capture.mode == TerminalInputCaptureMode::PassThrough {
-            forward.push(ch);
-            continue;
-        }
-
-        if ch == '\u{0008}' || ch == '\u{007f}' {
-            let _ = capture.pending.pop();
-            if capture.mode == TerminalInputCaptureMode::SuppressCandidate {
-                append_local_echo_delta(
-                    capture,
-                    builtin_local_display_text(&capture.pending),
-                    &mut local_echo,
-                );
-            }
-            continue;
-        }
-
-        capture.pending.push(ch);
-        if capture.mode == TerminalInputCaptureMode::Unknown {
-            let cleaned_for_mode = strip_ansi_control_sequences(&capture.pending);
-            if let Some(first_non_ws) = cleaned_for_mode.chars().find(|c| !c.is_whitespace()) {
-                if first_non_ws == ':' {
-                    capture.mode = TerminalInputCaptureMode::SuppressCandidate;
-                } else {
-                    capture.mode = TerminalInputCaptureMode::PassThrough;
-                    forward.push_str(&capture.pending);
-                    capture.pending.clear();
-                }
-            }
-        }
-        if capture.mode == TerminalInputCaptureMode::SuppressCandidate {
-            append_local_echo_delta(
-                capture,
-                builtin_local_display_text(&capture.pending),
-                &mut local_echo,
-            );
-        }
-    }
-
-    (forward, builtins, local_echo)
-}
-
 fn emit_terminal_output_for_session<R: Runtime>(
     app: &AppHandle<R>,
     session_id: &str,
@@ -1592,58 +688,46 @@ fn execute_terminal_builtin_command<R: Runtime>(
     session_id: &str,
     invocation: &TerminalBuiltinInvocation,
 ) -> Result<(), String> {
-    let mut out = String::new();
-    match &invocation.command {
-        TerminalBuiltinCommand::Ping => {
-            out.push_str("pong\r\n");
-        }
-        TerminalBuiltinCommand::Usage => {
-            out.push_str("[nagomi] usage: :ng ping\r\n");
-        }
-        TerminalBuiltinCommand::Unknown(raw) => {
-            out.push_str(&format!("[nagomi] unknown :ng command: {raw}\r\n"));
-        }
-    }
+    let out = builtin_command_output(&invocation.command);
     emit_terminal_output_for_session(app, session_id, &out)
 }
 
-fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R>) {
+// `GET /events`: 現在の aggregate を最初に送り、以後は bus のイベントを SSE で流す
+// `GET /events`: send the current aggregate first, then relay bus events as SSE.
+fn serve_control_events<R: Runtime>(
+    mut stream: TcpStream,
+    app: &AppHandle<R>,
+    request: &control::HttpRequest,
+) {
+    let Some(bus) = app.try_state::<control::EventBus>() else {
+        let err = ControlError::new(503, "unavailable", "event bus not ready");
+        let _ = control::write_response(&mut stream, &err.to_response());
+        return;
+    };
+    control::serve_events(stream, &bus, request, || {
+        app.try_state::<ObservedStates>()
+            .map(|states| states.aggregate_events())
+            .unwrap_or_default()
+    });
+}
+
+// 要求を読み、`/health` と認証失敗はその場で返す。処理を続ける要求だけ返す
+// Reads the request and answers `/health` and auth failures in place; returns only the ones to serve.
+fn accept_health_request<R: Runtime>(
+    stream: &mut TcpStream,
+    app: &AppHandle<R>,
+    token: &str,
+) -> Option<control::HttpRequest> {
     let _ = stream.set_read_timeout(Some(Duration::from_millis(500)));
-    let started_at = Instant::now();
-    let mut buffer: Vec<u8> = Vec::with_capacity(512);
-    let mut temp = [0u8; 256];
-    loop {
-        match stream.read(&mut temp) {
-            Ok(0) => break,
-            Ok(size) => {
-                buffer.extend_from_slice(&temp[..size]);
-                if has_header_terminator(&buffer) || buffer.len() >= 8192 {
-                    break;
-                }
-            }
-            Err(err)
-                if matches!(
-                    err.kind(),
-                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
-                ) =>
-            {
-                if started_at.elapsed() > Duration::from_secs(2) {
-                    break;
-                }
-                continue;
-            }
-            Err(err) => {
-                let _ = log_worker_event(app, &format!("health read failed: {err}"));
-                return;
-            }
-        }
-        if started_at.elapsed() > Duration::from_secs(2) {
-            break;
+    let request = match control::read_request(stream) {
+        Ok(request) => request,
+        Err(err) => {
+            let _ = log_worker_event(app, &format!("health read failed: {}", err.message));
+            let _ = control::write_response(stream, &err.to_response());
+            return None;
         }
-    }
-    let request = String::from_utf8_lossy(&buffer);
-    let first_line = request.lines().next().unwrap_or_default();
-    if first_line.starts_with("GET /health ") {
+    };
+    if request.method == "GET" && request.path == "/health" {
         let body = format!(r#"{{"status":"ok","pid":{}}}"#, std::process::id());
         let response = format!(
             "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
@@ -1651,24 +735,54 @@ fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R
             body
         );
         let _ = stream.write_all(response.as_bytes());
-        return;
+        return None;
+    }
+    if control::requires_auth(&request.method, &request.path) {
+        if let Err(err) = control::authorize(&request, token) {
+            let _ = control::write_response(stream, &err.to_response());
+            return None;
+        }
     }
+    Some(request)
+}
 
-    if let Some(path) = first_line.split_whitespace().nth(1) {
-        if path.starts_with("/open-terminal") {
-            let requested_session_id = path
-                .splitn(2, '?')
-                .nth(1)
-                .and_then(|query| {
-                    query
-                        .split('&')
-                        .find(|part| part.starts_with("session_id="))
-                })
-                .and_then(|part| part.splitn(2, '=').nth(1))
-                .filter(|value| !value.is_empty())
-                .map(|value| value.to_string());
+fn handle_health_connection<R: Runtime>(
+    mut stream: TcpStream,
+    app: &AppHandle<R>,
+    request: control::HttpRequest,
+) {
+    match control::route(&request.method, &request.path) {
+        Ok(Some(ControlRoute::Events)) => {
+            serve_control_events(stream, app, &request);
+            return;
+        }
+        Ok(Some(route)) => {
+            let response = control::handle(&AppControlBackend { app }, route, &request);
+            let _ = control::write_response(&mut stream, &response);
+            return;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            let _ = control::write_response(&mut stream, &err.to_response());
+            return;
+        }
+    }
 
-            let mut session_id = requested_session_id.unwrap_or_else(generate_terminal_session_id);
+    {
+        let path = request.target.as_str();
+        if path.starts_with("/open-terminal") {
+            let query = path.splitn(2, '?').nth(1).unwrap_or("");
+            let pairs = parse_query_pairs(query);
+            let requested_session_id = pairs
+                .get("session_id")
+                .filter(|value| !value.is_empty())
+                .cloned();
+            let profile_name = pairs
+                .get("profile")
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty());
+
+            let mut session_id = requested_session_id.unwrap_or_else(generate_terminal_session_id);
             if let Some(state) = app.try_state::<TerminalSessionState>() {
                 if let Ok(guard) = state.labels.lock() {
                     if guard.contains_key(&session_id) {
@@ -1677,7 +791,29 @@ fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R
                 }
             }
 
-            let _ = open_terminal_window_inner(app.clone(), session_id.clone());
+            let cwd = pairs.get("cwd").map(|value| value.trim().to_string());
+            let opened = terminal_launch_options(app, profile_name.as_deref(), cwd.as_deref())
+                .and_then(|options| {
+                    open_terminal_window_with_options(app.clone(), session_id.clone(), options)
+                });
+            if let Err(err) = opened {
+                let _ = log_worker_event(app, &format!("open-terminal failed: {err}"));
+                let (status, label) = if err.starts_with("terminal profile not found") {
+                    ("404 Not Found", "not_found")
+                } else if err.starts_with("terminal cwd is not a directory") {
+                    ("400 Bad Request", "bad_request")
+                } else {
+                    ("500 Internal Server Error", "error")
+                };
+                let body = serde_json::json!({ "status": label, "error": err }).to_string();
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+                return;
+            }
             let body = format!(r#"{{"status":"ok","session_id":"{}"}}"#, session_id);
             let response = format!(
                 "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
@@ -1687,6 +823,70 @@ fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R
             let _ = stream.write_all(response.as_bytes());
             return;
         }
+        if path.starts_with("/save-workspace") || path.starts_with("/open-workspace") {
+            // 保存はファイルを書くので POST のみ / Saving writes a file, so it only accepts POST.
+            if path.starts_with("/save-workspace") && request.method != "POST" {
+                write_health_json(
+                    &mut stream,
+                    "405 Method Not Allowed",
+                    &serde_json::json!({
+                        "status": "method_not_allowed",
+                        "error": format!("{} is not allowed on /save-workspace", request.method),
+                    }),
+                );
+                return;
+            }
+            let query = path.splitn(2, '?').nth(1).unwrap_or("");
+            let pairs = parse_query_pairs(query);
+            let name = pairs
+                .get("name")
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default();
+            if name.is_empty() {
+                write_health_json(
+                    &mut stream,
+                    "400 Bad Request",
+                    &serde_json::json!({ "status": "bad_request", "error": "name is required" }),
+                );
+                return;
+            }
+            let result = if path.starts_with("/save-workspace") {
+                save_workspace_inner(app, &name).map(|path| {
+                    serde_json::json!({ "status": "ok", "path": path.to_string_lossy() })
+                })
+            } else {
+                open_workspace_inner(app, &name)
+                    .map(|session_ids| serde_json::json!({ "status": "ok", "session_ids": session_ids }))
+            };
+            match result {
+                Ok(body) => write_health_json(&mut stream, "200 OK", &body),
+                Err(err) => {
+                    let _ = log_worker_event(app, &format!("workspace request failed: {err}"));
+                    let (status, label) = if err.starts_with("workspace not found") {
+                        ("404 Not Found", "not_found")
+                    } else if err.starts_with("no terminal windows") || err.contains("name is empty") {
+                        ("400 Bad Request", "bad_request")
+                    } else {
+                        ("500 Internal Server Error", "error")
+                    };
+                    write_health_json(
+                        &mut stream,
+                        status,
+                        &serde_json::json!({ "status": label, "error": err }),
+                    );
+                }
+            }
+            return;
+        }
+        if path.starts_with("/list-workspaces") {
+            let names = workspace::list_workspaces(&workspaces_dir(app));
+            write_health_json(
+                &mut stream,
+                "200 OK",
+                &serde_json::json!({ "status": "ok", "workspaces": names }),
+            );
+            return;
+        }
         if path.starts_with("/terminal-send") {
             // Test-only endpoint guarded by env flag. / 繝・せ繝育畑繧ｨ繝ｳ繝峨・繧､繝ｳ繝茨ｼ育腸蠅・､画焚縺ｧ譛牙柑蛹厄ｼ・
             if !test_endpoints_enabled() {
@@ -1720,7 +920,7 @@ fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R
                     text.len()
                 ),
             );
-            match send_terminal_input_for_session(app, &session_id, &text) {
+            match app.state::<SessionRegistry>().send_input(&session_id, &text) {
                 Ok(()) => {
                     let body = r#"{"status":"ok"}"#;
                     let response = format!(
@@ -1764,48 +964,144 @@ fn handle_health_connection<R: Runtime>(mut stream: TcpStream, app: &AppHandle<R
     let _ = stream.write_all(response.as_bytes());
 }
 
-fn read_settings(path: &Path) -> Result<Settings> {
-    if !path.exists() {
-        return Ok(Settings::default());
+// 制御 API から見た terminal セッション操作 / Terminal session operations behind the control API.
+struct AppControlBackend<'a, R: Runtime> {
+    app: &'a AppHandle<R>,
+}
+
+fn control_error(err: String) -> ControlError {
+    if err.starts_with("terminal profile not found") || err.contains("terminal session not started")
+    {
+        ControlError::not_found(err)
+    } else if err.starts_with("terminal cwd is not a directory") {
+        ControlError::bad_request(err)
+    } else {
+        ControlError::internal(err)
+    }
+}
+
+impl<R: Runtime> AppControlBackend<'_, R> {
+    fn session_label(&self, session_id: &str) -> Option<String> {
+        self.app
+            .try_state::<TerminalSessionState>()
+            .and_then(|state| state.labels.lock().ok().and_then(|labels| labels.get(session_id).cloned()))
     }
-    let raw = fs::read_to_string(path)?;
-    // Windows PowerShell can write UTF-8 with BOM. serde_json rejects BOM, so strip it.
-    // Windows PowerShell 縺ｯ UTF-8 BOM 莉倥″縺ｧ菫晏ｭ倥＠縺後■縺ｧ縲《erde_json 縺悟ｼｾ縺上◆繧・勁蜴ｻ縺吶ｋ縲・
-    let raw = raw.trim_start_matches('\u{feff}');
-    if raw.trim().is_empty() {
-        return Ok(Settings::default());
-    }
-    match serde_json::from_str(raw) {
-        Ok(settings) => Ok(settings),
-        Err(_) => {
-            // Recover from corrupted settings instead of failing app startup.
-            // 險ｭ螳壹ヵ繧｡繧､繝ｫ遐ｴ謳阪〒繧りｵｷ蜍輔〒縺阪ｋ繧医≧縺ｫ蠕ｩ譌ｧ縺吶ｋ・磯驕ｿ縺励※譌｢螳壼､縺ｫ謌ｻ縺呻ｼ峨・
-            let ts_ms = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_else(|_| Duration::from_millis(0))
-                .as_millis();
-            if let Some(parent) = path.parent() {
-                let file_name = path
-                    .file_name()
-                    .and_then(|name| name.to_str())
-                    .unwrap_or("settings.json");
-                let backup = parent.join(format!("{file_name}.corrupt.{ts_ms}.json"));
-                // Best-effort backup. Ignore errors; we still want to boot.
-                let _ = fs::write(&backup, raw.as_bytes());
+}
+
+impl<R: Runtime> ControlBackend for AppControlBackend<'_, R> {
+    fn list_sessions(&self) -> Vec<SessionInfo> {
+        let (Some(state), Some(registry), Some(observed)) = (
+            self.app.try_state::<TerminalSessionState>(),
+            self.app.try_state::<SessionRegistry>(),
+            self.app.try_state::<ObservedStates>(),
+        ) else {
+            return Vec::new();
+        };
+        let labels = state.labels.lock().map(|guard| guard.clone()).unwrap_or_default();
+        let mut sessions: Vec<SessionInfo> = labels
+            .iter()
+            .map(|(session_id, label)| {
+                let mut info = registry.session_info(session_id, &observed);
+                if let Some(title) = self
+                    .app
+                    .get_webview_window(label)
+                    .and_then(|window| window.title().ok())
+                {
+                    info.title = title;
+                }
+                info
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        sessions
+    }
+
+    fn open_session(&self, request: control::OpenSessionRequest) -> Result<String, ControlError> {
+        let session_id = match request
+            .session_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+        {
+            Some(id) if self.session_label(&id).is_some() => {
+                return Err(ControlError::new(
+                    409,
+                    "conflict",
+                    format!("session already exists: {id}"),
+                ));
             }
-            Ok(Settings::default())
+            Some(id) => id,
+            None => generate_terminal_session_id(),
+        };
+        let profile = request
+            .profile
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        let options = terminal_launch_options(self.app, profile.as_deref(), request.cwd.as_deref())
+            .map_err(control_error)?;
+        open_terminal_window_with_options(self.app.clone(), session_id.clone(), options)
+            .map_err(control_error)?;
+        Ok(session_id)
+    }
+
+    fn send_input(&self, session_id: &str, text: &str) -> Result<(), ControlError> {
+        let _ = log_worker_event(
+            self.app,
+            &format!("control send: session={session_id} size={}", text.len()),
+        );
+        self.app.state::<SessionRegistry>().send_input(session_id, text).map_err(control_error)
+    }
+
+    fn resize(&self, session_id: &str, cols: u16, rows: u16) -> Result<(), ControlError> {
+        resize_terminal_session_inner(self.app, session_id, cols, rows).map_err(control_error)
+    }
+
+    fn stop(&self, session_id: &str) -> Result<(), ControlError> {
+        let label = self.session_label(session_id);
+        stop_terminal_session_inner(self.app, session_id, "control-api").map_err(control_error)?;
+        // window 側の beforeunload でも stop されるが、二重でも害はない
+        // The window's beforeunload stops again; a second stop is harmless.
+        if let Some(window) = label.and_then(|label| self.app.get_webview_window(&label)) {
+            let _ = window.close();
         }
+        Ok(())
     }
-}
 
-fn write_settings(path: &Path, settings: &Settings) -> Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+    fn tail(&self, session_id: &str) -> Result<String, ControlError> {
+        if self.session_label(session_id).is_none() {
+            return Err(ControlError::not_found(format!("session not found: {session_id}")));
+        }
+        Ok(self
+            .app
+            .try_state::<SessionRegistry>()
+            .and_then(|registry| registry.tail(session_id))
+            .unwrap_or_default())
     }
-    let raw = serde_json::to_string_pretty(settings)?;
-    let mut file = fs::File::create(path)?;
-    file.write_all(raw.as_bytes())?;
-    Ok(())
+
+    fn hook_source_enabled(&self, source: &str) -> bool {
+        let base_dir = hooks_base_dir();
+        self.app
+            .try_state::<CompletionHookState>()
+            .and_then(|state| {
+                let mut manager = state.manager.lock().ok()?;
+                Some(manager.accept(source, &base_dir))
+            })
+            .unwrap_or(false)
+    }
+
+    fn ingest_hook(&self, event: HookEvent) -> Result<(), ControlError> {
+        handle_hook_event(self.app, event);
+        Ok(())
+    }
+}
+
+fn write_health_json(stream: &mut TcpStream, status: &str, body: &serde_json::Value) {
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
 }
 
 #[tauri::command]
@@ -1816,12 +1112,7 @@ fn load_settings<R: Runtime>(
     ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
     let path = settings_path(&app);
     let mut settings = read_settings(&path).map_err(|err| err.to_string())?;
-    settings.character_renderer = normalize_character_renderer(&settings.character_renderer).to_string();
-    settings.character_3d_scale = normalize_character_3d_scale(settings.character_3d_scale);
-    settings.character_3d_yaw_deg = normalize_character_3d_yaw_deg(settings.character_3d_yaw_deg);
-    settings.character_3d_vrm_path = settings.character_3d_vrm_path.trim().to_string();
-    settings.character_motion_default_paths =
-        normalize_character_motion_path_map(&settings.character_motion_default_paths);
+    normalize_settings(&mut settings);
     Ok(settings)
 }
 
@@ -1833,18 +1124,14 @@ fn save_settings<R: Runtime>(
 ) -> Result<(), String> {
     ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
     let mut settings = settings;
-    settings.character_renderer =
-        normalize_character_renderer(&settings.character_renderer).to_string();
-    settings.character_3d_scale = normalize_character_3d_scale(settings.character_3d_scale);
-    settings.character_3d_yaw_deg =
-        normalize_character_3d_yaw_deg(settings.character_3d_yaw_deg);
-    settings.character_3d_vrm_path = settings.character_3d_vrm_path.trim().to_string();
-    settings.character_motion_default_paths =
-        normalize_character_motion_path_map(&settings.character_motion_default_paths);
+    normalize_settings(&mut settings);
+    settings.terminal_layout = layout::normalize_layout_kind(&settings.terminal_layout).to_string();
     let path = settings_path(&app);
-    let hook_tool = settings.llm_tool.clone();
     write_settings(&path, &settings).map_err(|err| err.to_string())?;
-    apply_completion_hook_tool(&app, Some(&hook_tool));
+    apply_completion_hook_sources(&app, &settings);
+    apply_aggregate_settings(&app, &settings);
+    register_terminal_state_shortcuts(&app, &settings);
+    apply_tray_settings(&app, &settings);
     let _ = app.emit("settings-updated", settings.clone());
     schedule_watcher_window_sync(&app, settings.terminal_watcher_enabled);
     Ok(())
@@ -2087,7 +1374,15 @@ fn list_available_terminal_shell_kinds<R: Runtime>(
     }
     #[cfg(not(windows))]
     {
-        Ok(Vec::new())
+        let path_var = std::env::var("PATH").unwrap_or_default();
+        let etc_shells = read_etc_shells();
+        let mut shells = vec![TERMINAL_SHELL_LOGIN.to_string()];
+        for kind in UNIX_TERMINAL_SHELL_KINDS {
+            if resolve_unix_shell_path(kind, &path_var, &etc_shells).is_some() {
+                shells.push(kind.to_string());
+            }
+        }
+        Ok(shells)
     }
 }
 
@@ -2102,35 +1397,33 @@ fn report_terminal_observation<R: Runtime>(
     if session_id.trim().is_empty() {
         return Ok(());
     }
-    let normalized = normalize_observed_state(&state);
-    let aggregate_state = {
-        let aggregate = app.state::<TerminalAggregateState>();
-        let mut guard = aggregate
-            .per_session
-            .lock()
-            .map_err(|_| "aggregate state lock failed".to_string())?;
-        guard.insert(session_id, normalized);
-        aggregate_observed_state(&guard)
-    };
-    let should_emit = {
-        let aggregate = app.state::<TerminalAggregateState>();
-        let mut last_guard = aggregate
-            .last_state
-            .lock()
-            .map_err(|_| "aggregate state lock failed".to_string())?;
-        if *last_guard == aggregate_state {
-            false
-        } else {
-            *last_guard = aggregate_state.clone();
-            true
-        }
-    };
-    if should_emit {
-        emit_terminal_aggregate_state(&app, &aggregate_state);
-    }
+    // 知らない状態名や許されない遷移は拒否する（記録は状態機械側）
+    // Unknown state names and invalid transitions are rejected; the state machine logs them.
+    let observed = TerminalState::parse(&state).ok_or_else(|| format!("unknown terminal state: {state}"))?;
+    app.state::<ObservedStates>()
+        .observe(&session_id, observed, &TauriSink::new(&app))
+        .map_err(|err| err.to_string())?;
     Ok(())
 }
 
+// auto-pickup が有効なら need-input に入った terminal を前面に出す
+// When auto-pickup is on, bring forward a terminal that just entered need-input.
+fn auto_pickup_need_input_terminal<R: Runtime>(app: &AppHandle<R>, session_id: &str) {
+    let settings = read_settings(&settings_path(app)).unwrap_or_else(|_| Settings::default());
+    if !settings.terminal_auto_pickup_need_input {
+        return;
+    }
+    let label = app
+        .try_state::<TerminalSessionState>()
+        .and_then(|state| state.labels.lock().ok().and_then(|guard| guard.get(session_id).cloned()))
+        .unwrap_or_else(|| terminal_window_label(session_id));
+    let Some(window) = app.get_webview_window(&label) else {
+        return;
+    };
+    let _ = window.unminimize();
+    let _ = pickup_terminal_window_handle(app, &window);
+}
+
 #[tauri::command]
 fn ensure_codex_hook<R: Runtime>(
     app: AppHandle<R>,
@@ -2138,8 +1431,9 @@ fn ensure_codex_hook<R: Runtime>(
 ) -> Result<CodexHookSetupResult, String> {
     ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
     let base_dir = hooks_base_dir();
-    let (script_path, hook_path, legacy_py) = ensure_codex_hook_files(&base_dir)?;
-    let (status, message) = ensure_codex_config(&script_path, &legacy_py)?;
+    let hook_path = ensure_codex_hook_files(&base_dir)?;
+    let cli_path = resolve_cli_path();
+    let (status, message) = ensure_codex_config(&cli_path)?;
     Ok(CodexHookSetupResult {
         status,
         message,
@@ -2147,875 +1441,247 @@ fn ensure_codex_hook<R: Runtime>(
             .unwrap_or_else(|| PathBuf::from("."))
             .to_string_lossy()
             .to_string(),
-        script_path: script_path.to_string_lossy().to_string(),
+        cli_path: cli_path.to_string_lossy().to_string(),
         hook_path: hook_path.to_string_lossy().to_string(),
     })
 }
 
+// dry_run なら差分だけ返す / With dry_run only the diff is returned.
+fn apply_agent_hook_edit(
+    edit: ConfigEdit,
+    action: HookInstallAction,
+    dry_run: bool,
+) -> Result<AgentHookSetupResult, String> {
+    let backup_path = if dry_run { None } else { edit.apply()? };
+    Ok(AgentHookSetupResult {
+        status: hook_install_status(&edit, action).to_string(),
+        config_path: edit.path.to_string_lossy().to_string(),
+        backup_path: backup_path.map(|path| path.to_string_lossy().to_string()),
+        diff: edit.diff(),
+    })
+}
+
+fn hook_install_action(uninstall: Option<bool>) -> HookInstallAction {
+    if uninstall.unwrap_or(false) {
+        HookInstallAction::Uninstall
+    } else {
+        HookInstallAction::Install
+    }
+}
+
 #[tauri::command]
-fn subworker_codex_session_started<R: Runtime>(
+fn ensure_claude_hook<R: Runtime>(
     app: AppHandle<R>,
     ipc_session_id: String,
-    resume: bool,
-) -> Result<(), String> {
+    project: Option<String>,
+    uninstall: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<AgentHookSetupResult, String> {
     ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
-    if resume {
-        let _ = log_worker_event(&app, "subworker codex session sync: resume (kept)");
-        return Ok(());
-    }
-    if let Ok(mut guard) = app
-        .state::<SubworkerCodexSessionState>()
-        .by_ipc_session
-        .lock()
-    {
-        guard.remove(&ipc_session_id);
-    }
-    let _ = log_worker_event(&app, "subworker codex session sync: fresh (cleared)");
-    Ok(())
+    let action = hook_install_action(uninstall);
+    let project = project.map(PathBuf::from);
+    let edit = plan_claude_hooks(project.as_deref(), &resolve_cli_path(), action)?;
+    apply_agent_hook_edit(edit, action, dry_run.unwrap_or(false))
 }
 
 #[tauri::command]
-async fn subworker_llm_decide<R: Runtime>(
+fn ensure_opencode_hook<R: Runtime>(
     app: AppHandle<R>,
     ipc_session_id: String,
-    tool: String,
-    prompt: String,
-) -> Result<SubworkerLlmDecision, String> {
+    project: Option<String>,
+    uninstall: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<AgentHookSetupResult, String> {
     ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
-    let normalized_tool = tool.trim().to_ascii_lowercase();
-    let prior_codex_session = if normalized_tool == "codex" {
-        app.state::<SubworkerCodexSessionState>()
-            .by_ipc_session
-            .lock()
-            .ok()
-            .and_then(|guard| guard.get(&ipc_session_id).cloned())
-    } else {
-        None
-    };
-
-    let normalized_tool_for_worker = normalized_tool.clone();
-    let prior_codex_session_for_worker = prior_codex_session.clone();
-    let run_result = tauri::async_runtime::spawn_blocking(
-        move || -> Result<(SubworkerToolRunOutput, bool), String> {
-            let run_once = |resume_session_id: Option<String>| {
-                run_tool_subworker_decide(&tool, &prompt, resume_session_id.as_deref())
-            };
-
-            let first_result = run_once(prior_codex_session_for_worker.clone());
-            match first_result {
-                Ok(output) => Ok((output, false)),
-                Err(err) => {
-                    // If resume fails, retry fresh once and mark stale session for cleanup.
-                    // resume 失敗時は fresh を 1 回だけ再試行し、古いセッションを後で破棄する。
-                    if normalized_tool_for_worker == "codex"
-                        && prior_codex_session_for_worker.is_some()
-                    {
-                        let retry_output = run_once(None).map_err(|retry_err| {
-                            format!(
-                                "codex resume failed and fresh retry failed: resume={err}; fresh={retry_err}"
-                            )
-                        })?;
-                        Ok((retry_output, true))
-                    } else {
-                        Err(err.to_string())
-                    }
-                }
-            }
-        },
-    )
-    .await
-    .map_err(|err| format!("subworker worker task failed: {err}"))?;
-
-    let (outcome, clear_stale_session) = match run_result {
-        Ok(value) => value,
-        Err(err) => {
-            if normalized_tool == "codex" && prior_codex_session.is_some() {
-                if let Ok(mut guard) = app
-                    .state::<SubworkerCodexSessionState>()
-                    .by_ipc_session
-                    .lock()
-                {
-                    guard.remove(&ipc_session_id);
-                }
-            }
-            return Err(err);
-        }
-    };
-
-    if normalized_tool == "codex" {
-        if clear_stale_session {
-            if let Ok(mut guard) = app
-                .state::<SubworkerCodexSessionState>()
-                .by_ipc_session
-                .lock()
-            {
-                guard.remove(&ipc_session_id);
-            }
-        }
-        if let Some(next_session_id) = outcome.codex_thread_id.as_deref() {
-            if !next_session_id.trim().is_empty() {
-                if let Ok(mut guard) = app
-                    .state::<SubworkerCodexSessionState>()
-                    .by_ipc_session
-                    .lock()
-                {
-                    guard.insert(ipc_session_id, next_session_id.to_string());
-                }
-            }
-        }
-    }
-
-    Ok(outcome.decision)
-}
-
-fn apply_completion_hook_tool<R: Runtime>(app: &AppHandle<R>, tool: Option<&str>) {
-    let base_dir = hooks_base_dir();
-    let _ = fs::create_dir_all(&base_dir);
-    let state = app.state::<CompletionHookState>();
-    let mut guard = match state.manager.lock() {
-        Ok(guard) => guard,
-        Err(_) => return,
-    };
-    guard.set_tool(tool, &base_dir);
-}
-
-fn handle_hook_event<R: Runtime>(app: &AppHandle<R>, event: HookEvent) {
-    let state = normalize_hook_state(event.kind);
-    let summary = summarize_hook_event(&event);
-    let _ = append_project_prompt_history_event(app, &event, &state, &summary);
-    emit_hook_state(app, &event, &state, Some(&summary));
-}
-
-fn emit_hook_state<R: Runtime>(
-    app: &AppHandle<R>,
-    event: &HookEvent,
-    state: &str,
-    summary: Option<&str>,
-) {
-    let payload = HookStatePayload {
-        source: event.source.clone(),
-        kind: hook_kind_to_string(event.kind),
-        source_session_id: event.source_session_id.clone(),
-        state: state.to_string(),
-        summary: summary.map(|value| value.to_string()),
-    };
-    let _ = app.emit("completion-hook-state", payload);
-}
-
-struct SubworkerToolRunOutput {
-    decision: SubworkerLlmDecision,
-    codex_thread_id: Option<String>,
-}
-
-fn run_tool_subworker_decide(
-    tool: &str,
-    prompt: &str,
-    codex_session_id: Option<&str>,
-) -> Result<SubworkerToolRunOutput> {
-    let tool_key = tool.trim().to_ascii_lowercase();
-    let tool_path = resolve_tool_command(&tool_key);
-    let env_args = std::env::var("NAGOMI_SUBWORKER_TOOL_ARGS")
-        .ok()
-        .unwrap_or_default();
-    let timeout_ms = std::env::var("NAGOMI_SUBWORKER_TOOL_TIMEOUT_MS")
-        .ok()
-        .and_then(|value| value.parse::<u64>().ok())
-        .unwrap_or(45000);
-    let timeout = Duration::from_millis(timeout_ms);
-
-    if env_args.trim().is_empty() && tool_key == "codex" {
-        return run_codex_subworker_decide_with_session(
-            &tool_path,
-            prompt,
-            timeout,
-            codex_session_id,
-        );
-    }
-
-    if env_args.trim().is_empty() {
-        return Err(anyhow::anyhow!(
-            "subworker tool args missing (set NAGOMI_SUBWORKER_TOOL_ARGS)"
-        ));
-    }
-
-    let args = split_tool_args(&env_args);
-    let output = run_tool_command_stdout(&tool_path, &args, prompt, timeout)?;
-    let decision = parse_subworker_llm_output_relaxed(&output)
-        .ok_or_else(|| anyhow::anyhow!("invalid subworker llm output"))?;
-    Ok(SubworkerToolRunOutput {
-        decision,
-        codex_thread_id: None,
-    })
-}
-
-fn resolve_tool_command(tool_key: &str) -> String {
-    let direct = std::env::var("NAGOMI_TOOL_PATH")
-        .ok()
-        .filter(|value| !value.trim().is_empty());
-    if let Some(value) = direct {
-        return value;
-    }
-    let cli = std::env::var("NAGOMI_TOOL_CLI")
-        .ok()
-        .filter(|value| !value.trim().is_empty());
-    cli.unwrap_or_else(|| tool_key.to_string())
-}
-
-fn split_tool_args(raw: &str) -> Vec<String> {
-    raw.split_whitespace()
-        .filter(|part| !part.is_empty())
-        .map(|part| part.to_string())
-        .collect()
-}
-
-fn build_tool_command(tool_path: &str, args: &[String]) -> Command {
-    #[cfg(windows)]
-    {
-        use std::path::Path;
-        let ext = Path::new(tool_path)
-            .extension()
-            .and_then(|value| value.to_str())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-        if ext == "exe" || ext == "com" {
-            let mut command = Command::new(tool_path);
-            command.args(args);
-            return command;
-        }
-        // codex / npm global installs are often .cmd on Windows. Use cmd.exe to resolve PATHEXT.
-        // codex / npm 縺ｮ繧ｰ繝ｭ繝ｼ繝舌Ν繧､繝ｳ繧ｹ繝医・繝ｫ縺ｯ Windows 縺ｧ縺ｯ .cmd 縺ｫ縺ｪ繧翫′縺｡縺ｪ縺ｮ縺ｧ縲…md.exe 邨檎罰縺ｧ隗｣豎ｺ縺吶ｋ縲・
-        let mut command = Command::new("cmd.exe");
-        command.arg("/c").arg(tool_path).args(args);
-        return command;
-    }
-
-    #[cfg(not(windows))]
-    {
-        let mut command = Command::new(tool_path);
-        command.args(args);
-        command
-    }
-}
-
-fn internal_hooks_dir() -> PathBuf {
-    std::env::temp_dir().join("nagomi-internal-hooks")
-}
-
-fn apply_internal_tool_env(command: &mut Command) {
-    // Prevent internal tool invocations (judge/subworker) from emitting completion-hook events
-    // that loop back into the currently observed Terminal session.
-    // 蜀・Κ繝・・繝ｫ蜻ｼ縺ｳ蜃ｺ縺暦ｼ・udge/subworker・峨′ completion-hook 繧堤匱轣ｫ縺輔○縲・    // 螳溯｡御ｸｭ繧ｻ繝・す繝ｧ繝ｳ縺ｸ騾・ｵ√☆繧九Ν繝ｼ繝励ｒ驕ｿ縺代ｋ縲・
-    command.env("NAGOMI_HOOKS_DIR", internal_hooks_dir());
-}
-
-fn run_tool_command_stdout(
-    tool_path: &str,
-    args: &[String],
-    prompt: &str,
-    timeout: Duration,
-) -> Result<String> {
-    let mut command = build_tool_command(tool_path, args);
-    command
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    let mut child = command.spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        let _ = stdin.write_all(prompt.as_bytes());
-    }
-    let status = wait_with_timeout(&mut child, timeout)?;
-    let mut stdout = String::new();
-    if let Some(mut reader) = child.stdout.take() {
-        let _ = reader.read_to_string(&mut stdout);
-    }
-    let mut stderr = String::new();
-    if let Some(mut reader) = child.stderr.take() {
-        let _ = reader.read_to_string(&mut stderr);
-    }
-    if !status.success() {
-        return Err(anyhow::anyhow!("tool failed: {stderr}"));
-    }
-    Ok(stdout)
-}
-
-fn run_codex_subworker_decide_with_session(
-    tool_path: &str,
-    prompt: &str,
-    timeout: Duration,
-    codex_session_id: Option<&str>,
-) -> Result<SubworkerToolRunOutput> {
-    if let Some(existing_session_id) = codex_session_id.map(str::trim).filter(|v| !v.is_empty()) {
-        return run_codex_subworker_decide_resume(tool_path, existing_session_id, prompt, timeout);
-    }
-    run_codex_subworker_decide_fresh(tool_path, prompt, timeout)
-}
-
-fn run_codex_subworker_decide_fresh(
-    tool_path: &str,
-    prompt: &str,
-    timeout: Duration,
-) -> Result<SubworkerToolRunOutput> {
-    let schema = r#"{"type":"object","properties":{"action":{"type":"string","enum":["delegate_input","show_advice","noop"]},"confidence":{"type":"number","minimum":0,"maximum":0.99},"input":{"type":"string"},"advice_markdown":{"type":"string"},"reason":{"type":"string"}},"required":["action","confidence","input","advice_markdown","reason"],"additionalProperties":false}"#;
-    let schema_path = create_temp_file("nagomi-subworker-schema", "json", schema)?;
-    let output_path = create_temp_path("nagomi-subworker-output", "json");
-    let mut args = Vec::new();
-    args.push("exec".to_string());
-    args.push("--json".to_string());
-    args.push("--output-schema".to_string());
-    args.push(schema_path.to_string_lossy().to_string());
-    args.push("--output-last-message".to_string());
-    args.push(output_path.to_string_lossy().to_string());
-    args.push("--color".to_string());
-    args.push("never".to_string());
-    args.push("--sandbox".to_string());
-    args.push("read-only".to_string());
-    args.push("--skip-git-repo-check".to_string());
-
-    let mut command = build_tool_command(tool_path, &args);
-    apply_internal_tool_env(&mut command);
-    command
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    let mut child = command.spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        let _ = stdin.write_all(prompt.as_bytes());
-    }
-    let status = wait_with_timeout(&mut child, timeout)?;
-    let mut stderr = String::new();
-    if let Some(mut reader) = child.stderr.take() {
-        let _ = reader.read_to_string(&mut stderr);
-    }
-    let mut stdout = String::new();
-    if let Some(mut reader) = child.stdout.take() {
-        let _ = reader.read_to_string(&mut stdout);
-    }
-    let raw = fs::read_to_string(&output_path).unwrap_or_default();
-    let _ = fs::remove_file(&schema_path);
-    let _ = fs::remove_file(&output_path);
-    if !status.success() {
-        return Err(anyhow::anyhow!("tool failed: {stderr}"));
-    }
-    let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(&stdout);
-    let primary_payload = if raw.trim().is_empty() {
-        last_agent_message.unwrap_or_default()
-    } else {
-        raw
-    };
-    if let Some(decision) = parse_subworker_llm_output_relaxed(&primary_payload) {
-        return Ok(SubworkerToolRunOutput {
-            decision,
-            codex_thread_id: thread_id,
-        });
-    }
-    Err(anyhow::anyhow!(
-        "subworker tool output parse failed (fresh): stdout={}",
-        truncate_error_text(&stdout, 300)
-    ))
-}
-
-fn run_codex_subworker_decide_resume(
-    tool_path: &str,
-    session_id: &str,
-    prompt: &str,
-    timeout: Duration,
-) -> Result<SubworkerToolRunOutput> {
-    let mut args = Vec::new();
-    args.push("exec".to_string());
-    args.push("resume".to_string());
-    args.push("--json".to_string());
-    args.push("--skip-git-repo-check".to_string());
-    args.push(session_id.to_string());
-    args.push("-".to_string());
-
-    let mut command = build_tool_command(tool_path, &args);
-    apply_internal_tool_env(&mut command);
-    command
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    let mut child = command.spawn()?;
-    if let Some(mut stdin) = child.stdin.take() {
-        let _ = stdin.write_all(prompt.as_bytes());
-    }
-    let status = wait_with_timeout(&mut child, timeout)?;
-    let mut stderr = String::new();
-    if let Some(mut reader) = child.stderr.take() {
-        let _ = reader.read_to_string(&mut stderr);
-    }
-    let mut stdout = String::new();
-    if let Some(mut reader) = child.stdout.take() {
-        let _ = reader.read_to_string(&mut stdout);
-    }
-    if !status.success() {
-        return Err(anyhow::anyhow!(
-            "codex resume failed: {stderr}; stdout={}",
-            truncate_error_text(&stdout, 260)
-        ));
-    }
-    let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(&stdout);
-    let raw = last_agent_message.ok_or_else(|| {
-        anyhow::anyhow!(
-            "codex resume output missing agent message: stdout={}",
-            truncate_error_text(&stdout, 320)
-        )
-    })?;
-    let decision = parse_subworker_llm_output_relaxed(&raw).ok_or_else(|| {
-        anyhow::anyhow!(
-            "subworker tool output parse failed (resume): message={}",
-            truncate_error_text(&raw, 260)
-        )
-    })?;
-    Ok(SubworkerToolRunOutput {
-        decision,
-        codex_thread_id: thread_id.or_else(|| Some(session_id.to_string())),
-    })
-}
-
-fn parse_codex_exec_jsonl_stdout(stdout: &str) -> (Option<String>, Option<String>) {
-    let mut thread_id: Option<String> = None;
-    let mut last_agent_message: Option<String> = None;
-    for raw_line in stdout.lines() {
-        let line = raw_line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        if !line.starts_with('{') {
-            continue;
-        }
-        let parsed: serde_json::Value = match serde_json::from_str(line) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let event_type = parsed
-            .get("type")
-            .and_then(|v| v.as_str())
-            .unwrap_or_default();
-        if event_type == "thread.started" {
-            if let Some(value) = parsed.get("thread_id").and_then(|v| v.as_str()) {
-                let normalized = value.trim();
-                if !normalized.is_empty() {
-                    thread_id = Some(normalized.to_string());
-                }
-            }
-            continue;
-        }
-        if event_type == "item.completed" {
-            let item = match parsed.get("item").and_then(|v| v.as_object()) {
-                Some(item) => item,
-                None => continue,
-            };
-            let item_type = item
-                .get("type")
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .trim()
-                .to_ascii_lowercase();
-            if item_type == "agent_message" {
-                if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
-                    let normalized = text.trim();
-                    if !normalized.is_empty() {
-                        last_agent_message = Some(normalized.to_string());
-                    }
-                }
-            }
-        }
-    }
-    (thread_id, last_agent_message)
-}
-
-fn parse_subworker_llm_output(raw: &str) -> Option<SubworkerLlmDecision> {
-    let trimmed = raw.trim();
-    if trimmed.is_empty() {
-        return None;
-    }
-    let candidate = trimmed
-        .lines()
-        .rev()
-        .find(|line| !line.trim().is_empty())
-        .unwrap_or(trimmed);
-    let parsed: serde_json::Value = serde_json::from_str(candidate).ok()?;
-    Some(SubworkerLlmDecision {
-        action: parsed.get("action")?.as_str()?.to_string(),
-        confidence: parsed.get("confidence")?.as_f64()? as f32,
-        input: parsed.get("input")?.as_str()?.to_string(),
-        advice_markdown: parsed.get("advice_markdown")?.as_str()?.to_string(),
-        reason: parsed.get("reason")?.as_str()?.to_string(),
-    })
-}
-
-fn parse_subworker_llm_output_relaxed(raw: &str) -> Option<SubworkerLlmDecision> {
-    if let Some(parsed) = parse_subworker_llm_output(raw) {
-        return Some(parsed);
-    }
-    let trimmed = raw.trim();
-    let start = trimmed.find('{')?;
-    let end = trimmed.rfind('}')?;
-    if end < start {
-        return None;
-    }
-    parse_subworker_llm_output(&trimmed[start..=end])
-}
-
-fn truncate_error_text(text: &str, max_len: usize) -> String {
-    let normalized = text.trim().replace('\r', " ").replace('\n', " ");
-    if normalized.len() <= max_len {
-        return normalized;
-    }
-    let keep = max_len.saturating_sub(1);
-    let mut out = normalized.chars().take(keep).collect::<String>();
-    out.push('.');
-    out
-}
-
-fn wait_with_timeout(
-    child: &mut std::process::Child,
-    timeout: Duration,
-) -> Result<std::process::ExitStatus> {
-    let start = Instant::now();
-    loop {
-        if let Some(status) = child.try_wait()? {
-            return Ok(status);
-        }
-        if start.elapsed() >= timeout {
-            let _ = child.kill();
-            return Err(anyhow::anyhow!("tool timeout"));
-        }
-        thread::sleep(Duration::from_millis(50));
-    }
-}
-
-fn create_temp_path(prefix: &str, ext: &str) -> PathBuf {
-    static TOOL_SEQ: AtomicU64 = AtomicU64::new(0);
-    let nonce = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_millis(0))
-        .as_millis();
-    let seq = TOOL_SEQ.fetch_add(1, Ordering::Relaxed);
-    let filename = format!("{prefix}-{nonce}-{seq}.{ext}");
-    std::env::temp_dir().join(filename)
-}
-
-fn create_temp_file(prefix: &str, ext: &str, contents: &str) -> Result<PathBuf> {
-    let path = create_temp_path(prefix, ext);
-    fs::write(&path, contents)?;
-    Ok(path)
-}
-
-fn normalize_hook_state(kind: HookEventKind) -> String {
-    match kind {
-        HookEventKind::Completed => "success".to_string(),
-        HookEventKind::NeedInput => "need_input".to_string(),
-        HookEventKind::Error => "failure".to_string(),
-    }
-}
-
-fn summarize_hook_event(event: &HookEvent) -> String {
-    let raw = match event.raw.as_ref() {
-        Some(raw) => raw,
-        None => return hook_kind_to_string(event.kind),
-    };
-    let text = extract_text_from_value(raw)
-        .or_else(|| raw.get("event").and_then(extract_text_from_value))
-        .unwrap_or_default();
-    let summary = text
-        .lines()
-        .map(str::trim)
-        .filter(|line| !line.is_empty())
-        .take(2)
-        .collect::<Vec<_>>()
-        .join("\n");
-    if summary.is_empty() {
-        hook_kind_to_string(event.kind)
-    } else {
-        summary
-    }
-}
-
-fn extract_text_from_value(value: &serde_json::Value) -> Option<String> {
-    if let Some(text) = value.as_str() {
-        return Some(text.to_string());
-    }
-    let obj = value.as_object()?;
-    let keys = [
-        "last-assistant-message",
-        "last_assistant_message",
-        "message",
-        "summary",
-        "notification",
-    ];
-    for key in keys.iter() {
-        if let Some(text) = obj.get(*key).and_then(|val| val.as_str()) {
-            return Some(text.to_string());
-        }
-    }
-    None
-}
-
-struct ProjectPromptHistoryRecord {
-    project_key: String,
-    payload: serde_json::Value,
+    let action = hook_install_action(uninstall);
+    let project = project.map(PathBuf::from);
+    let edit = plan_opencode_plugin(project.as_deref(), &resolve_cli_path(), action)?;
+    apply_agent_hook_edit(edit, action, dry_run.unwrap_or(false))
 }
 
-fn append_project_prompt_history_event<R: Runtime>(
-    app: &AppHandle<R>,
-    event: &HookEvent,
-    state: &str,
-    summary: &str,
-) -> Result<Option<String>, String> {
-    let Some(record) = build_project_prompt_history_record(event, state, summary) else {
-        return Ok(None);
-    };
-    let path = project_prompt_history_path(app, &record.project_key);
-    append_jsonl_entry(&path, record.payload)?;
-    Ok(Some(path.to_string_lossy().to_string()))
-}
-
-fn build_project_prompt_history_record(
-    event: &HookEvent,
-    state: &str,
-    summary: &str,
-) -> Option<ProjectPromptHistoryRecord> {
-    let values = collect_hook_value_refs(event);
-    if values.is_empty() {
-        return None;
-    }
-
-    let cwd = read_hook_string_from_values(&values, &["cwd", "directory"]).unwrap_or_default();
-    let input_messages = read_hook_string_list_from_values(
-        &values,
-        &[
-            "input-messages",
-            "input_messages",
-            "inputMessages",
-            "user_messages",
-            "userMessages",
-            "prompts",
-        ],
-    );
-    let last_assistant_message = values
-        .iter()
-        .find_map(|value| extract_text_from_value(value))
-        .unwrap_or_default();
-
-    if cwd.trim().is_empty()
-        && input_messages.is_empty()
-        && last_assistant_message.trim().is_empty()
-    {
-        return None;
-    }
-
-    let project_locator = if cwd.trim().is_empty() {
-        event.source_session_id.clone().unwrap_or_else(|| {
-            format!(
-                "{}-{}",
-                event.source,
-                hook_kind_to_string(event.kind)
-            )
-        })
-    } else {
-        normalize_project_locator(&cwd)
-    };
-    let project_label = if cwd.trim().is_empty() {
-        "unknown".to_string()
-    } else {
-        project_label_from_locator(&cwd)
-    };
-    let project_key = project_prompt_history_key(&project_locator, &project_label);
-
-    let mut payload = serde_json::Map::new();
-    payload.insert(
-        "event_type".to_string(),
-        serde_json::Value::String("project_prompt_history".to_string()),
-    );
-    payload.insert(
-        "source".to_string(),
-        serde_json::Value::String(event.source.clone()),
-    );
-    payload.insert(
-        "hook_kind".to_string(),
-        serde_json::Value::String(hook_kind_to_string(event.kind)),
-    );
-    payload.insert(
-        "state".to_string(),
-        serde_json::Value::String(state.to_string()),
-    );
-    payload.insert(
-        "project_key".to_string(),
-        serde_json::Value::String(project_key.clone()),
-    );
-    payload.insert(
-        "project_label".to_string(),
-        serde_json::Value::String(project_label),
-    );
-    payload.insert(
-        "project_locator".to_string(),
-        serde_json::Value::String(project_locator),
-    );
-    if !cwd.trim().is_empty() {
-        payload.insert("cwd".to_string(), serde_json::Value::String(cwd));
-    }
-    if let Some(source_session_id) = event.source_session_id.as_ref() {
-        if !source_session_id.trim().is_empty() {
-            payload.insert(
-                "source_session_id".to_string(),
-                serde_json::Value::String(source_session_id.clone()),
-            );
-        }
-    }
-    if let Some(thread_id) = read_hook_string_from_values(&values, &["thread-id", "thread_id", "threadId"]) {
-        payload.insert(
-            "thread_id".to_string(),
-            serde_json::Value::String(thread_id),
-        );
-    }
-    if let Some(turn_id) = read_hook_string_from_values(&values, &["turn-id", "turn_id", "turnId"]) {
-        payload.insert("turn_id".to_string(), serde_json::Value::String(turn_id));
-    }
-    if !summary.trim().is_empty() {
-        payload.insert(
-            "summary".to_string(),
-            serde_json::Value::String(summary.to_string()),
-        );
-    }
-    if !input_messages.is_empty() {
-        payload.insert(
-            "input_messages".to_string(),
-            serde_json::Value::Array(
-                input_messages
-                    .into_iter()
-                    .map(serde_json::Value::String)
-                    .collect(),
-            ),
-        );
+#[tauri::command]
+fn subworker_codex_session_started<R: Runtime>(
+    app: AppHandle<R>,
+    ipc_session_id: String,
+    resume: bool,
+) -> Result<(), String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    if resume {
+        let _ = log_worker_event(&app, "subworker codex session sync: resume (kept)");
+        return Ok(());
     }
-    if !last_assistant_message.trim().is_empty() {
-        payload.insert(
-            "last_assistant_message".to_string(),
-            serde_json::Value::String(last_assistant_message),
-        );
+    if let Ok(mut guard) = app
+        .state::<SubworkerCodexSessionState>()
+        .by_ipc_session
+        .lock()
+    {
+        guard.remove(&ipc_session_id);
     }
-
-    Some(ProjectPromptHistoryRecord {
-        project_key,
-        payload: serde_json::Value::Object(payload),
-    })
+    let _ = log_worker_event(&app, "subworker codex session sync: fresh (cleared)");
+    Ok(())
 }
 
-fn collect_hook_value_refs<'a>(event: &'a HookEvent) -> Vec<&'a serde_json::Value> {
-    let Some(raw) = event.raw.as_ref() else {
-        return Vec::new();
+#[tauri::command]
+async fn subworker_llm_decide<R: Runtime>(
+    app: AppHandle<R>,
+    ipc_session_id: String,
+    tool: String,
+    prompt: String,
+) -> Result<SubworkerLlmDecision, String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    let normalized_tool = tool.trim().to_ascii_lowercase();
+    let prior_codex_session = if normalized_tool == "codex" {
+        app.state::<SubworkerCodexSessionState>()
+            .by_ipc_session
+            .lock()
+            .ok()
+            .and_then(|guard| guard.get(&ipc_session_id).cloned())
+    } else {
+        None
     };
-    let mut values = vec![raw];
-    if let Some(inner) = raw.get("event") {
-        values.push(inner);
-    }
-    values
-}
 
-fn read_hook_string_from_values(
-    values: &[&serde_json::Value],
-    keys: &[&str],
-) -> Option<String> {
-    for value in values {
-        let Some(obj) = value.as_object() else {
-            continue;
-        };
-        for key in keys {
-            if let Some(found) = obj.get(*key).and_then(normalize_hook_text_value) {
-                return Some(found);
+    let normalized_tool_for_worker = normalized_tool.clone();
+    let prior_codex_session_for_worker = prior_codex_session.clone();
+    let run_result = tauri::async_runtime::spawn_blocking(
+        move || -> Result<(SubworkerToolRunOutput, bool), String> {
+            let run_once = |resume_session_id: Option<String>| {
+                run_tool_subworker_decide(&tool, &prompt, resume_session_id.as_deref())
+            };
+
+            let first_result = run_once(prior_codex_session_for_worker.clone());
+            match first_result {
+                Ok(output) => Ok((output, false)),
+                Err(err) => {
+                    // If resume fails, retry fresh once and mark stale session for cleanup.
+                    // resume 失敗時は fresh を 1 回だけ再試行し、古いセッションを後で破棄する。
+                    if normalized_tool_for_worker == "codex"
+                        && prior_codex_session_for_worker.is_some()
+                    {
+                        let retry_output = run_once(None).map_err(|retry_err| {
+                            format!(
+                                "codex resume failed and fresh retry failed: resume={err}; fresh={retry_err}"
+                            )
+                        })?;
+                        Ok((retry_output, true))
+                    } else {
+                        Err(err.to_string())
+                    }
+                }
             }
-        }
-    }
-    None
-}
+        },
+    )
+    .await
+    .map_err(|err| format!("subworker worker task failed: {err}"))?;
 
-fn read_hook_string_list_from_values(
-    values: &[&serde_json::Value],
-    keys: &[&str],
-) -> Vec<String> {
-    for value in values {
-        let Some(obj) = value.as_object() else {
-            continue;
-        };
-        for key in keys {
-            let Some(found) = obj.get(*key) else {
-                continue;
-            };
-            let items = normalize_hook_text_list(found);
-            if !items.is_empty() {
-                return items;
+    let (outcome, clear_stale_session) = match run_result {
+        Ok(value) => value,
+        Err(err) => {
+            if normalized_tool == "codex" && prior_codex_session.is_some() {
+                if let Ok(mut guard) = app
+                    .state::<SubworkerCodexSessionState>()
+                    .by_ipc_session
+                    .lock()
+                {
+                    guard.remove(&ipc_session_id);
+                }
             }
+            return Err(err);
         }
-    }
-    Vec::new()
-}
-
-fn normalize_hook_text_list(value: &serde_json::Value) -> Vec<String> {
-    if let Some(text) = normalize_hook_text_value(value) {
-        return vec![text];
-    }
-    let Some(items) = value.as_array() else {
-        return Vec::new();
     };
-    items
-        .iter()
-        .filter_map(normalize_hook_text_value)
-        .collect()
-}
 
-fn normalize_hook_text_value(value: &serde_json::Value) -> Option<String> {
-    if let Some(text) = value.as_str() {
-        let trimmed = text.trim();
-        if !trimmed.is_empty() {
-            return Some(trimmed.to_string());
+    if normalized_tool == "codex" {
+        if clear_stale_session {
+            if let Ok(mut guard) = app
+                .state::<SubworkerCodexSessionState>()
+                .by_ipc_session
+                .lock()
+            {
+                guard.remove(&ipc_session_id);
+            }
         }
-    }
-    let obj = value.as_object()?;
-    for key in ["text", "content", "message", "prompt"] {
-        if let Some(text) = obj.get(key).and_then(|raw| raw.as_str()) {
-            let trimmed = text.trim();
-            if !trimmed.is_empty() {
-                return Some(trimmed.to_string());
+        if let Some(next_session_id) = outcome.codex_thread_id.as_deref() {
+            if !next_session_id.trim().is_empty() {
+                if let Ok(mut guard) = app
+                    .state::<SubworkerCodexSessionState>()
+                    .by_ipc_session
+                    .lock()
+                {
+                    guard.insert(ipc_session_id, next_session_id.to_string());
+                }
             }
         }
     }
-    None
-}
-
-fn normalize_project_locator(raw: &str) -> String {
-    raw.trim()
-        .replace('\\', "/")
-        .trim_end_matches('/')
-        .to_string()
-}
 
-fn project_label_from_locator(raw: &str) -> String {
-    let normalized = normalize_project_locator(raw);
-    let leaf = normalized.rsplit('/').next().unwrap_or(normalized.as_str());
-    sanitize_project_prompt_component(leaf)
+    Ok(outcome.decision)
 }
 
-fn sanitize_project_prompt_component(raw: &str) -> String {
-    sanitize_asset_component(raw).to_ascii_lowercase()
+// 有効な source をすべて同時に tail する / Tail every enabled source side by side.
+fn apply_completion_hook_sources<R: Runtime>(app: &AppHandle<R>, settings: &Settings) {
+    if let Ok(mut gate) = app.state::<SessionRegistry>().hook_gate.lock() {
+        gate.set_window_ms(settings.hook_dedup_window_ms);
+    }
+    let base_dir = hooks_base_dir();
+    let _ = fs::create_dir_all(&base_dir);
+    let state = app.state::<CompletionHookState>();
+    let mut guard = match state.manager.lock() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    guard.set_sources(&enabled_hook_sources(settings), &base_dir);
+}
+
+// 集約の優先順とグループ分けを反映し、watcher を追うグループの状態に合わせ直す
+// Applies the aggregate priority and grouping, then re-syncs the watcher with the group it follows.
+fn apply_aggregate_settings<R: Runtime>(app: &AppHandle<R>, settings: &Settings) {
+    let (Some(observed), Some(registry), Some(sessions), Some(watcher)) = (
+        app.try_state::<ObservedStates>(),
+        app.try_state::<SessionRegistry>(),
+        app.try_state::<TerminalSessionState>(),
+        app.try_state::<WatcherAggregateGroup>(),
+    ) else {
+        return;
+    };
+    if let Ok(mut name) = watcher.name.lock() {
+        *name = settings.watcher_aggregate_group.trim().to_string();
+    }
+    let sink = TauriSink::new(app);
+    observed.set_policy(aggregate_policy(settings), &sink);
+    let session_ids: Vec<String> = sessions
+        .labels
+        .lock()
+        .map(|labels| labels.keys().cloned().collect())
+        .unwrap_or_default();
+    for session_id in session_ids {
+        let group = registry
+            .launch_options(&session_id)
+            .and_then(|options| aggregate_group(&settings.aggregate_group_by, &options));
+        observed.set_group(&session_id, group, &sink);
+    }
+    emit_terminal_aggregate_state(app, &watcher_aggregate_state(app));
+    refresh_tray_status(app);
 }
 
-fn project_prompt_history_key(locator: &str, label: &str) -> String {
-    format!("{label}-{}", stable_text_hash(&normalize_project_locator(locator)))
+fn watcher_aggregate_group<R: Runtime>(app: &AppHandle<R>) -> String {
+    app.try_state::<WatcherAggregateGroup>()
+        .and_then(|watcher| watcher.name.lock().ok().map(|name| name.clone()))
+        .unwrap_or_default()
 }
 
-fn stable_text_hash(value: &str) -> String {
-    let mut hasher = DefaultHasher::new();
-    value.hash(&mut hasher);
-    format!("{:016x}", hasher.finish())
+fn watcher_aggregate_state<R: Runtime>(app: &AppHandle<R>) -> String {
+    app.try_state::<ObservedStates>()
+        .map(|observed| observed.scoped_aggregate(&watcher_aggregate_group(app)))
+        .unwrap_or_default()
+        .to_string()
 }
 
-fn hook_kind_to_string(kind: HookEventKind) -> String {
-    match kind {
-        HookEventKind::Completed => "completed".to_string(),
-        HookEventKind::NeedInput => "need_input".to_string(),
-        HookEventKind::Error => "error".to_string(),
-    }
+fn handle_hook_event<R: Runtime>(app: &AppHandle<R>, event: HookEvent) {
+    let history_dir = project_prompt_history_dir(app);
+    dispatch_hook_event(
+        &app.state::<SessionRegistry>(),
+        Some(&history_dir),
+        &event,
+        &TauriSink::new(app),
+    );
 }
 
 fn collect_terminal_windows<R: Runtime>(app: &AppHandle<R>) -> Vec<tauri::WebviewWindow<R>> {
@@ -3037,9 +1703,99 @@ fn window_frame_size<R: Runtime>(window: &tauri::WebviewWindow<R>) -> (u32, u32)
     }
 }
 
+// layout engine に渡す状態 / State handed to the layout engine.
+#[derive(Debug, Default)]
+struct TerminalLayoutContext {
+    // window label -> 観測状態 / Window label to observed state.
+    states: HashMap<String, String>,
+    focused: Option<String>,
+    // 空なら画面上の位置順 / Empty means on-screen position order.
+    order: Vec<String>,
+}
+
+fn terminal_layout_engine<R: Runtime>(app: &AppHandle<R>) -> Box<dyn LayoutEngine + Send + Sync> {
+    let settings = read_settings(&settings_path(app)).unwrap_or_else(|_| Settings::default());
+    layout::layout_engine(&settings.terminal_layout)
+}
+
+fn terminal_layout_context<R: Runtime>(app: &AppHandle<R>, keep_order: bool) -> TerminalLayoutContext {
+    let per_session = app
+        .try_state::<ObservedStates>()
+        .map(|states| states.snapshot())
+        .unwrap_or_default();
+    let labels = app
+        .try_state::<TerminalSessionState>()
+        .and_then(|state| state.labels.lock().ok().map(|guard| guard.clone()))
+        .unwrap_or_default();
+    let states = per_session
+        .into_iter()
+        .filter_map(|(session_id, state)| {
+            labels.get(&session_id).map(|label| (label.clone(), state.as_str().to_string()))
+        })
+        .collect();
+    let focused = app
+        .try_state::<SelectionState>()
+        .and_then(|state| state.current.lock().ok().and_then(|guard| guard.clone()));
+    let order = if keep_order {
+        app.state::<TerminalWindowLayoutState>()
+            .order
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    TerminalLayoutContext {
+        states,
+        focused,
+        order,
+    }
+}
+
+fn order_windows_by_position<R: Runtime>(
+    group: Vec<tauri::WebviewWindow<R>>,
+    area_x: i32,
+    area_y: i32,
+    height: u32,
+) -> Vec<tauri::WebviewWindow<R>> {
+    let threshold = row_group_threshold(height);
+    let mut centers: Vec<(i32, i32, tauri::WebviewWindow<R>)> = group
+        .into_iter()
+        .map(|window| {
+            let position = window.outer_position().ok();
+            let size = window.outer_size().ok();
+            let center_x = position.map(|pos| pos.x).unwrap_or(area_x)
+                + size.map(|s| s.width as i32 / 2).unwrap_or(0);
+            let center_y = position.map(|pos| pos.y).unwrap_or(area_y)
+                + size.map(|s| s.height as i32 / 2).unwrap_or(0);
+            (center_x, center_y, window)
+        })
+        .collect();
+    centers.sort_by_key(|(x, y, _)| (*y, *x));
+    let mut rows_grouped: Vec<Vec<(i32, i32, tauri::WebviewWindow<R>)>> = Vec::new();
+    for item in centers {
+        if let Some(row) = rows_grouped.last_mut() {
+            if (item.1 - row[0].1).abs() <= threshold {
+                row.push(item);
+                continue;
+            }
+        }
+        rows_grouped.push(vec![item]);
+    }
+    for row in &mut rows_grouped {
+        row.sort_by_key(|(x, _, _)| *x);
+    }
+    rows_grouped
+        .into_iter()
+        .flat_map(|row| row.into_iter().map(|(_, _, window)| window))
+        .collect()
+}
+
 fn compute_terminal_window_layout<R: Runtime>(
     monitors: &[tauri::Monitor],
     windows: Vec<tauri::WebviewWindow<R>>,
+    engine: &dyn LayoutEngine,
+    context: &TerminalLayoutContext,
 ) -> HashMap<String, WindowRect> {
     let mut groups: Vec<Vec<tauri::WebviewWindow<R>>> =
         (0..monitors.len()).map(|_| Vec::new()).collect();
@@ -3059,66 +1815,54 @@ fn compute_terminal_window_layout<R: Runtime>(
             Some(monitor) => monitor,
             None => continue,
         };
-        let area = monitor.work_area();
-        let base_x = area.position.x;
-        let base_y = area.position.y;
-        let width = area.size.width;
-        let height = area.size.height;
+        let work_area = monitor.work_area();
+        let area = WindowRect {
+            x: work_area.position.x,
+            y: work_area.position.y,
+            width: work_area.size.width,
+            height: work_area.size.height,
+        };
 
         group.sort_by_key(|window| window.label().to_string());
-        let count = group.len();
-        let (rows, cols) = grid_for_window_count(count);
-        if cols == 0 {
-            continue;
-        }
-        let (cell_width, cell_height) = cell_size(width, height, rows, cols);
-        let threshold = row_group_threshold(height);
-        let mut centers: Vec<(i32, i32, tauri::WebviewWindow<R>)> = group
-            .into_iter()
-            .map(|window| {
-                let position = window.outer_position().ok();
-                let size = window.outer_size().ok();
-                let center_x = position.map(|pos| pos.x).unwrap_or(base_x)
-                    + size.map(|s| s.width as i32 / 2).unwrap_or(0);
-                let center_y = position.map(|pos| pos.y).unwrap_or(base_y)
-                    + size.map(|s| s.height as i32 / 2).unwrap_or(0);
-                (center_x, center_y, window)
+        let keeps_order = !context.order.is_empty()
+            && group
+                .iter()
+                .all(|window| context.order.iter().any(|label| label == window.label()));
+        let ordered_windows = if keeps_order {
+            // 直前の並び順を保つ / Keep the previous arrangement order.
+            group.sort_by_key(|window| {
+                context
+                    .order
+                    .iter()
+                    .position(|label| label == window.label())
+            });
+            group
+        } else {
+            order_windows_by_position(group, area.x, area.y, area.height)
+        };
+
+        let items: Vec<LayoutItem> = ordered_windows
+            .iter()
+            .map(|window| LayoutItem {
+                label: window.label().to_string(),
+                state: context
+                    .states
+                    .get(window.label())
+                    .cloned()
+                    .unwrap_or_else(|| "idle".to_string()),
+                focused: context.focused.as_deref() == Some(window.label()),
             })
             .collect();
-        centers.sort_by_key(|(x, y, _)| (*y, *x));
-        let mut rows_grouped: Vec<Vec<(i32, i32, tauri::WebviewWindow<R>)>> = Vec::new();
-        for item in centers {
-            if let Some(row) = rows_grouped.last_mut() {
-                if (item.1 - row[0].1).abs() <= threshold {
-                    row.push(item);
-                    continue;
-                }
-            }
-            rows_grouped.push(vec![item]);
-        }
-        for row in &mut rows_grouped {
-            row.sort_by_key(|(x, _, _)| *x);
-        }
-        let ordered_windows: Vec<tauri::WebviewWindow<R>> = rows_grouped
-            .into_iter()
-            .flat_map(|row| row.into_iter().map(|(_, _, window)| window))
-            .collect();
-
-        for (index, window) in ordered_windows.into_iter().enumerate() {
-            let row = index / cols;
-            let col = index % cols;
-            let x = base_x + (col as i32 * cell_width as i32);
-            let y = base_y + (row as i32 * cell_height as i32);
+        let rects = engine.layout(area, &items);
+        for (window, cell) in ordered_windows.into_iter().zip(rects) {
             let (frame_width, frame_height) = window_frame_size(&window);
-            let target_width = cell_width.saturating_sub(frame_width).max(1);
-            let target_height = cell_height.saturating_sub(frame_height).max(1);
             layout.insert(
                 window.label().to_string(),
                 WindowRect {
-                    x,
-                    y,
-                    width: target_width,
-                    height: target_height,
+                    x: cell.x,
+                    y: cell.y,
+                    width: cell.width.saturating_sub(frame_width).max(1),
+                    height: cell.height.saturating_sub(frame_height).max(1),
                 },
             );
         }
@@ -3160,7 +1904,9 @@ fn get_or_recompute_terminal_layout<R: Runtime>(
         }
     }
 
-    let layout = compute_terminal_window_layout(monitors, windows);
+    let engine = terminal_layout_engine(app);
+    let context = terminal_layout_context(app, false);
+    let layout = compute_terminal_window_layout(monitors, windows, engine.as_ref(), &context);
     let order = layout_order_from_rects(&layout);
     if let Ok(mut guard) = state.layout.lock() {
         *guard = layout.clone();
@@ -3583,50 +2329,6 @@ fn primary_work_area<R: Runtime>(app: &AppHandle<R>) -> Result<(i32, i32, u32, u
     ))
 }
 
-fn normalize_observed_state(raw: &str) -> String {
-    let value = raw.trim().to_ascii_lowercase();
-    if value.is_empty() {
-        return "idle".to_string();
-    }
-    match value.as_str() {
-        "subworker_running" | "subworker-running" | "subworkerrunning" => {
-            "subworker-running".to_string()
-        }
-        "ai_running" | "ai-running" | "airunning" => "idle".to_string(),
-        "running" => "idle".to_string(),
-        "need_input" | "need-input" | "needinput" => "need-input".to_string(),
-        "fail" | "failure" | "error" => "fail".to_string(),
-        "success" => "success".to_string(),
-        "idle" => "idle".to_string(),
-        _ => value,
-    }
-}
-
-fn aggregate_observed_state(states: &HashMap<String, String>) -> String {
-    let mut has_need_input = false;
-    let mut has_fail = false;
-    let mut has_subworker_running = false;
-    for state in states.values() {
-        match state.as_str() {
-            "need-input" => has_need_input = true,
-            "fail" => has_fail = true,
-            "subworker-running" => has_subworker_running = true,
-            "success" | "idle" => {}
-            _ => {}
-        }
-    }
-    if has_need_input {
-        return "need-input".to_string();
-    }
-    if has_fail {
-        return "fail".to_string();
-    }
-    if has_subworker_running {
-        return "subworker-running".to_string();
-    }
-    "idle".to_string()
-}
-
 fn emit_terminal_aggregate_state<R: Runtime>(app: &AppHandle<R>, state: &str) {
     let payload = AggregateStatePayload {
         state: state.to_string(),
@@ -3752,13 +2454,7 @@ fn open_watcher_window<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
     improve_watcher_window_transparency_quality(&window);
     bind_watcher_window_events(app, &window);
     let _ = position_watcher_window(app, &window);
-    let state = app.state::<TerminalAggregateState>();
-    let last_state = state
-        .last_state
-        .lock()
-        .ok()
-        .map(|value| value.clone())
-        .unwrap_or_else(|| "idle".to_string());
+    let last_state = watcher_aggregate_state(app);
     emit_terminal_aggregate_state(app, &last_state);
     let _ = window.show();
     Ok(())
@@ -3825,13 +2521,7 @@ fn open_watcher_debug_window<R: Runtime>(app: &AppHandle<R>) -> Result<(), Strin
         _ => {}
     });
     let _ = position_watcher_debug_window(app, &window);
-    let state = app.state::<TerminalAggregateState>();
-    let last_state = state
-        .last_state
-        .lock()
-        .ok()
-        .map(|value| value.clone())
-        .unwrap_or_else(|| "idle".to_string());
+    let last_state = watcher_aggregate_state(app);
     emit_terminal_aggregate_state(app, &last_state);
     let _ = window.show();
     Ok(())
@@ -3939,17 +2629,16 @@ fn close_watcher_window<R: Runtime>(app: &AppHandle<R>) {
 
 fn close_character_windows_if_all_terminals_closed<R: Runtime>(app: &AppHandle<R>) {
     let terminal_windows_empty = collect_terminal_windows(app).is_empty();
-    let active_empty = app
-        .state::<TerminalSessionState>()
+    let registry = app.state::<SessionRegistry>();
+    let active_empty = registry
         .active
         .lock()
         .map(|active| active.is_empty())
         .unwrap_or(true);
-    let workers_empty = app
-        .state::<TerminalWorkerState>()
-        .processes
+    let workers_empty = registry
+        .workers
         .lock()
-        .map(|processes| processes.is_empty())
+        .map(|workers| workers.is_empty())
         .unwrap_or(true);
     if !terminal_windows_empty && !(active_empty && workers_empty) {
         return;
@@ -4176,27 +2865,6 @@ fn monitor_index_for_window<R: Runtime>(
     0
 }
 
-fn grid_for_window_count(count: usize) -> (usize, usize) {
-    let rows = if count >= 9 {
-        3
-    } else if count >= 4 {
-        2
-    } else {
-        1
-    };
-    let cols = (count + rows - 1) / rows;
-    (rows, cols)
-}
-
-fn cell_size(width: u32, height: u32, rows: usize, cols: usize) -> (u32, u32) {
-    if rows == 0 || cols == 0 {
-        return (width.max(1), height.max(1));
-    }
-    let cell_width = (width / cols as u32).max(1);
-    let cell_height = (height / rows as u32).max(1);
-    (cell_width, cell_height)
-}
-
 fn row_group_threshold(height: u32) -> i32 {
     let min = 80i32;
     let dynamic = (height as f32 * 0.12) as i32;
@@ -4239,23 +2907,20 @@ fn pickup_terminal_window_handle<R: Runtime>(
     let monitor = monitors
         .get(monitor_index)
         .ok_or_else(|| "monitor not found".to_string())?;
-    let area = monitor.work_area();
-    let base_x = area.position.x;
-    let base_y = area.position.y;
-    let width = area.size.width;
-    let height = area.size.height;
+    let work_area = monitor.work_area();
+    let area = WindowRect {
+        x: work_area.position.x,
+        y: work_area.position.y,
+        width: work_area.size.width,
+        height: work_area.size.height,
+    };
+    let tile = layout.get(&new_label).copied().unwrap_or(area);
+    let zoom = terminal_layout_engine(app).focus_rect(area, tile);
     let (frame_width, frame_height) = window_frame_size(window);
-    let target_width = ((width as f32) * 0.8) as u32;
-    let target_height = ((height as f32) * 0.8) as u32;
-    let target_width = target_width.saturating_sub(frame_width).max(1);
-    let target_height = target_height.saturating_sub(frame_height).max(1);
-    let center_x = base_x + ((width.saturating_sub(target_width)) as i32 / 2);
-    let center_y = base_y + ((height.saturating_sub(target_height)) as i32 / 2);
     let expanded = WindowRect {
-        x: center_x,
-        y: center_y,
-        width: target_width,
-        height: target_height,
+        width: zoom.width.saturating_sub(frame_width).max(1),
+        height: zoom.height.saturating_sub(frame_height).max(1),
+        ..zoom
     };
 
     if let Ok(mut guard) = app.state::<SelectionState>().current.lock() {
@@ -4278,6 +2943,8 @@ fn register_terminal_window<R: Runtime>(
         .lock()
         .map_err(|_| "terminal labels lock".to_string())?;
     guard.insert(session_id.to_string(), label.to_string());
+    drop(guard);
+    refresh_tray_terminals(app);
     Ok(())
 }
 
@@ -4289,46 +2956,6 @@ fn terminal_session_id_for_label<R: Runtime>(app: &AppHandle<R>, label: &str) ->
         .find_map(|(session_id, stored_label)| (stored_label == label).then(|| session_id.clone()))
 }
 
-fn notify_smoke_match<R: Runtime>(app: &AppHandle<R>, session_id: &str, chunk: &str) {
-    let state = match app.try_state::<TerminalSmokeState>() {
-        Some(state) => state,
-        None => return,
-    };
-    let mut guard = match state.waiters.lock() {
-        Ok(guard) => guard,
-        Err(_) => return,
-    };
-    if let Some(waiter) = guard.get(session_id) {
-        if chunk.to_lowercase().contains(&waiter.token) {
-            if let Some(waiter) = guard.remove(session_id) {
-                let _ = waiter.sender.send(Ok(()));
-                let _ = log_worker_event(
-                    app,
-                    &format!("terminal smoke matched: session={session_id}"),
-                );
-            }
-        }
-    }
-}
-
-fn notify_smoke_error<R: Runtime>(app: &AppHandle<R>, session_id: &str, message: &str) {
-    let state = match app.try_state::<TerminalSmokeState>() {
-        Some(state) => state,
-        None => return,
-    };
-    let mut guard = match state.waiters.lock() {
-        Ok(guard) => guard,
-        Err(_) => return,
-    };
-    if let Some(waiter) = guard.remove(session_id) {
-        let _ = waiter.sender.send(Err(message.to_string()));
-        let _ = log_worker_event(
-            app,
-            &format!("terminal smoke error: session={session_id} message={message}"),
-        );
-    }
-}
-
 #[tauri::command]
 fn start_terminal_session<R: Runtime>(
     app: AppHandle<R>,
@@ -4342,29 +2969,32 @@ fn start_terminal_session<R: Runtime>(
         &app,
         &format!("terminal start requested: {session_id} cols={cols} rows={rows}"),
     );
-    let terminal_state = app
-        .try_state::<TerminalSessionState>()
-        .ok_or_else(|| "terminal session state missing".to_string())?;
-    {
-        let active = terminal_state
-            .active
-            .lock()
-            .map_err(|_| "terminal session lock".to_string())?;
-        if active.contains(&session_id) {
-            return Ok(());
-        }
+    let registry = app.state::<SessionRegistry>();
+    if registry.is_active(&session_id) {
+        return Ok(());
     }
     let settings = read_settings(&settings_path(&app)).unwrap_or_else(|_| Settings::default());
-    let cmd = if cfg!(windows) {
-        build_windows_terminal_command(&settings)
-    } else {
-        "sh".to_string()
-    };
-    #[cfg(windows)]
-    let env = Some(build_windows_terminal_env(&session_id));
-    #[cfg(not(windows))]
-    let env: Option<HashMap<String, String>> = None;
-
+    let mut options = registry.launch_options(&session_id).unwrap_or_default();
+    let plan = build_terminal_launch_plan(&settings, &session_id, &options)?;
+    // 実際に使った cwd を残す（履歴・一覧・workspace 保存が参照する）
+    // Keep the cwd actually used; history, listings and workspace saves read it.
+    options.cwd = plan.cwd.clone();
+    registry.set_launch_options(&session_id, options.clone());
+    let _ = log_worker_event(
+        &app,
+        &format!(
+            "terminal launch: {session_id} profile={} cmd={:?} argv={:?} login_shell={} cwd={:?}",
+            options
+                .profile
+                .as_ref()
+                .map(|profile| profile.name.as_str())
+                .unwrap_or("-"),
+            plan.cmd,
+            plan.argv,
+            plan.login_shell,
+            plan.cwd
+        ),
+    );
     let worker_path = worker::resolve_worker_path().map_err(|err| err.to_string())?;
     let mut process = worker::WorkerProcess::spawn(&worker_path).map_err(|err| err.to_string())?;
     if let Some(rx) = process.take_receiver() {
@@ -4378,27 +3008,17 @@ fn start_terminal_session<R: Runtime>(
     process
         .send_start_session(nagomi_protocol::StartSession {
             session_id: session_id.clone(),
-            cmd,
-            cwd: None,
-            env,
+            cmd: plan.cmd,
+            argv: plan.argv,
+            login_shell: plan.login_shell,
+            cwd: plan.cwd,
+            env: Some(plan.env),
             cols,
             rows,
+            ..Default::default()
         })
         .map_err(|err| err.to_string())?;
-    {
-        let workers = app.state::<TerminalWorkerState>();
-        let mut guard = workers
-            .processes
-            .lock()
-            .map_err(|_| "terminal worker lock".to_string())?;
-        guard.insert(session_id.clone(), process);
-    }
-    let mut active = terminal_state
-        .active
-        .lock()
-        .map_err(|_| "terminal session lock".to_string())?;
-    active.insert(session_id);
-    Ok(())
+    registry.insert_worker(&session_id, process, &TauriSink::new(&app))
 }
 
 #[tauri::command]
@@ -4416,7 +3036,7 @@ fn terminal_send_input<R: Runtime>(
         &app,
         &format!("terminal input requested: {session_id} size={}", text.len()),
     );
-    send_terminal_input_for_session(&app, &session_id, &text)
+    app.state::<SessionRegistry>().send_input(&session_id, &text)
 }
 
 #[tauri::command]
@@ -4427,27 +3047,22 @@ fn terminal_resize<R: Runtime>(
     cols: u16,
     rows: u16,
 ) -> Result<(), String> {
-    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    resize_terminal_session_inner(&app, &session_id, cols, rows)
+}
+
+fn resize_terminal_session_inner<R: Runtime>(
+    app: &AppHandle<R>,
+    session_id: &str,
+    cols: u16,
+    rows: u16,
+) -> Result<(), String> {
     let _ = log_worker_event(
-        &app,
+        app,
         &format!("terminal resize requested: {session_id} cols={cols} rows={rows}"),
     );
-    let workers = app.state::<TerminalWorkerState>();
-    let mut guard = workers
-        .processes
-        .lock()
-        .map_err(|_| "terminal worker lock".to_string())?;
-    let Some(process) = guard.get_mut(&session_id) else {
-        return Err("terminal session not started".to_string());
-    };
-    process
-        .send_resize(nagomi_protocol::Resize {
-            session_id,
-            cols,
-            rows,
-        })
-        .map_err(|err| err.to_string())?;
-    Ok(())
+    app.state::<SessionRegistry>()
+        .resize(session_id, cols, rows)
 }
 
 fn stop_terminal_session_inner<R: Runtime>(
@@ -4459,13 +3074,6 @@ fn stop_terminal_session_inner<R: Runtime>(
     let terminal_state = app
         .try_state::<TerminalSessionState>()
         .ok_or_else(|| "terminal session state missing".to_string())?;
-    {
-        let mut active = terminal_state
-            .active
-            .lock()
-            .map_err(|_| "terminal session lock".to_string())?;
-        active.remove(session_id);
-    }
     {
         let mut labels = terminal_state
             .labels
@@ -4473,26 +3081,16 @@ fn stop_terminal_session_inner<R: Runtime>(
             .map_err(|_| "terminal labels lock".to_string())?;
         labels.remove(session_id);
     }
-    if let Ok(mut captures) = app.state::<TerminalBuiltinCommandState>().captures.lock() {
-        captures.remove(session_id);
-    }
-    let workers = app.state::<TerminalWorkerState>();
-    let mut guard = workers
-        .processes
-        .lock()
-        .map_err(|_| "terminal worker lock".to_string())?;
-    if let Some(mut process) = guard.remove(session_id) {
-        let _ = process.send_stop_session(nagomi_protocol::StopSession {
-            session_id: session_id.to_string(),
-        });
-        let _ = process.stop();
-    }
-    let workers_empty = guard.is_empty();
-    drop(guard);
+    let registry = app.state::<SessionRegistry>();
+    let outcome = registry.stop(session_id, reason, &TauriSink::new(app))?;
+    let workers_empty = outcome.workers_empty;
+    // 閉じた terminal を全体・グループの集約から外す / Drop the closed terminal from every aggregate.
+    app.state::<ObservedStates>().forget(session_id, &TauriSink::new(app));
+    refresh_tray_terminals(app);
 
     let flags = app.state::<OrchestratorRuntimeFlags>();
     if flags.exit_on_last_terminal {
-        let active_empty = terminal_state
+        let active_empty = registry
             .active
             .lock()
             .map_err(|_| "terminal session lock".to_string())?
@@ -4765,19 +3363,65 @@ fn open_terminal_window_offset_from_current<R: Runtime + 'static>(
 fn open_terminal_window_inner<R: Runtime>(
     app: AppHandle<R>,
     session_id: String,
+) -> Result<(), String> {
+    open_terminal_window_with_options(app, session_id, TerminalLaunchOptions::default())
+}
+
+// profile 名と cwd から起動条件を組み立てる / Build launch options from a profile name and a cwd.
+fn terminal_launch_options<R: Runtime>(
+    app: &AppHandle<R>,
+    profile_name: Option<&str>,
+    cwd: Option<&str>,
+) -> Result<TerminalLaunchOptions, String> {
+    let settings = read_settings(&settings_path(app)).unwrap_or_else(|_| Settings::default());
+    launch_options(&settings, profile_name, cwd)
+}
+
+fn open_terminal_window_with_options<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: String,
+    options: TerminalLaunchOptions,
 ) -> Result<(), String> {
     let _ = log_worker_event(
         &app,
         &format!("terminal window open requested: {session_id}"),
     );
     let label = terminal_window_label(&session_id);
-    let title = format!("Terminal {session_id}");
+    let title = terminal_window_title(&session_id, &options);
     let settings = read_settings(&settings_path(&app)).unwrap_or_else(|_| Settings::default());
-    let theme_mode = normalize_terminal_theme_mode(&settings.terminal_theme);
-    let theme_palette =
-        normalize_terminal_theme_palette(theme_mode, &settings.terminal_theme_palette);
-    let query =
+    let (theme_mode, theme_palette) = match options
+        .profile
+        .as_ref()
+        .map(|profile| profile.theme.trim().to_ascii_lowercase())
+        .filter(|theme| !theme.is_empty())
+    {
+        Some(theme) => {
+            let mode = normalize_terminal_theme_mode(if theme.starts_with("light") {
+                "light"
+            } else {
+                "dark"
+            });
+            (mode, normalize_terminal_theme_palette(mode, &theme))
+        }
+        None => {
+            let mode = normalize_terminal_theme_mode(&settings.terminal_theme);
+            (
+                mode,
+                normalize_terminal_theme_palette(mode, &settings.terminal_theme_palette),
+            )
+        }
+    };
+    let mut query =
         format!("view=terminal&session_id={session_id}&theme={theme_mode}&palette={theme_palette}");
+    if let Some(cwd) = options.cwd.as_deref() {
+        query.push_str(&format!("&cwd={}", url_encode(cwd)));
+    }
+    let group = aggregate_group(&settings.aggregate_group_by, &options);
+    if let Some(registry) = app.try_state::<SessionRegistry>() {
+        registry.set_launch_options(&session_id, options);
+    }
+    app.state::<ObservedStates>()
+        .set_group(&session_id, group, &TauriSink::new(&app));
     create_window(&app, &label, &title, &query).map_err(|err| err.to_string())?;
     sync_watcher_window(&app, &settings);
     mark_terminal_layout_arranged(&app, false);
@@ -4789,6 +3433,237 @@ fn open_terminal_window_inner<R: Runtime>(
     Ok(())
 }
 
+fn workspaces_dir<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
+    app_config_dir(app).join("workspaces")
+}
+
+fn monitor_work_areas(monitors: &[tauri::Monitor]) -> Vec<(Option<String>, WorkspaceRect)> {
+    monitors
+        .iter()
+        .map(|monitor| {
+            let area = monitor.work_area();
+            (
+                monitor.name().cloned(),
+                WorkspaceRect {
+                    x: area.position.x,
+                    y: area.position.y,
+                    width: area.size.width,
+                    height: area.size.height,
+                },
+            )
+        })
+        .collect()
+}
+
+// 開いている terminal を workspace として写し取る / Snapshot the open terminals as a workspace.
+fn capture_workspace<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<Workspace, String> {
+    let mut windows = collect_terminal_windows(app);
+    if windows.is_empty() {
+        return Err("no terminal windows to save".to_string());
+    }
+    let monitors = available_monitors(app)?;
+    let areas = monitor_work_areas(&monitors);
+    let order = app
+        .state::<TerminalWindowLayoutState>()
+        .order
+        .lock()
+        .map(|guard| guard.clone())
+        .unwrap_or_default();
+    // 整列済みの順番を優先し、残りは画面上の位置順 / Arranged order first, then on-screen position.
+    windows.sort_by_key(|window| {
+        let rank = order
+            .iter()
+            .position(|label| label == window.label())
+            .unwrap_or(usize::MAX);
+        let rect = current_window_rect(window);
+        (
+            rank,
+            monitor_index_for_window(window, &monitors),
+            rect.map(|rect| (rect.y, rect.x)).unwrap_or_default(),
+            window.label().to_string(),
+        )
+    });
+    let launch = app
+        .try_state::<SessionRegistry>()
+        .and_then(|registry| registry.launch.lock().ok().map(|guard| guard.clone()))
+        .unwrap_or_default();
+
+    let mut terminals = Vec::new();
+    for window in windows {
+        let Some(session_id) = terminal_session_id_for_label(app, window.label()) else {
+            continue;
+        };
+        let options = launch.get(&session_id).cloned().unwrap_or_default();
+        let profile = options.profile.clone().unwrap_or_default();
+        let cwd = options.effective_cwd().unwrap_or_default();
+        let monitor = monitor_index_for_window(&window, &monitors);
+        let (monitor_name, monitor_area) = areas
+            .get(monitor)
+            .cloned()
+            .map(|(name, area)| (name, Some(area)))
+            .unwrap_or_default();
+        terminals.push(WorkspaceTerminal {
+            order: terminals.len(),
+            session_id,
+            title: window.title().unwrap_or_default(),
+            profile: profile.name.trim().to_string(),
+            cmd: profile.cmd.trim().to_string(),
+            cwd,
+            monitor,
+            monitor_name,
+            monitor_area,
+            rect: current_window_rect(&window).map(|rect| WorkspaceRect {
+                x: rect.x,
+                y: rect.y,
+                width: rect.width,
+                height: rect.height,
+            }),
+        });
+    }
+    Ok(Workspace {
+        version: WORKSPACE_FILE_VERSION,
+        name: name.trim().to_string(),
+        saved_at_ms: unix_now_ms(),
+        terminals,
+    })
+}
+
+fn save_workspace_inner<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<PathBuf, String> {
+    let workspace = capture_workspace(app, name)?;
+    let path = workspace::write_workspace(&workspaces_dir(app), &workspace)
+        .map_err(|err| err.to_string())?;
+    let _ = log_worker_event(
+        app,
+        &format!(
+            "workspace saved: name={} terminals={} path={}",
+            workspace.name,
+            workspace.terminals.len(),
+            path.display()
+        ),
+    );
+    refresh_tray_menu(app);
+    Ok(path)
+}
+
+// 保存順に window を開き、各 session は通常どおり worker で起動される
+// Open windows in saved order; each session is then spawned through the worker as usual.
+fn open_workspace_inner<R: Runtime>(app: &AppHandle<R>, name: &str) -> Result<Vec<String>, String> {
+    let workspace =
+        workspace::read_workspace(&workspaces_dir(app), name).map_err(|err| err.to_string())?;
+    let settings = read_settings(&settings_path(app)).unwrap_or_else(|_| Settings::default());
+    let monitors = available_monitors(app)?;
+    let areas = monitor_work_areas(&monitors);
+    let mut session_ids = Vec::new();
+    let mut layout = HashMap::new();
+    let mut order = Vec::new();
+    let mut failures = Vec::new();
+    for terminal in workspace.ordered_terminals() {
+        let mut options = workspace_launch_options(&settings, terminal);
+        if let Some(cwd) = options.cwd.take() {
+            match resolve_terminal_cwd(&cwd) {
+                Ok(cwd) => options.cwd = Some(cwd),
+                Err(err) => {
+                    let _ = log_worker_event(app, &format!("workspace cwd skipped: {err}"));
+                }
+            }
+        }
+        let in_use = app
+            .try_state::<TerminalSessionState>()
+            .and_then(|state| {
+                state
+                    .labels
+                    .lock()
+                    .ok()
+                    .map(|guard| guard.contains_key(&terminal.session_id))
+            })
+            .unwrap_or(false);
+        let session_id = if terminal.session_id.trim().is_empty() || in_use {
+            generate_terminal_session_id()
+        } else {
+            terminal.session_id.clone()
+        };
+        // 1 つ失敗しても残りは開く / One failure doesn't stop the rest of the restore.
+        if let Err(err) = open_terminal_window_with_options(app.clone(), session_id.clone(), options) {
+            let _ = log_worker_event(
+                app,
+                &format!("workspace terminal skipped: session_id={session_id} error={err}"),
+            );
+            failures.push(err);
+            continue;
+        }
+        let label = terminal_window_label(&session_id);
+        if let (Some(rect), Some(window)) = (terminal.rect, app.get_webview_window(&label)) {
+            let monitor = workspace::pick_monitor(terminal, &areas);
+            let placed = match areas.get(monitor) {
+                Some((_, area)) => workspace::place_rect(rect, terminal.monitor_area, *area),
+                None => rect,
+            };
+            let rect = WindowRect {
+                x: placed.x,
+                y: placed.y,
+                width: placed.width,
+                height: placed.height,
+            };
+            apply_window_rect(app, &window, rect);
+            layout.insert(label.clone(), rect);
+        }
+        order.push(label);
+        session_ids.push(session_id);
+    }
+    if session_ids.is_empty() {
+        if let Some(err) = failures.into_iter().next() {
+            return Err(err);
+        }
+        return Ok(session_ids);
+    }
+    if layout.len() == order.len() {
+        let state = app.state::<TerminalWindowLayoutState>();
+        if let Ok(mut guard) = state.layout.lock() {
+            *guard = layout;
+        }
+        if let Ok(mut guard) = state.order.lock() {
+            *guard = order;
+        }
+        mark_terminal_layout_arranged(app, true);
+    }
+    let _ = log_worker_event(
+        app,
+        &format!(
+            "workspace opened: name={} terminals={} skipped={}",
+            workspace.name,
+            session_ids.len(),
+            failures.len()
+        ),
+    );
+    Ok(session_ids)
+}
+
+#[tauri::command]
+fn save_workspace<R: Runtime>(
+    app: AppHandle<R>,
+    ipc_session_id: String,
+    name: String,
+) -> Result<String, String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    save_workspace_inner(&app, &name).map(|path| path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn open_workspace<R: Runtime>(
+    app: AppHandle<R>,
+    ipc_session_id: String,
+    name: String,
+) -> Result<Vec<String>, String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    open_workspace_inner(&app, &name)
+}
+
+#[tauri::command]
+fn list_workspaces<R: Runtime>(app: AppHandle<R>, ipc_session_id: String) -> Result<Vec<String>, String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    Ok(workspace::list_workspaces(&workspaces_dir(&app)))
+}
+
 fn open_settings_window_inner<R: Runtime + 'static>(app: AppHandle<R>) {
     let focus_existing = app.get_webview_window(WINDOW_SETTINGS).is_some();
     thread::spawn(move || {
@@ -4835,6 +3710,9 @@ fn set_current_window_title<R: Runtime>(
     window
         .set_title(title.trim())
         .map_err(|err| err.to_string())?;
+    if window.label().starts_with("terminal-") {
+        refresh_tray_terminals(window.app_handle());
+    }
     Ok(())
 }
 
@@ -4927,28 +3805,7 @@ fn terminal_smoke<R: Runtime>(
             .as_millis()
     );
 
-    let (tx, rx) = std::sync::mpsc::channel();
-    let smoke_state = if let Some(state) = app.try_state::<TerminalSmokeState>() {
-        state
-    } else {
-        app.manage(TerminalSmokeState {
-            waiters: Mutex::new(HashMap::new()),
-        });
-        app.state::<TerminalSmokeState>()
-    };
-    {
-        let mut guard = smoke_state
-            .waiters
-            .lock()
-            .map_err(|_| "terminal smoke lock".to_string())?;
-        guard.insert(
-            session_id.clone(),
-            TerminalSmokeWaiter {
-                token: token.to_lowercase(),
-                sender: tx,
-            },
-        );
-    }
+    let rx = app.state::<SmokeWaiters>().register(&session_id, &token);
     let _ = log_worker_event(
         &app,
         &format!("terminal smoke start: session={session_id} token={token}"),
@@ -4982,6 +3839,7 @@ fn terminal_smoke<R: Runtime>(
             env: None,
             cols: 120,
             rows: 30,
+            ..Default::default()
         })
         .map_err(|err| err.to_string())?;
     let input = if cfg!(windows) {
@@ -5004,11 +3862,13 @@ fn terminal_smoke<R: Runtime>(
         Ok(Ok(())) => Ok(()),
         Ok(Err(err)) => Err(err),
         Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-            notify_smoke_error(&app, &session_id, "timeout waiting for output");
+            app.state::<SmokeWaiters>()
+                .fail(&session_id, "timeout waiting for output");
             Err("timeout waiting for output".to_string())
         }
         Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-            notify_smoke_error(&app, &session_id, "smoke channel disconnected");
+            app.state::<SmokeWaiters>()
+                .fail(&session_id, "smoke channel disconnected");
             Err("smoke channel disconnected".to_string())
         }
     }
@@ -5029,7 +3889,16 @@ fn arrange_terminal_windows_inner<R: Runtime>(app: AppHandle<R>) -> Result<(), S
         return Ok(());
     }
     let monitors = available_monitors(&app)?;
-    let (layout, order) = get_or_recompute_terminal_layout(&app, &monitors, windows);
+    // 状態依存の engine もあるので毎回計算し直す（順番は維持）
+    // Always recompute since some engines depend on state; the previous order is kept.
+    let engine = terminal_layout_engine(&app);
+    let context = terminal_layout_context(&app, true);
+    let layout = compute_terminal_window_layout(&monitors, windows, engine.as_ref(), &context);
+    let order = layout_order_from_rects(&layout);
+    let _ = log_worker_event(
+        &app,
+        &format!("terminal arrange: layout={} windows={}", engine.name(), layout.len()),
+    );
     for (label, rect) in &layout {
         if let Some(window) = app.get_webview_window(&label) {
             apply_window_rect(&app, &window, *rect);
@@ -5128,6 +3997,131 @@ fn focus_next_terminal_window<R: Runtime>(
     Ok(())
 }
 
+// 状態（need-input / fail）で次の terminal を選んで前面に出す
+// Bring forward the next terminal whose observed state matches `target`.
+fn focus_terminal_by_state_inner<R: Runtime>(
+    app: &AppHandle<R>,
+    target: &str,
+    direction: Option<&str>,
+) -> Result<Option<String>, String> {
+    let target = TerminalState::parse(target)
+        .ok_or_else(|| format!("unknown terminal state: {target}"))?
+        .as_str();
+    let windows = collect_terminal_windows(app);
+    if windows.is_empty() {
+        return Ok(None);
+    }
+    let monitors = available_monitors(app)?;
+    let (_, mut order) = get_or_recompute_terminal_layout(app, &monitors, windows.clone());
+    for window in &windows {
+        let label = window.label().to_string();
+        if !order.contains(&label) {
+            order.push(label);
+        }
+    }
+    let context = terminal_layout_context(app, false);
+    let focused_label = windows
+        .iter()
+        .find(|window| window.is_focused().unwrap_or(false))
+        .map(|window| window.label().to_string());
+    let current_label = context
+        .focused
+        .filter(|label| order.contains(label))
+        .or(focused_label);
+    let Some(target_label) = layout::next_label_in_state(
+        &order,
+        &context.states,
+        current_label.as_deref(),
+        target,
+        direction == Some("prev"),
+    ) else {
+        return Ok(None);
+    };
+    if let Some(window) = app.get_webview_window(&target_label) {
+        let _ = window.unminimize();
+        pickup_terminal_window_handle(app, &window)?;
+    }
+    Ok(Some(target_label))
+}
+
+#[tauri::command]
+fn focus_terminal_by_state<R: Runtime>(
+    app: AppHandle<R>,
+    ipc_session_id: String,
+    state: String,
+    direction: Option<String>,
+) -> Result<Option<String>, String> {
+    ipc_session::touch_ipc_session(&app, &ipc_session_id)?;
+    focus_terminal_by_state_inner(&app, &state, direction.as_deref())
+}
+
+// Settings の「次の入力待ち / 次の失敗」を OS 全体のショートカットとして登録し直す
+// Re-register the "next need-input / next failed" bindings as global shortcuts.
+fn register_terminal_state_shortcuts<R: Runtime>(app: &AppHandle<R>, settings: &Settings) {
+    let Some(state) = app.try_state::<TerminalStateShortcuts>() else {
+        return;
+    };
+    let Ok(mut registered) = state.registered.lock() else {
+        return;
+    };
+    let shortcuts = app.global_shortcut();
+    for accelerator in registered.drain(..) {
+        if let Err(err) = shortcuts.unregister(accelerator.as_str()) {
+            let _ = log_worker_event(
+                app,
+                &format!("global shortcut unregister failed ({accelerator}): {err}"),
+            );
+        }
+    }
+    let bindings = [
+        (settings.terminal_keybind_next_need_input.as_str(), "need-input"),
+        (settings.terminal_keybind_next_failed.as_str(), "fail"),
+    ];
+    for (binding, target) in bindings {
+        let Some(accelerator) = global_shortcut_accelerator(binding) else {
+            continue;
+        };
+        let result = shortcuts.on_shortcut(accelerator.as_str(), move |app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                let _ = focus_terminal_by_state_inner(app, target, None);
+            }
+        });
+        match result {
+            Ok(()) => registered.push(accelerator),
+            Err(err) => {
+                let _ = log_worker_event(
+                    app,
+                    &format!("global shortcut register failed ({accelerator}): {err}"),
+                );
+            }
+        }
+    }
+}
+
+// 画面側の "Ctrl+Shift+N" 形式を global shortcut 用に変換（Ctrl は macOS では Cmd）
+// Convert the frontend binding format; "Ctrl" means the primary modifier (Cmd on macOS).
+fn global_shortcut_accelerator(binding: &str) -> Option<String> {
+    let parts: Vec<&str> = binding
+        .split('+')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    if parts.len() < 2 {
+        return None;
+    }
+    let converted: Vec<&str> = parts
+        .iter()
+        .map(|part| {
+            if part.eq_ignore_ascii_case("ctrl") {
+                "CommandOrControl"
+            } else {
+                part
+            }
+        })
+        .collect();
+    Some(converted.join("+"))
+}
+
 fn create_window<R: Runtime>(
     app: &AppHandle<R>,
     label: &str,
@@ -5199,7 +4193,7 @@ fn create_window<R: Runtime>(
     Ok(())
 }
 
-fn build_tray<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
+fn build_tray_menu<R: Runtime>(app: &AppHandle<R>) -> Result<Menu<R>> {
     let menu = Menu::new(app)?;
     let open_settings =
         MenuItem::with_id(app, "open_settings", "Open Settings", true, None::<&str>)?;
@@ -5226,16 +4220,165 @@ fn build_tray<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
     )?;
     let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
 
-    menu.append(&open_terminal)?;
-    menu.append(&open_character_watcher)?;
-    menu.append(&arrange_terminals)?;
-    menu.append(&open_settings)?;
-    menu.append(&PredefinedMenuItem::separator(app)?)?;
-    menu.append(&quit)?;
+    // 開いている terminal を 1 つずつ（タイトルと状態）/ One entry per open terminal with its title and state.
+    let terminals = tray_terminal_entries(app);
+    for (session_id, label) in &terminals {
+        let item = MenuItem::with_id(
+            app,
+            format!("{TRAY_TERMINAL_ID_PREFIX}{session_id}"),
+            label,
+            true,
+            None::<&str>,
+        )?;
+        menu.append(&item)?;
+    }
+    if !terminals.is_empty() {
+        menu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+    menu.append(&open_terminal)?;
+    let profile_names: Vec<String> = app
+        .try_state::<TrayMenuState>()
+        .and_then(|state| state.profiles.lock().ok().map(|names| names.clone()))
+        .unwrap_or_default();
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        if let Ok(mut listed) = state.terminals.lock() {
+            *listed = terminals;
+        }
+    }
+    if !profile_names.is_empty() {
+        let profiles = Submenu::with_id(app, "open_profile", "Open Profile", true)?;
+        for name in profile_names {
+            let item = MenuItem::with_id(
+                app,
+                format!("{TRAY_PROFILE_ID_PREFIX}{name}"),
+                &name,
+                true,
+                None::<&str>,
+            )?;
+            profiles.append(&item)?;
+        }
+        menu.append(&profiles)?;
+    }
+    let workspace_names = workspace::list_workspaces(&workspaces_dir(app));
+    if !workspace_names.is_empty() {
+        let workspaces = Submenu::with_id(app, "open_workspace", "Open Workspace", true)?;
+        for name in workspace_names {
+            let item = MenuItem::with_id(
+                app,
+                format!("{TRAY_WORKSPACE_ID_PREFIX}{name}"),
+                &name,
+                true,
+                None::<&str>,
+            )?;
+            workspaces.append(&item)?;
+        }
+        menu.append(&workspaces)?;
+    }
+    menu.append(&open_character_watcher)?;
+    menu.append(&arrange_terminals)?;
+    menu.append(&open_settings)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&quit)?;
+    Ok(menu)
+}
+
+fn tray_terminal_entries<R: Runtime>(app: &AppHandle<R>) -> Vec<(String, String)> {
+    AppControlBackend { app }
+        .list_sessions()
+        .iter()
+        .map(|session| (session.session_id.clone(), tray::tray_terminal_label(session)))
+        .collect()
+}
+
+// 設定のプロファイル名を写して tray メニューを作り直す
+// Copy the profile names from settings and rebuild the tray menu.
+fn apply_tray_settings<R: Runtime>(app: &AppHandle<R>, settings: &Settings) {
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        if let Ok(mut profiles) = state.profiles.lock() {
+            *profiles = settings
+                .terminal_profiles
+                .iter()
+                .map(|profile| profile.name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect();
+        }
+    }
+    refresh_tray_menu(app);
+}
+
+// profile・workspace 変更後に tray メニューを作り直す / Rebuild the tray menu after profiles or workspaces change.
+fn refresh_tray_menu<R: Runtime>(app: &AppHandle<R>) {
+    let Some(tray) = app.tray_by_id(&tauri::tray::TrayIconId::new("main")) else {
+        return;
+    };
+    match build_tray_menu(app) {
+        Ok(menu) => {
+            let _ = tray.set_menu(Some(menu));
+        }
+        Err(err) => {
+            let _ = log_worker_event(app, &format!("tray menu rebuild failed: {err}"));
+        }
+    }
+}
+
+// 全体の集約状態と状態ごとの数を tooltip に出す / Show the overall aggregate and per-state counts in the tooltip.
+fn refresh_tray_tooltip<R: Runtime>(app: &AppHandle<R>) {
+    let Some(tray) = app.tray_by_id(&tauri::tray::TrayIconId::new("main")) else {
+        return;
+    };
+    let sessions = AppControlBackend { app }.list_sessions();
+    let _ = tray.set_tooltip(Some(tray::tray_tooltip(tray_aggregate(app), &sessions)));
+}
+
+// 集約が変わったらアイコンの印と tooltip を描き直す
+// Redraw the icon badge and tooltip when the aggregate changes.
+fn refresh_tray_status<R: Runtime>(app: &AppHandle<R>) {
+    refresh_tray_tooltip(app);
+    let Some(tray) = app.tray_by_id(&tauri::tray::TrayIconId::new("main")) else {
+        return;
+    };
+    if let Some(icon) = tray_state_icon(app, tray_aggregate(app)) {
+        let _ = tray.set_icon(Some(icon));
+    }
+}
+
+fn tray_aggregate<R: Runtime>(app: &AppHandle<R>) -> TerminalState {
+    app.try_state::<ObservedStates>()
+        .map(|observed| observed.aggregate())
+        .unwrap_or_default()
+}
+
+fn tray_state_icon<R: Runtime>(
+    app: &AppHandle<R>,
+    state: TerminalState,
+) -> Option<tauri::image::Image<'static>> {
+    let icon = app.default_window_icon()?;
+    let rgba = tray::badge_icon_rgba(icon.rgba(), icon.width(), icon.height(), state);
+    Some(tauri::image::Image::new_owned(rgba, icon.width(), icon.height()))
+}
+
+// terminal の開閉・状態・タイトルで項目が変わったときだけメニューと tooltip を作り直す。
+// アイコンは集約が変わったときに `refresh_tray_status` が描き直す
+// Rebuild the menu and tooltip only when a terminal entry changed (open, close, state or rename);
+// the icon is redrawn by `refresh_tray_status` when the aggregate moves.
+fn refresh_tray_terminals<R: Runtime>(app: &AppHandle<R>) {
+    let entries = tray_terminal_entries(app);
+    let unchanged = app
+        .try_state::<TrayMenuState>()
+        .and_then(|state| state.terminals.lock().ok().map(|listed| *listed == entries))
+        .unwrap_or(false);
+    if unchanged {
+        return;
+    }
+    refresh_tray_menu(app);
+    refresh_tray_tooltip(app);
+}
 
+fn build_tray<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
+    let menu = build_tray_menu(app)?;
     let mut tray = TrayIconBuilder::<R>::with_id("main")
         .menu(&menu)
-        .tooltip("nagomi")
+        .tooltip(tray::tray_tooltip(TerminalState::Idle, &[]))
         .on_menu_event(|app, event| match event.id() {
             id if id == "open_settings" => {
                 open_settings_window_inner(app.clone());
@@ -5250,6 +4393,34 @@ fn build_tray<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
             id if id == "arrange_terminals" => {
                 let _ = arrange_terminal_windows_inner(app.clone());
             }
+            id if id.as_ref().starts_with(TRAY_PROFILE_ID_PREFIX) => {
+                let name = id.as_ref()[TRAY_PROFILE_ID_PREFIX.len()..].to_string();
+                let session_id = generate_terminal_session_id();
+                let opened = terminal_launch_options(app, Some(&name), None).and_then(|options| {
+                    open_terminal_window_with_options(app.clone(), session_id, options)
+                });
+                if let Err(err) = opened {
+                    let _ = log_worker_event(app, &format!("tray profile open failed: {err}"));
+                }
+            }
+            id if id.as_ref().starts_with(TRAY_TERMINAL_ID_PREFIX) => {
+                let session_id = &id.as_ref()[TRAY_TERMINAL_ID_PREFIX.len()..];
+                let window = AppControlBackend { app }
+                    .session_label(session_id)
+                    .and_then(|label| app.get_webview_window(&label));
+                if let Some(window) = window {
+                    let _ = window.show();
+                    if let Err(err) = pickup_terminal_window_handle(app, &window) {
+                        let _ = log_worker_event(app, &format!("tray pickup failed: {err}"));
+                    }
+                }
+            }
+            id if id.as_ref().starts_with(TRAY_WORKSPACE_ID_PREFIX) => {
+                let name = id.as_ref()[TRAY_WORKSPACE_ID_PREFIX.len()..].to_string();
+                if let Err(err) = open_workspace_inner(app, &name) {
+                    let _ = log_worker_event(app, &format!("tray workspace open failed: {err}"));
+                }
+            }
             id if id == "quit" => {
                 app.exit(0);
             }
@@ -5292,47 +4463,12 @@ fn log_worker_event<R: Runtime>(app: &AppHandle<R>, message: &str) -> Result<()>
 
 fn start_worker_reader<R: Runtime>(app: AppHandle<R>) {
     thread::spawn(move || {
-        use std::collections::HashMap;
-
-        #[derive(Debug)]
-        struct PendingOutput {
-            queued_at: Instant,
-            bytes: usize,
-            chunks: Vec<String>,
-        }
-
-        // Terminal output coalescing defaults / 郢ｧ・ｿ郢晢ｽｼ郢晄ｺ倥Μ郢晢ｽｫ陷・ｽｺ陷牙ｸ帷ｲ玖抄阮吶・隴鯉ｽ｢陞ｳ螢ｼﾂ・､
-        const OUTPUT_BUSY_THRESHOLD_BYTES: usize = 256 * 1024;
-        const OUTPUT_FLUSH_DELAY_NORMAL: Duration = Duration::from_millis(16);
-        const OUTPUT_FLUSH_BYTES_NORMAL: usize = 64 * 1024;
-        const OUTPUT_FLUSH_DELAY_BUSY: Duration = Duration::from_millis(32);
-        const OUTPUT_FLUSH_BYTES_BUSY: usize = 128 * 1024;
-
-        let debug_io = std::env::var_os("NAGOMI_DEBUG_WORKER_IO").is_some();
-        let enable_broadcast =
-            std::env::var_os("NAGOMI_ENABLE_TERMINAL_OUTPUT_BROADCAST").is_some();
-        let mut pending: HashMap<(String, String), PendingOutput> = HashMap::new();
+        let sink = TauriSink::new(&app);
+        let mut coalescer = OutputCoalescer::default();
 
         loop {
-            let total_pending_bytes: usize = pending.values().map(|entry| entry.bytes).sum();
-            let (flush_delay, flush_bytes) = if total_pending_bytes > OUTPUT_BUSY_THRESHOLD_BYTES {
-                (OUTPUT_FLUSH_DELAY_BUSY, OUTPUT_FLUSH_BYTES_BUSY)
-            } else {
-                (OUTPUT_FLUSH_DELAY_NORMAL, OUTPUT_FLUSH_BYTES_NORMAL)
-            };
-
             // Flush deadline drives worker read timeout so we can flush ~16ms even when output is sparse.
-            let now = Instant::now();
-            let mut next_deadline: Option<Instant> = None;
-            for entry in pending.values() {
-                let deadline = entry.queued_at + flush_delay;
-                next_deadline = Some(next_deadline.map_or(deadline, |prev| prev.min(deadline)));
-            }
-            let timeout = next_deadline
-                .map(|deadline| deadline.saturating_duration_since(now))
-                .unwrap_or(Duration::from_millis(200))
-                .min(Duration::from_millis(200));
-
+            let timeout = coalescer.next_timeout(Instant::now());
             let message = {
                 let state = app.state::<WorkerState>();
                 let process = state.process.lock().expect("worker lock");
@@ -5342,231 +4478,48 @@ fn start_worker_reader<R: Runtime>(app: AppHandle<R>) {
             if let Some(message) = message {
                 match message {
                     Message::Output(output) => {
-                        notify_smoke_match(&app, &output.session_id, &output.chunk);
-                        let session_id = output.session_id;
-                        let stream = output.stream;
-                        let chunk = output.chunk;
-
-                        if debug_io {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "output received: session={session_id} stream={stream} size={}",
-                                    chunk.len()
-                                ),
-                            );
-                        }
-
-                        let key = (session_id.clone(), stream.clone());
-                        let entry = pending.entry(key).or_insert_with(|| PendingOutput {
-                            queued_at: Instant::now(),
-                            bytes: 0,
-                            chunks: Vec::new(),
-                        });
-                        entry.bytes += chunk.len();
-                        entry.chunks.push(chunk);
+                        app.state::<SmokeWaiters>()
+                            .match_output(&output.session_id, &output.chunk);
+                        coalescer.push(
+                            &output.session_id,
+                            &output.stream,
+                            output.chunk,
+                            Instant::now(),
+                        );
                     }
                     Message::Exit(exit) => {
-                        if exit.exit_code == 0 {
-                            let mut matched = false;
-                            if let Some(state) = app.try_state::<TerminalSmokeState>() {
-                                if let Ok(mut guard) = state.waiters.lock() {
-                                    if let Some(waiter) = guard.remove(&exit.session_id) {
-                                        let _ = waiter.sender.send(Ok(()));
-                                        let _ = log_worker_event(
-                                            &app,
-                                            &format!(
-                                                "terminal smoke exit ok: session={}",
-                                                exit.session_id
-                                            ),
-                                        );
-                                        matched = true;
-                                    }
-                                }
-                            }
-                            if !matched {
-                                notify_smoke_error(&app, &exit.session_id, "exit before token: 0");
-                            }
-                        } else {
-                            notify_smoke_error(
-                                &app,
-                                &exit.session_id,
-                                &format!("exit before token: {}", exit.exit_code),
-                            );
+                        if app
+                            .state::<SmokeWaiters>()
+                            .exit(&exit.session_id, exit.exit_code)
+                            && exit.exit_code == 0
+                        {
+                            sink.log(&format!(
+                                "terminal smoke exit ok: session={}",
+                                exit.session_id
+                            ));
                         }
-                        let _ = log_worker_event(
-                            &app,
-                            &format!("exit {}: {}", exit.session_id, exit.exit_code),
-                        );
-                        println!("[worker] exit {}: {}", exit.session_id, exit.exit_code);
+                        sink.log(&format!("exit {}: {}", exit.session_id, exit.exit_code));
                         let session = app.state::<SessionState>();
                         let mut guard = session.current.lock().expect("session lock");
                         *guard = None;
-                        let label = {
-                            let state = app.state::<TerminalSessionState>();
-                            if let Ok(mut active) = state.active.lock() {
-                                active.remove(&exit.session_id);
-                            }
-                            let guard = state.labels.lock().ok();
-                            guard
-                                .and_then(|map| map.get(&exit.session_id).cloned())
-                                .unwrap_or_default()
-                        };
-                        if label.is_empty() {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "terminal exit ignored: session={} label missing",
-                                    exit.session_id
-                                ),
-                            );
-                        } else if let Some(window) = app.get_webview_window(&label) {
-                            let payload = TerminalExitPayload {
-                                session_id: exit.session_id.clone(),
-                                exit_code: exit.exit_code,
-                            };
-                            match window.emit("terminal-exit", payload) {
-                                Ok(()) => {
-                                    let _ = log_worker_event(
-                                        &app,
-                                        &format!(
-                                            "terminal exit emitted: session={} label={}",
-                                            exit.session_id, label
-                                        ),
-                                    );
-                                }
-                                Err(err) => {
-                                    let _ = log_worker_event(
-                                        &app,
-                                        &format!(
-                                            "terminal exit emit failed: session={} label={} err={}",
-                                            exit.session_id, label, err
-                                        ),
-                                    );
-                                }
-                            }
-                        } else {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "terminal exit ignored: session={} window missing label={}",
-                                    exit.session_id, label
-                                ),
-                            );
+                        if let Ok(mut active) = app.state::<SessionRegistry>().active.lock() {
+                            active.remove(&exit.session_id);
                         }
+                        sink.session_exit(&exit.session_id, exit.exit_code);
                         close_character_windows_if_all_terminals_closed(&app);
                     }
                     Message::Error(error) => {
-                        notify_smoke_error(&app, &error.session_id, &error.message);
-                        let _ = log_worker_event(
-                            &app,
-                            &format!("error {}: {}", error.session_id, error.message),
-                        );
-                        println!("[worker] error {}: {}", error.session_id, error.message);
-                        let label = {
-                            let state = app.state::<TerminalSessionState>();
-                            let guard = state.labels.lock().ok();
-                            guard
-                                .and_then(|map| map.get(&error.session_id).cloned())
-                                .unwrap_or_default()
-                        };
-                        if label.is_empty() {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "terminal error ignored: session={} label missing",
-                                    error.session_id
-                                ),
-                            );
-                        } else if let Some(window) = app.get_webview_window(&label) {
-                            let payload = TerminalErrorPayload {
-                                session_id: error.session_id.clone(),
-                                message: error.message,
-                            };
-                            match window.emit("terminal-error", payload) {
-                                Ok(()) => {
-                                    let _ = log_worker_event(
-                                        &app,
-                                        &format!(
-                                            "terminal error emitted: session={} label={}",
-                                            error.session_id, label
-                                        ),
-                                    );
-                                }
-                                Err(err) => {
-                                    let _ = log_worker_event(
-                                        &app,
-                                        &format!(
-                                        "terminal error emit failed: session={} label={} err={}",
-                                        error.session_id, label, err
-                                    ),
-                                    );
-                                }
-                            }
-                        } else {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "terminal error ignored: session={} window missing label={}",
-                                    error.session_id, label
-                                ),
-                            );
-                        }
+                        app.state::<SmokeWaiters>()
+                            .fail(&error.session_id, &error.message);
+                        sink.log(&format!("error {}: {}", error.session_id, error.message));
+                        sink.session_error(&error.session_id, &error.message, error.recoverable);
                     }
                     _ => {}
                 }
             }
 
-            // Flush due output buffers (size or time).
-            let now = Instant::now();
-            let mut flush_keys: Vec<(String, String)> = Vec::new();
-            for (key, entry) in pending.iter() {
-                if entry.bytes >= flush_bytes || now.duration_since(entry.queued_at) >= flush_delay
-                {
-                    flush_keys.push(key.clone());
-                }
-            }
-
-            for (session_id, stream) in flush_keys {
-                let Some(entry) = pending.remove(&(session_id.clone(), stream.clone())) else {
-                    continue;
-                };
-                if entry.chunks.is_empty() {
-                    continue;
-                }
-                let chunk = if entry.chunks.len() == 1 {
-                    entry.chunks.into_iter().next().unwrap_or_default()
-                } else {
-                    entry.chunks.concat()
-                };
-
-                let label = {
-                    let state = app.state::<TerminalSessionState>();
-                    let guard = state.labels.lock().ok();
-                    guard
-                        .and_then(|map| map.get(&session_id).cloned())
-                        .unwrap_or_default()
-                };
-
-                if !label.is_empty() {
-                    if let Some(window) = app.get_webview_window(&label) {
-                        let payload = TerminalOutputPayload {
-                            session_id: session_id.clone(),
-                            chunk: chunk.clone(),
-                            stream: stream.clone(),
-                        };
-                        let _ = window.emit("terminal-output", payload);
-                    }
-                }
-
-                if enable_broadcast {
-                    let broadcast_payload = TerminalOutputPayload {
-                        session_id,
-                        chunk,
-                        stream,
-                    };
-                    let _ = app.emit("terminal-output-broadcast", broadcast_payload);
-                }
+            for output in coalescer.drain_due(Instant::now()) {
+                sink.session_output(&output.session_id, &output.stream, &output.chunk);
             }
         }
     });
@@ -5577,198 +4530,8 @@ fn start_terminal_worker_reader<R: Runtime>(
     rx: std::sync::mpsc::Receiver<Message>,
 ) {
     thread::spawn(move || {
-        use std::collections::HashMap;
-
-        #[derive(Debug)]
-        struct PendingOutput {
-            queued_at: Instant,
-            bytes: usize,
-            chunks: Vec<String>,
-        }
-
-        // Terminal output coalescing defaults / 郢ｧ・ｿ郢晢ｽｼ郢晄ｺ倥Μ郢晢ｽｫ陷・ｽｺ陷牙ｸ帷ｲ玖抄阮吶・隴鯉ｽ｢陞ｳ螢ｼﾂ・､
-        const OUTPUT_BUSY_THRESHOLD_BYTES: usize = 256 * 1024;
-        const OUTPUT_FLUSH_DELAY_NORMAL: Duration = Duration::from_millis(16);
-        const OUTPUT_FLUSH_BYTES_NORMAL: usize = 64 * 1024;
-        const OUTPUT_FLUSH_DELAY_BUSY: Duration = Duration::from_millis(32);
-        const OUTPUT_FLUSH_BYTES_BUSY: usize = 128 * 1024;
-
-        let debug_io = std::env::var_os("NAGOMI_DEBUG_WORKER_IO").is_some();
-        let enable_broadcast =
-            std::env::var_os("NAGOMI_ENABLE_TERMINAL_OUTPUT_BROADCAST").is_some();
-        let mut pending: HashMap<(String, String), PendingOutput> = HashMap::new();
-
-        loop {
-            let total_pending_bytes: usize = pending.values().map(|entry| entry.bytes).sum();
-            let (flush_delay, flush_bytes) = if total_pending_bytes > OUTPUT_BUSY_THRESHOLD_BYTES {
-                (OUTPUT_FLUSH_DELAY_BUSY, OUTPUT_FLUSH_BYTES_BUSY)
-            } else {
-                (OUTPUT_FLUSH_DELAY_NORMAL, OUTPUT_FLUSH_BYTES_NORMAL)
-            };
-
-            // Flush deadline drives recv timeout so we can flush ~16ms even when output is sparse.
-            let now = Instant::now();
-            let mut next_deadline: Option<Instant> = None;
-            for entry in pending.values() {
-                let deadline = entry.queued_at + flush_delay;
-                next_deadline = Some(next_deadline.map_or(deadline, |prev| prev.min(deadline)));
-            }
-            let timeout = next_deadline
-                .map(|deadline| deadline.saturating_duration_since(now))
-                .unwrap_or(Duration::from_millis(200))
-                .min(Duration::from_millis(200));
-
-            let message = rx.recv_timeout(timeout).ok();
-            if let Some(message) = message {
-                match message {
-                    Message::Output(output) => {
-                        let session_id = output.session_id;
-                        let stream = output.stream;
-                        let chunk = output.chunk;
-
-                        if debug_io {
-                            let _ = log_worker_event(
-                                &app,
-                                &format!(
-                                    "terminal output received: session={session_id} stream={stream} size={}",
-                                    chunk.len()
-                                ),
-                            );
-                        }
-
-                        let key = (session_id.clone(), stream.clone());
-                        let entry = pending.entry(key).or_insert_with(|| PendingOutput {
-                            queued_at: Instant::now(),
-                            bytes: 0,
-                            chunks: Vec::new(),
-                        });
-                        entry.bytes += chunk.len();
-                        entry.chunks.push(chunk);
-                    }
-                    Message::Exit(exit) => {
-                        let _ = log_worker_event(
-                            &app,
-                            &format!("terminal exit {}: {}", exit.session_id, exit.exit_code),
-                        );
-                        println!(
-                            "[terminal-worker] exit {}: {}",
-                            exit.session_id, exit.exit_code
-                        );
-
-                        let label = {
-                            let state = app.state::<TerminalSessionState>();
-                            let guard = state.labels.lock().ok();
-                            guard
-                                .and_then(|map| map.get(&exit.session_id).cloned())
-                                .unwrap_or_default()
-                        };
-                        if !label.is_empty() {
-                            if let Some(window) = app.get_webview_window(&label) {
-                                let payload = TerminalExitPayload {
-                                    session_id: exit.session_id.clone(),
-                                    exit_code: exit.exit_code,
-                                };
-                                let _ = window.emit("terminal-exit", payload);
-                            }
-                        }
-
-                        if let Ok(mut active) = app.state::<TerminalSessionState>().active.lock() {
-                            active.remove(&exit.session_id);
-                        }
-                        if let Ok(mut captures) =
-                            app.state::<TerminalBuiltinCommandState>().captures.lock()
-                        {
-                            captures.remove(&exit.session_id);
-                        }
-                        if let Ok(mut guard) = app.state::<TerminalWorkerState>().processes.lock() {
-                            if let Some(mut process) = guard.remove(&exit.session_id) {
-                                let _ = process.stop();
-                            }
-                        }
-                        close_character_windows_if_all_terminals_closed(&app);
-                    }
-                    Message::Error(error) => {
-                        let _ = log_worker_event(
-                            &app,
-                            &format!("terminal error {}: {}", error.session_id, error.message),
-                        );
-                        println!(
-                            "[terminal-worker] error {}: {}",
-                            error.session_id, error.message
-                        );
-
-                        let label = {
-                            let state = app.state::<TerminalSessionState>();
-                            let guard = state.labels.lock().ok();
-                            guard
-                                .and_then(|map| map.get(&error.session_id).cloned())
-                                .unwrap_or_default()
-                        };
-                        if !label.is_empty() {
-                            if let Some(window) = app.get_webview_window(&label) {
-                                let payload = TerminalErrorPayload {
-                                    session_id: error.session_id.clone(),
-                                    message: error.message,
-                                };
-                                let _ = window.emit("terminal-error", payload);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-
-            // Flush due output buffers (size or time).
-            let now = Instant::now();
-            let mut flush_keys: Vec<(String, String)> = Vec::new();
-            for (key, entry) in pending.iter() {
-                if entry.bytes >= flush_bytes || now.duration_since(entry.queued_at) >= flush_delay
-                {
-                    flush_keys.push(key.clone());
-                }
-            }
-
-            for (session_id, stream) in flush_keys {
-                let Some(entry) = pending.remove(&(session_id.clone(), stream.clone())) else {
-                    continue;
-                };
-                if entry.chunks.is_empty() {
-                    continue;
-                }
-                let chunk = if entry.chunks.len() == 1 {
-                    entry.chunks.into_iter().next().unwrap_or_default()
-                } else {
-                    entry.chunks.concat()
-                };
-
-                let label = {
-                    let state = app.state::<TerminalSessionState>();
-                    let guard = state.labels.lock().ok();
-                    guard
-                        .and_then(|map| map.get(&session_id).cloned())
-                        .unwrap_or_default()
-                };
-                if !label.is_empty() {
-                    if let Some(window) = app.get_webview_window(&label) {
-                        let payload = TerminalOutputPayload {
-                            session_id: session_id.clone(),
-                            chunk: chunk.clone(),
-                            stream: stream.clone(),
-                        };
-                        let _ = window.emit("terminal-output", payload);
-                    }
-                }
-
-                if enable_broadcast {
-                    let broadcast_payload = TerminalOutputPayload {
-                        session_id,
-                        chunk,
-                        stream,
-                    };
-                    let _ = app.emit("terminal-output-broadcast", broadcast_payload);
-                }
-            }
-        }
+        let sink = TauriSink::new(&app);
+        app.state::<SessionRegistry>().run_reader(&rx, &sink);
     });
 }
 
@@ -5803,6 +4566,7 @@ fn start_worker_session<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
         env: None,
         cols: 120,
         rows: 30,
+        ..Default::default()
     })?;
 
     let session = app.state::<SessionState>();
@@ -5857,6 +4621,7 @@ fn stop_worker_session<R: Runtime>(app: &AppHandle<R>) -> Result<()> {
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .invoke_handler(tauri::generate_handler![
             load_settings,
             save_settings,
@@ -5876,7 +4641,10 @@ fn main() {
             toggle_character_debug_watcher,
             emit_character_motion_debug,
             report_terminal_observation,
+            focus_terminal_by_state,
             ensure_codex_hook,
+            ensure_claude_hook,
+            ensure_opencode_hook,
             subworker_codex_session_started,
             subworker_llm_decide,
             open_terminal_window,
@@ -5886,6 +4654,9 @@ fn main() {
             open_terminal_window_same_position_for_session,
             open_settings_window,
             arrange_terminal_windows,
+            save_workspace,
+            open_workspace,
+            list_workspaces,
             pickup_terminal_window,
             pickup_terminal_window_by_index,
             focus_next_terminal_window,
@@ -5914,22 +4685,20 @@ fn main() {
             handle.manage(SelectionState {
                 current: Mutex::new(None),
             });
-            handle.manage(TerminalAggregateState::default());
+            handle.manage(ObservedStates::default());
+            handle.manage(WatcherAggregateGroup::default());
+            handle.manage(TerminalStateShortcuts::default());
+            handle.manage(TrayMenuState::default());
+            handle.manage(control::EventBus::default());
             handle.manage(TerminalWindowLayoutState::default());
             handle.manage(CharacterDebugWindowControlState::default());
             handle.manage(TerminalSessionState {
-                active: Mutex::new(HashSet::new()),
                 labels: Mutex::new(HashMap::new()),
             });
-            handle.manage(TerminalBuiltinCommandState::default());
+            handle.manage(SessionRegistry::default());
             let (terminal_tx, terminal_rx) = std::sync::mpsc::channel::<Message>();
             handle.manage(TerminalWorkerBus { tx: terminal_tx });
-            handle.manage(TerminalWorkerState {
-                processes: Mutex::new(HashMap::new()),
-            });
-            handle.manage(TerminalSmokeState {
-                waiters: Mutex::new(HashMap::new()),
-            });
+            handle.manage(SmokeWaiters::default());
             handle.manage(OrchestratorRuntimeFlags {
                 exit_on_last_terminal: should_exit_on_last_terminal(),
             });
@@ -5950,8 +4719,11 @@ fn main() {
             let path = settings_path(handle);
             let settings = read_settings(&path)?;
             write_settings(&path, &settings)?;
-            apply_completion_hook_tool(&handle, Some(settings.llm_tool.as_str()));
+            apply_completion_hook_sources(&handle, &settings);
+            apply_aggregate_settings(handle, &settings);
             sync_watcher_window(&handle, &settings);
+            register_terminal_state_shortcuts(handle, &settings);
+            apply_tray_settings(handle, &settings);
 
             build_tray(handle)?;
             Ok(())
@@ -5964,100 +4736,16 @@ fn main() {
 mod tests {
     use super::*;
     use serde_json::Value;
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    fn temp_settings_path(name: &str) -> PathBuf {
-        let mut path = std::env::temp_dir();
-        let nonce = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("time")
-            .as_nanos();
-        path.push(format!("nagomi-{name}-{nonce}.json"));
-        path
-    }
-
-    #[test]
-    fn settings_roundtrip() {
-        let path = temp_settings_path("roundtrip");
-        let settings = Settings {
-            notifications_enabled: false,
-            audio_enabled: true,
-            volume: 0.5,
-            llm_tool: "codex".to_string(),
-            subworker_enabled: true,
-            subworker_debug_enabled: false,
-            subworker_mode: "careful".to_string(),
-            subworker_confidence_threshold: 0.82,
-            subworker_prompt_template_markdown: "### prompt\n{{last_terminal_output}}\n".to_string(),
-            status_debug_enabled: false,
-            character_id: "test".to_string(),
-            character_renderer: "3d".to_string(),
-            character_3d_vrm_path: "C:/tmp/test.vrm".to_string(),
-            character_3d_scale: 1.2,
-            character_3d_yaw_deg: -15.0,
-            character_motion_default_paths: HashMap::from([
-                ("neutral".to_string(), "C:/tmp/motions/neutral.vrma".to_string()),
-                ("waiting".to_string(), "C:/tmp/motions/waiting.vrma".to_string()),
-            ]),
-            log_retention_lines: 100,
-            terminal_watcher_enabled: true,
-            terminal_font_family:
-                "ui-monospace, 'Cascadia Mono', Consolas, 'SFMono-Regular', Menlo, Monaco, 'Liberation Mono', 'DejaVu Sans Mono', monospace"
-                    .to_string(),
-            terminal_font_size: 14,
-            terminal_theme: "light".to_string(),
-            terminal_theme_palette: "light-sand".to_string(),
-            terminal_scrollback_lines: 5000,
-            terminal_copy_on_select: false,
-            terminal_internal_commands_enabled: true,
-            terminal_shell_kind: TERMINAL_SHELL_WSL.to_string(),
-            terminal_wsl_distro: "Ubuntu".to_string(),
-            terminal_keybind_arrange: "Ctrl+Shift+Y".to_string(),
-            terminal_keybind_focus_next: "Ctrl+Shift+J".to_string(),
-            terminal_keybind_focus_prev: "Ctrl+Shift+K".to_string(),
-        };
-
-        write_settings(&path, &settings).expect("write settings");
-        let loaded = read_settings(&path).expect("read settings");
-        assert_eq!(loaded, settings);
-
-        let _ = fs::remove_file(&path);
-    }
-
-    #[test]
-    fn settings_default_when_missing() {
-        let path = temp_settings_path("missing");
-        let loaded = read_settings(&path).expect("read settings");
-        assert_eq!(loaded, Settings::default());
-    }
 
     #[test]
-    fn settings_default_when_empty_file() {
-        let path = temp_settings_path("empty");
-        fs::write(&path, "").expect("write empty settings");
-        let loaded = read_settings(&path).expect("read settings");
-        assert_eq!(loaded, Settings::default());
-        let _ = fs::remove_file(&path);
-    }
-
-    #[test]
-    fn theme_palette_normalization_supports_monochrome() {
-        assert_eq!(
-            normalize_terminal_theme_palette("light", "light-mono"),
-            "light-mono"
-        );
-        assert_eq!(
-            normalize_terminal_theme_palette("dark", "dark-mono"),
-            "dark-mono"
-        );
-        assert_eq!(
-            normalize_terminal_theme_palette("light", "dark-mono"),
-            "light-sand"
-        );
+    fn global_shortcut_accelerator_maps_primary_modifier() {
         assert_eq!(
-            normalize_terminal_theme_palette("dark", "light-mono"),
-            "dark-ink"
+            global_shortcut_accelerator("Ctrl+Shift+N").as_deref(),
+            Some("CommandOrControl+Shift+N")
         );
+        assert_eq!(global_shortcut_accelerator("Alt+E").as_deref(), Some("Alt+E"));
+        assert_eq!(global_shortcut_accelerator(""), None);
+        assert_eq!(global_shortcut_accelerator("N"), None);
     }
 
     #[test]
@@ -6076,277 +4764,6 @@ mod tests {
         assert!(should_reuse_cached_layout(true, 4, 4, true));
     }
 
-    #[test]
-    fn parse_terminal_builtin_command_supports_ng_ping() {
-        assert_eq!(
-            parse_terminal_builtin_command(":ng ping"),
-            Some(TerminalBuiltinCommand::Ping)
-        );
-        assert_eq!(
-            parse_terminal_builtin_command("  :ng ping  "),
-            Some(TerminalBuiltinCommand::Ping)
-        );
-        assert_eq!(
-            parse_terminal_builtin_command(":ng"),
-            Some(TerminalBuiltinCommand::Usage)
-        );
-        assert_eq!(
-            parse_terminal_builtin_command(":ng unknown arg"),
-            Some(TerminalBuiltinCommand::Unknown("unknown arg".to_string()))
-        );
-        assert_eq!(
-            parse_terminal_builtin_command(":ngping"),
-            Some(TerminalBuiltinCommand::Unknown("ping".to_string()))
-        );
-        assert_eq!(parse_terminal_builtin_command("echo ok"), None);
-    }
-
-    #[test]
-    fn parse_terminal_builtin_command_ignores_ansi_prefix() {
-        let with_csi = "\u{001b}[200~:ng ping";
-        assert_eq!(
-            parse_terminal_builtin_command(with_csi),
-            Some(TerminalBuiltinCommand::Ping)
-        );
-        let with_osc = "\u{001b}]0;title\u{0007}:ng ping";
-        assert_eq!(
-            parse_terminal_builtin_command(with_osc),
-            Some(TerminalBuiltinCommand::Ping)
-        );
-    }
-
-    #[test]
-    fn process_terminal_input_chunk_intercepts_internal_command() {
-        let mut capture = TerminalInputCaptureState::default();
-        let (forward, invocations, local_echo) =
-            process_terminal_input_chunk(&mut capture, ":ng ping\r\n");
-        assert_eq!(forward, "");
-        assert_eq!(
-            invocations,
-            vec![TerminalBuiltinInvocation {
-                command: TerminalBuiltinCommand::Ping,
-                line: ":ng ping".to_string(),
-            }]
-        );
-        assert_eq!(local_echo, ":ng ping\r\n");
-
-        let mut capture = TerminalInputCaptureState::default();
-        let (forward, invocations, local_echo) =
-            process_terminal_input_chunk(&mut capture, "echo ok\r\n");
-        assert_eq!(forward, "echo ok\r\n");
-        assert!(invocations.is_empty());
-        assert_eq!(local_echo, "");
-    }
-
-    #[test]
-    fn process_terminal_input_chunk_supports_chunked_internal_command() {
-        let mut capture = TerminalInputCaptureState::default();
-        let (forward1, invocations1, local_echo1) =
-            process_terminal_input_chunk(&mut capture, ":ng ");
-        let (forward2, invocations2, local_echo2) =
-            process_terminal_input_chunk(&mut capture, "ping\r");
-        let (forward3, invocations3, local_echo3) =
-            process_terminal_input_chunk(&mut capture, "\n");
-        assert_eq!(forward1, "");
-        assert!(invocations1.is_empty());
-        assert_eq!(local_echo1, ":ng ");
-        assert_eq!(forward2, "");
-        assert_eq!(
-            invocations2,
-            vec![TerminalBuiltinInvocation {
-                command: TerminalBuiltinCommand::Ping,
-                line: ":ng ping".to_string(),
-            }]
-        );
-        assert_eq!(local_echo2, "ping\r\n");
-        assert_eq!(forward3, "");
-        assert!(invocations3.is_empty());
-        assert_eq!(local_echo3, "");
-    }
-
-    #[test]
-    fn process_terminal_input_chunk_intercepts_internal_with_ansi_wrapper() {
-        let mut capture = TerminalInputCaptureState::default();
-        let (forward1, invocations1, local_echo1) =
-            process_terminal_input_chunk(&mut capture, "\u{001b}[200~:ng ");
-        let (forward2, invocations2, local_echo2) =
-            process_terminal_input_chunk(&mut capture, "ping\u{001b}[201~\r\n");
-        assert_eq!(forward1, "");
-        assert!(invocations1.is_empty());
-        assert_eq!(local_echo1, ":ng ");
-        assert_eq!(forward2, "");
-        assert_eq!(
-            invocations2,
-            vec![TerminalBuiltinInvocation {
-                command: TerminalBuiltinCommand::Ping,
-                line: ":ng ping".to_string(),
-            }]
-        );
-        assert_eq!(local_echo2, "ping\r\n");
-    }
-
-    #[test]
-    fn parse_codex_exec_jsonl_stdout_extracts_thread_and_latest_agent_message() {
-        let stdout = r#"{"type":"session.configured"}
-{"type":"thread.started","thread_id":"thread_abc123"}
-{"type":"item.completed","item":{"type":"tool_call","text":"ignored"}}
-{"type":"item.completed","item":{"type":"agent_message","text":"first message"}}
-{"type":"item.completed","item":{"type":"agent_message","text":"final message"}}
-"#;
-        let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(stdout);
-        assert_eq!(thread_id.as_deref(), Some("thread_abc123"));
-        assert_eq!(last_agent_message.as_deref(), Some("final message"));
-    }
-
-    #[test]
-    fn parse_subworker_llm_output_relaxed_extracts_embedded_json_object() {
-        let raw = r#"debug prefix: start
-{"action":"show_advice","confidence":0.77,"input":"","advice_markdown":"- try this","reason":"need clarification"}
-debug suffix: end"#;
-        let parsed = parse_subworker_llm_output_relaxed(raw).expect("parsed decision");
-        assert_eq!(parsed.action, "show_advice");
-        assert!((parsed.confidence - 0.77).abs() < f32::EPSILON);
-        assert_eq!(parsed.input, "");
-        assert_eq!(parsed.advice_markdown, "- try this");
-        assert_eq!(parsed.reason, "need clarification");
-    }
-
-    #[test]
-    fn parse_subworker_llm_output_relaxed_returns_none_for_invalid_payload() {
-        let raw = "this is not a json response";
-        assert!(parse_subworker_llm_output_relaxed(raw).is_none());
-    }
-
-    #[test]
-    fn build_project_prompt_history_record_extracts_hook_io_by_project() {
-        let event = HookEvent {
-            source: "codex".to_string(),
-            kind: HookEventKind::Completed,
-            ts_ms: 123,
-            source_session_id: Some("ipc-1".to_string()),
-            raw: Some(serde_json::json!({
-                "source": "codex",
-                "cwd": "C:/Users/kitad/workspace/yurutsuku",
-                "event": {
-                    "type": "agent-turn-complete",
-                    "thread-id": "thread_123",
-                    "turn-id": "turn_456",
-                    "input-messages": ["Fix the failing test."],
-                    "last-assistant-message": "Patched and verified."
-                }
-            })),
-        };
-
-        let record = build_project_prompt_history_record(
-            &event,
-            "success",
-            "Patched and verified.",
-        )
-        .expect("record");
-        let payload = record.payload.as_object().expect("payload object");
-
-        assert!(record.project_key.starts_with("yurutsuku-"));
-        assert_eq!(
-            payload.get("cwd").and_then(|value| value.as_str()),
-            Some("C:/Users/kitad/workspace/yurutsuku")
-        );
-        assert_eq!(
-            payload
-                .get("input_messages")
-                .and_then(|value| value.as_array())
-                .and_then(|items| items.first())
-                .and_then(|value| value.as_str()),
-            Some("Fix the failing test.")
-        );
-        assert_eq!(
-            payload
-                .get("last_assistant_message")
-                .and_then(|value| value.as_str()),
-            Some("Patched and verified.")
-        );
-        assert_eq!(
-            payload.get("thread_id").and_then(|value| value.as_str()),
-            Some("thread_123")
-        );
-        assert_eq!(
-            payload.get("turn_id").and_then(|value| value.as_str()),
-            Some("turn_456")
-        );
-    }
-
-    #[test]
-    fn project_prompt_history_key_is_stable_for_same_locator() {
-        let left = project_prompt_history_key(
-            "C:/Users/kitad/workspace/yurutsuku",
-            "yurutsuku",
-        );
-        let right = project_prompt_history_key(
-            "C:/Users/kitad/workspace/yurutsuku/",
-            "yurutsuku",
-        );
-        let other = project_prompt_history_key(
-            "C:/Users/kitad/workspace/another-project",
-            "another-project",
-        );
-
-        assert_eq!(
-            left,
-            project_prompt_history_key("C:/Users/kitad/workspace/yurutsuku", "yurutsuku")
-        );
-        assert_eq!(left, right);
-        assert_ne!(left, other);
-        assert_eq!(
-            normalize_project_locator("C:/Users/kitad/workspace/yurutsuku/"),
-            "C:/Users/kitad/workspace/yurutsuku"
-        );
-    }
-
-    #[test]
-    fn rewrite_codex_notify_config_keeps_notify_at_top_level() {
-        let current = r#"model = "gpt-5.4"
-[features]
-
-[notice.model_migrations]
-"gpt-5.2" = "gpt-5.3-codex"
-# added by nagomi
-notify = "nagomi-codex-notify"
-"#;
-        let next = rewrite_codex_notify_config_text(
-            current,
-            r#"notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]"#,
-        );
-        let expected = r#"model = "gpt-5.4"
-
-# added by nagomi
-notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]
-
-[features]
-
-[notice.model_migrations]
-"gpt-5.2" = "gpt-5.3-codex""#;
-        assert_eq!(next, expected);
-    }
-
-    #[test]
-    fn rewrite_codex_notify_config_replaces_existing_top_level_notify() {
-        let current = r#"model = "gpt-5.4"
-# added by nagomi
-notify = ["nagomi-codex-notify"]
-
-[features]"#;
-        let next = rewrite_codex_notify_config_text(
-            current,
-            r#"notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]"#,
-        );
-        let expected = r#"model = "gpt-5.4"
-
-# added by nagomi
-notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]
-
-[features]"#;
-        assert_eq!(next, expected);
-    }
-
     #[test]
     fn tauri_config_windows() {
         let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tauri.conf.json");
@@ -6390,46 +4807,5 @@ notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]
         assert_eq!(tray.id().as_ref(), "main");
     }
 
-    #[cfg(windows)]
-    #[test]
-    fn windows_terminal_command_matches_shell_kind() {
-        let mut settings = Settings::default();
-
-        settings.terminal_shell_kind = TERMINAL_SHELL_CMD.to_string();
-        settings.terminal_wsl_distro = String::new();
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, false),
-            "cmd.exe"
-        );
-
-        settings.terminal_shell_kind = TERMINAL_SHELL_POWERSHELL.to_string();
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, false),
-            "powershell.exe"
-        );
-
-        settings.terminal_shell_kind = TERMINAL_SHELL_POWERSHELL7.to_string();
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, false),
-            "powershell.exe"
-        );
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, true),
-            "pwsh.exe"
-        );
-
-        settings.terminal_shell_kind = TERMINAL_SHELL_WSL.to_string();
-        settings.terminal_wsl_distro = String::new();
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, false),
-            "wsl.exe"
-        );
-
-        settings.terminal_wsl_distro = "Ubuntu".to_string();
-        assert_eq!(
-            build_windows_terminal_command_with_pwsh(&settings, false),
-            "wsl.exe -d \"Ubuntu\""
-        );
-    }
 }
 
@@ -0,0 +1,1128 @@
+use crate::hook_tail::JsonlTail;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEventKind {
+    Completed,
+    NeedInput,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HookEvent {
+    pub source: String,
+    pub kind: HookEventKind,
+    pub ts_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Value>,
+}
+
+pub type HookCallback = Arc<dyn Fn(HookEvent) + Send + Sync + 'static>;
+
+pub trait CompletionHook: Send {
+    fn start(&mut self, on_event: HookCallback);
+    fn stop(&mut self);
+}
+
+pub struct CodexCompletionHook {
+    tail: JsonlTail,
+}
+
+impl CodexCompletionHook {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+        }
+    }
+}
+
+impl CompletionHook for CodexCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        self.tail.start(move |value| {
+            if let Some(event) = codex_hook_event(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+pub struct ClaudeCodeCompletionHook {
+    tail: JsonlTail,
+}
+
+impl ClaudeCodeCompletionHook {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+        }
+    }
+}
+
+impl CompletionHook for ClaudeCodeCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        self.tail.start(move |value| {
+            if let Some(event) = claude_hook_event(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+pub struct OpenCodeCompletionHook {
+    tail: JsonlTail,
+}
+
+impl OpenCodeCompletionHook {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+        }
+    }
+}
+
+impl CompletionHook for OpenCodeCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        self.tail.start(move |value| {
+            if let Some(event) = opencode_hook_event(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+pub struct GeminiCompletionHook {
+    tail: JsonlTail,
+}
+
+impl GeminiCompletionHook {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+        }
+    }
+}
+
+impl CompletionHook for GeminiCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        self.tail.start(move |value| {
+            if let Some(event) = gemini_hook_event(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+pub struct AiderCompletionHook {
+    tail: JsonlTail,
+}
+
+impl AiderCompletionHook {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+        }
+    }
+}
+
+impl CompletionHook for AiderCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        self.tail.start(move |value| {
+            if let Some(event) = aider_hook_event(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+// マッピングファイルで宣言された source / A source declared by a mapping file.
+pub struct MappedCompletionHook {
+    tail: JsonlTail,
+    mapping: Arc<HookSourceMapping>,
+}
+
+impl MappedCompletionHook {
+    pub fn new(path: PathBuf, mapping: Arc<HookSourceMapping>) -> Self {
+        Self {
+            tail: JsonlTail::new(path),
+            mapping,
+        }
+    }
+}
+
+impl CompletionHook for MappedCompletionHook {
+    fn start(&mut self, on_event: HookCallback) {
+        let mapping = self.mapping.clone();
+        self.tail.start(move |value| {
+            if let Some(event) = mapping.parse(value) {
+                on_event(event);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.tail.stop();
+    }
+}
+
+// jsonl の 1 行と同じ形の payload を event にする。file tail と POST /hooks/<source> で共有
+// Turns a payload shaped like one jsonl line into an event; shared by the file tail and
+// POST /hooks/<source>.
+pub fn parse_hook_event(source: &str, value: Value) -> Option<HookEvent> {
+    match normalize_hook_source(source)? {
+        "codex" => codex_hook_event(value),
+        "claude" => claude_hook_event(value),
+        "gemini" => gemini_hook_event(value),
+        "aider" => aider_hook_event(value),
+        _ => opencode_hook_event(value),
+    }
+}
+
+fn codex_hook_event(value: Value) -> Option<HookEvent> {
+    let (event, raw) = unwrap_event(value);
+    if !matches_source(&raw, "codex") {
+        return None;
+    }
+    let kind = codex_event_kind(&event)?;
+    let source_session_id = read_any_string_from_values(
+        &[&event, &raw],
+        &[
+            "source_session_id",
+            "sourceSessionId",
+            "nagomi_session_id",
+            "NAGOMI_SESSION_ID",
+        ],
+    )
+    .or_else(|| read_any_string(&event, &["thread-id", "thread_id", "threadId"]));
+    Some(HookEvent {
+        source: "codex".to_string(),
+        kind,
+        ts_ms: hook_ts_ms(&event, &raw),
+        source_session_id,
+        raw: Some(raw),
+    })
+}
+
+fn claude_hook_event(value: Value) -> Option<HookEvent> {
+    let (event, raw) = unwrap_event(value);
+    if !matches_source(&raw, "claude") {
+        return None;
+    }
+    let kind = match event.get("hook_event_name").and_then(|v| v.as_str()) {
+        Some("Stop") => HookEventKind::Completed,
+        Some("PermissionRequest") | Some("Notification") => HookEventKind::NeedInput,
+        _ => return None,
+    };
+    let source_session_id = read_any_string_from_values(
+        &[&event, &raw],
+        &[
+            "source_session_id",
+            "sourceSessionId",
+            "nagomi_session_id",
+            "NAGOMI_SESSION_ID",
+            "session_id",
+            "sessionId",
+        ],
+    );
+    Some(HookEvent {
+        source: "claude".to_string(),
+        kind,
+        ts_ms: hook_ts_ms(&event, &raw),
+        source_session_id,
+        raw: Some(raw),
+    })
+}
+
+fn opencode_hook_event(value: Value) -> Option<HookEvent> {
+    let (event, raw) = unwrap_event(value);
+    if !matches_source(&raw, "opencode") {
+        return None;
+    }
+    let kind = match event.get("type").and_then(|v| v.as_str()) {
+        Some("session.idle") => HookEventKind::Completed,
+        Some("session.error") => HookEventKind::Error,
+        Some("permission.updated") | Some("permission.replied") => HookEventKind::NeedInput,
+        _ => return None,
+    };
+    let source_session_id = read_any_string_from_values(
+        &[&event, &raw],
+        &[
+            "source_session_id",
+            "sourceSessionId",
+            "nagomi_session_id",
+            "NAGOMI_SESSION_ID",
+            "session_id",
+            "sessionId",
+        ],
+    );
+    Some(HookEvent {
+        source: "opencode".to_string(),
+        kind,
+        ts_ms: hook_ts_ms(&event, &raw),
+        source_session_id,
+        raw: Some(raw),
+    })
+}
+
+// Gemini CLI の hooks（stdin に JSON）/ Gemini CLI hooks, JSON on stdin.
+fn gemini_hook_event(value: Value) -> Option<HookEvent> {
+    let (event, raw) = unwrap_event(value);
+    if !matches_source(&raw, "gemini") {
+        return None;
+    }
+    let kind = match event.get("hook_event_name").and_then(|v| v.as_str()) {
+        Some("AfterAgent") => HookEventKind::Completed,
+        Some("Notification") => HookEventKind::NeedInput,
+        _ => return None,
+    };
+    let source_session_id = read_any_string_from_values(
+        &[&event, &raw],
+        &[
+            "source_session_id",
+            "sourceSessionId",
+            "nagomi_session_id",
+            "NAGOMI_SESSION_ID",
+            "session_id",
+            "sessionId",
+        ],
+    );
+    Some(HookEvent {
+        source: "gemini".to_string(),
+        kind,
+        ts_ms: hook_ts_ms(&event, &raw),
+        source_session_id,
+        raw: Some(raw),
+    })
+}
+
+// Aider の `--notifications-command` は応答を終えて入力を待つときに呼ばれる（payload は自前）
+// Aider runs `--notifications-command` when it finishes a reply and waits; the payload is ours.
+fn aider_hook_event(value: Value) -> Option<HookEvent> {
+    let (event, raw) = unwrap_event(value);
+    if !matches_source(&raw, "aider") {
+        return None;
+    }
+    let type_name = read_any_string(&event, &["type", "event"])
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let kind = if type_name.contains("error") || type_name.contains("fail") {
+        HookEventKind::Error
+    } else {
+        HookEventKind::Completed
+    };
+    let source_session_id = read_any_string_from_values(
+        &[&event, &raw],
+        &[
+            "source_session_id",
+            "sourceSessionId",
+            "nagomi_session_id",
+            "NAGOMI_SESSION_ID",
+        ],
+    );
+    Some(HookEvent {
+        source: "aider".to_string(),
+        kind,
+        ts_ms: hook_ts_ms(&event, &raw),
+        source_session_id,
+        raw: Some(raw),
+    })
+}
+
+// 宣言的な source: `<hooks>/sources/<name>.toml`（または `.json`）。name はファイル名
+// A declarative source in `<hooks>/sources/<name>.toml` (or `.json`); the file stem is its name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HookSourceMapping {
+    #[serde(skip)]
+    pub name: String,
+    // event 種別を持つ field（`.` 区切り）/ Dotted path to the field holding the event type.
+    pub event_field: String,
+    // agent 自身の session id（NAGOMI_SESSION_ID が無いとき）/ The agent's own id, used without NAGOMI_SESSION_ID.
+    pub session_field: Option<String>,
+    pub completed: Vec<String>,
+    pub need_input: Vec<String>,
+    pub error: Vec<String>,
+}
+
+impl Default for HookSourceMapping {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            event_field: "type".to_string(),
+            session_field: None,
+            completed: Vec::new(),
+            need_input: Vec::new(),
+            error: Vec::new(),
+        }
+    }
+}
+
+impl HookSourceMapping {
+    pub fn parse(&self, value: Value) -> Option<HookEvent> {
+        let (event, raw) = unwrap_event(value);
+        if !matches_source(&raw, &self.name) {
+            return None;
+        }
+        let type_name = read_path_string(&event, &self.event_field)
+            .or_else(|| read_path_string(&raw, &self.event_field))?;
+        let listed = |values: &[String]| {
+            values
+                .iter()
+                .any(|value| value.eq_ignore_ascii_case(&type_name))
+        };
+        let kind = if listed(&self.completed) {
+            HookEventKind::Completed
+        } else if listed(&self.need_input) {
+            HookEventKind::NeedInput
+        } else if listed(&self.error) {
+            HookEventKind::Error
+        } else {
+            return None;
+        };
+        let source_session_id = read_any_string_from_values(
+            &[&event, &raw],
+            &[
+                "source_session_id",
+                "sourceSessionId",
+                "nagomi_session_id",
+                "NAGOMI_SESSION_ID",
+            ],
+        )
+        .or_else(|| {
+            let path = self.session_field.as_deref()?;
+            read_path_string(&event, path).or_else(|| read_path_string(&raw, path))
+        });
+        Some(HookEvent {
+            source: self.name.clone(),
+            kind,
+            ts_ms: hook_ts_ms(&event, &raw),
+            source_session_id,
+            raw: Some(raw),
+        })
+    }
+}
+
+// `a.b.c` をたどる。文字列以外のスカラーは文字列にする / Walks `a.b.c`; other scalars are stringified.
+fn read_path_string(value: &Value, path: &str) -> Option<String> {
+    let found = path
+        .split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |current, key| current.get(key))?;
+    match found {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(found.to_string()),
+        _ => None,
+    }
+}
+
+pub fn hook_mappings_dir(base_dir: &Path) -> PathBuf {
+    base_dir.join("sources")
+}
+
+// ファイル名にそのまま使うので英小文字・数字・`-`・`_` だけ / Used as a file name, so [a-z0-9_-] only.
+fn is_mapping_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn read_hook_mapping(path: &Path, name: &str) -> Result<HookSourceMapping, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str::<HookSourceMapping>(&text).map_err(|err| err.to_string())
+    } else {
+        toml::from_str::<HookSourceMapping>(&text).map_err(|err| err.to_string())
+    };
+    let mut mapping = parsed.map_err(|err| format!("{}: {err}", path.display()))?;
+    if mapping.completed.is_empty() && mapping.need_input.is_empty() && mapping.error.is_empty() {
+        return Err(format!(
+            "{}: map at least one of completed / need_input / error",
+            path.display()
+        ));
+    }
+    mapping.name = name.to_string();
+    Ok(mapping)
+}
+
+// 組み込み source と同名のファイルは無視する / Files named after a built-in source are ignored.
+pub fn load_hook_mapping(base_dir: &Path, name: &str) -> Option<Result<HookSourceMapping, String>> {
+    if !is_mapping_name(name) || normalize_hook_source(name).is_some() {
+        return None;
+    }
+    let dir = hook_mappings_dir(base_dir);
+    ["toml", "json"]
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| path.is_file())
+        .map(|path| read_hook_mapping(&path, name))
+}
+
+// sources ディレクトリの全マッピング（壊れたファイルは Err）/ Every mapping in the sources dir; broken files are Err.
+pub fn load_hook_mappings(base_dir: &Path) -> Vec<Result<HookSourceMapping, String>> {
+    let Ok(entries) = std::fs::read_dir(hook_mappings_dir(base_dir)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let ext = path.extension()?.to_str()?;
+            if ext != "toml" && ext != "json" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+        .iter()
+        .filter_map(|name| load_hook_mapping(base_dir, name))
+        .collect()
+}
+
+// 組み込みの規則か、マッピングファイルの規則 / Either a built-in source or a mapped one.
+#[derive(Debug, Clone)]
+pub enum HookSource {
+    Builtin(&'static str),
+    Mapped(Arc<HookSourceMapping>),
+}
+
+impl HookSource {
+    pub fn name(&self) -> &str {
+        match self {
+            HookSource::Builtin(name) => name,
+            HookSource::Mapped(mapping) => &mapping.name,
+        }
+    }
+
+    pub fn parse(&self, value: Value) -> Option<HookEvent> {
+        match self {
+            HookSource::Builtin(name) => parse_hook_event(name, value),
+            HookSource::Mapped(mapping) => mapping.parse(value),
+        }
+    }
+}
+
+pub fn resolve_hook_source(raw: &str, base_dir: &Path) -> Option<HookSource> {
+    if let Some(name) = normalize_hook_source(raw) {
+        return Some(HookSource::Builtin(name));
+    }
+    let mapping = load_hook_mapping(base_dir, raw.trim())?.ok()?;
+    Some(HookSource::Mapped(Arc::new(mapping)))
+}
+
+pub fn normalize_hook_state(kind: HookEventKind) -> String {
+    match kind {
+        HookEventKind::Completed => "success".to_string(),
+        HookEventKind::NeedInput => "need_input".to_string(),
+        HookEventKind::Error => "failure".to_string(),
+    }
+}
+
+pub fn hook_kind_to_string(kind: HookEventKind) -> String {
+    match kind {
+        HookEventKind::Completed => "completed".to_string(),
+        HookEventKind::NeedInput => "need_input".to_string(),
+        HookEventKind::Error => "error".to_string(),
+    }
+}
+
+// 通知や履歴に使う 1〜2 行の要約 / One- or two-line summary for notifications and history.
+pub fn summarize_hook_event(event: &HookEvent) -> String {
+    let raw = match event.raw.as_ref() {
+        Some(raw) => raw,
+        None => return hook_kind_to_string(event.kind),
+    };
+    let text = extract_text_from_value(raw)
+        .or_else(|| raw.get("event").and_then(extract_text_from_value))
+        .unwrap_or_default();
+    let summary = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(2)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if summary.is_empty() {
+        hook_kind_to_string(event.kind)
+    } else {
+        summary
+    }
+}
+
+pub fn extract_text_from_value(value: &Value) -> Option<String> {
+    if let Some(text) = value.as_str() {
+        return Some(text.to_string());
+    }
+    let obj = value.as_object()?;
+    let keys = [
+        "last-assistant-message",
+        "last_assistant_message",
+        "message",
+        "summary",
+        "notification",
+    ];
+    for key in keys.iter() {
+        if let Some(text) = obj.get(*key).and_then(|val| val.as_str()) {
+            return Some(text.to_string());
+        }
+    }
+    None
+}
+
+// 組み込みの source 名 / Built-in source names.
+pub const HOOK_SOURCES: [&str; 5] = ["codex", "claude", "opencode", "gemini", "aider"];
+
+// profile の llm_tool 表記（claudecode など）も受ける / Also accepts profile llm_tool spellings.
+pub fn normalize_hook_source(raw: &str) -> Option<&'static str> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "codex" => Some("codex"),
+        "claude" | "claudecode" | "claude-code" => Some("claude"),
+        "opencode" => Some("opencode"),
+        "gemini" | "gemini-cli" => Some("gemini"),
+        "aider" => Some("aider"),
+        _ => None,
+    }
+}
+
+// 有効な source の hook を同時に動かす。どの terminal 宛てかは event の source_session_id で決まる
+// Runs the hooks of every enabled source at once; the event's source_session_id decides which
+// terminal it belongs to.
+// マッピングファイルのある source は置くだけで有効になる。起動後に置いたものは最初の POST か
+// 次の設定保存で動き出す
+// Mapped sources need no setting: a file added while running starts on its first POST or the
+// next settings save.
+pub struct CompletionHookManager {
+    active: BTreeMap<String, Box<dyn CompletionHook>>,
+    on_event: HookCallback,
+}
+
+impl CompletionHookManager {
+    pub fn new(on_event: HookCallback) -> Self {
+        Self {
+            active: BTreeMap::new(),
+            on_event,
+        }
+    }
+
+    // 一覧（とマッピングファイル）にない hook は止め、新しく入ったものだけ起動する
+    // Stops hooks missing from the list and the mapping files; starts only the new ones.
+    pub fn set_sources(&mut self, sources: &[&str], base_dir: &Path) {
+        let mut wanted: Vec<String> = sources
+            .iter()
+            .filter_map(|source| normalize_hook_source(source))
+            .map(str::to_string)
+            .collect();
+        wanted.extend(
+            load_hook_mappings(base_dir)
+                .into_iter()
+                .filter_map(Result::ok)
+                .map(|mapping| mapping.name),
+        );
+        let removed: Vec<String> = self
+            .active
+            .keys()
+            .filter(|source| !wanted.contains(source))
+            .cloned()
+            .collect();
+        for source in removed {
+            if let Some(mut hook) = self.active.remove(&source) {
+                hook.stop();
+            }
+        }
+        for source in &wanted {
+            self.enable(source, base_dir);
+        }
+    }
+
+    // 他の source はそのままで 1 つ足す / Adds one source, leaving the others running.
+    pub fn enable(&mut self, source: &str, base_dir: &Path) {
+        let Some(source) = resolve_hook_source(source, base_dir) else {
+            return;
+        };
+        if self.active.contains_key(source.name()) {
+            return;
+        }
+        let name = source.name().to_string();
+        let path = tool_hook_path(base_dir, &name);
+        let mut hook: Box<dyn CompletionHook> = match source {
+            HookSource::Builtin("codex") => Box::new(CodexCompletionHook::new(path)),
+            HookSource::Builtin("claude") => Box::new(ClaudeCodeCompletionHook::new(path)),
+            HookSource::Builtin("gemini") => Box::new(GeminiCompletionHook::new(path)),
+            HookSource::Builtin("aider") => Box::new(AiderCompletionHook::new(path)),
+            HookSource::Builtin(_) => Box::new(OpenCodeCompletionHook::new(path)),
+            HookSource::Mapped(mapping) => Box::new(MappedCompletionHook::new(path, mapping)),
+        };
+        hook.start(self.on_event.clone());
+        self.active.insert(name, hook);
+    }
+
+    // POST /hooks/<source> を受けてよいか。未起動のマッピングはここで起動する
+    // Whether POST /hooks/<source> is accepted; a mapping not started yet is started here.
+    pub fn accept(&mut self, source: &str, base_dir: &Path) -> bool {
+        if self.is_active(source) {
+            return true;
+        }
+        if normalize_hook_source(source).is_some() {
+            return false;
+        }
+        self.enable(source, base_dir);
+        self.is_active(source)
+    }
+
+    pub fn is_active(&self, source: &str) -> bool {
+        let name = normalize_hook_source(source).unwrap_or(source.trim());
+        self.active.contains_key(name)
+    }
+
+    pub fn active_sources(&self) -> Vec<String> {
+        self.active.keys().cloned().collect()
+    }
+
+    pub fn stop(&mut self) {
+        for (_, mut hook) in std::mem::take(&mut self.active) {
+            hook.stop();
+        }
+    }
+}
+
+pub fn hooks_base_dir() -> PathBuf {
+    if let Some(home) = std::env::var_os("USERPROFILE").or_else(|| std::env::var_os("HOME")) {
+        return PathBuf::from(home).join(".nagomi").join("hooks");
+    }
+    PathBuf::from(".nagomi").join("hooks")
+}
+
+pub fn tool_hook_path(base_dir: &Path, tool: &str) -> PathBuf {
+    base_dir.join(format!("{tool}.jsonl"))
+}
+
+// hook コマンドが受け取った文字列（argv か stdin）。aider の通知のように payload の無い hook は
+// 空の event になる。JSON でない文字列は完了と取り違えないよう拒む
+// The text a hook command received on argv or stdin. Payload-less hooks such as aider's
+// notification give an empty event; non-JSON text is rejected so it never reads as a completion.
+pub fn parse_hook_input(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_str(text).map_err(|err| format!("hook event is not JSON: {err}"))
+}
+
+// jsonl の 1 行 / POST /hooks/<source> の body になる形 / One jsonl line, also the POST body.
+pub fn hook_payload(
+    source: &str,
+    event: Value,
+    source_session_id: Option<&str>,
+    ts_ms: u64,
+) -> Value {
+    let mut payload = json!({
+        "source": source,
+        "event": event,
+        "ts_ms": ts_ms,
+    });
+    if let Some(session_id) = source_session_id.filter(|id| !id.trim().is_empty()) {
+        payload["source_session_id"] = json!(session_id);
+    }
+    payload
+}
+
+// Orchestrator が止まっているときの受け皿 / The fallback while the orchestrator is down.
+pub fn append_hook_payload(base_dir: &Path, source: &str, payload: &Value) -> std::io::Result<()> {
+    std::fs::create_dir_all(base_dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(tool_hook_path(base_dir, source))?;
+    writeln!(file, "{payload}")
+}
+
+fn unwrap_event(value: Value) -> (Value, Value) {
+    if let Some(event) = value.get("event").cloned() {
+        (event, value)
+    } else {
+        (value.clone(), value)
+    }
+}
+
+fn matches_source(raw: &Value, expected: &str) -> bool {
+    let source = raw.get("source").and_then(|value| value.as_str());
+    match source {
+        Some(found) => found == expected,
+        None => true,
+    }
+}
+
+fn read_any_string(event: &Value, keys: &[&str]) -> Option<String> {
+    for key in keys {
+        if let Some(value) = event.get(*key).and_then(|v| v.as_str()) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+// 前の key ほど優先（どの value にあっても）。agent 自身の session_id より nagomi の id を選ぶため
+// Earlier keys win whichever value holds them, so the nagomi id beats the agent's own session_id.
+fn read_any_string_from_values(values: &[&Value], keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        values
+            .iter()
+            .find_map(|value| read_any_string(value, std::slice::from_ref(key)))
+    })
+}
+
+fn codex_event_kind(event: &Value) -> Option<HookEventKind> {
+    let type_name = event
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let status = event
+        .get("status")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    if type_name == "agent-turn-complete"
+        || type_name == "turn.completed"
+        || status.contains("complete")
+    {
+        return Some(HookEventKind::Completed);
+    }
+    if type_name.contains("error") || type_name.contains("fail") || status.contains("error") {
+        return Some(HookEventKind::Error);
+    }
+    if type_name.contains("need-input")
+        || type_name.contains("input")
+        || type_name.contains("permission")
+        || type_name.contains("request")
+        || status.contains("waiting")
+    {
+        return Some(HookEventKind::NeedInput);
+    }
+    None
+}
+
+// hook 自身の時刻（emit が付ける `ts_ms`）。無ければ読んだ時刻
+// The hook's own time (the `ts_ms` emit adds); falls back to the time it was read.
+fn hook_ts_ms(event: &Value, raw: &Value) -> u64 {
+    [raw, event]
+        .iter()
+        .find_map(|value| {
+            ["ts_ms", "tsMs", "timestamp_ms"]
+                .iter()
+                .find_map(|key| value.get(*key).and_then(Value::as_u64))
+        })
+        .unwrap_or_else(now_ms)
+}
+
+pub fn now_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("open hook log");
+        file.write_all(text.as_bytes()).expect("append");
+    }
+
+    #[test]
+    fn emitted_payloads_parse_back_for_every_source() {
+        let codex = parse_hook_input(r#" {"type":"agent-turn-complete"} "#).expect("json input");
+        let payload = hook_payload("codex", codex, Some("term-a"), 7);
+        assert_eq!(payload["ts_ms"], 7);
+        let event = parse_hook_event("codex", payload).expect("codex event");
+        assert_eq!(event.kind, HookEventKind::Completed);
+        assert_eq!(event.source_session_id.as_deref(), Some("term-a"));
+
+        // nagomi の session id は agent 自身の session_id より優先
+        // The nagomi session id wins over the agent's own session_id.
+        let claude = json!({ "hook_event_name": "Stop", "session_id": "claude-123" });
+        let event = parse_hook_event("claude", hook_payload("claude", claude, Some("term-b"), 0))
+            .expect("claude event");
+        assert_eq!(event.source_session_id.as_deref(), Some("term-b"));
+
+        assert!(parse_hook_input("not json").is_err());
+        assert_eq!(parse_hook_input("  \n"), Ok(json!({})));
+        let blank = hook_payload("opencode", json!({}), Some(" "), 0);
+        assert!(blank.get("source_session_id").is_none());
+    }
+
+    #[test]
+    fn the_payload_ts_ms_wins_over_the_read_time() {
+        let turn = json!({ "type": "agent-turn-complete" });
+        let event =
+            parse_hook_event("codex", hook_payload("codex", turn, None, 7)).expect("codex event");
+        assert_eq!(event.ts_ms, 7);
+        // 外側の ts_ms を優先し、無ければ event 側の別名も見る
+        // The outer ts_ms comes first; the event's own aliases are used without it.
+        assert_eq!(hook_ts_ms(&json!({ "tsMs": 3 }), &json!({ "ts_ms": 5 })), 5);
+        assert_eq!(hook_ts_ms(&json!({ "timestamp_ms": 3 }), &json!({})), 3);
+        let before = now_ms();
+        assert!(hook_ts_ms(&json!({}), &json!({})) >= before);
+    }
+
+    #[test]
+    fn manager_runs_every_enabled_source_at_once() {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("nagomi-hook-manager-{nonce}"));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let mut manager = CompletionHookManager::new(Arc::new(move |event: HookEvent| {
+            if let Ok(tx) = tx.lock() {
+                let _ = tx.send(event);
+            }
+        }));
+        manager.set_sources(&["codex", "claude", "unknown"], &dir);
+        assert_eq!(manager.active_sources(), vec!["claude", "codex"]);
+
+        append(
+            &tool_hook_path(&dir, "codex"),
+            "{\"type\":\"agent-turn-complete\",\"NAGOMI_SESSION_ID\":\"term-a\"}\n",
+        );
+        append(
+            &tool_hook_path(&dir, "claude"),
+            "{\"hook_event_name\":\"Stop\",\"source_session_id\":\"term-b\"}\n",
+        );
+        let mut routed: Vec<(String, Option<String>)> = (0..2)
+            .map(|_| {
+                let event = rx.recv_timeout(Duration::from_secs(5)).expect("hook event");
+                (event.source, event.source_session_id)
+            })
+            .collect();
+        routed.sort();
+        assert_eq!(
+            routed,
+            vec![
+                ("claude".to_string(), Some("term-b".to_string())),
+                ("codex".to_string(), Some("term-a".to_string())),
+            ]
+        );
+
+        manager.set_sources(&["claude"], &dir);
+        assert_eq!(manager.active_sources(), vec!["claude"]);
+        manager.enable("claudecode", &dir);
+        manager.enable("opencode", &dir);
+        assert_eq!(manager.active_sources(), vec!["claude", "opencode"]);
+        manager.stop();
+        assert!(manager.active_sources().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn gemini_and_aider_events_map_to_kinds() {
+        let kind = |source: &str, value: Value| parse_hook_event(source, value).map(|e| e.kind);
+        assert_eq!(
+            kind(
+                "gemini-cli",
+                json!({ "hook_event_name": "AfterAgent", "session_id": "g1" })
+            ),
+            Some(HookEventKind::Completed)
+        );
+        assert_eq!(
+            kind(
+                "gemini",
+                json!({ "hook_event_name": "Notification", "notification_type": "ToolPermission" })
+            ),
+            Some(HookEventKind::NeedInput)
+        );
+        assert_eq!(
+            kind("gemini", json!({ "hook_event_name": "BeforeTool" })),
+            None
+        );
+        // aider の通知コマンドは payload を持たないので、届いた時点で完了扱い
+        // Aider's notification command carries no payload, so any event means completed.
+        assert_eq!(kind("aider", json!({})), Some(HookEventKind::Completed));
+        assert_eq!(
+            kind("aider", json!({ "type": "error" })),
+            Some(HookEventKind::Error)
+        );
+        assert_eq!(
+            kind("aider", json!({ "source": "codex", "event": {} })),
+            None
+        );
+    }
+
+    #[test]
+    fn mapping_files_declare_new_sources() {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("nagomi-hook-mapping-{nonce}"));
+        let sources = hook_mappings_dir(&dir);
+        std::fs::create_dir_all(&sources).expect("create sources dir");
+        std::fs::write(
+            sources.join("mycli.toml"),
+            "event_field = \"status\"\nsession_field = \"meta.sid\"\ncompleted = [\"finished\"]\nneed_input = [\"Waiting\"]\nerror = [\"crashed\"]\n",
+        )
+        .expect("write toml mapping");
+        std::fs::write(
+            sources.join("jsoncli.json"),
+            r#"{"event_field":"kind","completed":["done"]}"#,
+        )
+        .expect("write json mapping");
+        std::fs::write(sources.join("broken.toml"), "completed = [\"x\"\n").expect("write broken");
+        std::fs::write(sources.join("empty.toml"), "event_field = \"type\"\n")
+            .expect("write empty");
+        std::fs::write(sources.join("codex.toml"), "completed = [\"x\"]\n").expect("write shadow");
+
+        let loaded = load_hook_mappings(&dir);
+        let names: Vec<Result<String, ()>> = loaded
+            .iter()
+            .map(|mapping| mapping.as_ref().map(|m| m.name.clone()).map_err(|_| ()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                Err(()),
+                Err(()),
+                Ok("jsoncli".to_string()),
+                Ok("mycli".to_string())
+            ]
+        );
+
+        let source = resolve_hook_source("mycli", &dir).expect("mapped source");
+        let event = source
+            .parse(json!({ "status": "FINISHED", "meta": { "sid": "own-1" } }))
+            .expect("completed");
+        assert_eq!(event.source, "mycli");
+        assert_eq!(event.kind, HookEventKind::Completed);
+        assert_eq!(event.source_session_id.as_deref(), Some("own-1"));
+        // emit が包んだ形では nagomi の session id が優先
+        // In the shape emit writes, the nagomi session id wins.
+        let wrapped = hook_payload(
+            "mycli",
+            json!({ "status": "waiting", "meta": { "sid": "own-1" } }),
+            Some("term-a"),
+            1,
+        );
+        let event = source.parse(wrapped).expect("need input");
+        assert_eq!(event.kind, HookEventKind::NeedInput);
+        assert_eq!(event.source_session_id.as_deref(), Some("term-a"));
+        assert!(source.parse(json!({ "status": "thinking" })).is_none());
+        assert!(resolve_hook_source("broken", &dir).is_none());
+        assert!(resolve_hook_source("../mycli", &dir).is_none());
+
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let mut manager = CompletionHookManager::new(Arc::new(move |event: HookEvent| {
+            if let Ok(tx) = tx.lock() {
+                let _ = tx.send(event);
+            }
+        }));
+        manager.set_sources(&["codex"], &dir);
+        assert_eq!(manager.active_sources(), vec!["codex", "jsoncli", "mycli"]);
+        assert!(manager.is_active("jsoncli"));
+        append(&tool_hook_path(&dir, "jsoncli"), "{\"kind\":\"done\"}\n");
+        let event = rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("mapped event");
+        assert_eq!(
+            (event.source.as_str(), event.kind),
+            ("jsoncli", HookEventKind::Completed)
+        );
+
+        // 起動後に置いたマッピングは最初の受け付けで動き出す / A mapping added later starts when first accepted.
+        std::fs::write(sources.join("latecli.toml"), "completed = [\"done\"]\n")
+            .expect("write late mapping");
+        assert!(!manager.is_active("latecli"));
+        assert!(manager.accept("latecli", &dir));
+        assert!(manager.is_active("latecli"));
+        assert!(!manager.accept("claude", &dir));
+        assert!(!manager.accept("nothing", &dir));
+        manager.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn codex_event_kind_completed_by_type() {
+        let event = json!({ "type": "agent-turn-complete" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Completed));
+        let event = json!({ "type": "turn.completed" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Completed));
+    }
+
+    #[test]
+    fn codex_event_kind_completed_by_status() {
+        let event = json!({ "status": "complete" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Completed));
+    }
+
+    #[test]
+    fn codex_event_kind_error() {
+        let event = json!({ "type": "agent-error" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Error));
+        let event = json!({ "type": "agent-failed" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Error));
+        let event = json!({ "status": "error" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::Error));
+    }
+
+    #[test]
+    fn codex_event_kind_need_input() {
+        let event = json!({ "type": "need-input" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::NeedInput));
+        let event = json!({ "type": "input" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::NeedInput));
+        let event = json!({ "type": "permission_request" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::NeedInput));
+        let event = json!({ "type": "request" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::NeedInput));
+        let event = json!({ "status": "waiting" });
+        assert_eq!(codex_event_kind(&event), Some(HookEventKind::NeedInput));
+    }
+
+    #[test]
+    fn codex_event_kind_none_when_unknown() {
+        let event = json!({ "type": "progress" });
+        assert_eq!(codex_event_kind(&event), None);
+    }
+}
@@ -0,0 +1,1514 @@
+// ローカル制御 API（loopback HTTP + JSON）/ Local control API: JSON over loopback HTTP.
+//
+// 解析・ルーティング・応答組み立てはここで完結させ、実際のセッション操作は
+// `ControlBackend` 経由で main 側に任せる。
+// Parsing, routing and response building live here; session operations go through `ControlBackend`.
+
+use crate::completion_hook::{hooks_base_dir, resolve_hook_source, HookEvent};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const MAX_HEADER_BYTES: usize = 16 * 1024;
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+// 1 セッションあたり保持する直近出力 / Recent output kept per session.
+pub const TAIL_CAPACITY_BYTES: usize = 256 * 1024;
+const DEFAULT_TAIL_BYTES: usize = 16 * 1024;
+// 1 要求を読み切るまでの上限（少しずつ送る client もここで切る）
+// Total time to read one request; also cuts off clients that trickle bytes.
+const READ_DEADLINE: Duration = Duration::from_secs(2);
+// 認証前の読み取りを同時に何本まで許すか / How many connections may be read before auth at once.
+pub const MAX_PENDING_CONNECTIONS: usize = 32;
+// app_config_dir 直下のトークンファイル / Token file directly under app_config_dir.
+pub const CONTROL_TOKEN_FILE: &str = "control_token";
+// 小文字化済みのヘッダー名 / Lowercased header name.
+pub const CONTROL_TOKEN_HEADER: &str = "x-nagomi-token";
+const CONTROL_TOKEN_BYTES: usize = 32;
+pub const EVENTS_PATH: &str = "/events";
+// `POST /hooks/<source>` で hook event を直接受ける / Hook events are posted to `/hooks/<source>`.
+pub const HOOKS_PATH: &str = "/hooks";
+// 購読者ごとの未送信イベント上限。超えたら遅い購読者として切る
+// Per-subscriber backlog; a subscriber that falls this far behind is dropped.
+const EVENT_QUEUE_CAPACITY: usize = 1024;
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(15);
+// 読み遅れる SSE 購読者に書き込みで詰まらない上限 / Write timeout so a stalled SSE client cannot block us.
+const EVENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlError {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ControlError {
+    pub fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, "bad_request", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, "not_found", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(500, "error", message)
+    }
+
+    pub fn to_response(&self) -> ControlResponse {
+        ControlResponse {
+            status: self.status,
+            body: json!({ "status": self.code, "error": self.message }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl ControlResponse {
+    pub fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    // query 込みの生のリクエストターゲット / Raw request target including the query.
+    pub target: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // ヘッダー名は小文字 / Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn json_body<T: for<'de> Deserialize<'de>>(&self) -> Result<T, ControlError> {
+        if self.body.iter().all(u8::is_ascii_whitespace) {
+            return serde_json::from_str("{}")
+                .map_err(|err| ControlError::bad_request(format!("request body required: {err}")));
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|err| ControlError::bad_request(format!("invalid JSON body: {err}")))
+    }
+}
+
+pub fn url_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b'%' if i + 2 < bytes.len() => {
+                let hex = &bytes[i + 1..i + 3];
+                let hi = (hex[0] as char).to_digit(16);
+                let lo = (hex[1] as char).to_digit(16);
+                if let (Some(hi), Some(lo)) = (hi, lo) {
+                    out.push(((hi << 4) + lo) as u8);
+                    i += 3;
+                } else {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+pub fn url_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+pub fn parse_query_pairs(query: &str) -> HashMap<String, String> {
+    let mut pairs = HashMap::new();
+    for part in query.split('&') {
+        if part.is_empty() {
+            continue;
+        }
+        let mut iter = part.splitn(2, '=');
+        let key = iter.next().unwrap_or_default();
+        let value = iter.next().unwrap_or_default();
+        if key.is_empty() {
+            continue;
+        }
+        pairs.insert(url_decode(key), url_decode(value));
+    }
+    pairs
+}
+
+fn header_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    if let Some(index) = buffer.windows(4).position(|slice| slice == b"\r\n\r\n") {
+        return Some((index, index + 4));
+    }
+    buffer
+        .windows(2)
+        .position(|slice| slice == b"\n\n")
+        .map(|index| (index, index + 2))
+}
+
+fn read_more<R: Read>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    deadline: Instant,
+) -> Result<bool, ControlError> {
+    let mut temp = [0u8; 4096];
+    loop {
+        if Instant::now() >= deadline {
+            return Err(ControlError::new(408, "timeout", "request read timed out"));
+        }
+        match reader.read(&mut temp) {
+            Ok(0) => return Ok(false),
+            Ok(size) => {
+                buffer.extend_from_slice(&temp[..size]);
+                return Ok(true);
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) => {}
+            Err(err) => {
+                return Err(ControlError::bad_request(format!(
+                    "request read failed: {err}"
+                )))
+            }
+        }
+    }
+}
+
+// ヘッダーと Content-Length 分の body を読む / Read the head and a Content-Length body.
+pub fn read_request<R: Read>(reader: &mut R) -> Result<HttpRequest, ControlError> {
+    read_request_within(reader, READ_DEADLINE)
+}
+
+fn read_request_within<R: Read>(
+    reader: &mut R,
+    limit: Duration,
+) -> Result<HttpRequest, ControlError> {
+    let deadline = Instant::now() + limit;
+    let mut buffer: Vec<u8> = Vec::with_capacity(1024);
+    let (head_len, body_start) = loop {
+        if let Some(found) = header_end(&buffer) {
+            break found;
+        }
+        if buffer.len() > MAX_HEADER_BYTES {
+            return Err(ControlError::new(
+                431,
+                "bad_request",
+                "request headers too large",
+            ));
+        }
+        if !read_more(reader, &mut buffer, deadline)? {
+            if buffer.is_empty() {
+                return Err(ControlError::bad_request("empty request"));
+            }
+            // 終端なしで閉じられた場合も受け付ける / Accept a head closed without a blank line.
+            break (buffer.len(), buffer.len());
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_len]).to_string();
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ControlError::bad_request("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(ControlError::bad_request("unsupported HTTP version"));
+    }
+    let mut headers = HashMap::new();
+    for line in lines {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(ControlError::bad_request("malformed header line"));
+        };
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let content_length = match headers.get("content-length") {
+        Some(raw) => raw
+            .parse::<usize>()
+            .map_err(|_| ControlError::bad_request("invalid Content-Length"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_BYTES {
+        return Err(ControlError::new(
+            413,
+            "bad_request",
+            "request body too large",
+        ));
+    }
+    let mut body: Vec<u8> = buffer[body_start.min(buffer.len())..].to_vec();
+    while body.len() < content_length {
+        if !read_more(reader, &mut body, deadline)? {
+            return Err(ControlError::bad_request("request body truncated"));
+        }
+    }
+    body.truncate(content_length);
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, parse_query_pairs(query)),
+        None => (target, HashMap::new()),
+    };
+    Ok(HttpRequest {
+        method: method.to_ascii_uppercase(),
+        target: target.to_string(),
+        path: path.to_string(),
+        query,
+        headers,
+        body,
+    })
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+// 認証前の接続数の上限。listener thread は読まずに permit を取って渡すだけにする
+// Caps connections still being read before auth, so the listener thread never reads itself.
+#[derive(Clone)]
+pub struct PendingLimit {
+    active: Arc<AtomicUsize>,
+    max: usize,
+}
+
+// 落とすと枠を返す / Dropping the permit frees its slot.
+pub struct PendingPermit {
+    active: Arc<AtomicUsize>,
+}
+
+impl PendingLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<PendingPermit> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()?;
+        Some(PendingPermit {
+            active: self.active.clone(),
+        })
+    }
+}
+
+impl Drop for PendingPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// 枠が無いときは読まずに 503 を返して閉じる / Out of slots: answer 503 without reading and close.
+pub fn refuse_busy(stream: &mut TcpStream) {
+    let _ = stream.set_write_timeout(Some(Duration::from_millis(200)));
+    let err = ControlError::new(503, "busy", "too many pending connections");
+    let _ = write_response(stream, &err.to_response());
+}
+
+pub fn write_response<W: Write>(writer: &mut W, response: &ControlResponse) -> std::io::Result<()> {
+    let body = response.body.to_string();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        status_text(response.status),
+        body.len()
+    );
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(body.as_bytes())?;
+    writer.flush()
+}
+
+fn is_valid_token(token: &str) -> bool {
+    token.len() == CONTROL_TOKEN_BYTES * 2 && token.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+// Windows の app_config_dir（%APPDATA%）はユーザー専用なので ACL は触らない
+// On Windows the app_config_dir (%APPDATA%) is already per-user; leave the ACL alone.
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// install ごとのトークンを読む。無い/壊れていれば作り直す（所有者のみ読める 0600）
+// Load the per-install token, creating it (owner-only 0600) when missing or malformed.
+pub fn load_or_create_token(dir: &Path) -> Result<String> {
+    let path = dir.join(CONTROL_TOKEN_FILE);
+    if let Ok(raw) = fs::read_to_string(&path) {
+        let token = raw.trim().to_string();
+        if is_valid_token(&token) {
+            restrict_permissions(&path)?;
+            return Ok(token);
+        }
+    }
+    fs::create_dir_all(dir)?;
+    let mut bytes = [0u8; CONTROL_TOKEN_BYTES];
+    getrandom::fill(&mut bytes).map_err(|err| anyhow!("control token generation failed: {err}"))?;
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    let tmp = path.with_extension("tmp");
+    let _ = fs::remove_file(&tmp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(token.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, &path)?;
+    restrict_permissions(&path)?;
+    Ok(token)
+}
+
+// 認証なしで答えるのは `GET /health` だけ / Only `GET /health` is answered without the token.
+pub fn requires_auth(method: &str, path: &str) -> bool {
+    !(method == "GET" && path == "/health")
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// `X-Nagomi-Token: <token>` か `Authorization: Bearer <token>` を受け付ける
+// Accept either `X-Nagomi-Token: <token>` or `Authorization: Bearer <token>`.
+pub fn authorize(request: &HttpRequest, token: &str) -> Result<(), ControlError> {
+    let presented = request
+        .headers
+        .get(CONTROL_TOKEN_HEADER)
+        .map(|value| value.trim())
+        .or_else(|| {
+            request
+                .headers
+                .get("authorization")
+                .and_then(|value| value.trim().strip_prefix("Bearer "))
+                .map(str::trim)
+        });
+    match presented {
+        Some(value) if constant_time_eq(value.as_bytes(), token.as_bytes()) => Ok(()),
+        Some(_) => Err(ControlError::new(
+            401,
+            "unauthorized",
+            "invalid control token",
+        )),
+        None => Err(ControlError::new(
+            401,
+            "unauthorized",
+            "control token required",
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlRoute {
+    ListSessions,
+    OpenSession,
+    GetSession(String),
+    SendInput(String),
+    Resize(String),
+    Stop(String),
+    Tail(String),
+    // jsonl の 1 行と同じ payload の hook event / A hook event with the same payload as one jsonl line.
+    Hook(String),
+    // 接続を保持して SSE を流す（`handle` では扱わない）
+    // Holds the connection open for SSE; served by the caller, not by `handle`.
+    Events,
+}
+
+// `/sessions`・`/events`・`/hooks` 以外は None（従来のエンドポイントへ回す）
+// Returns None outside `/sessions`, `/events` and `/hooks` so the caller can fall back to the
+// legacy endpoints.
+pub fn route(method: &str, path: &str) -> Result<Option<ControlRoute>, ControlError> {
+    let trimmed = path.trim_end_matches('/');
+    let method_not_allowed = || {
+        Err(ControlError::new(
+            405,
+            "method_not_allowed",
+            format!("{method} is not allowed on {trimmed}"),
+        ))
+    };
+    if trimmed == EVENTS_PATH {
+        return match method {
+            "GET" => Ok(Some(ControlRoute::Events)),
+            _ => method_not_allowed(),
+        };
+    }
+    if let Some(source) = trimmed
+        .strip_prefix(HOOKS_PATH)
+        .and_then(|rest| rest.strip_prefix('/'))
+    {
+        let source = url_decode(source);
+        if source.is_empty() || source.contains('/') {
+            return Err(ControlError::not_found(format!(
+                "unknown endpoint: {trimmed}"
+            )));
+        }
+        return match method {
+            "POST" => Ok(Some(ControlRoute::Hook(source))),
+            _ => method_not_allowed(),
+        };
+    }
+    let Some(rest) = trimmed.strip_prefix("/sessions") else {
+        return Ok(None);
+    };
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return Ok(None);
+    }
+    let segments: Vec<String> = rest
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(url_decode)
+        .collect();
+    let route = match (segments.as_slice(), method) {
+        ([], "GET") => ControlRoute::ListSessions,
+        ([], "POST") => ControlRoute::OpenSession,
+        ([], _) => return method_not_allowed(),
+        ([id], "GET") => ControlRoute::GetSession(id.clone()),
+        ([id], "DELETE") => ControlRoute::Stop(id.clone()),
+        ([_], _) => return method_not_allowed(),
+        ([id, action], _) => match (action.as_str(), method) {
+            ("send", "POST") => ControlRoute::SendInput(id.clone()),
+            ("resize", "POST") => ControlRoute::Resize(id.clone()),
+            ("stop", "POST") => ControlRoute::Stop(id.clone()),
+            ("tail", "GET") => ControlRoute::Tail(id.clone()),
+            ("send" | "resize" | "stop" | "tail", _) => return method_not_allowed(),
+            _ => {
+                return Err(ControlError::not_found(format!(
+                    "unknown endpoint: {trimmed}"
+                )))
+            }
+        },
+        _ => {
+            return Err(ControlError::not_found(format!(
+                "unknown endpoint: {trimmed}"
+            )))
+        }
+    };
+    Ok(Some(route))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub title: String,
+    // normalize 済みの観測状態 / Normalized observed state.
+    pub state: String,
+    pub cwd: Option<String>,
+    pub pid: Option<u32>,
+    pub profile: Option<String>,
+    // PTY が動いているか / Whether the PTY session is running.
+    pub running: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenSessionRequest {
+    pub session_id: Option<String>,
+    pub profile: Option<String>,
+    pub cwd: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendInputRequest {
+    text: String,
+    // true なら末尾に Enter（CR）を足す / Append Enter (CR) when true.
+    #[serde(default)]
+    enter: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResizeRequest {
+    cols: u16,
+    rows: u16,
+}
+
+pub trait ControlBackend {
+    fn list_sessions(&self) -> Vec<SessionInfo>;
+    fn open_session(&self, request: OpenSessionRequest) -> Result<String, ControlError>;
+    fn send_input(&self, session_id: &str, text: &str) -> Result<(), ControlError>;
+    fn resize(&self, session_id: &str, cols: u16, rows: u16) -> Result<(), ControlError>;
+    fn stop(&self, session_id: &str) -> Result<(), ControlError>;
+    // 保持している直近出力すべて / All buffered recent output.
+    fn tail(&self, session_id: &str) -> Result<String, ControlError>;
+    // 受け付ける source か。マッピングの source はここで有効にしてよい
+    // Whether the source is accepted; a mapped source may be enabled here on first use.
+    fn hook_source_enabled(&self, source: &str) -> bool;
+    // file tail を経由せず handle_hook_event へ渡す / Hand a hook event straight to handle_hook_event.
+    fn ingest_hook(&self, event: HookEvent) -> Result<(), ControlError>;
+}
+
+fn find_session(
+    backend: &dyn ControlBackend,
+    session_id: &str,
+) -> Result<SessionInfo, ControlError> {
+    backend
+        .list_sessions()
+        .into_iter()
+        .find(|session| session.session_id == session_id)
+        .ok_or_else(|| ControlError::not_found(format!("session not found: {session_id}")))
+}
+
+fn query_usize(request: &HttpRequest, key: &str) -> Result<Option<usize>, ControlError> {
+    match request.query.get(key).map(|value| value.trim()) {
+        None | Some("") => Ok(None),
+        Some(raw) => raw.parse::<usize>().map(Some).map_err(|_| {
+            ControlError::bad_request(format!("{key} must be a non-negative integer"))
+        }),
+    }
+}
+
+fn dispatch(
+    backend: &dyn ControlBackend,
+    route: ControlRoute,
+    request: &HttpRequest,
+) -> Result<ControlResponse, ControlError> {
+    match route {
+        ControlRoute::ListSessions => {
+            let sessions = backend.list_sessions();
+            Ok(ControlResponse::ok(
+                json!({ "status": "ok", "sessions": sessions }),
+            ))
+        }
+        ControlRoute::OpenSession => {
+            let body: OpenSessionRequest = request.json_body()?;
+            let session_id = backend.open_session(body)?;
+            Ok(ControlResponse::ok(
+                json!({ "status": "ok", "session_id": session_id }),
+            ))
+        }
+        ControlRoute::GetSession(session_id) => {
+            let session = find_session(backend, &session_id)?;
+            Ok(ControlResponse::ok(
+                json!({ "status": "ok", "session": session }),
+            ))
+        }
+        ControlRoute::SendInput(session_id) => {
+            let body: SendInputRequest = request.json_body()?;
+            let mut text = body.text;
+            if body.enter {
+                text.push('\r');
+            }
+            if text.is_empty() {
+                return Err(ControlError::bad_request("text is empty"));
+            }
+            backend.send_input(&session_id, &text)?;
+            Ok(ControlResponse::ok(
+                json!({ "status": "ok", "bytes": text.len() }),
+            ))
+        }
+        ControlRoute::Resize(session_id) => {
+            let body: ResizeRequest = request.json_body()?;
+            if body.cols == 0 || body.rows == 0 {
+                return Err(ControlError::bad_request("cols and rows must be positive"));
+            }
+            backend.resize(&session_id, body.cols, body.rows)?;
+            Ok(ControlResponse::ok(json!({ "status": "ok" })))
+        }
+        ControlRoute::Stop(session_id) => {
+            find_session(backend, &session_id)?;
+            backend.stop(&session_id)?;
+            Ok(ControlResponse::ok(json!({ "status": "ok" })))
+        }
+        ControlRoute::Tail(session_id) => {
+            let lines = query_usize(request, "lines")?;
+            let bytes = query_usize(request, "bytes")?;
+            let buffered = backend.tail(&session_id)?;
+            let data = match lines {
+                Some(lines) => last_lines(&buffered, lines),
+                None => last_bytes(&buffered, bytes.unwrap_or(DEFAULT_TAIL_BYTES)),
+            };
+            Ok(ControlResponse::ok(json!({
+                "status": "ok",
+                "session_id": session_id,
+                "data": data,
+            })))
+        }
+        ControlRoute::Hook(source) => {
+            // マッピングファイルの source も受ける / Mapped sources are accepted too.
+            let Some(source) = resolve_hook_source(&source, &hooks_base_dir()) else {
+                return Err(ControlError::not_found(format!(
+                    "unknown hook source: {source}"
+                )));
+            };
+            if !backend.hook_source_enabled(source.name()) {
+                return Err(ControlError::new(
+                    409,
+                    "conflict",
+                    format!("hook source is disabled: {}", source.name()),
+                ));
+            }
+            let body: Value = request.json_body()?;
+            // 完了・入力待ち・エラー以外の通知は file tail と同じく読み捨てる
+            // Notifications other than completion, need-input and error are dropped, as the
+            // file tail does.
+            let accepted = match source.parse(body) {
+                Some(event) => {
+                    backend.ingest_hook(event)?;
+                    true
+                }
+                None => false,
+            };
+            Ok(ControlResponse::ok(json!({
+                "status": "ok",
+                "source": source.name(),
+                "accepted": accepted,
+            })))
+        }
+        ControlRoute::Events => Err(ControlError::bad_request(
+            "the event stream needs its own connection",
+        )),
+    }
+}
+
+pub fn handle(
+    backend: &dyn ControlBackend,
+    route: ControlRoute,
+    request: &HttpRequest,
+) -> ControlResponse {
+    dispatch(backend, route, request).unwrap_or_else(|err| err.to_response())
+}
+
+fn next_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+// 末尾 max_bytes 以内（文字の途中では切らない）/ At most the last max_bytes, never splitting a char.
+pub fn last_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    &text[next_char_boundary(text, text.len() - max_bytes)..]
+}
+
+// 末尾 n 行。最後の改行の後ろの書きかけ行も 1 行と数える
+// The last n lines; a trailing partial line counts as one.
+pub fn last_lines(text: &str, lines: usize) -> &str {
+    if lines == 0 {
+        return "";
+    }
+    let body = text.strip_suffix('\n').unwrap_or(text);
+    let mut remaining = lines;
+    for (index, byte) in body.bytes().enumerate().rev() {
+        if byte == b'\n' {
+            remaining -= 1;
+            if remaining == 0 {
+                return &text[index + 1..];
+            }
+        }
+    }
+    text
+}
+
+// セッションごとの直近出力（上限付き）/ Bounded recent output for one session.
+#[derive(Debug, Clone, Default)]
+pub struct OutputTail {
+    data: String,
+}
+
+impl OutputTail {
+    pub fn push(&mut self, chunk: &str) {
+        self.data.push_str(chunk);
+        if self.data.len() > TAIL_CAPACITY_BYTES {
+            let cut = next_char_boundary(&self.data, self.data.len() - TAIL_CAPACITY_BYTES);
+            self.data.drain(..cut);
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.data
+    }
+}
+
+// 制御 API の購読者へ流すイベント（`type` で種類を区別）
+// Events pushed to control API subscribers, tagged by `type`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ControlEvent {
+    Output {
+        session_id: String,
+        stream: String,
+        data: String,
+    },
+    // 出力監視（judge）による terminal ごとの状態遷移 / Per-terminal state change from the output judge.
+    State {
+        session_id: String,
+        state: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        previous: Option<String>,
+    },
+    // AI Coding Agent の hook による状態遷移 / State change reported by an agent hook.
+    Hook {
+        source: String,
+        kind: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_session_id: Option<String>,
+        state: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        summary: Option<String>,
+    },
+    SessionOpened {
+        session_id: String,
+    },
+    SessionClosed {
+        session_id: String,
+        reason: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+    },
+    // group が無ければ全体、あれば `aggregate_group_by` のグループ / Overall without a group, else one group.
+    Aggregate {
+        state: String,
+        // グループの key（project は正規化したパス）/ Group key; the canonical path for projects.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        // 表示用の名前（project はディレクトリ名）/ Display name; the directory name for projects.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+}
+
+impl ControlEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ControlEvent::Output { .. } => "output",
+            ControlEvent::State { .. } => "state",
+            ControlEvent::Hook { .. } => "hook",
+            ControlEvent::SessionOpened { .. } => "session-opened",
+            ControlEvent::SessionClosed { .. } => "session-closed",
+            ControlEvent::Aggregate { .. } => "aggregate",
+        }
+    }
+
+    // nagomi の session に紐づくイベントだけ Some / Some only for events tied to a nagomi session.
+    fn session_id(&self) -> Option<&str> {
+        match self {
+            ControlEvent::Output { session_id, .. }
+            | ControlEvent::State { session_id, .. }
+            | ControlEvent::SessionOpened { session_id }
+            | ControlEvent::SessionClosed { session_id, .. } => Some(session_id),
+            ControlEvent::Hook { .. } | ControlEvent::Aggregate { .. } => None,
+        }
+    }
+}
+
+// `?session_id=a,b&types=output,state`。空なら全件
+// `?session_id=a,b&types=output,state`; an empty list matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    pub session_ids: Vec<String>,
+    pub types: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        let list = |key: &str| -> Vec<String> {
+            query
+                .get(key)
+                .map(|raw| {
+                    raw.split(',')
+                        .map(str::trim)
+                        .filter(|value| !value.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Self {
+            session_ids: list("session_id"),
+            types: list("types"),
+        }
+    }
+
+    // session 指定は session に紐づくイベントにだけ効く（aggregate/hook は常に通す）
+    // The session filter only applies to session-scoped events; aggregate and hook always pass.
+    pub fn matches(&self, event: &ControlEvent) -> bool {
+        if !self.types.is_empty() && !self.types.iter().any(|kind| kind == event.kind()) {
+            return false;
+        }
+        match event.session_id() {
+            Some(session_id) if !self.session_ids.is_empty() => {
+                self.session_ids.iter().any(|id| id == session_id)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<(EventFilter, SyncSender<ControlEvent>)>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<ControlEvent> {
+        let (tx, rx) = mpsc::sync_channel(EVENT_QUEUE_CAPACITY);
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push((filter, tx));
+        }
+        rx
+    }
+
+    // 購読者がいなければイベント自体を組み立てない / Skip building the event when nobody listens.
+    pub fn publish(&self, make: impl FnOnce() -> ControlEvent) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = make();
+        subscribers.retain(|(filter, tx)| {
+            if !filter.matches(&event) {
+                return true;
+            }
+            match tx.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+pub fn sse_frame(event: &ControlEvent) -> String {
+    let data = serde_json::to_string(event).unwrap_or_else(|_| "{}".to_string());
+    format!("event: {}\ndata: {data}\n\n", event.kind())
+}
+
+// ヘッダー → initial → 以後は受信ごとに 1 frame。無音が続けば keepalive コメントを送る
+// Head, then `initial`, then one frame per event; idle periods get a keepalive comment.
+// 書き込み失敗（切断）か bus 側の切断で戻る / Returns when the client goes away or the bus drops us.
+pub fn stream_events<W: Write>(
+    out: &mut W,
+    rx: &Receiver<ControlEvent>,
+    initial: &[ControlEvent],
+) -> std::io::Result<()> {
+    out.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+    )?;
+    for event in initial {
+        out.write_all(sse_frame(event).as_bytes())?;
+    }
+    out.flush()?;
+    loop {
+        match rx.recv_timeout(EVENT_KEEPALIVE) {
+            Ok(event) => out.write_all(sse_frame(&event).as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => out.write_all(b": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        out.flush()?;
+    }
+}
+
+// `GET /events`: 購読してから現在の集約（`current`）を最初に送り、以後は bus のイベントを SSE で流す。
+// GUI と headless の両方がここを使う
+// `GET /events`: subscribe, send the current aggregates from `current` first, then relay bus
+// events as SSE. Shared by the GUI and headless servers.
+pub fn serve_events(
+    mut stream: TcpStream,
+    bus: &EventBus,
+    request: &HttpRequest,
+    current: impl FnOnce() -> Vec<ControlEvent>,
+) {
+    let filter = EventFilter::from_query(&request.query);
+    let rx = bus.subscribe(filter.clone());
+    let initial: Vec<ControlEvent> = current()
+        .into_iter()
+        .filter(|event| filter.matches(event))
+        .collect();
+    let _ = stream.set_write_timeout(Some(EVENT_WRITE_TIMEOUT));
+    let _ = stream_events(&mut stream, &rx, &initial);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion_hook::HookEventKind;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    #[derive(Default)]
+    struct FakeBackend {
+        sessions: Vec<SessionInfo>,
+        sent: RefCell<Vec<(String, String)>>,
+        stopped: RefCell<Vec<String>>,
+        hooks: RefCell<Vec<HookEvent>>,
+        disabled_hooks: Vec<String>,
+        output: String,
+    }
+
+    impl ControlBackend for FakeBackend {
+        fn list_sessions(&self) -> Vec<SessionInfo> {
+            self.sessions.clone()
+        }
+
+        fn open_session(&self, request: OpenSessionRequest) -> Result<String, ControlError> {
+            Ok(request.session_id.unwrap_or_else(|| "new".to_string()))
+        }
+
+        fn send_input(&self, session_id: &str, text: &str) -> Result<(), ControlError> {
+            if !self
+                .sessions
+                .iter()
+                .any(|session| session.session_id == session_id)
+            {
+                return Err(ControlError::not_found("terminal session not started"));
+            }
+            self.sent
+                .borrow_mut()
+                .push((session_id.to_string(), text.to_string()));
+            Ok(())
+        }
+
+        fn resize(&self, _session_id: &str, _cols: u16, _rows: u16) -> Result<(), ControlError> {
+            Ok(())
+        }
+
+        fn stop(&self, session_id: &str) -> Result<(), ControlError> {
+            self.stopped.borrow_mut().push(session_id.to_string());
+            Ok(())
+        }
+
+        fn tail(&self, _session_id: &str) -> Result<String, ControlError> {
+            Ok(self.output.clone())
+        }
+
+        fn hook_source_enabled(&self, source: &str) -> bool {
+            !self.disabled_hooks.iter().any(|name| name == source)
+        }
+
+        fn ingest_hook(&self, event: HookEvent) -> Result<(), ControlError> {
+            self.hooks.borrow_mut().push(event);
+            Ok(())
+        }
+    }
+
+    fn backend() -> FakeBackend {
+        FakeBackend {
+            sessions: vec![SessionInfo {
+                session_id: "a".to_string(),
+                title: "api".to_string(),
+                state: "need-input".to_string(),
+                cwd: Some("/srv/api".to_string()),
+                pid: Some(42),
+                profile: None,
+                running: true,
+            }],
+            output: "one\ntwo\nthree\n".to_string(),
+            ..FakeBackend::default()
+        }
+    }
+
+    fn request(raw: &str) -> HttpRequest {
+        read_request(&mut Cursor::new(raw.as_bytes().to_vec())).expect("parse request")
+    }
+
+    fn call(backend: &FakeBackend, raw: &str) -> ControlResponse {
+        let request = request(raw);
+        match route(&request.method, &request.path) {
+            Ok(Some(route)) => handle(backend, route, &request),
+            Ok(None) => panic!("not a control route: {}", request.path),
+            Err(err) => err.to_response(),
+        }
+    }
+
+    #[test]
+    fn read_request_parses_headers_query_and_body() {
+        let parsed = request(
+            "POST /sessions/a%20b/send?x=1&y=two%2F HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 13\r\n\r\n{\"text\":\"ls\"}trailing",
+        );
+        assert_eq!(parsed.method, "POST");
+        assert_eq!(parsed.path, "/sessions/a%20b/send");
+        assert_eq!(parsed.query.get("y").map(String::as_str), Some("two/"));
+        assert_eq!(
+            parsed.headers.get("content-type").map(String::as_str),
+            Some("application/json")
+        );
+        assert_eq!(parsed.body, b"{\"text\":\"ls\"}");
+        assert_eq!(
+            route(&parsed.method, &parsed.path).unwrap(),
+            Some(ControlRoute::SendInput("a b".to_string()))
+        );
+    }
+
+    #[test]
+    fn read_request_rejects_malformed_input() {
+        let bad = |raw: &str| read_request(&mut Cursor::new(raw.as_bytes().to_vec())).unwrap_err();
+        assert_eq!(bad("GARBAGE\r\n\r\n").status, 400);
+        assert_eq!(bad("GET / HTTP/1.1\r\nno-colon\r\n\r\n").status, 400);
+        assert_eq!(
+            bad("POST /sessions HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}").message,
+            "request body truncated"
+        );
+        let huge = format!(
+            "POST /sessions HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        assert_eq!(bad(&huge).status, 413);
+    }
+
+    #[test]
+    fn read_request_gives_up_on_a_trickling_client() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            // 読み取りの timeout より短い間隔で 1 byte ずつ送る / One byte at a time, faster than the read timeout.
+            for byte in b"GET /sessions HTTP/1.1\r\nX-Slow: "
+                .iter()
+                .cycle()
+                .take(200)
+            {
+                if stream.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let (mut stream, _) = listener.accept().expect("accept");
+        stream
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .expect("read timeout");
+        let started = Instant::now();
+        let err = read_request_within(&mut stream, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(err.status, 408);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(stream);
+        client.join().expect("client");
+    }
+
+    #[test]
+    fn pending_limit_hands_out_bounded_permits() {
+        let limit = PendingLimit::new(2);
+        let first = limit.try_acquire().expect("first");
+        let _second = limit.try_acquire().expect("second");
+        assert!(limit.try_acquire().is_none());
+        drop(first);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[test]
+    fn route_maps_methods_and_rejects_unknown_paths() {
+        assert_eq!(route("GET", "/health").unwrap(), None);
+        assert_eq!(route("GET", "/events").unwrap(), Some(ControlRoute::Events));
+        assert_eq!(route("POST", "/events").unwrap_err().status, 405);
+        assert_eq!(route("GET", "/sessionsx").unwrap(), None);
+        assert_eq!(
+            route("GET", "/sessions/").unwrap(),
+            Some(ControlRoute::ListSessions)
+        );
+        assert_eq!(
+            route("DELETE", "/sessions/a").unwrap(),
+            Some(ControlRoute::Stop("a".to_string()))
+        );
+        assert_eq!(route("GET", "/sessions/a/send").unwrap_err().status, 405);
+        assert_eq!(route("PUT", "/sessions").unwrap_err().status, 405);
+        assert_eq!(route("GET", "/sessions/a/nope").unwrap_err().status, 404);
+        assert_eq!(
+            route("POST", "/hooks/claude").unwrap(),
+            Some(ControlRoute::Hook("claude".to_string()))
+        );
+        assert_eq!(route("GET", "/hooks/claude").unwrap_err().status, 405);
+        assert_eq!(route("POST", "/hooks/a/b").unwrap_err().status, 404);
+        assert_eq!(route("POST", "/hooks").unwrap(), None);
+    }
+
+    #[test]
+    fn hooks_endpoint_takes_jsonl_shaped_payloads() {
+        let backend = backend();
+        let post = |path: &str, body: &str| {
+            call(
+                &backend,
+                &format!(
+                    "POST {path} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                ),
+            )
+        };
+        let stop = post(
+            "/hooks/claudecode",
+            r#"{"hook_event_name":"Stop","source_session_id":"a","last_assistant_message":"done"}"#,
+        );
+        assert_eq!(stop.status, 200);
+        assert_eq!(stop.body["source"], "claude");
+        assert_eq!(stop.body["accepted"], true);
+
+        // codex notify のラッパー形式（event + source）もそのまま通る
+        // The wrapped codex notify shape (event + source) is accepted as-is.
+        let wrapped = post(
+            "/hooks/codex",
+            r#"{"source":"codex","event":{"type":"agent-turn-complete"},"NAGOMI_SESSION_ID":"a"}"#,
+        );
+        assert_eq!(wrapped.body["accepted"], true);
+
+        let ignored = post("/hooks/opencode", r#"{"type":"message.updated"}"#);
+        assert_eq!(ignored.status, 200);
+        assert_eq!(ignored.body["accepted"], false);
+        let gemini = post("/hooks/gemini", r#"{"hook_event_name":"AfterAgent"}"#);
+        assert_eq!(gemini.body["accepted"], true);
+        assert_eq!(post("/hooks/nosuch", "{}").status, 404);
+        assert_eq!(post("/hooks/codex", "not json").status, 400);
+
+        let hooks = backend.hooks.borrow();
+        let routed: Vec<(&str, Option<&str>, HookEventKind)> = hooks
+            .iter()
+            .map(|event| {
+                (
+                    event.source.as_str(),
+                    event.source_session_id.as_deref(),
+                    event.kind,
+                )
+            })
+            .collect();
+        assert_eq!(
+            routed,
+            vec![
+                ("claude", Some("a"), HookEventKind::Completed),
+                ("codex", Some("a"), HookEventKind::Completed),
+                ("gemini", None, HookEventKind::Completed),
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_hook_sources_are_refused() {
+        let backend = FakeBackend {
+            disabled_hooks: vec!["codex".to_string()],
+            ..backend()
+        };
+        let body = r#"{"type":"agent-turn-complete"}"#;
+        let refused = call(
+            &backend,
+            &format!(
+                "POST /hooks/codex HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        );
+        assert_eq!(refused.status, 409);
+        assert!(backend.hooks.borrow().is_empty());
+    }
+
+    #[test]
+    fn list_get_and_stop_sessions() {
+        let backend = backend();
+        let listed = call(&backend, "GET /sessions HTTP/1.1\r\n\r\n");
+        assert_eq!(listed.status, 200);
+        assert_eq!(listed.body["sessions"][0]["pid"], 42);
+        assert_eq!(listed.body["sessions"][0]["state"], "need-input");
+
+        let missing = call(&backend, "GET /sessions/zz HTTP/1.1\r\n\r\n");
+        assert_eq!(missing.status, 404);
+        assert_eq!(missing.body["status"], "not_found");
+
+        let stopped = call(&backend, "POST /sessions/a/stop HTTP/1.1\r\n\r\n");
+        assert_eq!(stopped.status, 200);
+        assert_eq!(*backend.stopped.borrow(), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn send_and_resize_validate_bodies() {
+        let backend = backend();
+        let body = r#"{"text":"ls","enter":true}"#;
+        let sent = call(
+            &backend,
+            &format!(
+                "POST /sessions/a/send HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        );
+        assert_eq!(sent.status, 200);
+        assert_eq!(
+            *backend.sent.borrow(),
+            vec![("a".to_string(), "ls\r".to_string())]
+        );
+
+        let invalid = call(
+            &backend,
+            "POST /sessions/a/send HTTP/1.1\r\nContent-Length: 5\r\n\r\n{oops",
+        );
+        assert_eq!(invalid.status, 400);
+        assert!(invalid.body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid JSON body"));
+
+        let body = r#"{"text":"x"}"#;
+        let unknown = call(
+            &backend,
+            &format!(
+                "POST /sessions/zz/send HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        );
+        assert_eq!(unknown.status, 404);
+
+        let body = r#"{"cols":0,"rows":24}"#;
+        let resized = call(
+            &backend,
+            &format!(
+                "POST /sessions/a/resize HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        );
+        assert_eq!(resized.status, 400);
+    }
+
+    #[test]
+    fn tail_trims_by_lines_or_bytes() {
+        let backend = backend();
+        let lines = call(&backend, "GET /sessions/a/tail?lines=2 HTTP/1.1\r\n\r\n");
+        assert_eq!(lines.body["data"], "two\nthree\n");
+        let bytes = call(&backend, "GET /sessions/a/tail?bytes=3 HTTP/1.1\r\n\r\n");
+        assert_eq!(bytes.body["data"], "ee\n");
+        let bad = call(&backend, "GET /sessions/a/tail?lines=-1 HTTP/1.1\r\n\r\n");
+        assert_eq!(bad.status, 400);
+
+        assert_eq!(last_lines("a\nb", 1), "b");
+        assert_eq!(last_lines("a\nb", 5), "a\nb");
+        assert_eq!(last_bytes("aé", 1), "");
+    }
+
+    #[test]
+    fn output_tail_is_bounded_on_char_boundaries() {
+        let mut tail = OutputTail::default();
+        tail.push("é");
+        tail.push(&"x".repeat(TAIL_CAPACITY_BYTES - 1));
+        assert_eq!(tail.as_str().len(), TAIL_CAPACITY_BYTES - 1);
+        assert!(tail.as_str().starts_with('x'));
+    }
+
+    #[test]
+    fn event_filter_scopes_output_by_session_and_type() {
+        let output = |session_id: &str| ControlEvent::Output {
+            session_id: session_id.to_string(),
+            stream: "stdout".to_string(),
+            data: "hi".to_string(),
+        };
+        let aggregate = ControlEvent::Aggregate {
+            state: "need-input".to_string(),
+            group: None,
+            label: None,
+        };
+        let request = request("GET /events?session_id=a,b&types=output,aggregate HTTP/1.1\r\n\r\n");
+        let filter = EventFilter::from_query(&request.query);
+        assert_eq!(filter.session_ids, vec!["a", "b"]);
+        assert!(filter.matches(&output("a")));
+        assert!(!filter.matches(&output("c")));
+        assert!(filter.matches(&aggregate));
+        assert!(!filter.matches(&ControlEvent::SessionOpened {
+            session_id: "a".to_string()
+        }));
+        assert!(EventFilter::default().matches(&output("c")));
+    }
+
+    #[test]
+    fn event_bus_streams_sse_frames_and_drops_gone_subscribers() {
+        let bus = EventBus::default();
+        let mut built = false;
+        bus.publish(|| {
+            built = true;
+            ControlEvent::Aggregate {
+                state: "idle".to_string(),
+                group: None,
+                label: None,
+            }
+        });
+        assert!(!built);
+
+        let only_b = bus.subscribe(EventFilter {
+            session_ids: vec!["b".to_string()],
+            ..EventFilter::default()
+        });
+        let all = bus.subscribe(EventFilter::default());
+        bus.publish(|| ControlEvent::SessionClosed {
+            session_id: "a".to_string(),
+            reason: "exit".to_string(),
+            exit_code: Some(0),
+        });
+        assert!(only_b.try_recv().is_err());
+        drop(only_b);
+        bus.publish(|| ControlEvent::SessionOpened {
+            session_id: "b".to_string(),
+        });
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+        drop(bus);
+
+        let mut out = Vec::new();
+        let initial = [ControlEvent::Aggregate {
+            state: "idle".to_string(),
+            group: None,
+            label: None,
+        }];
+        stream_events(&mut out, &all, &initial).expect("stream");
+        let text = String::from_utf8(out).expect("utf8");
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream"));
+        let frames: Vec<&str> = text
+            .split("\r\n\r\n")
+            .nth(1)
+            .unwrap()
+            .split("\n\n")
+            .collect();
+        assert_eq!(
+            frames[0],
+            r#"event: aggregate
+data: {"type":"aggregate","state":"idle"}"#
+        );
+        assert_eq!(
+            frames[1],
+            r#"event: session-closed
+data: {"type":"session-closed","session_id":"a","reason":"exit","exit_code":0}"#
+        );
+        assert_eq!(
+            frames[2],
+            r#"event: session-opened
+data: {"type":"session-opened","session_id":"b"}"#
+        );
+    }
+
+    #[test]
+    fn control_token_is_created_once_with_owner_only_permissions() {
+        let dir = std::env::temp_dir().join(format!(
+            "nagomi-control-token-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        ));
+        let token = load_or_create_token(&dir).expect("create token");
+        assert!(is_valid_token(&token));
+        assert_eq!(load_or_create_token(&dir).expect("reload token"), token);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join(CONTROL_TOKEN_FILE))
+                .expect("token metadata")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::write(dir.join(CONTROL_TOKEN_FILE), "short").expect("corrupt token");
+        let replaced = load_or_create_token(&dir).expect("replace token");
+        assert!(is_valid_token(&replaced));
+        assert_ne!(replaced, token);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unauthenticated_requests_are_rejected() {
+        let token = "ab".repeat(CONTROL_TOKEN_BYTES);
+        let missing = request("GET /sessions HTTP/1.1\r\n\r\n");
+        assert_eq!(authorize(&missing, &token).unwrap_err().status, 401);
+        let wrong = request(&format!(
+            "GET /sessions HTTP/1.1\r\nX-Nagomi-Token: {}\r\n\r\n",
+            "cd".repeat(CONTROL_TOKEN_BYTES)
+        ));
+        let err = authorize(&wrong, &token).unwrap_err();
+        assert_eq!(err.to_response().body["status"], "unauthorized");
+        let prefix = request(&format!(
+            "GET /sessions HTTP/1.1\r\nX-Nagomi-Token: {}\r\n\r\n",
+            &token[..10]
+        ));
+        assert!(authorize(&prefix, &token).is_err());
+
+        let header = request(&format!(
+            "GET /sessions HTTP/1.1\r\nx-nagomi-token: {token}\r\n\r\n"
+        ));
+        assert!(authorize(&header, &token).is_ok());
+        let bearer = request(&format!(
+            "GET /sessions HTTP/1.1\r\nAuthorization: Bearer {token}\r\n\r\n"
+        ));
+        assert!(authorize(&bearer, &token).is_ok());
+
+        assert!(!requires_auth("GET", "/health"));
+        assert!(requires_auth("POST", "/health"));
+        assert!(requires_auth("GET", "/terminal-send"));
+        assert!(requires_auth("GET", "/open-terminal"));
+        assert!(requires_auth("GET", "/sessions"));
+    }
+
+    #[test]
+    fn url_encode_roundtrips_paths() {
+        let path = "C:/Users/me/my project/日本語&x=1";
+        let encoded = url_encode(path);
+        assert!(!encoded.contains(' '));
+        assert!(!encoded.contains('&'));
+        assert_eq!(url_decode(&encoded), path);
+    }
+}
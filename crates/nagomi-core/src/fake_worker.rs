@@ -0,0 +1,338 @@
+// 台本どおりに出力・終了・エラーを返す worker。PTY なしで reader や状態遷移を試す
+// A worker that plays back scripted output, exits and errors, so readers and state
+// transitions can be tested without a PTY.
+use crate::worker::{InProcessWorker, WorkerProcess};
+use nagomi_protocol::{ErrorMessage, Exit, Message, Output, SessionStarted};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT_SLICE: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FakeStep {
+    Output { stream: String, chunk: String },
+    Exit(i32),
+    Error { message: String, recoverable: bool },
+    Wait(Duration),
+}
+
+// StartSession を受けたら頭から再生する / Played from the top once StartSession arrives.
+#[derive(Debug, Clone, Default)]
+pub struct FakeScript {
+    steps: Vec<FakeStep>,
+}
+
+impl FakeScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn output(self, chunk: &str) -> Self {
+        self.step(FakeStep::Output {
+            stream: "stdout".to_string(),
+            chunk: chunk.to_string(),
+        })
+    }
+
+    pub fn wait(self, duration: Duration) -> Self {
+        self.step(FakeStep::Wait(duration))
+    }
+
+    pub fn exit(self, exit_code: i32) -> Self {
+        self.step(FakeStep::Exit(exit_code))
+    }
+
+    pub fn error(self, message: &str, recoverable: bool) -> Self {
+        self.step(FakeStep::Error {
+            message: message.to_string(),
+            recoverable,
+        })
+    }
+
+    pub fn step(mut self, step: FakeStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+}
+
+// orchestrator から届いたメッセージの記録 / Messages the orchestrator sent, for assertions.
+#[derive(Clone, Default)]
+pub struct FakeWorkerLog {
+    messages: Arc<Mutex<Vec<Message>>>,
+}
+
+impl FakeWorkerLog {
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_default()
+    }
+
+    fn push(&self, message: Message) {
+        if let Ok(mut guard) = self.messages.lock() {
+            guard.push(message);
+        }
+    }
+}
+
+// 本物の worker と同じく 1 session だけ持つ / Holds a single session, like the real worker.
+pub struct FakeWorker {
+    script: FakeScript,
+    pid: Option<u32>,
+    echo_input: bool,
+    log: FakeWorkerLog,
+    // exit は 1 回だけ / Exit is sent at most once.
+    exited: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl FakeWorker {
+    pub fn new(script: FakeScript) -> Self {
+        Self {
+            script,
+            pid: None,
+            echo_input: false,
+            log: FakeWorkerLog::default(),
+            exited: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    // SendInput をそのまま出力に返す / Echo SendInput text back as output.
+    pub fn echo_input(mut self) -> Self {
+        self.echo_input = true;
+        self
+    }
+
+    pub fn log(&self) -> FakeWorkerLog {
+        self.log.clone()
+    }
+
+    pub fn spawn(self) -> WorkerProcess {
+        WorkerProcess::in_process(self)
+    }
+
+    fn play(&self, session_id: String, out: &Sender<Message>) {
+        // Wait までは同期で送るので、待ちのない台本は StartSession の戻りで全部届いている
+        // Steps before the first Wait are sent synchronously, so a script without waits has
+        // fully arrived by the time StartSession returns.
+        let steps = self.script.steps.clone();
+        let split = steps
+            .iter()
+            .position(|step| matches!(step, FakeStep::Wait(_)))
+            .unwrap_or(steps.len());
+        let (now, later) = steps.split_at(split);
+        let player = Player {
+            session_id,
+            out: out.clone(),
+            exited: self.exited.clone(),
+            stopped: self.stopped.clone(),
+        };
+        for step in now {
+            player.emit(step);
+        }
+        if later.is_empty() {
+            return;
+        }
+        let later = later.to_vec();
+        thread::spawn(move || {
+            for step in &later {
+                if player.stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                player.emit(step);
+            }
+        });
+    }
+}
+
+struct Player {
+    session_id: String,
+    out: Sender<Message>,
+    exited: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Player {
+    fn emit(&self, step: &FakeStep) {
+        let session_id = self.session_id.clone();
+        let message = match step {
+            // stop されたら待ちを切り上げる / A stop cuts the wait short.
+            FakeStep::Wait(duration) => {
+                let deadline = Instant::now() + *duration;
+                while !self.stopped.load(Ordering::SeqCst) {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        break;
+                    }
+                    thread::sleep(left.min(WAIT_SLICE));
+                }
+                return;
+            }
+            FakeStep::Output { stream, chunk } => Message::Output(Output {
+                session_id,
+                stream: stream.clone(),
+                chunk: chunk.clone(),
+            }),
+            FakeStep::Exit(exit_code) => {
+                if self.exited.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                Message::Exit(Exit {
+                    session_id,
+                    exit_code: *exit_code,
+                })
+            }
+            FakeStep::Error {
+                message,
+                recoverable,
+            } => Message::Error(ErrorMessage {
+                session_id,
+                message: message.clone(),
+                recoverable: *recoverable,
+            }),
+        };
+        let _ = self.out.send(message);
+    }
+}
+
+impl InProcessWorker for FakeWorker {
+    fn handle(&mut self, message: Message, out: &Sender<Message>) {
+        self.log.push(message.clone());
+        match message {
+            Message::StartSession(start) => {
+                let _ = out.send(Message::SessionStarted(SessionStarted {
+                    session_id: start.session_id.clone(),
+                    pid: self.pid,
+                }));
+                self.play(start.session_id, out);
+            }
+            Message::SendInput(input) if self.echo_input => {
+                let _ = out.send(Message::Output(Output {
+                    session_id: input.session_id,
+                    stream: "stdout".to_string(),
+                    chunk: input.text,
+                }));
+            }
+            // 止められた session は非 0 で終わる / A stopped session ends with a non-zero code.
+            Message::StopSession(stop) => {
+                self.stopped.store(true, Ordering::SeqCst);
+                if !self.exited.swap(true, Ordering::SeqCst) {
+                    let _ = out.send(Message::Exit(Exit {
+                        session_id: stop.session_id,
+                        exit_code: 1,
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nagomi_protocol::{SendInput, StartSession, StopSession};
+
+    fn start(process: &mut WorkerProcess, session_id: &str) {
+        process
+            .send_start_session(StartSession {
+                session_id: session_id.to_string(),
+                cols: 80,
+                rows: 24,
+                ..Default::default()
+            })
+            .expect("start session");
+    }
+
+    #[test]
+    fn plays_the_script_without_waits_synchronously() {
+        let mut process = FakeWorker::new(FakeScript::new().output("hi").exit(0))
+            .with_pid(42)
+            .spawn();
+        let rx = process.take_receiver().expect("receiver");
+        start(&mut process, "s1");
+        let messages: Vec<Message> = rx.try_iter().collect();
+        assert_eq!(
+            messages,
+            vec![
+                Message::SessionStarted(SessionStarted {
+                    session_id: "s1".to_string(),
+                    pid: Some(42),
+                }),
+                Message::Output(Output {
+                    session_id: "s1".to_string(),
+                    stream: "stdout".to_string(),
+                    chunk: "hi".to_string(),
+                }),
+                Message::Exit(Exit {
+                    session_id: "s1".to_string(),
+                    exit_code: 0,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_session_exits_once_and_cancels_the_rest() {
+        let worker = FakeWorker::new(
+            FakeScript::new()
+                .wait(Duration::from_millis(50))
+                .output("late"),
+        );
+        let log = worker.log();
+        let mut process = worker.spawn();
+        let rx = process.take_receiver().expect("receiver");
+        start(&mut process, "s1");
+        process
+            .send_stop_session(StopSession {
+                session_id: "s1".to_string(),
+            })
+            .expect("stop session");
+        process.stop().expect("stop worker");
+        let messages: Vec<Message> = rx.iter().collect();
+        assert!(matches!(messages.last(), Some(Message::Exit(exit)) if exit.exit_code == 1));
+        assert!(!messages
+            .iter()
+            .any(|message| matches!(message, Message::Output(_))));
+        assert_eq!(log.messages().len(), 2);
+    }
+
+    #[test]
+    fn echo_input_round_trips_through_the_wire_format() {
+        let mut process = FakeWorker::new(FakeScript::new()).echo_input().spawn();
+        let rx = process.take_receiver().expect("receiver");
+        start(&mut process, "s1");
+        process
+            .send_input(SendInput {
+                session_id: "s1".to_string(),
+                text: "ls\n".to_string(),
+            })
+            .expect("send input");
+        let echoed = rx.try_iter().find_map(|message| match message {
+            Message::Output(output) => Some(output.chunk),
+            _ => None,
+        });
+        assert_eq!(echoed.as_deref(), Some("ls\n"));
+        process.stop().expect("stop worker");
+        assert!(process
+            .send_input(SendInput {
+                session_id: "s1".to_string(),
+                text: "after".to_string(),
+            })
+            .is_err());
+    }
+}
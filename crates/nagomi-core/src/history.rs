@@ -0,0 +1,410 @@
+// hook の入出力を project ごとの履歴（JSONL）に残す / Record hook input/output as per-project JSONL history.
+use crate::completion_hook::{extract_text_from_value, hook_kind_to_string, HookEvent};
+use crate::paths::sanitize_asset_component;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn append_jsonl_entry(path: &Path, payload: serde_json::Value) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut entry = payload;
+    if let Some(obj) = entry.as_object_mut() {
+        let stamped = u64::try_from(now_ms).unwrap_or(u64::MAX);
+        obj.insert(
+            "ts_ms".to_string(),
+            serde_json::Value::Number(stamped.into()),
+        );
+    }
+    let raw = serde_json::to_string(&entry).map_err(|err| err.to_string())?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| err.to_string())?;
+    writeln!(file, "{}", raw).map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+pub struct ProjectPromptHistoryRecord {
+    pub project_key: String,
+    pub payload: serde_json::Value,
+}
+
+// `<dir>/<project_key>.jsonl` に 1 行追記する / Append one line to `<dir>/<project_key>.jsonl`.
+pub fn append_project_prompt_history(
+    dir: &Path,
+    event: &HookEvent,
+    state: &str,
+    summary: &str,
+    launch_cwd: Option<&str>,
+) -> Result<Option<PathBuf>, String> {
+    let Some(record) = build_project_prompt_history_record(event, state, summary, launch_cwd)
+    else {
+        return Ok(None);
+    };
+    let path = dir.join(format!("{}.jsonl", record.project_key));
+    append_jsonl_entry(&path, record.payload)?;
+    Ok(Some(path))
+}
+
+pub fn build_project_prompt_history_record(
+    event: &HookEvent,
+    state: &str,
+    summary: &str,
+    fallback_cwd: Option<&str>,
+) -> Option<ProjectPromptHistoryRecord> {
+    let values = collect_hook_value_refs(event);
+    if values.is_empty() {
+        return None;
+    }
+
+    let cwd = read_hook_string_from_values(&values, &["cwd", "directory"])
+        .filter(|cwd| !cwd.trim().is_empty())
+        .or_else(|| fallback_cwd.map(str::to_string))
+        .unwrap_or_default();
+    let input_messages = read_hook_string_list_from_values(
+        &values,
+        &[
+            "input-messages",
+            "input_messages",
+            "inputMessages",
+            "user_messages",
+            "userMessages",
+            "prompts",
+        ],
+    );
+    let last_assistant_message = values
+        .iter()
+        .find_map(|value| extract_text_from_value(value))
+        .unwrap_or_default();
+
+    if cwd.trim().is_empty()
+        && input_messages.is_empty()
+        && last_assistant_message.trim().is_empty()
+    {
+        return None;
+    }
+
+    let project_locator = if cwd.trim().is_empty() {
+        event
+            .source_session_id
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", event.source, hook_kind_to_string(event.kind)))
+    } else {
+        normalize_project_locator(&cwd)
+    };
+    let project_label = if cwd.trim().is_empty() {
+        "unknown".to_string()
+    } else {
+        project_label_from_locator(&cwd)
+    };
+    let project_key = project_prompt_history_key(&project_locator, &project_label);
+
+    let mut payload = serde_json::Map::new();
+    payload.insert(
+        "event_type".to_string(),
+        serde_json::Value::String("project_prompt_history".to_string()),
+    );
+    payload.insert(
+        "source".to_string(),
+        serde_json::Value::String(event.source.clone()),
+    );
+    payload.insert(
+        "hook_kind".to_string(),
+        serde_json::Value::String(hook_kind_to_string(event.kind)),
+    );
+    payload.insert(
+        "state".to_string(),
+        serde_json::Value::String(state.to_string()),
+    );
+    payload.insert(
+        "project_key".to_string(),
+        serde_json::Value::String(project_key.clone()),
+    );
+    payload.insert(
+        "project_label".to_string(),
+        serde_json::Value::String(project_label),
+    );
+    payload.insert(
+        "project_locator".to_string(),
+        serde_json::Value::String(project_locator),
+    );
+    if !cwd.trim().is_empty() {
+        payload.insert("cwd".to_string(), serde_json::Value::String(cwd));
+    }
+    if let Some(source_session_id) = event.source_session_id.as_ref() {
+        if !source_session_id.trim().is_empty() {
+            payload.insert(
+                "source_session_id".to_string(),
+                serde_json::Value::String(source_session_id.clone()),
+            );
+        }
+    }
+    if let Some(thread_id) =
+        read_hook_string_from_values(&values, &["thread-id", "thread_id", "threadId"])
+    {
+        payload.insert(
+            "thread_id".to_string(),
+            serde_json::Value::String(thread_id),
+        );
+    }
+    if let Some(turn_id) = read_hook_string_from_values(&values, &["turn-id", "turn_id", "turnId"])
+    {
+        payload.insert("turn_id".to_string(), serde_json::Value::String(turn_id));
+    }
+    if !summary.trim().is_empty() {
+        payload.insert(
+            "summary".to_string(),
+            serde_json::Value::String(summary.to_string()),
+        );
+    }
+    if !input_messages.is_empty() {
+        payload.insert(
+            "input_messages".to_string(),
+            serde_json::Value::Array(
+                input_messages
+                    .into_iter()
+                    .map(serde_json::Value::String)
+                    .collect(),
+            ),
+        );
+    }
+    if !last_assistant_message.trim().is_empty() {
+        payload.insert(
+            "last_assistant_message".to_string(),
+            serde_json::Value::String(last_assistant_message),
+        );
+    }
+
+    Some(ProjectPromptHistoryRecord {
+        project_key,
+        payload: serde_json::Value::Object(payload),
+    })
+}
+
+fn collect_hook_value_refs(event: &HookEvent) -> Vec<&serde_json::Value> {
+    let Some(raw) = event.raw.as_ref() else {
+        return Vec::new();
+    };
+    let mut values = vec![raw];
+    if let Some(inner) = raw.get("event") {
+        values.push(inner);
+    }
+    values
+}
+
+fn read_hook_string_from_values(values: &[&serde_json::Value], keys: &[&str]) -> Option<String> {
+    for value in values {
+        let Some(obj) = value.as_object() else {
+            continue;
+        };
+        for key in keys {
+            if let Some(found) = obj.get(*key).and_then(normalize_hook_text_value) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+fn read_hook_string_list_from_values(values: &[&serde_json::Value], keys: &[&str]) -> Vec<String> {
+    for value in values {
+        let Some(obj) = value.as_object() else {
+            continue;
+        };
+        for key in keys {
+            let Some(found) = obj.get(*key) else {
+                continue;
+            };
+            let items = normalize_hook_text_list(found);
+            if !items.is_empty() {
+                return items;
+            }
+        }
+    }
+    Vec::new()
+}
+
+fn normalize_hook_text_list(value: &serde_json::Value) -> Vec<String> {
+    if let Some(text) = normalize_hook_text_value(value) {
+        return vec![text];
+    }
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+    items.iter().filter_map(normalize_hook_text_value).collect()
+}
+
+fn normalize_hook_text_value(value: &serde_json::Value) -> Option<String> {
+    if let Some(text) = value.as_str() {
+        let trimmed = text.trim();
+        if !trimmed.is_empty() {
+            return Some(trimmed.to_string());
+        }
+    }
+    let obj = value.as_object()?;
+    for key in ["text", "content", "message", "prompt"] {
+        if let Some(text) = obj.get(key).and_then(|raw| raw.as_str()) {
+            let trimmed = text.trim();
+            if !trimmed.is_empty() {
+                return Some(trimmed.to_string());
+            }
+        }
+    }
+    None
+}
+
+fn normalize_project_locator(raw: &str) -> String {
+    raw.trim()
+        .replace('\\', "/")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn project_label_from_locator(raw: &str) -> String {
+    let normalized = normalize_project_locator(raw);
+    let leaf = normalized.rsplit('/').next().unwrap_or(normalized.as_str());
+    sanitize_project_prompt_component(leaf)
+}
+
+fn sanitize_project_prompt_component(raw: &str) -> String {
+    sanitize_asset_component(raw).to_ascii_lowercase()
+}
+
+fn project_prompt_history_key(locator: &str, label: &str) -> String {
+    format!(
+        "{label}-{}",
+        stable_text_hash(&normalize_project_locator(locator))
+    )
+}
+
+fn stable_text_hash(value: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion_hook::HookEventKind;
+
+    #[test]
+    fn build_project_prompt_history_record_extracts_hook_io_by_project() {
+        let event = HookEvent {
+            source: "codex".to_string(),
+            kind: HookEventKind::Completed,
+            ts_ms: 123,
+            source_session_id: Some("ipc-1".to_string()),
+            raw: Some(serde_json::json!({
+                "source": "codex",
+                "cwd": "C:/Users/kitad/workspace/yurutsuku",
+                "event": {
+                    "type": "agent-turn-complete",
+                    "thread-id": "thread_123",
+                    "turn-id": "turn_456",
+                    "input-messages": ["Fix the failing test."],
+                    "last-assistant-message": "Patched and verified."
+                }
+            })),
+        };
+
+        let record = build_project_prompt_history_record(
+            &event,
+            "success",
+            "Patched and verified.",
+            Some("/ignored/when/hook/has/cwd"),
+        )
+        .expect("record");
+        let payload = record.payload.as_object().expect("payload object");
+
+        assert!(record.project_key.starts_with("yurutsuku-"));
+        assert_eq!(
+            payload.get("cwd").and_then(|value| value.as_str()),
+            Some("C:/Users/kitad/workspace/yurutsuku")
+        );
+        assert_eq!(
+            payload
+                .get("input_messages")
+                .and_then(|value| value.as_array())
+                .and_then(|items| items.first())
+                .and_then(|value| value.as_str()),
+            Some("Fix the failing test.")
+        );
+        assert_eq!(
+            payload
+                .get("last_assistant_message")
+                .and_then(|value| value.as_str()),
+            Some("Patched and verified.")
+        );
+        assert_eq!(
+            payload.get("thread_id").and_then(|value| value.as_str()),
+            Some("thread_123")
+        );
+        assert_eq!(
+            payload.get("turn_id").and_then(|value| value.as_str()),
+            Some("turn_456")
+        );
+    }
+
+    #[test]
+    fn build_project_prompt_history_record_falls_back_to_launch_cwd() {
+        let event = HookEvent {
+            source: "claude".to_string(),
+            kind: HookEventKind::Completed,
+            ts_ms: 123,
+            source_session_id: Some("terminal-1".to_string()),
+            raw: Some(serde_json::json!({
+                "source": "claude",
+                "last-assistant-message": "Done."
+            })),
+        };
+
+        let record = build_project_prompt_history_record(
+            &event,
+            "success",
+            "Done.",
+            Some("/home/me/work/yurutsuku"),
+        )
+        .expect("record");
+        let payload = record.payload.as_object().expect("payload object");
+
+        assert!(record.project_key.starts_with("yurutsuku-"));
+        assert_eq!(
+            payload
+                .get("project_label")
+                .and_then(|value| value.as_str()),
+            Some("yurutsuku")
+        );
+    }
+
+    #[test]
+    fn project_prompt_history_key_is_stable_for_same_locator() {
+        let left = project_prompt_history_key("C:/Users/kitad/workspace/yurutsuku", "yurutsuku");
+        let right = project_prompt_history_key("C:/Users/kitad/workspace/yurutsuku/", "yurutsuku");
+        let other = project_prompt_history_key(
+            "C:/Users/kitad/workspace/another-project",
+            "another-project",
+        );
+
+        assert_eq!(
+            left,
+            project_prompt_history_key("C:/Users/kitad/workspace/yurutsuku", "yurutsuku")
+        );
+        assert_eq!(left, right);
+        assert_ne!(left, other);
+        assert_eq!(
+            normalize_project_locator("C:/Users/kitad/workspace/yurutsuku/"),
+            "C:/Users/kitad/workspace/yurutsuku"
+        );
+    }
+}
@@ -0,0 +1,275 @@
+// hook event の重複を畳み、session ごとの順序を守る / Collapses duplicate hook events and keeps per-session order.
+//
+// 同じ hook が file tail と POST の両方から届いたり、agent が同じ通知を続けて出したりしても
+// 状態・履歴・通知は 1 回にする。順序は読んだ時刻ではなく hook 自身の `ts_ms` で決める。
+// The same hook can arrive through both the file tail and POST, or an agent can repeat a
+// notification; state, history and notifications still happen once. Order follows the hook's
+// own `ts_ms`, not the time it was read.
+use crate::completion_hook::{HookEvent, HookEventKind};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+pub const DEFAULT_HOOK_DEDUP_WINDOW_MS: u64 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookVerdict {
+    Accept,
+    // 窓内に同じ内容の event がある / The same content already arrived within the window.
+    Duplicate,
+    // 同じ session でより新しい event を反映済み / A newer event for the session is already applied.
+    OutOfOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DedupKey {
+    source: String,
+    session: String,
+    kind: HookEventKind,
+    content: u64,
+}
+
+#[derive(Debug)]
+pub struct HookGate {
+    window_ms: u64,
+    recent: HashMap<DedupKey, u64>,
+    // session（無ければ source）ごとの最新 ts / Latest ts per session, or per source without one.
+    latest: HashMap<String, u64>,
+}
+
+impl Default for HookGate {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOOK_DEDUP_WINDOW_MS)
+    }
+}
+
+impl HookGate {
+    // 0 で重複の畳み込みを止める（順序の保証は残る）/ 0 turns dedup off; ordering still applies.
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            recent: HashMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    pub fn set_window_ms(&mut self, window_ms: u64) {
+        self.window_ms = window_ms;
+        if window_ms == 0 {
+            self.recent.clear();
+        }
+    }
+
+    pub fn admit(&mut self, event: &HookEvent) -> HookVerdict {
+        let ts = event.ts_ms;
+        if self.window_ms > 0 {
+            let window = self.window_ms;
+            let key = DedupKey {
+                source: event.source.clone(),
+                session: event.source_session_id.clone().unwrap_or_default(),
+                kind: event.kind,
+                content: content_hash(event.raw.as_ref()),
+            };
+            if let Some(&seen) = self.recent.get(&key) {
+                if ts.abs_diff(seen) <= window {
+                    return HookVerdict::Duplicate;
+                }
+            }
+            self.recent
+                .retain(|_, seen| seen.saturating_add(window) >= ts);
+            self.recent.insert(key, ts);
+        }
+        let order_key = match &event.source_session_id {
+            Some(session_id) => session_id.clone(),
+            None => format!("source:{}", event.source),
+        };
+        let latest = self.latest.entry(order_key).or_insert(ts);
+        if ts < *latest {
+            return HookVerdict::OutOfOrder;
+        }
+        *latest = ts;
+        HookVerdict::Accept
+    }
+
+    // 閉じた session の記録を捨てる。残すと map が増え続ける
+    // Drops what is kept for a closed session; otherwise the maps only ever grow.
+    pub fn forget(&mut self, session_id: &str) {
+        self.latest.remove(session_id);
+        self.recent.retain(|key, _| key.session != session_id);
+    }
+}
+
+// 届いた時刻で変わる field は除いて比べる / Fields that change with delivery time are left out.
+fn content_hash(raw: Option<&Value>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    if let Some(raw) = raw {
+        let mut raw = raw.clone();
+        strip_volatile(&mut raw);
+        if let Some(event) = raw.get_mut("event") {
+            strip_volatile(event);
+        }
+        raw.to_string().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn strip_volatile(value: &mut Value) {
+    if let Some(object) = value.as_object_mut() {
+        for key in ["ts_ms", "tsMs", "timestamp_ms"] {
+            object.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(
+        source: &str,
+        session: Option<&str>,
+        kind: HookEventKind,
+        ts_ms: u64,
+        raw: Value,
+    ) -> HookEvent {
+        HookEvent {
+            source: source.to_string(),
+            kind,
+            ts_ms,
+            source_session_id: session.map(str::to_string),
+            raw: Some(raw),
+        }
+    }
+
+    #[test]
+    fn bursts_of_the_same_hook_collapse_within_the_window() {
+        let mut gate = HookGate::new(1_000);
+        let stop = |ts: u64| {
+            event(
+                "claude",
+                Some("term-a"),
+                HookEventKind::Completed,
+                ts,
+                json!({ "event": { "hook_event_name": "Stop", "ts_ms": ts }, "ts_ms": ts }),
+            )
+        };
+        assert_eq!(gate.admit(&stop(100)), HookVerdict::Accept);
+        assert_eq!(gate.admit(&stop(150)), HookVerdict::Duplicate);
+        assert_eq!(gate.admit(&stop(900)), HookVerdict::Duplicate);
+        // 窓を過ぎた同じ内容は新しい完了 / Past the window the same content is a new completion.
+        assert_eq!(gate.admit(&stop(1_200)), HookVerdict::Accept);
+
+        // 内容・kind・session・source のどれかが違えば別物
+        // A different content, kind, session or source is a different event.
+        let other = |source: &str, session: &str, kind, message: &str| {
+            event(
+                source,
+                Some(session),
+                kind,
+                1_300,
+                json!({ "message": message }),
+            )
+        };
+        assert_eq!(
+            gate.admit(&other(
+                "claude",
+                "term-a",
+                HookEventKind::NeedInput,
+                "allow?"
+            )),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&other(
+                "claude",
+                "term-a",
+                HookEventKind::NeedInput,
+                "allow rm?"
+            )),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&other(
+                "claude",
+                "term-b",
+                HookEventKind::NeedInput,
+                "allow?"
+            )),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&other(
+                "codex",
+                "term-a",
+                HookEventKind::NeedInput,
+                "allow?"
+            )),
+            HookVerdict::Accept
+        );
+    }
+
+    #[test]
+    fn out_of_order_lines_do_not_override_newer_state() {
+        let mut gate = HookGate::new(0);
+        let line = |session: Option<&str>, kind, ts| {
+            event("codex", session, kind, ts, json!({ "ts": ts }))
+        };
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::NeedInput, 2_000)),
+            HookVerdict::Accept
+        );
+        // 古い完了が後から届いても入力待ちを上書きしない
+        // A late, older completion does not override need-input.
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 1_500)),
+            HookVerdict::OutOfOrder
+        );
+        assert_eq!(
+            gate.admit(&line(Some("b"), HookEventKind::Completed, 1_500)),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 2_000)),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&line(None, HookEventKind::Completed, 10)),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&line(None, HookEventKind::Error, 5)),
+            HookVerdict::OutOfOrder
+        );
+
+        // 窓 0 なら重複も通す / With a zero window duplicates pass.
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 2_000)),
+            HookVerdict::Accept
+        );
+        gate.set_window_ms(500);
+        assert_eq!(gate.window_ms(), 500);
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 2_100)),
+            HookVerdict::Accept
+        );
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 2_100)),
+            HookVerdict::Duplicate
+        );
+
+        // 閉じた session は順序も重複も覚えていない / A closed session keeps no order or dedup state.
+        gate.forget("a");
+        assert!(!gate.latest.contains_key("a"));
+        assert!(gate.recent.keys().all(|key| key.session != "a"));
+        assert_eq!(
+            gate.admit(&line(Some("a"), HookEventKind::Completed, 100)),
+            HookVerdict::Accept
+        );
+    }
+}
@@ -0,0 +1,820 @@
+// agent の hook 設定を導入/削除する / Install and remove agent hook configs.
+// Codex は ~/.codex/config.toml の notify、Claude Code は settings.json の hooks、OpenCode は plugin ファイル。
+// Codex uses notify in ~/.codex/config.toml, Claude Code the hooks in settings.json, OpenCode a plugin file.
+// どれも `nagomi hook emit --source <tool>` を呼ぶ / All of them run `nagomi hook emit --source <tool>`.
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::worker::workspace_root;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookInstallAction {
+    Install,
+    Uninstall,
+}
+
+// 設定ファイル 1 つの書き換え計画。差分の確認と適用（バックアップ付き）を分ける
+// A planned rewrite of one config file, so the diff can be previewed before it is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEdit {
+    pub path: PathBuf,
+    pub before: Option<String>,
+    // None はファイル削除 / None removes the file.
+    pub after: Option<String>,
+}
+
+impl ConfigEdit {
+    fn load(path: PathBuf) -> Result<Self, String> {
+        let before = match fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(format!("{}: {err}", path.display())),
+        };
+        Ok(Self {
+            after: before.clone(),
+            path,
+            before,
+        })
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.before == self.after
+    }
+
+    pub fn diff(&self) -> String {
+        unified_diff(
+            &self.path.to_string_lossy(),
+            self.before.as_deref().unwrap_or(""),
+            self.after.as_deref().unwrap_or(""),
+        )
+    }
+
+    pub fn backup_path(&self) -> PathBuf {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.path.with_file_name(format!("{name}.nagomi.bak"))
+    }
+
+    // 既存ファイルは `<name>.nagomi.bak` に退避してから書く。退避は最初の 1 回だけで、
+    // 以後の適用では上書きしない（nagomi が触る前の内容を残す）。退避先を返す
+    // Existing files are copied to `<name>.nagomi.bak` first; returns that path. Only the first
+    // backup is written, so it keeps the content from before nagomi ever touched the file.
+    pub fn apply(&self) -> Result<Option<PathBuf>, String> {
+        if self.is_noop() {
+            return Ok(None);
+        }
+        let backup = match &self.before {
+            Some(before) => {
+                let backup = self.backup_path();
+                if !backup.exists() {
+                    fs::write(&backup, before).map_err(|err| err.to_string())?;
+                }
+                Some(backup)
+            }
+            None => None,
+        };
+        match &self.after {
+            Some(after) => {
+                if let Some(parent) = self.path.parent() {
+                    fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+                }
+                fs::write(&self.path, after).map_err(|err| err.to_string())?;
+            }
+            None => fs::remove_file(&self.path).map_err(|err| err.to_string())?,
+        }
+        Ok(backup)
+    }
+}
+
+// 行単位の差分（前後 2 行の文脈付き）/ Line diff with two lines of context.
+pub fn unified_diff(label: &str, before: &str, after: &str) -> String {
+    const CONTEXT: usize = 2;
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    // 最長共通部分列 / Longest common subsequence table.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+            ops.push(('+', new[j]));
+            j += 1;
+        } else {
+            ops.push(('-', old[i]));
+            i += 1;
+        }
+    }
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- {label}\n+++ {label}\n");
+    let mut last_shown: Option<usize> = None;
+    for (k, (mark, line)) in ops.iter().enumerate() {
+        let near = changed
+            .iter()
+            .any(|&c| k + CONTEXT >= c && k <= c + CONTEXT);
+        if !near {
+            continue;
+        }
+        if last_shown.map(|last| k > last + 1).unwrap_or(true) {
+            out.push_str("@@\n");
+        }
+        out.push(*mark);
+        out.push_str(line);
+        out.push('\n');
+        last_shown = Some(k);
+    }
+    out
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("USERPROFILE")
+        .or_else(|| std::env::var_os("HOME"))
+        .map(PathBuf::from)
+}
+
+pub fn codex_config_path() -> Option<PathBuf> {
+    Some(home_dir()?.join(".codex").join("config.toml"))
+}
+
+fn cli_exe_name() -> &'static str {
+    if cfg!(windows) {
+        "nagomi.exe"
+    } else {
+        "nagomi"
+    }
+}
+
+// notify から呼ぶ nagomi CLI: 実行中バイナリの隣 → 開発ビルド → PATH の順
+// The nagomi CLI that notify runs: next to this binary, then the dev build, then PATH.
+pub fn resolve_cli_path() -> PathBuf {
+    if let Ok(exe_path) = std::env::current_exe() {
+        if let Some(dir) = exe_path.parent() {
+            let candidate = dir.join(cli_exe_name());
+            if candidate.exists() {
+                return candidate;
+            }
+        }
+    }
+    if let Some(root) = workspace_root() {
+        let candidate = root.join("target").join("debug").join(cli_exe_name());
+        if candidate.exists() {
+            return candidate;
+        }
+    }
+    PathBuf::from(cli_exe_name())
+}
+
+fn toml_escape_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "/")
+        .replace('"', "\\\"")
+}
+
+pub fn codex_notify_line(cli_path: &Path) -> String {
+    format!(
+        "notify = [\"{}\", \"hook\", \"emit\", \"--source\", \"codex\"]",
+        toml_escape_path(cli_path)
+    )
+}
+
+pub fn ensure_codex_hook_files(base_dir: &Path) -> Result<PathBuf, String> {
+    fs::create_dir_all(base_dir).map_err(|err| err.to_string())?;
+    let hook_path = base_dir.join("codex.jsonl");
+    if !hook_path.exists() {
+        fs::write(&hook_path, "").map_err(|err| err.to_string())?;
+    }
+    Ok(hook_path)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CodexNotifyState {
+    Missing,
+    Current,
+    // 以前の nagomi が入れた notify（node/py スクリプト、npm コマンド、別パスの CLI）
+    // A notify from an older nagomi (node/py script, npm command, CLI at another path).
+    Legacy,
+    Foreign,
+}
+
+fn is_nagomi_notify(line: &str) -> bool {
+    line.contains("nagomi_codex_notify")
+        || line.contains("nagomi-codex-notify")
+        || (line.contains("\"hook\", \"emit\"") && line.contains("\"codex\""))
+}
+
+fn classify_codex_notify(current: &str, notify_line: &str) -> CodexNotifyState {
+    let mut seen_table = false;
+    let mut top_level = None;
+    let mut has_legacy = false;
+    for line in current.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            seen_table = true;
+        }
+        if !trimmed.starts_with("notify") {
+            continue;
+        }
+        if !seen_table && top_level.is_none() {
+            top_level = Some(trimmed);
+        }
+        has_legacy |= is_nagomi_notify(trimmed);
+    }
+    match top_level {
+        Some(line) if line == notify_line => CodexNotifyState::Current,
+        Some(line) if is_nagomi_notify(line) => CodexNotifyState::Legacy,
+        Some(_) => CodexNotifyState::Foreign,
+        None if has_legacy => CodexNotifyState::Legacy,
+        None => CodexNotifyState::Missing,
+    }
+}
+
+const CODEX_FOREIGN_NOTIFY: &str = "notify already present; remove it first to install nagomi's";
+
+// Settings からの導入。書き換えは `plan_codex_notify` が決め、ここは結果を UI の status にする
+// The Settings install: `plan_codex_notify` decides the rewrite and this maps it to a UI status.
+pub fn ensure_codex_config(cli_path: &Path) -> Result<(String, String), String> {
+    let edit = match plan_codex_notify(cli_path, HookInstallAction::Install) {
+        Ok(edit) => edit,
+        Err(err) if err == CODEX_FOREIGN_NOTIFY => {
+            return Ok((
+                "skipped_existing_notify".to_string(),
+                "notify already present; skipped updating config".to_string(),
+            ));
+        }
+        Err(err) => return Err(err),
+    };
+    let current = edit.before.as_deref().unwrap_or("");
+    let state = classify_codex_notify(current, &codex_notify_line(cli_path));
+    let (status, message) = if edit.is_noop() {
+        ("already_installed", "codex notify already configured")
+    } else if state == CodexNotifyState::Legacy {
+        ("migrated_to_cli", "codex notify updated")
+    } else {
+        ("installed", "codex notify configured")
+    };
+    edit.apply()?;
+    Ok((status.to_string(), message.to_string()))
+}
+
+pub fn plan_codex_notify(cli_path: &Path, action: HookInstallAction) -> Result<ConfigEdit, String> {
+    let config_path = codex_config_path().ok_or_else(|| "codex config path missing".to_string())?;
+    let mut edit = ConfigEdit::load(config_path)?;
+    let current = edit.before.clone().unwrap_or_default();
+    let notify_line = codex_notify_line(cli_path);
+    let state = classify_codex_notify(&current, &notify_line);
+    let next = match (action, state) {
+        (HookInstallAction::Install, CodexNotifyState::Current) => return Ok(edit),
+        (HookInstallAction::Install, CodexNotifyState::Foreign) => {
+            return Err(CODEX_FOREIGN_NOTIFY.to_string());
+        }
+        (HookInstallAction::Install, _) => rewrite_codex_notify_config_text(&current, &notify_line),
+        (HookInstallAction::Uninstall, CodexNotifyState::Missing | CodexNotifyState::Foreign) => {
+            return Ok(edit);
+        }
+        (HookInstallAction::Uninstall, _) => remove_codex_notify_config_text(&current),
+    };
+    edit.after = Some(format!("{next}\n"));
+    Ok(edit)
+}
+
+// nagomi が入れた notify 行だけを外す / Drop only the notify lines nagomi added.
+fn remove_codex_notify_config_text(current: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let mut removed = false;
+    for line in current.lines() {
+        let trimmed = line.trim();
+        if trimmed == "# added by nagomi"
+            || (trimmed.starts_with("notify") && is_nagomi_notify(trimmed))
+        {
+            removed = true;
+            continue;
+        }
+        // 挿入時に足した空行を詰める / Collapse the blank lines left around the removed block.
+        let prev_blank = out
+            .last()
+            .map(|prev| prev.trim().is_empty())
+            .unwrap_or(true);
+        if removed && trimmed.is_empty() && prev_blank {
+            continue;
+        }
+        removed = false;
+        out.push(line);
+    }
+    out.join("\n")
+}
+
+fn rewrite_codex_notify_config_text(current: &str, notify_line: &str) -> String {
+    let mut filtered_lines: Vec<String> = Vec::new();
+    let mut insert_at = None;
+    for line in current.lines() {
+        let trimmed = line.trim_start();
+        if insert_at.is_none() && trimmed.starts_with('[') {
+            insert_at = Some(filtered_lines.len());
+        }
+        if trimmed == "# added by nagomi" || trimmed.starts_with("notify") {
+            continue;
+        }
+        filtered_lines.push(line.to_string());
+    }
+    let insert_at = insert_at.unwrap_or(filtered_lines.len());
+    let mut next_lines: Vec<String> = Vec::new();
+    next_lines.extend(filtered_lines[..insert_at].iter().cloned());
+    // 旧 notify を外した跡の空行は 1 行にまとめる / Keep a single blank line where the old notify was.
+    while next_lines
+        .last()
+        .map(|line| line.trim().is_empty())
+        .unwrap_or(false)
+    {
+        next_lines.pop();
+    }
+    if !next_lines.is_empty() {
+        next_lines.push(String::new());
+    }
+    // notify はトップレベルに置く / Keep notify at TOML top-level so Codex can read it.
+    next_lines.push("# added by nagomi".to_string());
+    next_lines.push(notify_line.to_string());
+    if insert_at < filtered_lines.len() {
+        next_lines.push(String::new());
+    }
+    next_lines.extend(filtered_lines[insert_at..].iter().cloned());
+    next_lines.join("\n")
+}
+
+// Claude Code: settings.json の hooks に emit を足す / Claude Code: add emit to the hooks in settings.json.
+pub const CLAUDE_HOOK_EVENTS: [&str; 3] = ["Stop", "Notification", "PermissionRequest"];
+
+// project を渡すと `<project>/.claude/settings.json`、無ければ `~/.claude/settings.json`
+// `<project>/.claude/settings.json` for a project, `~/.claude/settings.json` otherwise.
+pub fn claude_settings_path(project: Option<&Path>) -> Option<PathBuf> {
+    let base = match project {
+        Some(dir) => dir.to_path_buf(),
+        None => home_dir()?,
+    };
+    Some(base.join(".claude").join("settings.json"))
+}
+
+fn shell_quote_path(path: &Path) -> String {
+    let text = path.to_string_lossy().to_string();
+    if text.contains(char::is_whitespace) {
+        format!("\"{text}\"")
+    } else {
+        text
+    }
+}
+
+pub fn claude_hook_command(cli_path: &Path) -> String {
+    format!("{} hook emit --source claude", shell_quote_path(cli_path))
+}
+
+// 以前の手書き例（nagomi_hook.py）も nagomi の hook とみなす
+// The earlier hand-written example (nagomi_hook.py) counts as nagomi's too.
+fn is_nagomi_claude_command(command: &str) -> bool {
+    command.contains("hook emit --source claude") || command.contains("nagomi_hook.py")
+}
+
+// 既存の hooks は残し、nagomi の command だけを足す/外す。`command` が None なら削除
+// Keeps every other hook and only adds or removes nagomi's; `command = None` uninstalls.
+pub fn merge_claude_hooks(current: &str, command: Option<&str>) -> Result<String, String> {
+    let original: Value = if current.trim().is_empty() {
+        Value::Object(Map::new())
+    } else {
+        serde_json::from_str(current).map_err(|err| format!("invalid settings.json: {err}"))?
+    };
+    let keep_empty_hooks = original
+        .get("hooks")
+        .and_then(Value::as_object)
+        .map(Map::is_empty)
+        .unwrap_or(false);
+    let mut root = original.clone();
+    let root_map = root
+        .as_object_mut()
+        .ok_or_else(|| "settings.json is not a JSON object".to_string())?;
+    let hooks = root_map
+        .entry("hooks")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| "settings.json `hooks` is not an object".to_string())?;
+    let mut kept: Vec<String> = Vec::new();
+    for (event, groups) in hooks.iter_mut() {
+        let Some(groups) = groups.as_array_mut() else {
+            continue;
+        };
+        let wanted = command.filter(|_| CLAUDE_HOOK_EVENTS.contains(&event.as_str()));
+        groups.retain_mut(|group| {
+            let Some(entries) = group.get_mut("hooks").and_then(Value::as_array_mut) else {
+                return true;
+            };
+            let before = entries.len();
+            entries.retain(|entry| {
+                let Some(cmd) = entry.get("command").and_then(Value::as_str) else {
+                    return true;
+                };
+                if !is_nagomi_claude_command(cmd) {
+                    return true;
+                }
+                // 同じ command が既にあれば 1 つだけ残す / Keep a single copy of the current command.
+                if wanted == Some(cmd) && !kept.contains(event) {
+                    kept.push(event.clone());
+                    return true;
+                }
+                false
+            });
+            !(entries.is_empty() && before > 0)
+        });
+    }
+    if let Some(command) = command {
+        for event in CLAUDE_HOOK_EVENTS {
+            if kept.iter().any(|name| name == event) {
+                continue;
+            }
+            let mut group = Map::new();
+            if event == "PermissionRequest" {
+                group.insert("matcher".to_string(), json!("*"));
+            }
+            group.insert(
+                "hooks".to_string(),
+                json!([{ "type": "command", "command": command }]),
+            );
+            let groups = hooks
+                .entry(event.to_string())
+                .or_insert_with(|| Value::Array(Vec::new()));
+            match groups.as_array_mut() {
+                Some(groups) => groups.push(Value::Object(group)),
+                None => return Err(format!("settings.json `hooks.{event}` is not an array")),
+            }
+        }
+    }
+    hooks.retain(|_, groups| groups.as_array().map(|g| !g.is_empty()).unwrap_or(true));
+    if hooks.is_empty() && !keep_empty_hooks {
+        root_map.remove("hooks");
+    }
+    // 変化が無ければ元の書式のまま / Leave the file's formatting alone when nothing changed.
+    if root == original {
+        return Ok(current.to_string());
+    }
+    let text = serde_json::to_string_pretty(&root).map_err(|err| err.to_string())?;
+    Ok(format!("{text}\n"))
+}
+
+pub fn plan_claude_hooks(
+    project: Option<&Path>,
+    cli_path: &Path,
+    action: HookInstallAction,
+) -> Result<ConfigEdit, String> {
+    let path = claude_settings_path(project).ok_or_else(|| "home directory missing".to_string())?;
+    let mut edit = ConfigEdit::load(path)?;
+    let current = edit.before.clone().unwrap_or_default();
+    let command = claude_hook_command(cli_path);
+    let command = (action == HookInstallAction::Install).then_some(command.as_str());
+    let next = merge_claude_hooks(&current, command)?;
+    if next != current {
+        edit.after = Some(next);
+    }
+    Ok(edit)
+}
+
+// OpenCode: plugin ファイルから emit を呼ぶ / OpenCode: a plugin file that runs emit.
+const OPENCODE_PLUGIN_MARKER: &str = "// added by nagomi";
+
+// project を渡すと `<project>/.opencode/plugins/nagomi.js`、無ければ `~/.config/opencode/plugins/nagomi.js`
+// `<project>/.opencode/plugins/nagomi.js` for a project, `~/.config/opencode/plugins/nagomi.js` otherwise.
+pub fn opencode_plugin_path(project: Option<&Path>) -> Option<PathBuf> {
+    let dir = match project {
+        Some(dir) => dir.join(".opencode"),
+        None => std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(home_dir()?.join(".config")))?
+            .join("opencode"),
+    };
+    Some(dir.join("plugins").join("nagomi.js"))
+}
+
+pub fn opencode_plugin_body(cli_path: &Path) -> String {
+    let cli = serde_json::to_string(&cli_path.to_string_lossy()).unwrap_or_default();
+    [
+        OPENCODE_PLUGIN_MARKER,
+        "// `nagomi hook uninstall --tool opencode` で削除できます / remove with `nagomi hook uninstall --tool opencode`",
+        &format!("const NAGOMI_CLI = {cli};"),
+        "const EVENTS = new Set([\"session.idle\", \"session.error\", \"permission.updated\", \"permission.replied\"]);",
+        "",
+        "export const NagomiNotify = async ({ $ }) => ({",
+        "  event: async ({ event }) => {",
+        "    if (!EVENTS.has(event.type)) return;",
+        "    await $`${NAGOMI_CLI} hook emit --source opencode ${JSON.stringify(event)}`.quiet().nothrow();",
+        "  },",
+        "});",
+        "",
+    ]
+    .join("\n")
+}
+
+pub fn plan_opencode_plugin(
+    project: Option<&Path>,
+    cli_path: &Path,
+    action: HookInstallAction,
+) -> Result<ConfigEdit, String> {
+    let path = opencode_plugin_path(project).ok_or_else(|| "home directory missing".to_string())?;
+    let mut edit = ConfigEdit::load(path)?;
+    // nagomi が書いていないファイルには触らない / Never touch a file nagomi did not write.
+    if let Some(before) = &edit.before {
+        if !before.starts_with(OPENCODE_PLUGIN_MARKER) {
+            return Err(format!("{} was not written by nagomi", edit.path.display()));
+        }
+    }
+    edit.after = match action {
+        HookInstallAction::Install => Some(opencode_plugin_body(cli_path)),
+        HookInstallAction::Uninstall => None,
+    };
+    Ok(edit)
+}
+
+// tool 名から導入計画を作る（codex は user 設定のみ）/ Plan by tool name (codex is user-level only).
+pub fn plan_hook_install(
+    tool: &str,
+    project: Option<&Path>,
+    cli_path: &Path,
+    action: HookInstallAction,
+) -> Result<ConfigEdit, String> {
+    match tool {
+        "codex" if project.is_some() => Err("codex notify is user-level only".to_string()),
+        "codex" => plan_codex_notify(cli_path, action),
+        "claude" => plan_claude_hooks(project, cli_path, action),
+        "opencode" => plan_opencode_plugin(project, cli_path, action),
+        other => Err(format!("unknown hook tool: {other}")),
+    }
+}
+
+// 適用結果の status / Status for an applied edit.
+pub fn hook_install_status(edit: &ConfigEdit, action: HookInstallAction) -> &'static str {
+    match (action, edit.is_noop()) {
+        (HookInstallAction::Install, true) => "already_installed",
+        (HookInstallAction::Install, false) => "installed",
+        (HookInstallAction::Uninstall, true) => "not_installed",
+        (HookInstallAction::Uninstall, false) => "removed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_codex_notify_config_keeps_notify_at_top_level() {
+        let current = r#"model = "gpt-5.4"
+[features]
+
+[notice.model_migrations]
+"gpt-5.2" = "gpt-5.3-codex"
+# added by nagomi
+notify = "nagomi-codex-notify"
+"#;
+        let next = rewrite_codex_notify_config_text(
+            current,
+            r#"notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]"#,
+        );
+        let expected = r#"model = "gpt-5.4"
+
+# added by nagomi
+notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]
+
+[features]
+
+[notice.model_migrations]
+"gpt-5.2" = "gpt-5.3-codex""#;
+        assert_eq!(next, expected);
+    }
+
+    #[test]
+    fn rewrite_codex_notify_config_replaces_existing_top_level_notify() {
+        let current = r#"model = "gpt-5.4"
+# added by nagomi
+notify = ["nagomi-codex-notify"]
+
+[features]"#;
+        let next = rewrite_codex_notify_config_text(
+            current,
+            r#"notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]"#,
+        );
+        let expected = r#"model = "gpt-5.4"
+
+# added by nagomi
+notify = ["node", "C:/Users/test/.nagomi/hooks/nagomi_codex_notify.js"]
+
+[features]"#;
+        assert_eq!(next, expected);
+    }
+
+    #[test]
+    fn codex_notify_is_classified_before_rewriting() {
+        let line = codex_notify_line(Path::new("C:\\tools\\nagomi.exe"));
+        assert_eq!(
+            line,
+            r#"notify = ["C:/tools/nagomi.exe", "hook", "emit", "--source", "codex"]"#
+        );
+        let cases = [
+            ("model = \"gpt-5.4\"\n[features]", CodexNotifyState::Missing),
+            (line.as_str(), CodexNotifyState::Current),
+            (
+                r#"notify = ["node", "/home/u/.nagomi/hooks/nagomi_codex_notify.js"]"#,
+                CodexNotifyState::Legacy,
+            ),
+            (
+                r#"notify = ["python3", "/home/u/.nagomi/hooks/nagomi_codex_notify.py"]"#,
+                CodexNotifyState::Legacy,
+            ),
+            (
+                r#"notify = ["/old/nagomi", "hook", "emit", "--source", "codex"]"#,
+                CodexNotifyState::Legacy,
+            ),
+            (
+                "[notice]\nnotify = \"nagomi-codex-notify\"",
+                CodexNotifyState::Legacy,
+            ),
+            (
+                r#"notify = ["notify-send", "codex"]"#,
+                CodexNotifyState::Foreign,
+            ),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(classify_codex_notify(config, &line), expected, "{config}");
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nonce = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("nagomi-hook-setup-{name}-{nonce}"));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn claude_commands(text: &str, event: &str) -> Vec<String> {
+        let value: Value = serde_json::from_str(text).expect("settings parse");
+        value["hooks"][event]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|group| group["hooks"].as_array().cloned().unwrap_or_default())
+            .filter_map(|entry| entry["command"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn claude_hooks_merge_into_existing_settings_and_uninstall_cleanly() {
+        let fixture = include_str!("../../../testdata/hook_setup/claude_settings.json");
+        let command = claude_hook_command(Path::new("/opt/nagomi/nagomi"));
+        let installed = merge_claude_hooks(fixture, Some(&command)).expect("install");
+        assert_eq!(
+            claude_commands(&installed, "Stop"),
+            vec!["say done".to_string(), command.clone()]
+        );
+        assert_eq!(
+            claude_commands(&installed, "Notification"),
+            vec![command.clone()]
+        );
+        assert_eq!(
+            claude_commands(&installed, "PermissionRequest"),
+            vec![command.clone()]
+        );
+        assert_eq!(
+            claude_commands(&installed, "PreToolUse"),
+            vec!["./scripts/check-bash.sh".to_string()]
+        );
+        // 既存キーの順序を保つ / Existing keys keep their order.
+        let keys: Vec<String> = serde_json::from_str::<Map<String, Value>>(&installed)
+            .expect("object")
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(keys, ["permissions", "hooks", "model"]);
+
+        let again = merge_claude_hooks(&installed, Some(&command)).expect("reinstall");
+        assert_eq!(again, installed);
+
+        let removed = merge_claude_hooks(&installed, None).expect("uninstall");
+        assert_eq!(
+            claude_commands(&removed, "Stop"),
+            vec!["say done".to_string()]
+        );
+        assert!(claude_commands(&removed, "Notification").is_empty());
+        assert!(claude_commands(&removed, "PermissionRequest").is_empty());
+        let removed_value: Value = serde_json::from_str(&removed).expect("parse");
+        assert_eq!(removed_value["model"], "sonnet");
+        assert!(removed_value["hooks"].get("Notification").is_none());
+
+        let fresh = merge_claude_hooks("", Some(&command)).expect("fresh install");
+        assert_eq!(
+            merge_claude_hooks(&fresh, None).expect("fresh uninstall"),
+            "{}\n"
+        );
+        assert!(merge_claude_hooks("[1]", Some(&command)).is_err());
+    }
+
+    #[test]
+    fn codex_notify_uninstall_restores_the_fixture_layout() {
+        let fixture = include_str!("../../../testdata/hook_setup/codex_config.toml");
+        let line = codex_notify_line(Path::new("/opt/nagomi/nagomi"));
+        assert_eq!(
+            classify_codex_notify(fixture, &line),
+            CodexNotifyState::Legacy
+        );
+        let installed = rewrite_codex_notify_config_text(fixture, &line);
+        assert_eq!(
+            classify_codex_notify(&installed, &line),
+            CodexNotifyState::Current
+        );
+        let removed = remove_codex_notify_config_text(&installed);
+        assert_eq!(
+            removed,
+            "model = \"gpt-5.4\"\napproval_policy = \"on-request\"\n\n[features]\nweb_search = true"
+        );
+        assert_eq!(
+            classify_codex_notify(&removed, &line),
+            CodexNotifyState::Missing
+        );
+    }
+
+    #[test]
+    fn opencode_plugin_edits_back_up_and_leave_foreign_files_alone() {
+        let project = temp_dir("opencode");
+        let cli = Path::new("/opt/nagomi/nagomi");
+        let install = plan_opencode_plugin(Some(&project), cli, HookInstallAction::Install)
+            .expect("plan install");
+        assert_eq!(
+            hook_install_status(&install, HookInstallAction::Install),
+            "installed"
+        );
+        assert!(install
+            .diff()
+            .contains("+const NAGOMI_CLI = \"/opt/nagomi/nagomi\";"));
+        assert_eq!(install.apply().expect("apply"), None);
+        let path = project.join(".opencode").join("plugins").join("nagomi.js");
+        assert!(fs::read_to_string(&path)
+            .expect("plugin")
+            .contains("--source opencode"));
+
+        let again = plan_opencode_plugin(Some(&project), cli, HookInstallAction::Install)
+            .expect("plan again");
+        assert_eq!(
+            hook_install_status(&again, HookInstallAction::Install),
+            "already_installed"
+        );
+        assert!(again.diff().is_empty());
+
+        let other = plan_opencode_plugin(
+            Some(&project),
+            Path::new("/usr/bin/nagomi"),
+            HookInstallAction::Install,
+        )
+        .expect("plan other cli");
+        let backup = other.apply().expect("apply other").expect("backup");
+        assert!(fs::read_to_string(&backup)
+            .expect("backup")
+            .contains("/opt/nagomi/nagomi"));
+
+        let uninstall = plan_opencode_plugin(Some(&project), cli, HookInstallAction::Uninstall)
+            .expect("plan uninstall");
+        assert_eq!(
+            hook_install_status(&uninstall, HookInstallAction::Uninstall),
+            "removed"
+        );
+        uninstall.apply().expect("remove");
+        assert!(!path.exists());
+        // 最初の退避は後の適用で上書きしない / Later applies keep the first backup.
+        assert!(fs::read_to_string(&backup)
+            .expect("first backup")
+            .contains("/opt/nagomi/nagomi"));
+
+        fs::write(&path, "export const Mine = async () => ({});\n").expect("write foreign");
+        assert!(plan_opencode_plugin(Some(&project), cli, HookInstallAction::Install).is_err());
+        let _ = fs::remove_dir_all(project);
+    }
+
+    #[test]
+    fn unified_diff_shows_changes_with_context() {
+        let diff = unified_diff("a.toml", "a\nb\nc\nd\ne\nf\ng\n", "a\nb\nc\nD\ne\nf\ng\n");
+        assert_eq!(diff, "--- a.toml\n+++ a.toml\n@@\n b\n c\n-d\n+D\n e\n f\n");
+        assert!(unified_diff("a", "x\n", "x\n").is_empty());
+    }
+}
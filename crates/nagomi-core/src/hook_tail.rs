@@ -0,0 +1,393 @@
+// hook の jsonl を追いかける。ファイル変更通知で起き、取れない環境では polling に落ちる
+// Follows a hook jsonl log. Wakes on filesystem notifications and falls back to polling
+// where the watcher is unavailable.
+//
+// 読んだ位置は `<log>.offset` に残すので、再起動しても取りこぼし・再生をしない。
+// The read position is kept in `<log>.offset` so a restart neither replays nor drops events.
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+#[cfg(not(unix))]
+use std::time::UNIX_EPOCH;
+
+// watcher が無いときの間隔 / Interval used when no watcher could be set up.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// watcher があっても取りこぼし対策にたまに見る / Occasional re-check in case the watcher misses one.
+const WATCH_RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct TailCursor {
+    // unix は inode、それ以外は作成時刻。取れなければ None
+    // The inode on unix, the creation time elsewhere; None where neither is available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_id: Option<u64>,
+    offset: u64,
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+// Windows には安定して取れる id が無いので作成時刻で代用する。同じ名前で作り直してすぐだと
+// tunneling で作成時刻が引き継がれることがあり、そのときの rotation は縮んだ場合にしか気づけない
+// Windows has no file id we can read here, so the creation time stands in. File-system tunneling
+// can hand a quickly recreated file the old creation time; such a rotation is then only noticed
+// when the new file is shorter than the saved offset.
+#[cfg(not(unix))]
+fn file_id(meta: &Metadata) -> Option<u64> {
+    let created = meta.created().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(created.as_nanos() as u64)
+}
+
+fn cursor_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".offset");
+    path.with_file_name(name)
+}
+
+// 1 本のログの読み取り位置。thread を持たないので同期的に試せる
+// Read position for one log. Owns no thread, so it can be driven synchronously.
+pub struct TailState {
+    path: PathBuf,
+    file: Option<File>,
+    cursor: TailCursor,
+    saved: Option<TailCursor>,
+}
+
+impl TailState {
+    // 保存済みの位置から再開。初回は今の末尾から（過去の event は流さない）
+    // Resume from the saved cursor. Without one, start at the current end so old events are not replayed.
+    pub fn open(path: PathBuf) -> Self {
+        let saved = fs::read_to_string(cursor_path(&path))
+            .ok()
+            .and_then(|raw| serde_json::from_str::<TailCursor>(&raw).ok());
+        let cursor = saved.unwrap_or_else(|| match fs::metadata(&path) {
+            Ok(meta) => TailCursor {
+                file_id: file_id(&meta),
+                offset: meta.len(),
+            },
+            Err(_) => TailCursor::default(),
+        });
+        Self {
+            path,
+            file: None,
+            cursor,
+            saved,
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.cursor.offset
+    }
+
+    // 読める完全な行をすべて渡す / Hand over every complete line that can be read.
+    pub fn poll(&mut self, on_line: &mut dyn FnMut(&str)) {
+        let current_id = fs::metadata(&self.path)
+            .ok()
+            .and_then(|meta| file_id(&meta));
+        // path が別ファイルを指したら、古い方を読み切ってから切り替える
+        // When the path names a different file, finish the old one before switching.
+        if self.file.is_some()
+            && current_id.is_some()
+            && self.cursor.file_id.is_some()
+            && current_id != self.cursor.file_id
+        {
+            self.read_available(on_line);
+            self.file = None;
+            self.cursor = TailCursor {
+                file_id: current_id,
+                offset: 0,
+            };
+        }
+        if self.file.is_none() {
+            let Ok(file) = File::open(&self.path) else {
+                return;
+            };
+            let opened_id = file.metadata().ok().and_then(|meta| file_id(&meta));
+            if self.cursor.file_id.is_some() && opened_id != self.cursor.file_id {
+                self.cursor.offset = 0;
+            }
+            self.cursor.file_id = opened_id;
+            self.file = Some(file);
+        }
+        // 同じファイルが縮んだら切り詰め / The same file shrinking means it was truncated.
+        let len = self
+            .file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        if len < self.cursor.offset {
+            self.cursor.offset = 0;
+        }
+        self.read_available(on_line);
+        self.save();
+    }
+
+    // 書きかけの行は改行が来るまで読まなかったことにする
+    // A half-written line is left unread until its newline arrives.
+    fn read_available(&mut self, on_line: &mut dyn FnMut(&str)) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if file.seek(SeekFrom::Start(self.cursor.offset)).is_err() {
+            return;
+        }
+        let mut buf = Vec::new();
+        if file.read_to_end(&mut buf).is_err() {
+            return;
+        }
+        let Some(end) = buf.iter().rposition(|byte| *byte == b'\n') else {
+            return;
+        };
+        for line in buf[..end].split(|byte| *byte == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end_matches('\r');
+            if !line.trim().is_empty() {
+                on_line(line);
+            }
+        }
+        self.cursor.offset += end as u64 + 1;
+    }
+
+    fn save(&mut self) {
+        if self.saved == Some(self.cursor) {
+            return;
+        }
+        if let Ok(raw) = serde_json::to_string(&self.cursor) {
+            if fs::write(cursor_path(&self.path), raw).is_ok() {
+                self.saved = Some(self.cursor);
+            }
+        }
+    }
+}
+
+enum TailSignal {
+    Changed,
+    Stop,
+}
+
+pub struct JsonlTail {
+    path: PathBuf,
+    signal: Option<Sender<TailSignal>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl JsonlTail {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            signal: None,
+            handle: None,
+        }
+    }
+
+    pub fn start<F>(&mut self, on_value: F)
+    where
+        F: Fn(Value) + Send + 'static,
+    {
+        if self.handle.is_some() {
+            return;
+        }
+        // 位置は呼び出し側で決める。start 直後の追記を「過去」として読み飛ばさないため
+        // Fix the position before returning so lines appended right after start are not
+        // mistaken for old events and skipped.
+        let mut state = TailState::open(self.path.clone());
+        let (tx, rx) = mpsc::channel();
+        let watcher = watch_log(&self.path, tx.clone());
+        self.signal = Some(tx);
+        self.handle = Some(thread::spawn(move || {
+            // watcher は thread と一緒に生きる / The watcher lives as long as the thread.
+            let interval = match watcher {
+                Some(_) => WATCH_RECHECK_INTERVAL,
+                None => POLL_INTERVAL,
+            };
+            let mut on_line = |line: &str| {
+                if let Ok(value) = serde_json::from_str::<Value>(line) {
+                    on_value(value);
+                }
+            };
+            loop {
+                state.poll(&mut on_line);
+                match rx.recv_timeout(interval) {
+                    Ok(TailSignal::Changed) | Err(RecvTimeoutError::Timeout) => {}
+                    Ok(TailSignal::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+                // 続けて来た通知はまとめて 1 回読む / Coalesce a burst of notifications into one read.
+                while let Ok(signal) = rx.try_recv() {
+                    if matches!(signal, TailSignal::Stop) {
+                        return;
+                    }
+                }
+            }
+            drop(watcher);
+        }));
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(signal) = self.signal.take() {
+            let _ = signal.send(TailSignal::Stop);
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// rotation で作り直されても追えるよう親ディレクトリを見る
+// Watch the parent directory so a log recreated by rotation is still followed.
+fn watch_log(path: &Path, tx: Sender<TailSignal>) -> Option<RecommendedWatcher> {
+    let dir = path.parent()?.to_path_buf();
+    let name = path.file_name()?.to_os_string();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if event.paths.is_empty()
+            || event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name() == Some(name.as_os_str()))
+        {
+            let _ = tx.send(TailSignal::Changed);
+        }
+    })
+    .ok()?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive).ok()?;
+    Some(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("nagomi-hook-tail-{name}-{nonce}"));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("open log");
+        file.write_all(text.as_bytes()).expect("append");
+    }
+
+    fn poll(state: &mut TailState) -> Vec<String> {
+        let mut lines = Vec::new();
+        state.poll(&mut |line| lines.push(line.to_string()));
+        lines
+    }
+
+    #[test]
+    fn starts_at_the_end_without_a_cursor_and_buffers_partial_lines() {
+        let dir = temp_dir("partial");
+        let path = dir.join("codex.jsonl");
+        append(&path, "{\"old\":1}\n");
+        let mut state = TailState::open(path.clone());
+        assert!(poll(&mut state).is_empty());
+
+        append(&path, "{\"a\":1}\n{\"b\":");
+        assert_eq!(poll(&mut state), vec!["{\"a\":1}"]);
+        let before = state.offset();
+        assert!(poll(&mut state).is_empty());
+        assert_eq!(state.offset(), before);
+
+        append(&path, "2}\r\n");
+        assert_eq!(poll(&mut state), vec!["{\"b\":2}"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn resumes_from_the_saved_cursor_after_a_restart() {
+        let dir = temp_dir("resume");
+        let path = dir.join("claude.jsonl");
+        let mut state = TailState::open(path.clone());
+        append(&path, "one\n");
+        assert_eq!(poll(&mut state), vec!["one"]);
+        drop(state);
+
+        append(&path, "two\n");
+        let mut state = TailState::open(path.clone());
+        assert_eq!(poll(&mut state), vec!["two"]);
+        assert!(poll(&mut state).is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn truncation_restarts_from_the_top() {
+        let dir = temp_dir("truncate");
+        let path = dir.join("opencode.jsonl");
+        let mut state = TailState::open(path.clone());
+        append(&path, "first line\n");
+        assert_eq!(poll(&mut state), vec!["first line"]);
+        fs::write(&path, "new\n").expect("truncate");
+        assert_eq!(poll(&mut state), vec!["new"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotation_drains_the_old_file_then_follows_the_new_one() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("codex.jsonl");
+        let mut state = TailState::open(path.clone());
+        append(&path, "a\n");
+        assert_eq!(poll(&mut state), vec!["a"]);
+
+        append(&path, "b\n");
+        fs::rename(&path, dir.join("codex.jsonl.1")).expect("rotate");
+        append(&path, "c\n");
+        assert_eq!(poll(&mut state), vec!["b", "c"]);
+        drop(state);
+
+        // 停止中の rotation は新しいファイルを頭から / Rotation while stopped reads the new file from the top.
+        fs::rename(&path, dir.join("codex.jsonl.2")).expect("rotate again");
+        append(&path, "d\n");
+        let mut state = TailState::open(path.clone());
+        assert_eq!(poll(&mut state), vec!["d"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn tail_thread_delivers_appended_values_and_stops() {
+        let dir = temp_dir("thread");
+        let path = dir.join("codex.jsonl");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut tail = JsonlTail::new(path.clone());
+        let sink = seen.clone();
+        append(&path, "{\"type\":\"old\"}\n");
+        tail.start(move |value| sink.lock().unwrap().push(value));
+        // start が戻った時点で位置は決まっているので、直後の追記も取りこぼさない
+        // The position is fixed once start returns, so a line appended right away is not skipped.
+        append(&path, "{\"type\":\"done\"}\nnot json\n");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while seen.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        tail.stop();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![serde_json::json!({ "type": "done" })]
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,1009 @@
+// terminal の起動条件（シェル・cwd・env）を組み立てる / Work out how a terminal is launched: shell, cwd and env.
+#[cfg(not(windows))]
+use crate::settings::UNIX_TERMINAL_SHELL_KINDS;
+use crate::settings::{
+    find_terminal_profile, normalize_terminal_shell_kind, Settings, TerminalProfile,
+};
+#[cfg(windows)]
+use crate::settings::{TERMINAL_SHELL_POWERSHELL, TERMINAL_SHELL_POWERSHELL7, TERMINAL_SHELL_WSL};
+use crate::workspace::WorkspaceTerminal;
+use std::collections::HashMap;
+#[cfg(windows)]
+use std::collections::HashSet;
+#[cfg(windows)]
+use std::ffi::OsStr;
+#[cfg(not(windows))]
+use std::fs;
+#[cfg(windows)]
+use std::os::windows::ffi::OsStrExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+#[cfg(any(windows, target_os = "macos"))]
+use std::process::Command;
+#[cfg(windows)]
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(windows)]
+use windows_sys::Win32::System::Environment::ExpandEnvironmentStringsW;
+#[cfg(windows)]
+use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_READ, REG_EXPAND_SZ, REG_SZ};
+#[cfg(windows)]
+use winreg::RegKey;
+
+// window を開く時点で決まる起動条件 / Launch options decided when the window is opened.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalLaunchOptions {
+    pub profile: Option<TerminalProfile>,
+    // 解決済みの絶対パス。profile.cwd より優先。起動後は実際に使った cwd
+    // Resolved absolute path that wins over profile.cwd; once launched, the cwd actually used.
+    pub cwd: Option<String>,
+    // workspace 復元時のタイトル / Window title restored from a workspace.
+    pub title: Option<String>,
+}
+
+// worker へ送る起動内容 / What gets sent to the worker in StartSession.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerminalLaunchPlan {
+    pub cmd: String,
+    pub argv: Option<Vec<String>>,
+    pub login_shell: bool,
+    pub cwd: Option<String>,
+    pub env: HashMap<String, String>,
+}
+
+impl TerminalLaunchOptions {
+    // 実際に使う cwd（明示 > profile）。profile の "~" は展開して返す
+    // The cwd that actually applies: explicit first, then the profile's with "~" expanded.
+    pub fn effective_cwd(&self) -> Option<String> {
+        self.cwd.clone().or_else(|| {
+            self.profile
+                .as_ref()
+                .map(|profile| profile.cwd.trim())
+                .filter(|cwd| !cwd.is_empty())
+                .map(|cwd| expand_home_path(cwd).to_string_lossy().to_string())
+        })
+    }
+}
+
+// "~" 始まりをホームに展開する / Expand a leading "~" to the home directory.
+// Unix は HOME、Windows は USERPROFILE を先に見る / HOME comes first on Unix, USERPROFILE on Windows.
+fn home_dir_var() -> Option<std::ffi::OsString> {
+    let (first, second) = if cfg!(windows) {
+        ("USERPROFILE", "HOME")
+    } else {
+        ("HOME", "USERPROFILE")
+    };
+    std::env::var_os(first).or_else(|| std::env::var_os(second))
+}
+
+fn expand_home_path(raw: &str) -> PathBuf {
+    let trimmed = raw.trim();
+    let rest = trimmed
+        .strip_prefix("~/")
+        .or_else(|| trimmed.strip_prefix("~\\"))
+        .or_else(|| (trimmed == "~").then_some(""));
+    match (rest, home_dir_var()) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(trimmed),
+    }
+}
+
+// 起動ディレクトリを検証して絶対パスにする / Validate a launch directory and make it absolute.
+pub fn resolve_terminal_cwd(raw: &str) -> Result<String, String> {
+    let path = expand_home_path(raw);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()
+            .map_err(|err| err.to_string())?
+            .join(path)
+    };
+    if !path.is_dir() {
+        return Err(format!(
+            "terminal cwd is not a directory: {}",
+            path.display()
+        ));
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+// タイトル用の短い cwd 表示 (frontend と同じ規則) / Short cwd label for titles, same rule as the frontend.
+fn cwd_label_for_title(cwd: &str) -> String {
+    let parts: Vec<&str> = cwd
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty())
+        .collect();
+    match parts.as_slice() {
+        [] => cwd.trim().to_string(),
+        [.., parent, leaf]
+            if matches!(leaf.to_ascii_lowercase().as_str(), "src" | "docs" | "tests") =>
+        {
+            format!("{parent}/{leaf}")
+        }
+        [.., leaf] => leaf.to_string(),
+    }
+}
+
+#[cfg(not(windows))]
+fn is_unix_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn parse_etc_shells(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(not(windows))]
+pub fn read_etc_shells() -> Vec<String> {
+    fs::read_to_string("/etc/shells")
+        .map(|text| parse_etc_shells(&text))
+        .unwrap_or_default()
+}
+
+// PATH を優先し、見つからなければ /etc/shells から探す / Prefer PATH, then fall back to /etc/shells.
+#[cfg(not(windows))]
+pub fn resolve_unix_shell_path(
+    kind: &str,
+    path_var: &str,
+    etc_shells: &[String],
+) -> Option<PathBuf> {
+    if let Some(path) = std::env::split_paths(path_var)
+        .map(|dir| dir.join(kind))
+        .find(|candidate| is_unix_executable(candidate))
+    {
+        return Some(path);
+    }
+    etc_shells.iter().map(PathBuf::from).find(|path| {
+        path.file_name().and_then(|name| name.to_str()) == Some(kind) && is_unix_executable(path)
+    })
+}
+
+// passwd の 7 番目のフィールド（uid 優先、次にユーザー名）/ 7th passwd field, matched by uid first, then user name.
+#[cfg(not(windows))]
+fn parse_passwd_shell(passwd: &str, uid: Option<u32>, user: Option<&str>) -> Option<String> {
+    let entries: Vec<Vec<&str>> = passwd
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 7 && !fields[6].trim().is_empty())
+        .collect();
+    let by_uid = uid.and_then(|uid| {
+        entries
+            .iter()
+            .find(|fields| fields[2].parse::<u32>().ok() == Some(uid))
+    });
+    let by_user = || user.and_then(|user| entries.iter().find(|fields| fields[0] == user));
+    by_uid
+        .or_else(by_user)
+        .map(|fields| fields[6].trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn macos_user_shell(user: &str) -> Option<String> {
+    let output = Command::new("dscl")
+        .args([".", "-read", &format!("/Users/{user}"), "UserShell"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("UserShell:"))
+        .map(|shell| shell.trim().to_string())
+        .filter(|shell| !shell.is_empty())
+}
+
+// ログインシェル（passwd → $SHELL → /bin/sh）/ The user's login shell from passwd, then $SHELL, then /bin/sh.
+#[cfg(not(windows))]
+fn passwd_login_shell() -> String {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    let uid = Some(unsafe { libc::getuid() });
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .ok();
+    let from_passwd = fs::read_to_string("/etc/passwd")
+        .ok()
+        .and_then(|text| parse_passwd_shell(&text, uid, user.as_deref()));
+    #[cfg(target_os = "macos")]
+    let from_passwd = from_passwd.or_else(|| macos_user_shell(user.as_deref()?));
+    from_passwd
+        .filter(|shell| is_unix_executable(Path::new(shell)))
+        .or_else(|| {
+            std::env::var("SHELL")
+                .ok()
+                .filter(|shell| !shell.trim().is_empty())
+        })
+        .unwrap_or_else(|| "/bin/sh".to_string())
+}
+
+// 選択された shell kind の argv と login_shell / argv and login_shell flag for the selected shell kind.
+#[cfg(not(windows))]
+fn build_unix_terminal_launch(
+    settings: &Settings,
+    path_var: &str,
+    etc_shells: &[String],
+    login_shell: &str,
+) -> (Vec<String>, bool) {
+    let kind = normalize_terminal_shell_kind(&settings.terminal_shell_kind);
+    let selected = UNIX_TERMINAL_SHELL_KINDS
+        .contains(&kind)
+        .then(|| resolve_unix_shell_path(kind, path_var, etc_shells))
+        .flatten();
+    match selected {
+        Some(path) => (
+            vec![path.to_string_lossy().to_string()],
+            cfg!(target_os = "macos"),
+        ),
+        None => {
+            let name = Path::new(login_shell)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(login_shell);
+            (
+                vec![login_shell.to_string()],
+                nagomi_protocol::LOGIN_SHELL_PROGRAMS.contains(&name),
+            )
+        }
+    }
+}
+
+#[cfg(windows)]
+pub fn is_windows_command_available(command_name: &str) -> bool {
+    const CREATE_NO_WINDOW_FLAG: u32 = 0x08000000;
+    let mut command = Command::new("where.exe");
+    command
+        .arg(command_name)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .creation_flags(CREATE_NO_WINDOW_FLAG);
+    command
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn build_windows_terminal_command_with_pwsh(settings: &Settings, pwsh_available: bool) -> String {
+    match normalize_terminal_shell_kind(&settings.terminal_shell_kind) {
+        TERMINAL_SHELL_POWERSHELL => "powershell.exe".to_string(),
+        TERMINAL_SHELL_POWERSHELL7 => {
+            if pwsh_available {
+                "pwsh.exe".to_string()
+            } else {
+                "powershell.exe".to_string()
+            }
+        }
+        TERMINAL_SHELL_WSL => {
+            let distro = settings.terminal_wsl_distro.trim();
+            if distro.is_empty() {
+                "wsl.exe".to_string()
+            } else {
+                let escaped = distro.replace('"', "\\\"");
+                format!("wsl.exe -d \"{escaped}\"")
+            }
+        }
+        _ => "cmd.exe".to_string(),
+    }
+}
+
+#[cfg(windows)]
+fn build_windows_terminal_command(settings: &Settings) -> String {
+    build_windows_terminal_command_with_pwsh(settings, is_windows_command_available("pwsh.exe"))
+}
+
+#[cfg(windows)]
+fn expand_environment_strings(value: &str) -> String {
+    let wide: Vec<u16> = OsStr::new(value).encode_wide().chain(Some(0)).collect();
+    let mut buffer: Vec<u16> = vec![0; 32768];
+    unsafe {
+        let mut len =
+            ExpandEnvironmentStringsW(wide.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32);
+        if len == 0 {
+            return value.to_string();
+        }
+        if len as usize > buffer.len() {
+            buffer.resize(len as usize, 0);
+            len =
+                ExpandEnvironmentStringsW(wide.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32);
+            if len == 0 {
+                return value.to_string();
+            }
+        }
+        let slice_len = len.saturating_sub(1) as usize;
+        String::from_utf16_lossy(&buffer[..slice_len])
+    }
+}
+
+#[cfg(windows)]
+fn decode_reg_value(value: &winreg::RegValue) -> Option<String> {
+    if value.vtype != REG_SZ && value.vtype != REG_EXPAND_SZ {
+        return None;
+    }
+    let mut units: Vec<u16> = Vec::with_capacity(value.bytes.len() / 2);
+    for chunk in value.bytes.chunks(2) {
+        if chunk.len() == 2 {
+            units.push(u16::from_le_bytes([chunk[0], chunk[1]]));
+        }
+    }
+    let len = units
+        .iter()
+        .position(|&unit| unit == 0)
+        .unwrap_or(units.len());
+    let mut text = String::from_utf16_lossy(&units[..len]);
+    if value.vtype == REG_EXPAND_SZ {
+        text = expand_environment_strings(&text);
+    }
+    Some(text)
+}
+
+#[cfg(windows)]
+fn read_registry_env(root: RegKey, path: &str) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+    let Ok(key) = root.open_subkey_with_flags(path, KEY_READ) else {
+        return env;
+    };
+    for item in key.enum_values() {
+        let Ok((name, value)) = item else {
+            continue;
+        };
+        let Some(text) = decode_reg_value(&value) else {
+            continue;
+        };
+        if name.is_empty() {
+            continue;
+        }
+        env.insert(name, text);
+    }
+    env
+}
+
+#[cfg(windows)]
+fn normalize_env_key(key: &str) -> String {
+    key.to_ascii_uppercase()
+}
+
+#[cfg(windows)]
+struct EnvCollector {
+    map: HashMap<String, (String, String)>,
+}
+
+#[cfg(windows)]
+impl EnvCollector {
+    fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: &str) {
+        let normalized = normalize_env_key(key);
+        if let Some((existing_key, existing_value)) = self.map.get_mut(&normalized) {
+            *existing_value = value.to_string();
+            if existing_key.is_empty() {
+                *existing_key = key.to_string();
+            }
+        } else {
+            self.map
+                .insert(normalized, (key.to_string(), value.to_string()));
+        }
+    }
+
+    fn insert_if_missing(&mut self, key: &str, value: &str) {
+        let normalized = normalize_env_key(key);
+        if !self.map.contains_key(&normalized) {
+            self.map
+                .insert(normalized, (key.to_string(), value.to_string()));
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&String> {
+        let normalized = normalize_env_key(key);
+        self.map.get(&normalized).map(|(_, value)| value)
+    }
+
+    fn into_map(self) -> HashMap<String, String> {
+        self.map
+            .into_iter()
+            .map(|(_, (key, value))| (key, value))
+            .collect()
+    }
+}
+
+#[cfg(windows)]
+fn get_case_insensitive(map: &HashMap<String, String>, key: &str) -> Option<String> {
+    map.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.clone())
+}
+
+#[cfg(windows)]
+fn normalize_path_entry(entry: &str) -> String {
+    entry
+        .trim()
+        .replace('/', "\\")
+        .trim_end_matches('\\')
+        .to_ascii_lowercase()
+}
+
+#[cfg(windows)]
+fn merge_paths(base: &str, extra: &str) -> String {
+    let mut merged: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for part in base.split(';') {
+        let trimmed = part.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let key = normalize_path_entry(trimmed);
+        if seen.insert(key) {
+            merged.push(trimmed.to_string());
+        }
+    }
+    for part in extra.split(';') {
+        let trimmed = part.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let key = normalize_path_entry(trimmed);
+        if seen.insert(key) {
+            merged.push(trimmed.to_string());
+        }
+    }
+    merged.join(";")
+}
+
+#[cfg(windows)]
+fn build_windows_terminal_env(session_id: &str) -> HashMap<String, String> {
+    let mut collector = EnvCollector::new();
+    for (key, value) in std::env::vars() {
+        collector.set_value(&key, &value);
+    }
+
+    let system_env = read_registry_env(
+        RegKey::predef(HKEY_LOCAL_MACHINE),
+        "SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Environment",
+    );
+    let user_env = read_registry_env(RegKey::predef(HKEY_CURRENT_USER), "Environment");
+
+    for (key, value) in &system_env {
+        collector.insert_if_missing(key, value);
+    }
+    for (key, value) in &user_env {
+        if key.eq_ignore_ascii_case("PATH") {
+            continue;
+        }
+        collector.insert_if_missing(key, value);
+    }
+
+    let system_path = get_case_insensitive(&system_env, "PATH");
+    let user_path = get_case_insensitive(&user_env, "PATH");
+    let registry_path = match (system_path, user_path) {
+        (Some(system), Some(user)) if !user.is_empty() => Some(format!("{system};{user}")),
+        (Some(system), _) => Some(system),
+        (_, Some(user)) => Some(user),
+        _ => None,
+    };
+
+    if let Some(registry_path) = registry_path {
+        let current_path = collector.get("PATH").cloned().unwrap_or_default();
+        let merged_path = merge_paths(&current_path, &registry_path);
+        if !merged_path.is_empty() {
+            collector.set_value("Path", &merged_path);
+        }
+    }
+
+    collector.set_value("NAGOMI_SESSION_ID", session_id);
+    collector.into_map()
+}
+
+// worker 側で継承環境に重ねる差分 / Overrides merged onto the inherited environment by the worker.
+#[cfg(not(windows))]
+fn build_unix_terminal_env(session_id: &str) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+    env.insert("TERM".to_string(), "xterm-256color".to_string());
+    env.insert("COLORTERM".to_string(), "truecolor".to_string());
+    let has_locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter().any(|key| {
+        std::env::var(key)
+            .map(|value| !value.trim().is_empty())
+            .unwrap_or(false)
+    });
+    if !has_locale {
+        let lang = if cfg!(target_os = "macos") {
+            "en_US.UTF-8"
+        } else {
+            "C.UTF-8"
+        };
+        env.insert("LANG".to_string(), lang.to_string());
+    }
+    env.insert("NAGOMI_SESSION_ID".to_string(), session_id.to_string());
+    env
+}
+
+// profile 名と cwd から起動条件を組み立てる / Build launch options from a profile name and a cwd.
+pub fn launch_options(
+    settings: &Settings,
+    profile_name: Option<&str>,
+    cwd: Option<&str>,
+) -> Result<TerminalLaunchOptions, String> {
+    let profile = match profile_name {
+        Some(name) => Some(
+            find_terminal_profile(settings, name)
+                .cloned()
+                .ok_or_else(|| format!("terminal profile not found: {name}"))?,
+        ),
+        None => None,
+    };
+    // profile の cwd も解決して持つので、履歴・一覧・集約グループに "~" が漏れない
+    // The profile's cwd is resolved too, so history, listings and groups never see a raw "~".
+    let cwd = match cwd.filter(|cwd| !cwd.trim().is_empty()) {
+        Some(cwd) => Some(resolve_terminal_cwd(cwd)?),
+        None => profile
+            .as_ref()
+            .map(|profile| profile.cwd.trim())
+            .filter(|cwd| !cwd.is_empty())
+            .map(resolve_terminal_cwd)
+            .transpose()?,
+    };
+    Ok(TerminalLaunchOptions {
+        profile,
+        cwd,
+        title: None,
+    })
+}
+
+pub fn build_terminal_launch_plan(
+    settings: &Settings,
+    session_id: &str,
+    options: &TerminalLaunchOptions,
+) -> Result<TerminalLaunchPlan, String> {
+    let profile = options.profile.clone().unwrap_or_default();
+    let mut effective = settings.clone();
+    if !profile.shell_kind.trim().is_empty() {
+        effective.terminal_shell_kind = profile.shell_kind.clone();
+    }
+    let custom_cmd = profile.cmd.trim().to_string();
+
+    #[cfg(windows)]
+    let (cmd, argv, login_shell, mut env) = (
+        if custom_cmd.is_empty() {
+            build_windows_terminal_command(&effective)
+        } else {
+            custom_cmd
+        },
+        None,
+        false,
+        build_windows_terminal_env(session_id),
+    );
+    #[cfg(not(windows))]
+    let (cmd, argv, login_shell, mut env) = {
+        let env = build_unix_terminal_env(session_id);
+        if custom_cmd.is_empty() {
+            let path_var = std::env::var("PATH").unwrap_or_default();
+            let (argv, login_shell) = build_unix_terminal_launch(
+                &effective,
+                &path_var,
+                &read_etc_shells(),
+                &passwd_login_shell(),
+            );
+            (String::new(), Some(argv), login_shell, env)
+        } else {
+            (custom_cmd, None, false, env)
+        }
+    };
+
+    // profile の env を重ねても session id は固定 / Profile env never overrides the session id.
+    for (key, value) in &profile.env {
+        env.insert(key.clone(), value.clone());
+    }
+    env.insert("NAGOMI_SESSION_ID".to_string(), session_id.to_string());
+
+    let cwd = match options.cwd.as_deref().filter(|cwd| !cwd.trim().is_empty()) {
+        Some(cwd) => Some(resolve_terminal_cwd(cwd)?),
+        None if profile.cwd.trim().is_empty() => None,
+        None => Some(resolve_terminal_cwd(&profile.cwd)?),
+    };
+
+    Ok(TerminalLaunchPlan {
+        cmd,
+        argv,
+        login_shell,
+        cwd,
+        env,
+    })
+}
+
+pub fn generate_terminal_session_id() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_millis(0))
+        .as_millis();
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("terminal-{nonce}-{seq}")
+}
+
+// 優先順: 明示タイトル > profile 名 > cwd > session id
+// Priority: explicit title, profile name, cwd, then session id.
+pub fn terminal_window_title(session_id: &str, options: &TerminalLaunchOptions) -> String {
+    if let Some(title) = options
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+    {
+        return title.to_string();
+    }
+    if let Some(name) = options
+        .profile
+        .as_ref()
+        .map(|profile| profile.name.trim())
+        .filter(|name| !name.is_empty())
+    {
+        return name.to_string();
+    }
+    let cwd = options
+        .cwd
+        .as_deref()
+        .or_else(|| options.profile.as_ref().map(|profile| profile.cwd.as_str()));
+    match cwd
+        .map(cwd_label_for_title)
+        .filter(|label| !label.is_empty())
+    {
+        Some(label) => label,
+        None => format!("Terminal {session_id}"),
+    }
+}
+
+// 保存内容から起動条件を作る。profile が消えていても cmd/cwd で再現する
+// Rebuild launch options from a saved terminal; cmd/cwd still apply if the profile is gone.
+pub fn workspace_launch_options(
+    settings: &Settings,
+    terminal: &WorkspaceTerminal,
+) -> TerminalLaunchOptions {
+    let mut profile = find_terminal_profile(settings, &terminal.profile).cloned();
+    if !terminal.cmd.trim().is_empty() {
+        profile.get_or_insert_with(TerminalProfile::default).cmd = terminal.cmd.trim().to_string();
+    }
+    let non_empty = |value: &str| {
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    };
+    TerminalLaunchOptions {
+        profile,
+        cwd: non_empty(&terminal.cwd),
+        title: non_empty(&terminal.title),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{TERMINAL_SHELL_CMD, TERMINAL_SHELL_NU, TERMINAL_SHELL_ZSH};
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time")
+            .as_nanos();
+        std::env::temp_dir().join(format!("nagomi-{name}-{nonce}"))
+    }
+
+    #[test]
+    fn terminal_launch_plan_applies_profile() {
+        let cwd = std::env::temp_dir();
+        let profile = TerminalProfile {
+            name: "logs".to_string(),
+            cmd: "tail -f app.log".to_string(),
+            cwd: cwd.to_string_lossy().to_string(),
+            env: HashMap::from([
+                ("LOG_LEVEL".to_string(), "debug".to_string()),
+                ("NAGOMI_SESSION_ID".to_string(), "spoofed".to_string()),
+            ]),
+            ..TerminalProfile::default()
+        };
+        let options = TerminalLaunchOptions {
+            profile: Some(profile.clone()),
+            ..TerminalLaunchOptions::default()
+        };
+        let plan = build_terminal_launch_plan(&Settings::default(), "session-1", &options)
+            .expect("launch plan");
+        assert_eq!(plan.cmd, "tail -f app.log");
+        assert_eq!(plan.argv, None);
+        assert!(!plan.login_shell);
+        assert_eq!(plan.cwd, Some(cwd.to_string_lossy().to_string()));
+        assert_eq!(plan.env.get("LOG_LEVEL").map(String::as_str), Some("debug"));
+        assert_eq!(
+            plan.env.get("NAGOMI_SESSION_ID").map(String::as_str),
+            Some("session-1")
+        );
+
+        let missing_cwd = TerminalLaunchOptions {
+            profile: Some(TerminalProfile {
+                cwd: temp_path("missing-cwd").to_string_lossy().to_string(),
+                ..profile.clone()
+            }),
+            ..TerminalLaunchOptions::default()
+        };
+        let err = build_terminal_launch_plan(&Settings::default(), "session-1", &missing_cwd)
+            .expect_err("missing cwd");
+        assert!(err.contains("not a directory"));
+
+        let requested = std::env::current_dir().expect("current dir");
+        let override_cwd = TerminalLaunchOptions {
+            profile: Some(profile),
+            cwd: Some(requested.to_string_lossy().to_string()),
+            title: None,
+        };
+        let plan = build_terminal_launch_plan(&Settings::default(), "session-1", &override_cwd)
+            .expect("launch plan");
+        assert_eq!(plan.cwd, Some(requested.to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn launch_options_keep_the_profile_cwd_resolved() {
+        let settings = Settings {
+            terminal_profiles: vec![TerminalProfile {
+                name: "here".to_string(),
+                cwd: ".".to_string(),
+                ..TerminalProfile::default()
+            }],
+            ..Settings::default()
+        };
+        let options = launch_options(&settings, Some("here"), None).expect("options");
+        let current = std::env::current_dir().expect("current dir");
+        assert_eq!(
+            options.effective_cwd(),
+            Some(current.join(".").to_string_lossy().to_string())
+        );
+
+        let Some(home) = std::env::var_os("USERPROFILE").or_else(|| std::env::var_os("HOME"))
+        else {
+            return;
+        };
+        let unresolved = TerminalLaunchOptions {
+            profile: Some(TerminalProfile {
+                cwd: "~/proj".to_string(),
+                ..TerminalProfile::default()
+            }),
+            ..TerminalLaunchOptions::default()
+        };
+        assert_eq!(
+            unresolved.effective_cwd(),
+            Some(
+                PathBuf::from(home)
+                    .join("proj")
+                    .to_string_lossy()
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn terminal_window_title_prefers_profile_then_cwd() {
+        let by_cwd = TerminalLaunchOptions {
+            cwd: Some("/home/me/work/api/src".to_string()),
+            ..TerminalLaunchOptions::default()
+        };
+        assert_eq!(terminal_window_title("s1", &by_cwd), "api/src");
+        assert_eq!(cwd_label_for_title("C:\\Users\\me\\yurutsuku"), "yurutsuku");
+
+        let by_profile = TerminalLaunchOptions {
+            profile: Some(TerminalProfile {
+                name: " logs ".to_string(),
+                ..TerminalProfile::default()
+            }),
+            ..by_cwd
+        };
+        assert_eq!(terminal_window_title("s1", &by_profile), "logs");
+        let restored = TerminalLaunchOptions {
+            title: Some("api: claude".to_string()),
+            ..by_profile
+        };
+        assert_eq!(terminal_window_title("s1", &restored), "api: claude");
+        assert_eq!(
+            terminal_window_title("s1", &TerminalLaunchOptions::default()),
+            "Terminal s1"
+        );
+    }
+
+    #[test]
+    fn workspace_launch_options_fall_back_to_saved_cmd() {
+        let settings = Settings {
+            terminal_profiles: vec![TerminalProfile {
+                name: "api".to_string(),
+                cmd: "claude".to_string(),
+                theme: "dark-ocean".to_string(),
+                ..TerminalProfile::default()
+            }],
+            ..Settings::default()
+        };
+        let saved = WorkspaceTerminal {
+            profile: "API".to_string(),
+            cwd: " /srv/api ".to_string(),
+            title: "api".to_string(),
+            ..WorkspaceTerminal::default()
+        };
+        let options = workspace_launch_options(&settings, &saved);
+        let profile = options.profile.expect("profile");
+        assert_eq!(profile.cmd, "claude");
+        assert_eq!(profile.theme, "dark-ocean");
+        assert_eq!(options.cwd.as_deref(), Some("/srv/api"));
+        assert_eq!(options.title.as_deref(), Some("api"));
+
+        let orphan = WorkspaceTerminal {
+            profile: "deleted".to_string(),
+            cmd: "htop".to_string(),
+            ..WorkspaceTerminal::default()
+        };
+        let options = workspace_launch_options(&settings, &orphan);
+        assert_eq!(
+            options.profile.map(|profile| profile.cmd),
+            Some("htop".to_string())
+        );
+        assert_eq!(options.cwd, None);
+        assert_eq!(options.title, None);
+
+        let shell = workspace_launch_options(&settings, &WorkspaceTerminal::default());
+        assert!(shell.profile.is_none());
+    }
+
+    #[test]
+    fn resolve_terminal_cwd_rejects_missing_directory() {
+        let dir = std::env::temp_dir();
+        assert_eq!(
+            resolve_terminal_cwd(&dir.to_string_lossy()).expect("temp dir"),
+            dir.to_string_lossy().to_string()
+        );
+        let err = resolve_terminal_cwd(&temp_path("missing-dir").to_string_lossy())
+            .expect_err("missing dir");
+        assert!(err.starts_with("terminal cwd is not a directory"));
+    }
+
+    #[test]
+    fn expand_home_path_handles_tilde() {
+        let home = home_dir_var().map(PathBuf::from).expect("home");
+        assert_eq!(expand_home_path("~"), home);
+        assert_eq!(expand_home_path("~/src/api"), home.join("src/api"));
+        assert_eq!(expand_home_path(" /srv/app "), PathBuf::from("/srv/app"));
+        assert_eq!(expand_home_path("~other/x"), PathBuf::from("~other/x"));
+        // Unix では USERPROFILE があっても HOME を使う / Unix uses HOME even when USERPROFILE is set.
+        if cfg!(unix) && std::env::var_os("HOME").is_some() {
+            assert_eq!(home_dir_var(), std::env::var_os("HOME"));
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn write_fake_shell(dir: &Path, name: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        fs::create_dir_all(dir).expect("create shell dir");
+        let path = dir.join(name);
+        fs::write(&path, "#!/bin/sh\n").expect("write fake shell");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("chmod fake shell");
+        path
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unix_shell_resolution_uses_path_then_etc_shells() {
+        let root = temp_path("shells");
+        let path_dir = root.join("bin");
+        let extra_dir = root.join("opt");
+        let zsh = write_fake_shell(&path_dir, "zsh");
+        let fish = write_fake_shell(&extra_dir, "fish");
+        fs::write(path_dir.join("nu"), "not executable").expect("write non-executable");
+        let path_var = path_dir.to_string_lossy().to_string();
+        let etc_shells = parse_etc_shells(&format!(
+            "# comment\n/bin/sh\n{}\n\n",
+            fish.to_string_lossy()
+        ));
+
+        assert_eq!(
+            resolve_unix_shell_path("zsh", &path_var, &etc_shells),
+            Some(zsh.clone())
+        );
+        assert_eq!(
+            resolve_unix_shell_path("fish", &path_var, &etc_shells),
+            Some(fish)
+        );
+        assert_eq!(resolve_unix_shell_path("nu", &path_var, &etc_shells), None);
+
+        let mut settings = Settings {
+            terminal_shell_kind: TERMINAL_SHELL_ZSH.to_string(),
+            ..Settings::default()
+        };
+        let (argv, _) = build_unix_terminal_launch(&settings, &path_var, &etc_shells, "/bin/bash");
+        assert_eq!(argv, vec![zsh.to_string_lossy().to_string()]);
+
+        // 利用不可の kind はログインシェルへ / Unavailable kinds fall back to the login shell.
+        settings.terminal_shell_kind = TERMINAL_SHELL_NU.to_string();
+        let (argv, login) =
+            build_unix_terminal_launch(&settings, &path_var, &etc_shells, "/bin/bash");
+        assert_eq!(argv, vec!["/bin/bash".to_string()]);
+        assert!(login);
+        settings.terminal_shell_kind = TERMINAL_SHELL_CMD.to_string();
+        let (argv, login) =
+            build_unix_terminal_launch(&settings, &path_var, &etc_shells, "/usr/bin/tcsh");
+        assert_eq!(argv, vec!["/usr/bin/tcsh".to_string()]);
+        assert!(!login);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn passwd_shell_prefers_uid_then_user() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/usr/bin/zsh\nbob:x:1001:1001::/home/bob:\n";
+        assert_eq!(
+            parse_passwd_shell(passwd, Some(1000), Some("root")),
+            Some("/usr/bin/zsh".to_string())
+        );
+        assert_eq!(
+            parse_passwd_shell(passwd, Some(4242), Some("root")),
+            Some("/bin/bash".to_string())
+        );
+        assert_eq!(parse_passwd_shell(passwd, Some(1001), Some("bob")), None);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unix_terminal_env_sets_session_id() {
+        let env = build_unix_terminal_env("session-1");
+        assert_eq!(
+            env.get("NAGOMI_SESSION_ID").map(String::as_str),
+            Some("session-1")
+        );
+        assert_eq!(env.get("TERM").map(String::as_str), Some("xterm-256color"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_terminal_command_matches_shell_kind() {
+        let mut settings = Settings::default();
+
+        settings.terminal_shell_kind = TERMINAL_SHELL_CMD.to_string();
+        settings.terminal_wsl_distro = String::new();
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, false),
+            "cmd.exe"
+        );
+
+        settings.terminal_shell_kind = TERMINAL_SHELL_POWERSHELL.to_string();
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, false),
+            "powershell.exe"
+        );
+
+        settings.terminal_shell_kind = TERMINAL_SHELL_POWERSHELL7.to_string();
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, false),
+            "powershell.exe"
+        );
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, true),
+            "pwsh.exe"
+        );
+
+        settings.terminal_shell_kind = TERMINAL_SHELL_WSL.to_string();
+        settings.terminal_wsl_distro = String::new();
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, false),
+            "wsl.exe"
+        );
+
+        settings.terminal_wsl_distro = "Ubuntu".to_string();
+        assert_eq!(
+            build_windows_terminal_command_with_pwsh(&settings, false),
+            "wsl.exe -d \"Ubuntu\""
+        );
+    }
+}
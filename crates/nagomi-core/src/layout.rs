@@ -0,0 +1,444 @@
+// Terminal window の並べ方（layout engine）/ Layout engines for arranging terminal windows.
+
+use std::collections::HashMap;
+
+pub const LAYOUT_GRID: &str = "grid";
+pub const LAYOUT_MASTER_STACK: &str = "master-stack";
+pub const LAYOUT_COLUMNS: &str = "columns";
+pub const LAYOUT_PRIORITY: &str = "priority";
+
+// master/stack と priority で大きい側が使う幅の割合 / Width share of the large region.
+const MAJOR_REGION_RATIO: f32 = 0.6;
+// focus 時の拡大率（grid などの既定）/ Default zoom ratio used on focus.
+const FOCUS_ZOOM_RATIO: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 配置対象の 1 枚。items は画面上の並び順で渡す / One window to place; items arrive in visual order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutItem {
+    pub label: String,
+    // normalize 済みの観測状態（idle/need-input/fail/...）/ Normalized observed state.
+    pub state: String,
+    pub focused: bool,
+}
+
+pub trait LayoutEngine {
+    fn name(&self) -> &'static str;
+
+    // items と同じ順で各 window の外枠 rect を返す / Outer rect per item, in the same order as items.
+    fn layout(&self, area: WindowRect, items: &[LayoutItem]) -> Vec<WindowRect>;
+
+    // focus した window の拡大先 / Where the focused window expands to.
+    fn focus_rect(&self, area: WindowRect, _tile: WindowRect) -> WindowRect {
+        centered_rect(area, FOCUS_ZOOM_RATIO)
+    }
+}
+
+pub fn normalize_layout_kind(kind: &str) -> &'static str {
+    match kind.trim().to_ascii_lowercase().as_str() {
+        LAYOUT_MASTER_STACK | "master_stack" | "master" => LAYOUT_MASTER_STACK,
+        LAYOUT_COLUMNS => LAYOUT_COLUMNS,
+        LAYOUT_PRIORITY => LAYOUT_PRIORITY,
+        _ => LAYOUT_GRID,
+    }
+}
+
+pub fn layout_engine(kind: &str) -> Box<dyn LayoutEngine + Send + Sync> {
+    match normalize_layout_kind(kind) {
+        LAYOUT_MASTER_STACK => Box::new(MasterStackLayout),
+        LAYOUT_COLUMNS => Box::new(ColumnsLayout),
+        LAYOUT_PRIORITY => Box::new(PriorityLayout),
+        _ => Box::new(GridLayout),
+    }
+}
+
+pub fn grid_for_window_count(count: usize) -> (usize, usize) {
+    let rows = if count >= 9 {
+        3
+    } else if count >= 4 {
+        2
+    } else {
+        1
+    };
+    let cols = count.div_ceil(rows);
+    (rows, cols)
+}
+
+pub fn cell_size(width: u32, height: u32, rows: usize, cols: usize) -> (u32, u32) {
+    if rows == 0 || cols == 0 {
+        return (width.max(1), height.max(1));
+    }
+    let cell_width = (width / cols as u32).max(1);
+    let cell_height = (height / rows as u32).max(1);
+    (cell_width, cell_height)
+}
+
+fn centered_rect(area: WindowRect, ratio: f32) -> WindowRect {
+    let width = ((area.width as f32) * ratio) as u32;
+    let height = ((area.height as f32) * ratio) as u32;
+    WindowRect {
+        x: area.x + (area.width.saturating_sub(width) as i32 / 2),
+        y: area.y + (area.height.saturating_sub(height) as i32 / 2),
+        width: width.max(1),
+        height: height.max(1),
+    }
+}
+
+fn grid_rects(area: WindowRect, count: usize) -> Vec<WindowRect> {
+    let (rows, cols) = grid_for_window_count(count);
+    if cols == 0 {
+        return Vec::new();
+    }
+    let (cell_width, cell_height) = cell_size(area.width, area.height, rows, cols);
+    (0..count)
+        .map(|index| WindowRect {
+            x: area.x + ((index % cols) as i32 * cell_width as i32),
+            y: area.y + ((index / cols) as i32 * cell_height as i32),
+            width: cell_width,
+            height: cell_height,
+        })
+        .collect()
+}
+
+// 左右に分割（左が ratio）/ Split into left (ratio) and right regions.
+fn split_columns(area: WindowRect, ratio: f32) -> (WindowRect, WindowRect) {
+    let left_width = (((area.width as f32) * ratio) as u32).clamp(1, area.width.max(1));
+    let left = WindowRect {
+        width: left_width,
+        ..area
+    };
+    let right = WindowRect {
+        x: area.x + left_width as i32,
+        width: area.width.saturating_sub(left_width).max(1),
+        ..area
+    };
+    (left, right)
+}
+
+fn stack_rects(area: WindowRect, count: usize) -> Vec<WindowRect> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let cell_height = (area.height / count as u32).max(1);
+    (0..count)
+        .map(|index| WindowRect {
+            y: area.y + (index as i32 * cell_height as i32),
+            height: cell_height,
+            ..area
+        })
+        .collect()
+}
+
+pub struct GridLayout;
+
+impl LayoutEngine for GridLayout {
+    fn name(&self) -> &'static str {
+        LAYOUT_GRID
+    }
+
+    fn layout(&self, area: WindowRect, items: &[LayoutItem]) -> Vec<WindowRect> {
+        grid_rects(area, items.len())
+    }
+}
+
+// focus 中の terminal を左に大きく、残りを右に縦積み
+// Focused terminal large on the left, the rest stacked on the right.
+pub struct MasterStackLayout;
+
+impl LayoutEngine for MasterStackLayout {
+    fn name(&self) -> &'static str {
+        LAYOUT_MASTER_STACK
+    }
+
+    fn layout(&self, area: WindowRect, items: &[LayoutItem]) -> Vec<WindowRect> {
+        if items.len() <= 1 {
+            return grid_rects(area, items.len());
+        }
+        let master = items.iter().position(|item| item.focused).unwrap_or(0);
+        let (master_area, stack_area) = split_columns(area, MAJOR_REGION_RATIO);
+        let mut stack = stack_rects(stack_area, items.len() - 1).into_iter();
+        (0..items.len())
+            .map(|index| {
+                if index == master {
+                    master_area
+                } else {
+                    stack.next().unwrap_or(stack_area)
+                }
+            })
+            .collect()
+    }
+
+    // 拡大先は master 枠 / Focus expands into the master slot.
+    fn focus_rect(&self, area: WindowRect, _tile: WindowRect) -> WindowRect {
+        split_columns(area, MAJOR_REGION_RATIO).0
+    }
+}
+
+pub struct ColumnsLayout;
+
+impl LayoutEngine for ColumnsLayout {
+    fn name(&self) -> &'static str {
+        LAYOUT_COLUMNS
+    }
+
+    fn layout(&self, area: WindowRect, items: &[LayoutItem]) -> Vec<WindowRect> {
+        if items.is_empty() {
+            return Vec::new();
+        }
+        let cell_width = (area.width / items.len() as u32).max(1);
+        (0..items.len())
+            .map(|index| WindowRect {
+                x: area.x + (index as i32 * cell_width as i32),
+                width: cell_width,
+                ..area
+            })
+            .collect()
+    }
+
+    // 列全体の高さのまま横に広げる / Widen the column while keeping the full height.
+    fn focus_rect(&self, area: WindowRect, tile: WindowRect) -> WindowRect {
+        let zoomed = centered_rect(area, FOCUS_ZOOM_RATIO);
+        WindowRect {
+            y: tile.y,
+            height: tile.height,
+            ..zoomed
+        }
+    }
+}
+
+fn state_priority(state: &str) -> Option<u8> {
+    match state {
+        "need-input" => Some(0),
+        "fail" => Some(1),
+        _ => None,
+    }
+}
+
+// need-input / fail の terminal を左の大きい枠に、残りを右の grid に
+// need-input / fail terminals get the large left region; the rest share a grid on the right.
+pub struct PriorityLayout;
+
+impl LayoutEngine for PriorityLayout {
+    fn name(&self) -> &'static str {
+        LAYOUT_PRIORITY
+    }
+
+    fn layout(&self, area: WindowRect, items: &[LayoutItem]) -> Vec<WindowRect> {
+        let mut urgent: Vec<(u8, usize)> = items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| state_priority(&item.state).map(|rank| (rank, index)))
+            .collect();
+        if urgent.is_empty() || urgent.len() == items.len() {
+            return grid_rects(area, items.len());
+        }
+        urgent.sort();
+        let (major_area, minor_area) = split_columns(area, MAJOR_REGION_RATIO);
+        let major = stack_rects(major_area, urgent.len());
+        let minor_indices: Vec<usize> = (0..items.len())
+            .filter(|index| !urgent.iter().any(|(_, urgent_index)| urgent_index == index))
+            .collect();
+        let minor = grid_rects(minor_area, minor_indices.len());
+
+        let mut rects = vec![area; items.len()];
+        for ((_, index), rect) in urgent.iter().zip(major) {
+            rects[*index] = rect;
+        }
+        for (index, rect) in minor_indices.into_iter().zip(minor) {
+            rects[index] = rect;
+        }
+        rects
+    }
+}
+
+// 並び順で current の次にある target 状態の window を探す（末尾で先頭に戻る）
+// Find the next window in `target` state after `current`, wrapping around the order.
+pub fn next_label_in_state(
+    order: &[String],
+    states: &HashMap<String, String>,
+    current: Option<&str>,
+    target: &str,
+    reverse: bool,
+) -> Option<String> {
+    if order.is_empty() {
+        return None;
+    }
+    let len = order.len();
+    let start = current
+        .and_then(|label| order.iter().position(|candidate| candidate == label))
+        .unwrap_or(if reverse { 0 } else { len - 1 });
+    (1..=len)
+        .map(|offset| {
+            if reverse {
+                (start + len - offset) % len
+            } else {
+                (start + offset) % len
+            }
+        })
+        .map(|index| &order[index])
+        .find(|label| states.get(*label).map(String::as_str) == Some(target))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: WindowRect = WindowRect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+
+    fn items(states: &[&str]) -> Vec<LayoutItem> {
+        states
+            .iter()
+            .enumerate()
+            .map(|(index, state)| LayoutItem {
+                label: format!("terminal-{index}"),
+                state: state.to_string(),
+                focused: false,
+            })
+            .collect()
+    }
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> WindowRect {
+        WindowRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn area_of(rect: &WindowRect) -> u64 {
+        rect.width as u64 * rect.height as u64
+    }
+
+    fn assert_inside(rects: &[WindowRect], area: WindowRect) {
+        for rect in rects {
+            assert!(rect.x >= area.x && rect.y >= area.y, "{rect:?}");
+            assert!(
+                rect.x + rect.width as i32 <= area.x + area.width as i32,
+                "{rect:?}"
+            );
+            assert!(
+                rect.y + rect.height as i32 <= area.y + area.height as i32,
+                "{rect:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_layout_kind_falls_back_to_grid() {
+        assert_eq!(normalize_layout_kind(" Master-Stack "), LAYOUT_MASTER_STACK);
+        assert_eq!(normalize_layout_kind("columns"), LAYOUT_COLUMNS);
+        assert_eq!(normalize_layout_kind("priority"), LAYOUT_PRIORITY);
+        assert_eq!(normalize_layout_kind("spiral"), LAYOUT_GRID);
+        assert_eq!(layout_engine("priority").name(), LAYOUT_PRIORITY);
+    }
+
+    #[test]
+    fn grid_layout_matches_row_rules() {
+        let rects = GridLayout.layout(AREA, &items(&["idle"; 5]));
+        assert_eq!(rects.len(), 5);
+        assert_eq!(rects[0], rect(0, 0, 640, 540));
+        assert_eq!(rects[3], rect(0, 540, 640, 540));
+        assert_eq!(rects[4], rect(640, 540, 640, 540));
+        assert_inside(&rects, AREA);
+        assert_eq!(
+            GridLayout.focus_rect(AREA, rects[0]),
+            rect(192, 108, 1536, 864)
+        );
+    }
+
+    #[test]
+    fn master_stack_puts_focused_window_in_master_slot() {
+        let mut list = items(&["idle", "idle", "idle"]);
+        list[2].focused = true;
+        let area = rect(100, 50, 1000, 900);
+        let rects = MasterStackLayout.layout(area, &list);
+        assert_eq!(rects[2], rect(100, 50, 600, 900));
+        assert_eq!(rects[0], rect(700, 50, 400, 450));
+        assert_eq!(rects[1], rect(700, 500, 400, 450));
+        assert_inside(&rects, area);
+        assert_eq!(MasterStackLayout.focus_rect(area, rects[0]), rects[2]);
+
+        let single = MasterStackLayout.layout(area, &items(&["idle"]));
+        assert_eq!(single, vec![area]);
+    }
+
+    #[test]
+    fn columns_layout_uses_full_height() {
+        let rects = ColumnsLayout.layout(AREA, &items(&["idle"; 4]));
+        assert_eq!(rects.len(), 4);
+        assert!(rects
+            .iter()
+            .all(|rect| rect.height == 1080 && rect.width == 480));
+        assert_eq!(rects[3].x, 1440);
+        let zoomed = ColumnsLayout.focus_rect(AREA, rects[1]);
+        assert_eq!(zoomed.height, 1080);
+        assert_eq!(zoomed.width, 1536);
+    }
+
+    #[test]
+    fn priority_layout_gives_urgent_windows_the_biggest_cells() {
+        let list = items(&["idle", "fail", "success", "need-input", "idle", "idle"]);
+        let rects = PriorityLayout.layout(AREA, &list);
+        assert_inside(&rects, AREA);
+        // need-input が先頭、fail が次 / need-input first, then fail.
+        assert_eq!(rects[3], rect(0, 0, 1152, 540));
+        assert_eq!(rects[1], rect(0, 540, 1152, 540));
+        let smallest_urgent = area_of(&rects[1]).min(area_of(&rects[3]));
+        for index in [0, 2, 4, 5] {
+            assert!(area_of(&rects[index]) < smallest_urgent, "index {index}");
+            assert!(rects[index].x >= 1152);
+        }
+    }
+
+    #[test]
+    fn priority_layout_without_urgent_windows_is_a_grid() {
+        let list = items(&["idle", "success", "idle"]);
+        assert_eq!(
+            PriorityLayout.layout(AREA, &list),
+            GridLayout.layout(AREA, &list)
+        );
+        let all_urgent = items(&["fail", "need-input"]);
+        assert_eq!(
+            PriorityLayout.layout(AREA, &all_urgent),
+            GridLayout.layout(AREA, &all_urgent)
+        );
+    }
+
+    #[test]
+    fn next_label_in_state_cycles_past_current() {
+        let order: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        let states: HashMap<String, String> = [
+            ("a", "need-input"),
+            ("b", "idle"),
+            ("c", "fail"),
+            ("d", "need-input"),
+        ]
+        .iter()
+        .map(|(label, state)| (label.to_string(), state.to_string()))
+        .collect();
+        let next = |current: Option<&str>, target: &str, reverse: bool| {
+            next_label_in_state(&order, &states, current, target, reverse)
+        };
+        assert_eq!(next(Some("a"), "need-input", false).as_deref(), Some("d"));
+        assert_eq!(next(Some("d"), "need-input", false).as_deref(), Some("a"));
+        assert_eq!(next(Some("a"), "need-input", true).as_deref(), Some("d"));
+        assert_eq!(next(None, "need-input", false).as_deref(), Some("a"));
+        assert_eq!(next(Some("c"), "fail", false).as_deref(), Some("c"));
+        assert_eq!(next(Some("zz"), "fail", false).as_deref(), Some("c"));
+        assert_eq!(next(Some("a"), "success", false), None);
+    }
+}
@@ -0,0 +1,22 @@
+// Tauri に依存しない orchestrator の部品（GUI と headless で共有）
+// Orchestrator building blocks with no Tauri dependency, shared by the GUI and headless builds.
+pub mod completion_hook;
+pub mod control;
+pub mod fake_worker;
+pub mod history;
+pub mod hook_gate;
+pub mod hook_setup;
+pub mod hook_tail;
+pub mod judge;
+pub mod launch;
+pub mod layout;
+pub mod paths;
+pub mod session;
+pub mod settings;
+pub mod sink;
+pub mod state;
+pub mod subworker;
+pub mod terminal_input;
+pub mod tray;
+pub mod worker;
+pub mod workspace;
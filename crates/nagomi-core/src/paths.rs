@@ -0,0 +1,46 @@
+// Tauri の app_config_dir と同じ場所を Tauri なしで求める
+// Resolve the same directory as Tauri's app_config_dir, without Tauri.
+use std::path::PathBuf;
+
+pub const APP_IDENTIFIER: &str = "com.kitfactory.nagomi";
+
+// `NAGOMI_APP_CONFIG_DIR` が最優先 / `NAGOMI_APP_CONFIG_DIR` always wins.
+pub fn app_config_dir() -> Option<PathBuf> {
+    if let Ok(value) = std::env::var("NAGOMI_APP_CONFIG_DIR") {
+        let trimmed = value.trim();
+        if !trimmed.is_empty() {
+            return Some(PathBuf::from(trimmed));
+        }
+    }
+    let base = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?)
+            .join("Library")
+            .join("Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|value| !value.is_empty()) {
+            Some(xdg) => PathBuf::from(xdg),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join(APP_IDENTIFIER))
+}
+
+// ファイル名の 1 要素として安全な文字だけ残す / Keep only characters that are safe in a single file name component.
+pub fn sanitize_asset_component(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' {
+            out.push(ch);
+        } else {
+            out.push('_');
+        }
+    }
+    let trimmed = out.trim_matches('_').trim_matches('.');
+    if trimmed.is_empty() {
+        "asset".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
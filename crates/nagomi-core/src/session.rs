@@ -0,0 +1,995 @@
+// terminal session の台帳と、worker からのメッセージ処理
+// The terminal session registry and the handling of worker messages.
+use crate::completion_hook::{
+    hook_kind_to_string, normalize_hook_state, summarize_hook_event, HookEvent, HookEventKind,
+};
+use crate::control::{ControlEvent, OutputTail, SessionInfo};
+use crate::history::append_project_prompt_history;
+use crate::hook_gate::{HookGate, HookVerdict};
+use crate::launch::{terminal_window_title, TerminalLaunchOptions};
+use crate::sink::{self, EventSink};
+use crate::state::ObservedStates;
+use crate::terminal_input::TerminalInputCaptureState;
+use crate::worker::WorkerProcess;
+use nagomi_protocol::{Message, Resize, SendInput, StopSession};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Terminal output coalescing defaults / 出力をまとめて送るときの既定値
+const OUTPUT_BUSY_THRESHOLD_BYTES: usize = 256 * 1024;
+const OUTPUT_FLUSH_DELAY_NORMAL: Duration = Duration::from_millis(16);
+const OUTPUT_FLUSH_BYTES_NORMAL: usize = 64 * 1024;
+const OUTPUT_FLUSH_DELAY_BUSY: Duration = Duration::from_millis(32);
+const OUTPUT_FLUSH_BYTES_BUSY: usize = 128 * 1024;
+const OUTPUT_IDLE_WAIT: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalOutputPayload {
+    pub session_id: String,
+    pub chunk: String,
+    pub stream: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalExitPayload {
+    pub session_id: String,
+    pub exit_code: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalErrorPayload {
+    pub session_id: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HookStatePayload {
+    pub source: String,
+    pub kind: String,
+    pub source_session_id: Option<String>,
+    // source_session_id が既知の terminal を指すときだけ入る / Set only when source_session_id names a known terminal.
+    pub session_id: Option<String>,
+    pub state: String,
+    pub summary: Option<String>,
+    // 状態機械へ渡す型付きの kind / Typed kind handed to the state machine.
+    #[serde(skip)]
+    pub event_kind: HookEventKind,
+}
+
+#[derive(Debug)]
+struct PendingOutput {
+    queued_at: Instant,
+    bytes: usize,
+    chunks: Vec<String>,
+}
+
+// まとめ終わった 1 回分の出力 / One flushed batch of output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoalescedOutput {
+    pub session_id: String,
+    pub stream: String,
+    pub chunk: String,
+}
+
+// 細切れの出力を 16ms / 64KiB 単位にまとめる。溜まりすぎたら 32ms / 128KiB に広げる
+// Batches output per 16ms / 64KiB, widening to 32ms / 128KiB once the backlog grows.
+// 時刻は呼び出し側が渡す / Callers pass the clock in.
+#[derive(Debug, Default)]
+pub struct OutputCoalescer {
+    pending: BTreeMap<(String, String), PendingOutput>,
+}
+
+impl OutputCoalescer {
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn limits(&self) -> (Duration, usize) {
+        let total: usize = self.pending.values().map(|entry| entry.bytes).sum();
+        if total > OUTPUT_BUSY_THRESHOLD_BYTES {
+            (OUTPUT_FLUSH_DELAY_BUSY, OUTPUT_FLUSH_BYTES_BUSY)
+        } else {
+            (OUTPUT_FLUSH_DELAY_NORMAL, OUTPUT_FLUSH_BYTES_NORMAL)
+        }
+    }
+
+    pub fn push(&mut self, session_id: &str, stream: &str, chunk: String, now: Instant) {
+        let entry = self
+            .pending
+            .entry((session_id.to_string(), stream.to_string()))
+            .or_insert_with(|| PendingOutput {
+                queued_at: now,
+                bytes: 0,
+                chunks: Vec::new(),
+            });
+        entry.bytes += chunk.len();
+        entry.chunks.push(chunk);
+    }
+
+    // 次の flush までの待ち時間（出力が疎でも ~16ms で出す）
+    // How long to wait for more messages so sparse output still flushes after ~16ms.
+    pub fn next_timeout(&self, now: Instant) -> Duration {
+        let (flush_delay, _) = self.limits();
+        self.pending
+            .values()
+            .map(|entry| (entry.queued_at + flush_delay).saturating_duration_since(now))
+            .min()
+            .unwrap_or(OUTPUT_IDLE_WAIT)
+            .min(OUTPUT_IDLE_WAIT)
+    }
+
+    // 期限か量に達した分を取り出す / Take the batches that are due by time or size.
+    pub fn drain_due(&mut self, now: Instant) -> Vec<CoalescedOutput> {
+        let (flush_delay, flush_bytes) = self.limits();
+        let due: Vec<(String, String)> = self
+            .pending
+            .iter()
+            .filter(|(_, entry)| {
+                entry.bytes >= flush_bytes || now.duration_since(entry.queued_at) >= flush_delay
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|key| {
+                let entry = self.pending.remove(&key)?;
+                Self::finish(key, entry)
+            })
+            .collect()
+    }
+
+    pub fn drain_all(&mut self) -> Vec<CoalescedOutput> {
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .filter_map(|(key, entry)| Self::finish(key, entry))
+            .collect()
+    }
+
+    fn finish(
+        (session_id, stream): (String, String),
+        entry: PendingOutput,
+    ) -> Option<CoalescedOutput> {
+        if entry.chunks.is_empty() {
+            return None;
+        }
+        let chunk = if entry.chunks.len() == 1 {
+            entry.chunks.into_iter().next().unwrap_or_default()
+        } else {
+            entry.chunks.concat()
+        };
+        Some(CoalescedOutput {
+            session_id,
+            stream,
+            chunk,
+        })
+    }
+}
+
+struct SmokeWaiter {
+    token: String,
+    sender: Sender<Result<(), String>>,
+}
+
+// smoke test が token の出力か終了を待つ / Smoke tests wait here for their token or an exit.
+#[derive(Default)]
+pub struct SmokeWaiters {
+    waiters: Mutex<HashMap<String, SmokeWaiter>>,
+}
+
+impl SmokeWaiters {
+    pub fn register(&self, session_id: &str, token: &str) -> Receiver<Result<(), String>> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut guard) = self.waiters.lock() {
+            guard.insert(
+                session_id.to_string(),
+                SmokeWaiter {
+                    token: token.to_lowercase(),
+                    sender,
+                },
+            );
+        }
+        receiver
+    }
+
+    fn finish(&self, session_id: &str, result: Result<(), String>) -> bool {
+        let waiter = match self.waiters.lock() {
+            Ok(mut guard) => guard.remove(session_id),
+            Err(_) => None,
+        };
+        match waiter {
+            Some(waiter) => {
+                let _ = waiter.sender.send(result);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // token が出たら成功 / Succeeds once the token shows up (case-insensitive).
+    pub fn match_output(&self, session_id: &str, chunk: &str) -> bool {
+        let matched = self
+            .waiters
+            .lock()
+            .ok()
+            .and_then(|guard| {
+                guard
+                    .get(session_id)
+                    .map(|waiter| chunk.to_lowercase().contains(&waiter.token))
+            })
+            .unwrap_or(false);
+        matched && self.finish(session_id, Ok(()))
+    }
+
+    pub fn fail(&self, session_id: &str, message: &str) -> bool {
+        self.finish(session_id, Err(message.to_string()))
+    }
+
+    // token 前でも exit 0 なら成功扱い / A clean exit counts as success even before the token.
+    pub fn exit(&self, session_id: &str, exit_code: i32) -> bool {
+        if exit_code == 0 {
+            self.finish(session_id, Ok(()))
+        } else {
+            self.fail(session_id, &format!("exit before token: {exit_code}"))
+        }
+    }
+}
+
+// stop の結果 / What a stop left behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopOutcome {
+    pub was_active: bool,
+    pub workers_empty: bool,
+}
+
+// GUI と headless が共有する session の台帳 / Session bookkeeping shared by the GUI and headless.
+#[derive(Default)]
+pub struct SessionRegistry {
+    pub active: Mutex<HashSet<String>>,
+    pub launch: Mutex<HashMap<String, TerminalLaunchOptions>>,
+    // worker が報告した子プロセスの pid / Child pid reported by the worker.
+    pub pids: Mutex<HashMap<String, u32>>,
+    // 制御 API の tail 用の直近出力 / Recent output served by the control API tail.
+    pub tails: Mutex<HashMap<String, OutputTail>>,
+    pub captures: Mutex<HashMap<String, TerminalInputCaptureState>>,
+    pub workers: Mutex<HashMap<String, WorkerProcess>>,
+    // hook の重複畳み込みと順序 / Hook dedup and ordering.
+    pub hook_gate: Mutex<HookGate>,
+}
+
+impl SessionRegistry {
+    pub fn is_active(&self, session_id: &str) -> bool {
+        self.active
+            .lock()
+            .map(|active| active.contains(session_id))
+            .unwrap_or(false)
+    }
+
+    pub fn launch_options(&self, session_id: &str) -> Option<TerminalLaunchOptions> {
+        self.launch.lock().ok()?.get(session_id).cloned()
+    }
+
+    pub fn set_launch_options(&self, session_id: &str, options: TerminalLaunchOptions) {
+        if let Ok(mut launch) = self.launch.lock() {
+            launch.insert(session_id.to_string(), options);
+        }
+    }
+
+    // 起動済み・起動中の terminal / A terminal that has been launched or is running.
+    pub fn is_known(&self, session_id: &str) -> bool {
+        self.is_active(session_id) || self.launch_options(session_id).is_some()
+    }
+
+    // hook が cwd を持たない場合に使う起動時の cwd / Launch cwd used when a hook carries none.
+    pub fn launch_cwd(&self, session_id: &str) -> Option<String> {
+        self.launch_options(session_id)?.effective_cwd()
+    }
+
+    // StartSession 済みの worker を登録する / Register a worker that has already been sent StartSession.
+    pub fn insert_worker(
+        &self,
+        session_id: &str,
+        process: WorkerProcess,
+        sink: &dyn EventSink,
+    ) -> Result<(), String> {
+        self.workers
+            .lock()
+            .map_err(|_| "terminal worker lock".to_string())?
+            .insert(session_id.to_string(), process);
+        self.active
+            .lock()
+            .map_err(|_| "terminal session lock".to_string())?
+            .insert(session_id.to_string());
+        sink::publish(sink, || ControlEvent::SessionOpened {
+            session_id: session_id.to_string(),
+        });
+        Ok(())
+    }
+
+    fn with_worker<T>(
+        &self,
+        session_id: &str,
+        f: impl FnOnce(&mut WorkerProcess) -> anyhow::Result<T>,
+    ) -> Result<T, String> {
+        let mut guard = self
+            .workers
+            .lock()
+            .map_err(|_| "terminal worker lock".to_string())?;
+        let Some(process) = guard.get_mut(session_id) else {
+            return Err("terminal session not started".to_string());
+        };
+        f(process).map_err(|err| err.to_string())
+    }
+
+    pub fn send_input(&self, session_id: &str, text: &str) -> Result<(), String> {
+        self.with_worker(session_id, |process| {
+            process.send_input(SendInput {
+                session_id: session_id.to_string(),
+                text: text.to_string(),
+            })
+        })
+    }
+
+    pub fn resize(&self, session_id: &str, cols: u16, rows: u16) -> Result<(), String> {
+        self.with_worker(session_id, |process| {
+            process.send_resize(Resize {
+                session_id: session_id.to_string(),
+                cols,
+                rows,
+            })
+        })
+    }
+
+    // 台帳から外して worker を止める / Drop the session from the registry and stop its worker.
+    pub fn stop(
+        &self,
+        session_id: &str,
+        reason: &str,
+        sink: &dyn EventSink,
+    ) -> Result<StopOutcome, String> {
+        let was_active = self
+            .active
+            .lock()
+            .map_err(|_| "terminal session lock".to_string())?
+            .remove(session_id);
+        if let Ok(mut launch) = self.launch.lock() {
+            launch.remove(session_id);
+        }
+        if let Ok(mut pids) = self.pids.lock() {
+            pids.remove(session_id);
+        }
+        if let Ok(mut tails) = self.tails.lock() {
+            tails.remove(session_id);
+        }
+        if let Ok(mut captures) = self.captures.lock() {
+            captures.remove(session_id);
+        }
+        if let Ok(mut gate) = self.hook_gate.lock() {
+            gate.forget(session_id);
+        }
+        let workers_empty = {
+            let mut guard = self
+                .workers
+                .lock()
+                .map_err(|_| "terminal worker lock".to_string())?;
+            if let Some(mut process) = guard.remove(session_id) {
+                let _ = process.send_stop_session(StopSession {
+                    session_id: session_id.to_string(),
+                });
+                let _ = process.stop();
+            }
+            guard.is_empty()
+        };
+        if was_active {
+            sink::publish(sink, || ControlEvent::SessionClosed {
+                session_id: session_id.to_string(),
+                reason: reason.to_string(),
+                exit_code: None,
+            });
+        }
+        Ok(StopOutcome {
+            was_active,
+            workers_empty,
+        })
+    }
+
+    pub fn tail(&self, session_id: &str) -> Option<String> {
+        let tails = self.tails.lock().ok()?;
+        tails.get(session_id).map(|tail| tail.as_str().to_string())
+    }
+
+    // 制御 API 向けの 1 件分。タイトルは起動条件から / One control API entry, titled from the launch options.
+    pub fn session_info(&self, session_id: &str, observed: &ObservedStates) -> SessionInfo {
+        let options = self.launch_options(session_id).unwrap_or_default();
+        SessionInfo {
+            session_id: session_id.to_string(),
+            title: terminal_window_title(session_id, &options),
+            state: observed
+                .get(session_id)
+                .unwrap_or_default()
+                .as_str()
+                .to_string(),
+            cwd: options.effective_cwd(),
+            pid: self
+                .pids
+                .lock()
+                .ok()
+                .and_then(|pids| pids.get(session_id).copied()),
+            profile: options.profile.map(|profile| profile.name),
+            running: self.is_active(session_id),
+        }
+    }
+
+    fn handle_message(
+        &self,
+        message: Message,
+        coalescer: &mut OutputCoalescer,
+        sink: &dyn EventSink,
+    ) {
+        match message {
+            Message::Output(output) => {
+                if let Ok(mut tails) = self.tails.lock() {
+                    tails
+                        .entry(output.session_id.clone())
+                        .or_default()
+                        .push(&output.chunk);
+                }
+                coalescer.push(
+                    &output.session_id,
+                    &output.stream,
+                    output.chunk,
+                    Instant::now(),
+                );
+            }
+            Message::SessionStarted(started) => {
+                sink.log(&format!(
+                    "terminal started {}: pid={:?}",
+                    started.session_id, started.pid
+                ));
+                if let Some(pid) = started.pid {
+                    if let Ok(mut pids) = self.pids.lock() {
+                        pids.insert(started.session_id, pid);
+                    }
+                }
+            }
+            Message::Exit(exit) => {
+                // 終了直前の出力を exit より先に届ける / Deliver trailing output before the exit.
+                for output in coalescer.drain_all() {
+                    self.deliver(output, sink);
+                }
+                sink.log(&format!(
+                    "terminal exit {}: {}",
+                    exit.session_id, exit.exit_code
+                ));
+                sink.session_exit(&exit.session_id, exit.exit_code);
+                let was_active = self
+                    .active
+                    .lock()
+                    .map(|mut active| active.remove(&exit.session_id))
+                    .unwrap_or(false);
+                if was_active {
+                    sink::publish(sink, || ControlEvent::SessionClosed {
+                        session_id: exit.session_id.clone(),
+                        reason: "exit".to_string(),
+                        exit_code: Some(exit.exit_code),
+                    });
+                }
+                if let Ok(mut pids) = self.pids.lock() {
+                    pids.remove(&exit.session_id);
+                }
+                if let Ok(mut captures) = self.captures.lock() {
+                    captures.remove(&exit.session_id);
+                }
+                let process = self
+                    .workers
+                    .lock()
+                    .ok()
+                    .and_then(|mut workers| workers.remove(&exit.session_id));
+                if let Some(mut process) = process {
+                    let _ = process.stop();
+                }
+                sink.session_closed(&exit.session_id);
+            }
+            Message::Error(error) => {
+                sink.log(&format!(
+                    "terminal error {}: {}",
+                    error.session_id, error.message
+                ));
+                sink.session_error(&error.session_id, &error.message, error.recoverable);
+            }
+            _ => {}
+        }
+    }
+
+    fn deliver(&self, output: CoalescedOutput, sink: &dyn EventSink) {
+        sink.session_output(&output.session_id, &output.stream, &output.chunk);
+        sink::publish(sink, || ControlEvent::Output {
+            session_id: output.session_id,
+            stream: output.stream,
+            data: output.chunk,
+        });
+    }
+
+    // worker のメッセージを送信側が全部閉じるまで処理する
+    // Process worker messages until every sender has gone away.
+    pub fn run_reader(&self, rx: &Receiver<Message>, sink: &dyn EventSink) {
+        let mut coalescer = OutputCoalescer::default();
+        loop {
+            let timeout = coalescer.next_timeout(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(message) => self.handle_message(message, &mut coalescer, sink),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            for output in coalescer.drain_due(Instant::now()) {
+                self.deliver(output, sink);
+            }
+        }
+        for output in coalescer.drain_all() {
+            self.deliver(output, sink);
+        }
+    }
+}
+
+// 完了 hook を状態・履歴・イベントに振り分ける。重複と古い event は捨てて None
+// Fan a completion hook out to state, history and events; duplicates and stale events give None.
+pub fn dispatch_hook_event(
+    registry: &SessionRegistry,
+    history_dir: Option<&Path>,
+    event: &HookEvent,
+    sink: &dyn EventSink,
+) -> Option<HookStatePayload> {
+    let verdict = registry
+        .hook_gate
+        .lock()
+        .map(|mut gate| gate.admit(event))
+        .unwrap_or(HookVerdict::Accept);
+    if verdict != HookVerdict::Accept {
+        sink.log(&format!(
+            "hook dropped ({verdict:?}): {} {} ts={}",
+            event.source,
+            hook_kind_to_string(event.kind),
+            event.ts_ms
+        ));
+        return None;
+    }
+    let state = normalize_hook_state(event.kind);
+    let summary = summarize_hook_event(event);
+    let session_id = event
+        .source_session_id
+        .as_deref()
+        .filter(|id| registry.is_known(id))
+        .map(str::to_string);
+    if let Some(dir) = history_dir {
+        let launch_cwd = session_id
+            .as_deref()
+            .and_then(|session_id| registry.launch_cwd(session_id));
+        if let Err(err) =
+            append_project_prompt_history(dir, event, &state, &summary, launch_cwd.as_deref())
+        {
+            sink.log(&format!("project prompt history append failed: {err}"));
+        }
+    }
+    let payload = HookStatePayload {
+        source: event.source.clone(),
+        kind: hook_kind_to_string(event.kind),
+        source_session_id: event.source_session_id.clone(),
+        session_id,
+        state,
+        summary: Some(summary),
+        event_kind: event.kind,
+    };
+    sink::publish(sink, || ControlEvent::Hook {
+        source: payload.source.clone(),
+        kind: payload.kind.clone(),
+        source_session_id: payload.source_session_id.clone(),
+        state: payload.state.clone(),
+        summary: payload.summary.clone(),
+    });
+    sink.hook_state(&payload);
+    Some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_worker::{FakeScript, FakeWorker};
+    use crate::state::{StateTrigger, TerminalState};
+
+    #[test]
+    fn coalescer_batches_until_the_flush_delay() {
+        let start = Instant::now();
+        let mut coalescer = OutputCoalescer::default();
+        assert_eq!(coalescer.next_timeout(start), OUTPUT_IDLE_WAIT);
+        coalescer.push("a", "stdout", "he".to_string(), start);
+        coalescer.push(
+            "a",
+            "stdout",
+            "llo".to_string(),
+            start + Duration::from_millis(5),
+        );
+        assert_eq!(
+            coalescer.next_timeout(start + Duration::from_millis(10)),
+            Duration::from_millis(6)
+        );
+        assert!(coalescer
+            .drain_due(start + Duration::from_millis(10))
+            .is_empty());
+        let flushed = coalescer.drain_due(start + OUTPUT_FLUSH_DELAY_NORMAL);
+        assert_eq!(
+            flushed,
+            vec![CoalescedOutput {
+                session_id: "a".to_string(),
+                stream: "stdout".to_string(),
+                chunk: "hello".to_string(),
+            }]
+        );
+        assert!(coalescer.is_empty());
+    }
+
+    #[test]
+    fn coalescer_flushes_large_batches_immediately() {
+        let start = Instant::now();
+        let mut coalescer = OutputCoalescer::default();
+        coalescer.push("a", "stdout", "x".repeat(OUTPUT_FLUSH_BYTES_NORMAL), start);
+        coalescer.push("b", "stdout", "y".to_string(), start);
+        let flushed = coalescer.drain_due(start);
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].session_id, "a");
+        assert_eq!(coalescer.drain_all().len(), 1);
+    }
+
+    #[test]
+    fn coalescer_widens_limits_when_busy() {
+        let start = Instant::now();
+        let mut coalescer = OutputCoalescer::default();
+        for session in ["a", "b", "c", "d", "e"] {
+            coalescer.push(
+                session,
+                "stdout",
+                "x".repeat(OUTPUT_FLUSH_BYTES_NORMAL - 1),
+                start,
+            );
+        }
+        // 合計が閾値を超えると 64KiB 未満でも 16ms では出さない
+        // Past the busy threshold, nothing flushes at 16ms.
+        assert!(coalescer
+            .drain_due(start + OUTPUT_FLUSH_DELAY_NORMAL)
+            .is_empty());
+        assert_eq!(
+            coalescer.drain_due(start + OUTPUT_FLUSH_DELAY_BUSY).len(),
+            5
+        );
+    }
+
+    #[test]
+    fn smoke_waiters_resolve_on_token_or_exit() {
+        let waiters = SmokeWaiters::default();
+        let ok = waiters.register("a", "OK");
+        assert!(!waiters.match_output("a", "nothing yet"));
+        assert!(waiters.match_output("a", "> ok\r\n"));
+        assert_eq!(ok.recv().unwrap(), Ok(()));
+        assert!(!waiters.exit("a", 0));
+
+        let failed = waiters.register("b", "ok");
+        assert!(waiters.exit("b", 2));
+        assert_eq!(
+            failed.recv().unwrap(),
+            Err("exit before token: 2".to_string())
+        );
+    }
+
+    // fake worker を reader に流すための sink / Sink used to drive the reader with a fake worker.
+    #[derive(Default)]
+    struct ScriptSink {
+        bus: crate::control::EventBus,
+        calls: Mutex<Vec<String>>,
+        aggregates: Mutex<Vec<String>>,
+        smoke: SmokeWaiters,
+        observed: ObservedStates,
+    }
+
+    impl ScriptSink {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl EventSink for ScriptSink {
+        fn bus(&self) -> Option<&crate::control::EventBus> {
+            Some(&self.bus)
+        }
+
+        fn session_output(&self, session_id: &str, stream: &str, chunk: &str) {
+            self.record(format!("output {session_id} {stream} {chunk}"));
+            self.smoke.match_output(session_id, chunk);
+        }
+
+        fn session_exit(&self, session_id: &str, exit_code: i32) {
+            self.record(format!("exit {session_id} {exit_code}"));
+            self.smoke.exit(session_id, exit_code);
+            let _ = self
+                .observed
+                .apply(session_id, StateTrigger::Exit(exit_code), self);
+        }
+
+        fn session_error(&self, session_id: &str, message: &str, recoverable: bool) {
+            self.record(format!("error {session_id} {message}"));
+            self.smoke.fail(session_id, message);
+            if !recoverable {
+                let _ = self
+                    .observed
+                    .apply(session_id, StateTrigger::WorkerFailed, self);
+            }
+        }
+
+        fn session_closed(&self, session_id: &str) {
+            self.record(format!("closed {session_id}"));
+        }
+
+        fn aggregate_changed(&self, state: &str) {
+            self.aggregates.lock().unwrap().push(state.to_string());
+        }
+
+        fn hook_state(&self, payload: &HookStatePayload) {
+            let target = payload.session_id.as_deref().unwrap_or("-");
+            self.record(format!(
+                "hook {} {target} {}",
+                payload.source, payload.state
+            ));
+        }
+    }
+
+    fn start_fake(
+        registry: &SessionRegistry,
+        sink: &ScriptSink,
+        session_id: &str,
+        worker: FakeWorker,
+    ) -> Receiver<Message> {
+        let mut process = worker.spawn();
+        let rx = process.take_receiver().expect("receiver");
+        process
+            .send_start_session(nagomi_protocol::StartSession {
+                session_id: session_id.to_string(),
+                cols: 80,
+                rows: 24,
+                ..Default::default()
+            })
+            .expect("start session");
+        registry
+            .insert_worker(session_id, process, sink)
+            .expect("insert worker");
+        rx
+    }
+
+    #[test]
+    fn reader_flushes_output_before_exit_and_cleans_up() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        let events = sink.bus.subscribe(Default::default());
+        let script = FakeScript::new().output("he").output("llo").exit(0);
+        let rx = start_fake(&registry, &sink, "s1", FakeWorker::new(script).with_pid(7));
+        assert_eq!(
+            registry.pids.lock().unwrap().get("s1"),
+            None,
+            "pid arrives through the reader"
+        );
+        registry.run_reader(&rx, &sink);
+
+        assert_eq!(
+            sink.calls(),
+            vec!["output s1 stdout hello", "exit s1 0", "closed s1"]
+        );
+        assert!(!registry.is_active("s1"));
+        assert!(registry.workers.lock().unwrap().is_empty());
+        assert!(registry.pids.lock().unwrap().is_empty());
+        assert_eq!(registry.tail("s1").as_deref(), Some("hello"));
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                ControlEvent::SessionOpened {
+                    session_id: "s1".to_string(),
+                },
+                ControlEvent::Output {
+                    session_id: "s1".to_string(),
+                    stream: "stdout".to_string(),
+                    data: "hello".to_string(),
+                },
+                ControlEvent::State {
+                    session_id: "s1".to_string(),
+                    state: "success".to_string(),
+                    previous: None,
+                },
+                ControlEvent::SessionClosed {
+                    session_id: "s1".to_string(),
+                    reason: "exit".to_string(),
+                    exit_code: Some(0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reader_flushes_separately_across_a_pause() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        let script = FakeScript::new()
+            .output("a")
+            .wait(Duration::from_millis(80))
+            .output("b")
+            .exit(0);
+        let rx = start_fake(&registry, &sink, "s1", FakeWorker::new(script));
+        registry.run_reader(&rx, &sink);
+        assert_eq!(
+            sink.calls(),
+            vec![
+                "output s1 stdout a",
+                "output s1 stdout b",
+                "exit s1 0",
+                "closed s1"
+            ]
+        );
+    }
+
+    #[test]
+    fn smoke_waiters_follow_the_scripted_worker() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        let matched = sink.smoke.register("s1", "OK");
+        let failed = sink.smoke.register("s2", "ok");
+        let rx1 = start_fake(
+            &registry,
+            &sink,
+            "s1",
+            FakeWorker::new(FakeScript::new().output("echo ok\r\nok\r\n").exit(3)),
+        );
+        let rx2 = start_fake(
+            &registry,
+            &sink,
+            "s2",
+            FakeWorker::new(FakeScript::new().exit(2)),
+        );
+        registry.run_reader(&rx1, &sink);
+        registry.run_reader(&rx2, &sink);
+        assert_eq!(matched.try_recv(), Ok(Ok(())));
+        assert_eq!(
+            failed.try_recv(),
+            Ok(Err("exit before token: 2".to_string()))
+        );
+    }
+
+    #[test]
+    fn errors_and_exit_codes_drive_observed_states() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        let rx1 = start_fake(
+            &registry,
+            &sink,
+            "warned",
+            FakeWorker::new(FakeScript::new().error("slow pty", true).exit(0)),
+        );
+        registry.run_reader(&rx1, &sink);
+        assert_eq!(sink.observed.get("warned"), Some(TerminalState::Success));
+        assert!(sink.aggregates.lock().unwrap().is_empty());
+
+        let rx2 = start_fake(
+            &registry,
+            &sink,
+            "broken",
+            FakeWorker::new(FakeScript::new().error("spawn failed", false).exit(1)),
+        );
+        registry.run_reader(&rx2, &sink);
+        assert_eq!(sink.observed.get("broken"), Some(TerminalState::Fail));
+        assert_eq!(*sink.aggregates.lock().unwrap(), vec!["fail".to_string()]);
+        assert!(sink
+            .calls()
+            .contains(&"error broken spawn failed".to_string()));
+
+        sink.observed.forget("broken", &sink);
+        assert_eq!(
+            *sink.aggregates.lock().unwrap(),
+            vec!["fail".to_string(), "idle".to_string()]
+        );
+    }
+
+    #[test]
+    fn stop_reaches_the_worker_and_ends_the_reader() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        let events = sink.bus.subscribe(Default::default());
+        let worker = FakeWorker::new(
+            FakeScript::new()
+                .output("ready")
+                .wait(Duration::from_secs(30))
+                .exit(0),
+        );
+        let log = worker.log();
+        let rx = start_fake(&registry, &sink, "s1", worker);
+        std::thread::scope(|scope| {
+            let (reader_registry, reader_sink) = (&registry, &sink);
+            let reader = scope.spawn(move || reader_registry.run_reader(&rx, reader_sink));
+            let outcome = registry.stop("s1", "test", &sink).expect("stop");
+            assert_eq!(
+                outcome,
+                StopOutcome {
+                    was_active: true,
+                    workers_empty: true,
+                }
+            );
+            reader.join().expect("reader");
+        });
+        assert!(log
+            .messages()
+            .iter()
+            .any(|message| matches!(message, Message::StopSession(_))));
+        let closed: Vec<ControlEvent> = events
+            .try_iter()
+            .filter(|event| matches!(event, ControlEvent::SessionClosed { .. }))
+            .collect();
+        assert_eq!(
+            closed,
+            vec![ControlEvent::SessionClosed {
+                session_id: "s1".to_string(),
+                reason: "test".to_string(),
+                exit_code: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn hook_events_route_to_known_terminals_only() {
+        let registry = SessionRegistry::default();
+        let sink = ScriptSink::default();
+        registry.set_launch_options("term-a", TerminalLaunchOptions::default());
+        let event = |source: &str, session: Option<&str>, kind| HookEvent {
+            source: source.to_string(),
+            kind,
+            ts_ms: 0,
+            source_session_id: session.map(str::to_string),
+            raw: None,
+        };
+
+        let routed = dispatch_hook_event(
+            &registry,
+            None,
+            &event("claude", Some("term-a"), HookEventKind::NeedInput),
+            &sink,
+        )
+        .expect("accepted");
+        assert_eq!(routed.session_id.as_deref(), Some("term-a"));
+        // 同じ hook の再送は状態にも履歴にも届かない / A resent hook reaches neither state nor history.
+        assert!(dispatch_hook_event(
+            &registry,
+            None,
+            &event("claude", Some("term-a"), HookEventKind::NeedInput),
+            &sink,
+        )
+        .is_none());
+        dispatch_hook_event(
+            &registry,
+            None,
+            &event("codex", Some("thread-9"), HookEventKind::Completed),
+            &sink,
+        );
+        dispatch_hook_event(
+            &registry,
+            None,
+            &event("opencode", None, HookEventKind::Error),
+            &sink,
+        );
+        assert_eq!(
+            sink.calls(),
+            vec![
+                "hook claude term-a need_input",
+                "hook codex - success",
+                "hook opencode - failure",
+            ]
+        );
+    }
+}
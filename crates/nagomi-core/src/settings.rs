@@ -0,0 +1,735 @@
+// settings.json の型・既定値・正規化 / The settings.json schema, its defaults and normalization.
+use crate::hook_gate::DEFAULT_HOOK_DEDUP_WINDOW_MS;
+use crate::layout;
+use crate::state::{
+    AggregatePolicy, AGGREGATE_GROUP_NONE, AGGREGATE_GROUP_PROFILE, AGGREGATE_GROUP_PROJECT,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TERMINAL_SHELL_CMD: &str = "cmd";
+
+pub const TERMINAL_SHELL_POWERSHELL: &str = "powershell";
+
+pub const TERMINAL_SHELL_POWERSHELL7: &str = "pwsh";
+
+pub const TERMINAL_SHELL_WSL: &str = "wsl";
+
+pub const TERMINAL_SHELL_LOGIN: &str = "login";
+
+pub const TERMINAL_SHELL_BASH: &str = "bash";
+
+pub const TERMINAL_SHELL_ZSH: &str = "zsh";
+
+pub const TERMINAL_SHELL_FISH: &str = "fish";
+
+pub const TERMINAL_SHELL_NU: &str = "nu";
+
+#[cfg(not(windows))]
+pub const UNIX_TERMINAL_SHELL_KINDS: [&str; 4] = [
+    TERMINAL_SHELL_BASH,
+    TERMINAL_SHELL_ZSH,
+    TERMINAL_SHELL_FISH,
+    TERMINAL_SHELL_NU,
+];
+
+const TERMINAL_KEYBIND_ARRANGE_DEFAULT: &str = "Ctrl+Shift+Y";
+
+const TERMINAL_KEYBIND_FOCUS_NEXT_DEFAULT: &str = "Ctrl+Shift+J";
+
+const TERMINAL_KEYBIND_FOCUS_PREV_DEFAULT: &str = "Ctrl+Shift+K";
+
+// OS 全体のキーを奪わないよう既定は未割り当て / Unassigned by default so no OS-wide keys are taken.
+const TERMINAL_KEYBIND_NEXT_NEED_INPUT_DEFAULT: &str = "";
+
+const TERMINAL_KEYBIND_NEXT_FAILED_DEFAULT: &str = "";
+
+const SUBWORKER_ENABLED_DEFAULT: bool = true;
+
+const SUBWORKER_DEBUG_ENABLED_DEFAULT: bool = false;
+
+const SUBWORKER_MODE_CAREFUL: &str = "careful";
+
+const SUBWORKER_CONFIDENCE_THRESHOLD_DEFAULT: f32 = 0.8;
+
+const STATUS_DEBUG_ENABLED_DEFAULT: bool = false;
+
+const CHARACTER_RENDERER_DEFAULT: &str = "3d";
+
+const CHARACTER_3D_SCALE_DEFAULT: f32 = 1.0;
+
+const CHARACTER_3D_YAW_DEG_DEFAULT: f32 = 0.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub notifications_enabled: bool,
+    pub audio_enabled: bool,
+    pub volume: f32,
+    pub llm_tool: String,
+    #[serde(default = "default_subworker_enabled")]
+    pub subworker_enabled: bool,
+    #[serde(default = "default_subworker_debug_enabled")]
+    pub subworker_debug_enabled: bool,
+    #[serde(default = "default_subworker_mode")]
+    pub subworker_mode: String,
+    #[serde(default = "default_subworker_confidence_threshold")]
+    pub subworker_confidence_threshold: f32,
+    // NOTE: Backward compatible alias for the previous display-template setting key.
+    // NOTE: 莉･蜑阪・陦ｨ遉ｺ逕ｨ繝・Φ繝励Ξ險ｭ螳壹く繝ｼ・・ubworker_advice_template_markdown・峨°繧峨・遘ｻ陦後ｒ蜿励￠繧九・    #[serde(default, alias = "subworker_advice_template_markdown")]
+    pub subworker_prompt_template_markdown: String,
+    #[serde(default = "default_status_debug_enabled")]
+    pub status_debug_enabled: bool,
+    pub character_id: String,
+    #[serde(default = "default_character_renderer")]
+    pub character_renderer: String,
+    #[serde(default)]
+    pub character_3d_vrm_path: String,
+    #[serde(default = "default_character_3d_scale")]
+    pub character_3d_scale: f32,
+    #[serde(default = "default_character_3d_yaw_deg")]
+    pub character_3d_yaw_deg: f32,
+    #[serde(default)]
+    pub character_motion_default_paths: HashMap<String, String>,
+    pub log_retention_lines: u32,
+    pub terminal_watcher_enabled: bool,
+    #[serde(default)]
+    pub terminal_font_family: String,
+    #[serde(default)]
+    pub terminal_font_size: u16,
+    #[serde(default)]
+    pub terminal_theme: String,
+    #[serde(default = "default_terminal_theme_palette")]
+    pub terminal_theme_palette: String,
+    #[serde(default)]
+    pub terminal_scrollback_lines: u32,
+    #[serde(default)]
+    pub terminal_copy_on_select: bool,
+    #[serde(default = "default_terminal_internal_commands_enabled")]
+    pub terminal_internal_commands_enabled: bool,
+    #[serde(default = "default_terminal_shell_kind")]
+    pub terminal_shell_kind: String,
+    #[serde(default)]
+    pub terminal_wsl_distro: String,
+    #[serde(default = "default_terminal_keybind_arrange")]
+    pub terminal_keybind_arrange: String,
+    #[serde(default = "default_terminal_keybind_focus_next")]
+    pub terminal_keybind_focus_next: String,
+    #[serde(default = "default_terminal_keybind_focus_prev")]
+    pub terminal_keybind_focus_prev: String,
+    #[serde(default = "default_terminal_keybind_next_need_input")]
+    pub terminal_keybind_next_need_input: String,
+    #[serde(default = "default_terminal_keybind_next_failed")]
+    pub terminal_keybind_next_failed: String,
+    #[serde(default)]
+    pub terminal_auto_pickup_need_input: bool,
+    #[serde(default = "default_terminal_layout")]
+    pub terminal_layout: String,
+    #[serde(default)]
+    pub terminal_profiles: Vec<TerminalProfile>,
+    // 完了 hook を source ごとに tail するか / Whether to tail each completion hook source.
+    #[serde(default = "default_hook_source_enabled")]
+    pub hook_codex_enabled: bool,
+    #[serde(default = "default_hook_source_enabled")]
+    pub hook_claude_enabled: bool,
+    #[serde(default = "default_hook_source_enabled")]
+    pub hook_opencode_enabled: bool,
+    #[serde(default = "default_hook_source_enabled")]
+    pub hook_gemini_enabled: bool,
+    #[serde(default = "default_hook_source_enabled")]
+    pub hook_aider_enabled: bool,
+    // 同じ hook を 1 回に畳む窓（0 で無効）/ Window for collapsing repeated hooks (0 disables).
+    #[serde(default = "default_hook_dedup_window_ms")]
+    pub hook_dedup_window_ms: u64,
+    // 集約で拾う状態と優先順（先頭ほど強い）/ States the aggregate picks up, strongest first.
+    #[serde(default = "default_aggregate_priority")]
+    pub aggregate_priority: Vec<String>,
+    // none / project / profile
+    #[serde(default = "default_aggregate_group_by")]
+    pub aggregate_group_by: String,
+    // watcher が追うグループ。空なら全体 / Group the watcher follows; empty follows every terminal.
+    #[serde(default)]
+    pub watcher_aggregate_group: String,
+}
+
+// 名前付きの terminal 起動プリセット / Named terminal launch preset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalProfile {
+    pub name: String,
+    // 空なら shell kind のシェルを起動 / Empty launches the shell for `shell_kind`.
+    pub cmd: String,
+    pub cwd: String,
+    pub env: HashMap<String, String>,
+    // 空なら Settings.terminal_shell_kind / Empty falls back to Settings.terminal_shell_kind.
+    pub shell_kind: String,
+    // palette 名（例: dark-ocean）。空なら Settings のテーマ / Palette id; empty uses the Settings theme.
+    pub theme: String,
+    // 完了 hook の tool（codex/claudecode/opencode）/ Completion hook tool.
+    pub llm_tool: String,
+}
+
+fn default_terminal_shell_kind() -> String {
+    if cfg!(windows) {
+        TERMINAL_SHELL_CMD.to_string()
+    } else {
+        TERMINAL_SHELL_LOGIN.to_string()
+    }
+}
+
+fn default_hook_source_enabled() -> bool {
+    true
+}
+
+fn default_hook_dedup_window_ms() -> u64 {
+    DEFAULT_HOOK_DEDUP_WINDOW_MS
+}
+
+fn default_aggregate_priority() -> Vec<String> {
+    AggregatePolicy::default().names()
+}
+
+fn default_aggregate_group_by() -> String {
+    AGGREGATE_GROUP_PROJECT.to_string()
+}
+
+fn default_terminal_layout() -> String {
+    layout::LAYOUT_GRID.to_string()
+}
+
+fn default_terminal_internal_commands_enabled() -> bool {
+    true
+}
+
+fn default_terminal_theme_palette() -> String {
+    "dark-ink".to_string()
+}
+
+fn default_terminal_keybind_arrange() -> String {
+    TERMINAL_KEYBIND_ARRANGE_DEFAULT.to_string()
+}
+
+fn default_terminal_keybind_focus_next() -> String {
+    TERMINAL_KEYBIND_FOCUS_NEXT_DEFAULT.to_string()
+}
+
+fn default_terminal_keybind_focus_prev() -> String {
+    TERMINAL_KEYBIND_FOCUS_PREV_DEFAULT.to_string()
+}
+
+fn default_terminal_keybind_next_need_input() -> String {
+    TERMINAL_KEYBIND_NEXT_NEED_INPUT_DEFAULT.to_string()
+}
+
+fn default_terminal_keybind_next_failed() -> String {
+    TERMINAL_KEYBIND_NEXT_FAILED_DEFAULT.to_string()
+}
+
+fn default_subworker_mode() -> String {
+    SUBWORKER_MODE_CAREFUL.to_string()
+}
+
+fn default_subworker_enabled() -> bool {
+    SUBWORKER_ENABLED_DEFAULT
+}
+
+fn default_subworker_debug_enabled() -> bool {
+    SUBWORKER_DEBUG_ENABLED_DEFAULT
+}
+
+fn default_subworker_confidence_threshold() -> f32 {
+    SUBWORKER_CONFIDENCE_THRESHOLD_DEFAULT
+}
+
+fn default_status_debug_enabled() -> bool {
+    STATUS_DEBUG_ENABLED_DEFAULT
+}
+
+pub fn default_character_renderer() -> String {
+    CHARACTER_RENDERER_DEFAULT.to_string()
+}
+
+pub fn default_character_3d_scale() -> f32 {
+    CHARACTER_3D_SCALE_DEFAULT
+}
+
+pub fn default_character_3d_yaw_deg() -> f32 {
+    CHARACTER_3D_YAW_DEG_DEFAULT
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            notifications_enabled: true,
+            audio_enabled: true,
+            volume: 0.8,
+            llm_tool: "codex".to_string(),
+            subworker_enabled: default_subworker_enabled(),
+            subworker_debug_enabled: default_subworker_debug_enabled(),
+            subworker_mode: default_subworker_mode(),
+            subworker_confidence_threshold: default_subworker_confidence_threshold(),
+            subworker_prompt_template_markdown: String::new(),
+            status_debug_enabled: default_status_debug_enabled(),
+            character_id: "default".to_string(),
+            character_renderer: default_character_renderer(),
+            character_3d_vrm_path: String::new(),
+            character_3d_scale: default_character_3d_scale(),
+            character_3d_yaw_deg: default_character_3d_yaw_deg(),
+            character_motion_default_paths: HashMap::new(),
+            log_retention_lines: 20_000,
+            terminal_watcher_enabled: true,
+            terminal_font_family: "ui-monospace, 'Cascadia Mono', Consolas, 'SFMono-Regular', Menlo, Monaco, 'Liberation Mono', 'DejaVu Sans Mono', monospace".to_string(),
+            terminal_font_size: 18,
+            terminal_theme: "dark".to_string(),
+            terminal_theme_palette: default_terminal_theme_palette(),
+            terminal_scrollback_lines: 5000,
+            terminal_copy_on_select: true,
+            terminal_internal_commands_enabled: default_terminal_internal_commands_enabled(),
+            terminal_shell_kind: default_terminal_shell_kind(),
+            terminal_wsl_distro: String::new(),
+            terminal_keybind_arrange: default_terminal_keybind_arrange(),
+            terminal_keybind_focus_next: default_terminal_keybind_focus_next(),
+            terminal_keybind_focus_prev: default_terminal_keybind_focus_prev(),
+            terminal_keybind_next_need_input: default_terminal_keybind_next_need_input(),
+            terminal_keybind_next_failed: default_terminal_keybind_next_failed(),
+            terminal_auto_pickup_need_input: false,
+            terminal_layout: default_terminal_layout(),
+            terminal_profiles: Vec::new(),
+            hook_codex_enabled: default_hook_source_enabled(),
+            hook_claude_enabled: default_hook_source_enabled(),
+            hook_opencode_enabled: default_hook_source_enabled(),
+            hook_gemini_enabled: default_hook_source_enabled(),
+            hook_aider_enabled: default_hook_source_enabled(),
+            hook_dedup_window_ms: default_hook_dedup_window_ms(),
+            aggregate_priority: default_aggregate_priority(),
+            aggregate_group_by: default_aggregate_group_by(),
+            watcher_aggregate_group: String::new(),
+        }
+    }
+}
+
+pub fn find_terminal_profile<'a>(
+    settings: &'a Settings,
+    name: &str,
+) -> Option<&'a TerminalProfile> {
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    settings
+        .terminal_profiles
+        .iter()
+        .find(|profile| profile.name.trim() == name)
+        .or_else(|| {
+            settings
+                .terminal_profiles
+                .iter()
+                .find(|profile| profile.name.trim().eq_ignore_ascii_case(name))
+        })
+}
+
+// 同時に tail する hook source / Hook sources to tail side by side.
+pub fn enabled_hook_sources(settings: &Settings) -> Vec<&'static str> {
+    [
+        ("codex", settings.hook_codex_enabled),
+        ("claude", settings.hook_claude_enabled),
+        ("opencode", settings.hook_opencode_enabled),
+        ("gemini", settings.hook_gemini_enabled),
+        ("aider", settings.hook_aider_enabled),
+    ]
+    .into_iter()
+    .filter_map(|(source, enabled)| enabled.then_some(source))
+    .collect()
+}
+
+pub fn normalize_terminal_shell_kind(kind: &str) -> &'static str {
+    match kind.trim().to_ascii_lowercase().as_str() {
+        TERMINAL_SHELL_POWERSHELL => TERMINAL_SHELL_POWERSHELL,
+        TERMINAL_SHELL_POWERSHELL7 => TERMINAL_SHELL_POWERSHELL7,
+        TERMINAL_SHELL_WSL => TERMINAL_SHELL_WSL,
+        TERMINAL_SHELL_CMD => TERMINAL_SHELL_CMD,
+        TERMINAL_SHELL_LOGIN => TERMINAL_SHELL_LOGIN,
+        TERMINAL_SHELL_BASH => TERMINAL_SHELL_BASH,
+        TERMINAL_SHELL_ZSH => TERMINAL_SHELL_ZSH,
+        TERMINAL_SHELL_FISH => TERMINAL_SHELL_FISH,
+        TERMINAL_SHELL_NU => TERMINAL_SHELL_NU,
+        _ if cfg!(windows) => TERMINAL_SHELL_CMD,
+        _ => TERMINAL_SHELL_LOGIN,
+    }
+}
+
+pub fn normalize_terminal_theme_mode(mode: &str) -> &'static str {
+    if mode.trim().eq_ignore_ascii_case("light") {
+        "light"
+    } else {
+        "dark"
+    }
+}
+
+fn default_terminal_theme_palette_for_mode(mode: &str) -> &'static str {
+    if mode == "light" {
+        "light-sand"
+    } else {
+        "dark-ink"
+    }
+}
+
+pub fn normalize_terminal_theme_palette(mode: &str, palette: &str) -> String {
+    let value = palette.trim().to_ascii_lowercase();
+    let valid = if mode == "light" {
+        matches!(
+            value.as_str(),
+            "light-sand" | "light-sage" | "light-sky" | "light-mono"
+        )
+    } else {
+        matches!(
+            value.as_str(),
+            "dark-ink" | "dark-ocean" | "dark-ember" | "dark-mono"
+        )
+    };
+    if valid {
+        value
+    } else {
+        default_terminal_theme_palette_for_mode(mode).to_string()
+    }
+}
+
+pub fn normalize_character_motion_state(raw: &str) -> Option<&'static str> {
+    let value = raw.trim().to_ascii_lowercase();
+    match value.as_str() {
+        "neutral" => Some("neutral"),
+        "processing" => Some("processing"),
+        "waiting" => Some("waiting"),
+        "need-user" | "need_user" => Some("need-user"),
+        "completion" => Some("completion"),
+        "error-alert" | "error_alert" => Some("error-alert"),
+        _ => None,
+    }
+}
+
+pub fn normalize_character_motion_path_map(
+    raw: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    raw.iter()
+        .filter_map(|(key, value)| {
+            let state = normalize_character_motion_state(key.as_str())?;
+            let path = value.trim().to_string();
+            if path.is_empty() {
+                return None;
+            }
+            Some((state.to_string(), path))
+        })
+        .collect()
+}
+
+pub fn normalize_character_renderer(_raw: &str) -> &'static str {
+    "3d"
+}
+
+pub fn normalize_character_3d_scale(raw: f32) -> f32 {
+    if !raw.is_finite() {
+        return CHARACTER_3D_SCALE_DEFAULT;
+    }
+    raw.clamp(0.2, 3.0)
+}
+
+pub fn normalize_character_3d_yaw_deg(raw: f32) -> f32 {
+    if !raw.is_finite() {
+        return CHARACTER_3D_YAW_DEG_DEFAULT;
+    }
+    raw.clamp(-180.0, 180.0)
+}
+
+// 読み込み・保存の両方で揃える値 / Values normalized on both load and save.
+pub fn normalize_settings(settings: &mut Settings) {
+    settings.character_renderer =
+        normalize_character_renderer(&settings.character_renderer).to_string();
+    settings.character_3d_scale = normalize_character_3d_scale(settings.character_3d_scale);
+    settings.character_3d_yaw_deg = normalize_character_3d_yaw_deg(settings.character_3d_yaw_deg);
+    settings.character_3d_vrm_path = settings.character_3d_vrm_path.trim().to_string();
+    settings.character_motion_default_paths =
+        normalize_character_motion_path_map(&settings.character_motion_default_paths);
+    settings.aggregate_priority = AggregatePolicy::from_names(&settings.aggregate_priority).names();
+    settings.aggregate_group_by =
+        normalize_aggregate_group_by(&settings.aggregate_group_by).to_string();
+    settings.watcher_aggregate_group = settings.watcher_aggregate_group.trim().to_string();
+}
+
+pub fn normalize_aggregate_group_by(raw: &str) -> &'static str {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        AGGREGATE_GROUP_NONE => AGGREGATE_GROUP_NONE,
+        AGGREGATE_GROUP_PROFILE => AGGREGATE_GROUP_PROFILE,
+        _ => AGGREGATE_GROUP_PROJECT,
+    }
+}
+
+pub fn aggregate_policy(settings: &Settings) -> AggregatePolicy {
+    AggregatePolicy::from_names(&settings.aggregate_priority)
+}
+
+pub fn read_settings(path: &Path) -> Result<Settings> {
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let raw = fs::read_to_string(path)?;
+    // Windows PowerShell can write UTF-8 with BOM. serde_json rejects BOM, so strip it.
+    // Windows PowerShell 縺ｯ UTF-8 BOM 莉倥″縺ｧ菫晏ｭ倥＠縺後■縺ｧ縲《erde_json 縺悟ｼｾ縺上◆繧・勁蜴ｻ縺吶ｋ縲・
+    let raw = raw.trim_start_matches('\u{feff}');
+    if raw.trim().is_empty() {
+        return Ok(Settings::default());
+    }
+    match serde_json::from_str(raw) {
+        Ok(settings) => Ok(settings),
+        Err(_) => {
+            // Recover from corrupted settings instead of failing app startup.
+            // 險ｭ螳壹ヵ繧｡繧､繝ｫ遐ｴ謳阪〒繧りｵｷ蜍輔〒縺阪ｋ繧医≧縺ｫ蠕ｩ譌ｧ縺吶ｋ・磯驕ｿ縺励※譌｢螳壼､縺ｫ謌ｻ縺呻ｼ峨・
+            let ts_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::from_millis(0))
+                .as_millis();
+            if let Some(parent) = path.parent() {
+                let file_name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("settings.json");
+                let backup = parent.join(format!("{file_name}.corrupt.{ts_ms}.json"));
+                // Best-effort backup. Ignore errors; we still want to boot.
+                let _ = fs::write(&backup, raw.as_bytes());
+            }
+            Ok(Settings::default())
+        }
+    }
+}
+
+pub fn write_settings(path: &Path, settings: &Settings) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let raw = serde_json::to_string_pretty(settings)?;
+    let mut file = fs::File::create(path)?;
+    file.write_all(raw.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_settings_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time")
+            .as_nanos();
+        path.push(format!("nagomi-{name}-{nonce}.json"));
+        path
+    }
+
+    #[test]
+    fn settings_roundtrip() {
+        let path = temp_settings_path("roundtrip");
+        let settings = Settings {
+            notifications_enabled: false,
+            audio_enabled: true,
+            volume: 0.5,
+            llm_tool: "codex".to_string(),
+            subworker_enabled: true,
+            subworker_debug_enabled: false,
+            subworker_mode: "careful".to_string(),
+            subworker_confidence_threshold: 0.82,
+            subworker_prompt_template_markdown: "### prompt\n{{last_terminal_output}}\n".to_string(),
+            status_debug_enabled: false,
+            character_id: "test".to_string(),
+            character_renderer: "3d".to_string(),
+            character_3d_vrm_path: "C:/tmp/test.vrm".to_string(),
+            character_3d_scale: 1.2,
+            character_3d_yaw_deg: -15.0,
+            character_motion_default_paths: HashMap::from([
+                ("neutral".to_string(), "C:/tmp/motions/neutral.vrma".to_string()),
+                ("waiting".to_string(), "C:/tmp/motions/waiting.vrma".to_string()),
+            ]),
+            log_retention_lines: 100,
+            terminal_watcher_enabled: true,
+            terminal_font_family:
+                "ui-monospace, 'Cascadia Mono', Consolas, 'SFMono-Regular', Menlo, Monaco, 'Liberation Mono', 'DejaVu Sans Mono', monospace"
+                    .to_string(),
+            terminal_font_size: 14,
+            terminal_theme: "light".to_string(),
+            terminal_theme_palette: "light-sand".to_string(),
+            terminal_scrollback_lines: 5000,
+            terminal_copy_on_select: false,
+            terminal_internal_commands_enabled: true,
+            terminal_shell_kind: TERMINAL_SHELL_WSL.to_string(),
+            terminal_wsl_distro: "Ubuntu".to_string(),
+            terminal_keybind_arrange: "Ctrl+Shift+Y".to_string(),
+            terminal_keybind_focus_next: "Ctrl+Shift+J".to_string(),
+            terminal_keybind_focus_prev: "Ctrl+Shift+K".to_string(),
+            terminal_keybind_next_need_input: "Ctrl+Alt+N".to_string(),
+            terminal_keybind_next_failed: "Ctrl+Alt+E".to_string(),
+            terminal_auto_pickup_need_input: true,
+            terminal_layout: layout::LAYOUT_PRIORITY.to_string(),
+            terminal_profiles: vec![TerminalProfile {
+                name: "api repo - claude".to_string(),
+                cmd: "claude".to_string(),
+                cwd: "~/src/api".to_string(),
+                env: HashMap::from([("NODE_ENV".to_string(), "development".to_string())]),
+                shell_kind: TERMINAL_SHELL_BASH.to_string(),
+                theme: "dark-ocean".to_string(),
+                llm_tool: "claudecode".to_string(),
+            }],
+            hook_codex_enabled: true,
+            hook_claude_enabled: false,
+            hook_opencode_enabled: true,
+            hook_gemini_enabled: false,
+            hook_aider_enabled: true,
+            hook_dedup_window_ms: 500,
+            aggregate_priority: vec!["fail".to_string(), "need-input".to_string()],
+            aggregate_group_by: "profile".to_string(),
+            watcher_aggregate_group: "api".to_string(),
+        };
+
+        write_settings(&path, &settings).expect("write settings");
+        let loaded = read_settings(&path).expect("read settings");
+        assert_eq!(loaded, settings);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn settings_default_when_missing() {
+        let path = temp_settings_path("missing");
+        let loaded = read_settings(&path).expect("read settings");
+        assert_eq!(loaded, Settings::default());
+    }
+
+    #[test]
+    fn settings_default_when_empty_file() {
+        let path = temp_settings_path("empty");
+        fs::write(&path, "").expect("write empty settings");
+        let loaded = read_settings(&path).expect("read settings");
+        assert_eq!(loaded, Settings::default());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn hook_sources_default_on_and_follow_the_flags() {
+        // flag のない古い settings は全 source を tail する
+        // Older settings without the flags tail every source.
+        let path = temp_settings_path("hook-sources");
+        let mut legacy = serde_json::to_value(Settings::default()).expect("serialize");
+        let object = legacy.as_object_mut().expect("settings object");
+        object.retain(|key, _| !key.starts_with("hook_"));
+        fs::write(&path, legacy.to_string()).expect("write settings");
+        let loaded = read_settings(&path).expect("read settings");
+        assert_eq!(
+            enabled_hook_sources(&loaded),
+            vec!["codex", "claude", "opencode", "gemini", "aider"]
+        );
+        let settings = Settings {
+            hook_opencode_enabled: false,
+            hook_aider_enabled: false,
+            ..loaded
+        };
+        assert_eq!(
+            enabled_hook_sources(&settings),
+            vec!["codex", "claude", "gemini"]
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn aggregate_settings_are_normalized() {
+        let mut settings = Settings {
+            aggregate_priority: vec![
+                "Need_Input".to_string(),
+                "nope".to_string(),
+                "need-input".to_string(),
+            ],
+            aggregate_group_by: "Profile ".to_string(),
+            watcher_aggregate_group: " api ".to_string(),
+            ..Settings::default()
+        };
+        normalize_settings(&mut settings);
+        assert_eq!(settings.aggregate_priority, ["need-input"]);
+        assert_eq!(settings.aggregate_group_by, AGGREGATE_GROUP_PROFILE);
+        assert_eq!(settings.watcher_aggregate_group, "api");
+        settings.aggregate_priority.clear();
+        settings.aggregate_group_by = "tags".to_string();
+        normalize_settings(&mut settings);
+        assert_eq!(
+            settings.aggregate_priority,
+            ["need-input", "fail", "subworker-running"]
+        );
+        assert_eq!(settings.aggregate_group_by, AGGREGATE_GROUP_PROJECT);
+    }
+
+    #[test]
+    fn theme_palette_normalization_supports_monochrome() {
+        assert_eq!(
+            normalize_terminal_theme_palette("light", "light-mono"),
+            "light-mono"
+        );
+        assert_eq!(
+            normalize_terminal_theme_palette("dark", "dark-mono"),
+            "dark-mono"
+        );
+        assert_eq!(
+            normalize_terminal_theme_palette("light", "dark-mono"),
+            "light-sand"
+        );
+        assert_eq!(
+            normalize_terminal_theme_palette("dark", "light-mono"),
+            "dark-ink"
+        );
+    }
+
+    #[test]
+    fn terminal_profile_lookup_trims_and_ignores_case() {
+        let settings = Settings {
+            terminal_profiles: vec![
+                TerminalProfile {
+                    name: "Logs".to_string(),
+                    ..TerminalProfile::default()
+                },
+                TerminalProfile {
+                    name: "logs".to_string(),
+                    cmd: "tail -f app.log".to_string(),
+                    ..TerminalProfile::default()
+                },
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(
+            find_terminal_profile(&settings, " logs ").map(|profile| profile.cmd.as_str()),
+            Some("tail -f app.log")
+        );
+        assert_eq!(
+            find_terminal_profile(&settings, "LOGS").map(|profile| profile.name.as_str()),
+            Some("Logs")
+        );
+        assert!(find_terminal_profile(&settings, "missing").is_none());
+        assert!(find_terminal_profile(&settings, "  ").is_none());
+    }
+
+    #[test]
+    fn terminal_shell_kind_normalization_covers_unix_shells() {
+        assert_eq!(normalize_terminal_shell_kind(" Bash "), TERMINAL_SHELL_BASH);
+        assert_eq!(normalize_terminal_shell_kind("zsh"), TERMINAL_SHELL_ZSH);
+        assert_eq!(normalize_terminal_shell_kind("fish"), TERMINAL_SHELL_FISH);
+        assert_eq!(normalize_terminal_shell_kind("nu"), TERMINAL_SHELL_NU);
+        assert_eq!(normalize_terminal_shell_kind("login"), TERMINAL_SHELL_LOGIN);
+        assert_eq!(normalize_terminal_shell_kind("cmd"), TERMINAL_SHELL_CMD);
+        let fallback = if cfg!(windows) {
+            TERMINAL_SHELL_CMD
+        } else {
+            TERMINAL_SHELL_LOGIN
+        };
+        assert_eq!(normalize_terminal_shell_kind("tcsh"), fallback);
+    }
+}
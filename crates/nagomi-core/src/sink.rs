@@ -0,0 +1,44 @@
+// runtime のイベントの出口。GUI は Tauri の window へ、headless は通知や stderr へ繋ぐ
+// Where runtime events go: the GUI forwards them to Tauri windows, headless to notifications and stderr.
+use crate::control::{ControlEvent, EventBus};
+use crate::session::HookStatePayload;
+use crate::state::StateChange;
+
+// 既定は何もしない。adapter は必要なものだけ実装する
+// Every method defaults to a no-op so an adapter only implements what it needs.
+pub trait EventSink: Send + Sync {
+    // 制御 API の購読者へ流す bus / Event bus feeding control API subscribers.
+    fn bus(&self) -> Option<&EventBus> {
+        None
+    }
+
+    fn log(&self, _message: &str) {}
+
+    // まとめ済みの出力 / Coalesced output for one session and stream.
+    fn session_output(&self, _session_id: &str, _stream: &str, _chunk: &str) {}
+
+    fn session_exit(&self, _session_id: &str, _exit_code: i32) {}
+
+    // recoverable でなければ session は続かない / Unrecoverable errors end the session.
+    fn session_error(&self, _session_id: &str, _message: &str, _recoverable: bool) {}
+
+    // worker を片付けた後 / Called once the session's worker has been cleaned up.
+    fn session_closed(&self, _session_id: &str) {}
+
+    // 状態機械が session の状態を動かした後 / Called after the state machine moved a session.
+    fn state_changed(&self, _session_id: &str, _change: &StateChange) {}
+
+    fn aggregate_changed(&self, _state: &str) {}
+
+    // `aggregate_group_by` でまとめたグループの集約 / Aggregate of one group under `aggregate_group_by`.
+    fn group_aggregate_changed(&self, _group: &str, _state: &str) {}
+
+    fn hook_state(&self, _payload: &HookStatePayload) {}
+}
+
+// 購読者がいるときだけ組み立てる / Builds the event only when someone is subscribed.
+pub fn publish(sink: &dyn EventSink, make: impl FnOnce() -> ControlEvent) {
+    if let Some(bus) = sink.bus() {
+        bus.publish(make);
+    }
+}
@@ -0,0 +1,924 @@
+// terminal ごとの状態機械と、その集約 / Per-terminal state machine and its aggregate.
+//
+// 状態はここだけが持つ。hook・終了・worker エラー・入力・出力判定・subworker の開始/終了を
+// `StateTrigger` として受け、許された遷移だけを反映して記録する。
+// This is the only owner of terminal state. Hooks, exits, worker errors, input, output
+// judgement and the subworker lifecycle arrive as `StateTrigger`s; only allowed transitions
+// are applied, and every one is logged.
+use crate::completion_hook::HookEventKind;
+use crate::control::ControlEvent;
+use crate::launch::TerminalLaunchOptions;
+use crate::sink::{self, EventSink};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TerminalState {
+    #[default]
+    Idle,
+    NeedInput,
+    Success,
+    Fail,
+    SubworkerRunning,
+}
+
+impl TerminalState {
+    pub const ALL: [TerminalState; 5] = [
+        TerminalState::Idle,
+        TerminalState::NeedInput,
+        TerminalState::Success,
+        TerminalState::Fail,
+        TerminalState::SubworkerRunning,
+    ];
+
+    // イベントや UI で使う名前 / The name used on the wire and in the UI.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TerminalState::Idle => "idle",
+            TerminalState::NeedInput => "need-input",
+            TerminalState::Success => "success",
+            TerminalState::Fail => "fail",
+            TerminalState::SubworkerRunning => "subworker-running",
+        }
+    }
+
+    // 別名も受けるが、知らない名前は None / Accepts aliases; unknown names give None.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let value = raw.trim().to_ascii_lowercase();
+        let state = match value.as_str() {
+            "" | "idle" | "running" | "ai_running" | "ai-running" | "airunning" => {
+                TerminalState::Idle
+            }
+            "need_input" | "need-input" | "needinput" => TerminalState::NeedInput,
+            "success" => TerminalState::Success,
+            "fail" | "failure" | "error" => TerminalState::Fail,
+            "subworker_running" | "subworker-running" | "subworkerrunning" => {
+                TerminalState::SubworkerRunning
+            }
+            _ => return None,
+        };
+        Some(state)
+    }
+
+    pub fn from_hook(kind: HookEventKind) -> Self {
+        match kind {
+            HookEventKind::Completed => TerminalState::Success,
+            HookEventKind::NeedInput => TerminalState::NeedInput,
+            HookEventKind::Error => TerminalState::Fail,
+        }
+    }
+}
+
+impl fmt::Display for TerminalState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 状態を動かすきっかけ / What moves a terminal's state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateTrigger {
+    Hook(HookEventKind),
+    Exit(i32),
+    // 続行できない worker エラー / An unrecoverable worker error.
+    WorkerFailed,
+    // 利用者（または制御 API）からの入力 / Input from the user or the control API.
+    Input,
+    // 出力の判定結果。subworker-running にはならない / An output judgement; never subworker-running.
+    Judged(TerminalState),
+    SubworkerStarted,
+    SubworkerFinished(TerminalState),
+}
+
+impl StateTrigger {
+    // WebView からの観測を遷移のきっかけへ読み替える
+    // Reads an observation reported by the WebView as a trigger.
+    pub fn from_observation(current: TerminalState, observed: TerminalState) -> Self {
+        match (current, observed) {
+            (TerminalState::SubworkerRunning, next) if next != TerminalState::SubworkerRunning => {
+                StateTrigger::SubworkerFinished(next)
+            }
+            (_, TerminalState::SubworkerRunning) => StateTrigger::SubworkerStarted,
+            (_, next) => StateTrigger::Judged(next),
+        }
+    }
+}
+
+impl fmt::Display for StateTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateTrigger::Hook(kind) => {
+                write!(
+                    f,
+                    "hook:{}",
+                    crate::completion_hook::hook_kind_to_string(*kind)
+                )
+            }
+            StateTrigger::Exit(code) => write!(f, "exit:{code}"),
+            StateTrigger::WorkerFailed => f.write_str("worker-failed"),
+            StateTrigger::Input => f.write_str("input"),
+            StateTrigger::Judged(state) => write!(f, "judge:{state}"),
+            StateTrigger::SubworkerStarted => f.write_str("subworker-started"),
+            StateTrigger::SubworkerFinished(state) => write!(f, "subworker-finished:{state}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionError {
+    pub from: TerminalState,
+    pub trigger: StateTrigger,
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid transition from {} on {}",
+            self.from, self.trigger
+        )
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+// 遷移表。hook・終了・エラー・入力はどこからでも効く。判定は subworker の実行中には効かず、
+// subworker は結果の出た状態（か実行中）からだけ始まり、実行中のときだけ終わる。
+// The transition table. Hooks, exits, errors and input apply from anywhere. Judgements do not
+// apply while a subworker runs; a subworker starts only from a settled state (or while already
+// running) and finishes only while running.
+pub fn transition(
+    from: TerminalState,
+    trigger: StateTrigger,
+) -> Result<TerminalState, TransitionError> {
+    use TerminalState::*;
+    let next = match (from, trigger) {
+        (_, StateTrigger::Hook(kind)) => TerminalState::from_hook(kind),
+        (_, StateTrigger::Exit(0)) => Success,
+        (_, StateTrigger::Exit(_)) | (_, StateTrigger::WorkerFailed) => Fail,
+        (_, StateTrigger::Input) => Idle,
+        (SubworkerRunning, StateTrigger::Judged(_))
+        | (_, StateTrigger::Judged(SubworkerRunning)) => {
+            return Err(TransitionError { from, trigger })
+        }
+        (_, StateTrigger::Judged(state)) => state,
+        (NeedInput | Success | Fail | SubworkerRunning, StateTrigger::SubworkerStarted) => {
+            SubworkerRunning
+        }
+        (SubworkerRunning, StateTrigger::SubworkerFinished(state)) if state != SubworkerRunning => {
+            state
+        }
+        _ => return Err(TransitionError { from, trigger }),
+    };
+    Ok(next)
+}
+
+pub const AGGREGATE_GROUP_NONE: &str = "none";
+pub const AGGREGATE_GROUP_PROJECT: &str = "project";
+pub const AGGREGATE_GROUP_PROFILE: &str = "profile";
+
+// 集約でどの状態を拾い、どれを優先するか / Which states the aggregate picks up, and in what order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregatePolicy {
+    priority: Vec<TerminalState>,
+}
+
+impl Default for AggregatePolicy {
+    fn default() -> Self {
+        Self {
+            priority: vec![
+                TerminalState::NeedInput,
+                TerminalState::Fail,
+                TerminalState::SubworkerRunning,
+            ],
+        }
+    }
+}
+
+impl AggregatePolicy {
+    // 知らない名前と重複は捨てる。何も残らなければ既定 / Unknown names and repeats are dropped; nothing left means the default.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Self {
+        let mut priority = Vec::new();
+        for state in names
+            .iter()
+            .filter_map(|name| TerminalState::parse(name.as_ref()))
+        {
+            if !priority.contains(&state) {
+                priority.push(state);
+            }
+        }
+        if priority.is_empty() {
+            return Self::default();
+        }
+        Self { priority }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.priority
+            .iter()
+            .map(|state| state.as_str().to_string())
+            .collect()
+    }
+
+    // 並びで先にある状態が 1 つでもあればそれ、どれも無ければ idle
+    // The first listed state that any session is in; idle when none is.
+    pub fn pick(&self, states: impl IntoIterator<Item = TerminalState>) -> TerminalState {
+        let present: Vec<TerminalState> = states.into_iter().collect();
+        self.priority
+            .iter()
+            .copied()
+            .find(|state| present.contains(state))
+            .unwrap_or_default()
+    }
+}
+
+pub fn aggregate_observed_state(
+    states: &HashMap<String, TerminalState>,
+    policy: &AggregatePolicy,
+) -> TerminalState {
+    policy.pick(states.values().copied())
+}
+
+// 集約グループ。`key` で束ね、`label` は表示用 / An aggregate group, keyed by `key` and shown as `label`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateGroup {
+    pub key: String,
+    pub label: String,
+}
+
+// `aggregate_group_by` に従って session のグループを決める。project は cwd から上へ辿った
+// git のルート（無ければ cwd）の正規化したパスで束ね、ディレクトリ名をラベルにする
+// The session's group under `aggregate_group_by`. A project is keyed by the canonical path of
+// the git root above the cwd (or the cwd outside a repository) and labelled with its basename.
+pub fn aggregate_group(group_by: &str, options: &TerminalLaunchOptions) -> Option<AggregateGroup> {
+    match group_by.trim().to_ascii_lowercase().as_str() {
+        AGGREGATE_GROUP_PROJECT => {
+            let cwd = std::fs::canonicalize(options.effective_cwd()?).ok()?;
+            let root = cwd
+                .ancestors()
+                .find(|dir| dir.join(".git").exists())
+                .unwrap_or(&cwd);
+            let key = root.to_string_lossy().to_string();
+            let label = root
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| key.clone());
+            Some(AggregateGroup { key, label })
+        }
+        AGGREGATE_GROUP_PROFILE => options
+            .profile
+            .as_ref()
+            .map(|profile| profile.name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .map(|name| AggregateGroup {
+                key: name.clone(),
+                label: name,
+            }),
+        _ => None,
+    }
+}
+
+// 1 回の遷移で何が変わったか / What a single transition changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateChange {
+    pub state: TerminalState,
+    pub previous: Option<TerminalState>,
+    pub trigger: StateTrigger,
+    // 全体の集約が変わったときだけ Some / Some only when the overall aggregate moved.
+    pub aggregate: Option<TerminalState>,
+}
+
+impl StateChange {
+    pub fn entered(&self, state: TerminalState) -> bool {
+        self.state == state && self.previous != Some(state)
+    }
+
+    pub fn changed(&self) -> bool {
+        self.previous != Some(self.state)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Aggregates {
+    policy: AggregatePolicy,
+    // session -> グループの key / Session to group key.
+    groups: HashMap<String, String>,
+    // グループの key -> 表示名 / Group key to display label.
+    labels: HashMap<String, String>,
+    last: TerminalState,
+    last_groups: BTreeMap<String, TerminalState>,
+}
+
+#[derive(Default)]
+pub struct ObservedStates {
+    per_session: Mutex<HashMap<String, TerminalState>>,
+    aggregates: Mutex<Aggregates>,
+}
+
+impl ObservedStates {
+    pub fn get(&self, session_id: &str) -> Option<TerminalState> {
+        self.per_session.lock().ok()?.get(session_id).copied()
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, TerminalState> {
+        self.per_session
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn aggregate(&self) -> TerminalState {
+        self.aggregates
+            .lock()
+            .map(|aggregates| aggregates.last)
+            .unwrap_or_default()
+    }
+
+    pub fn group_of(&self, session_id: &str) -> Option<String> {
+        self.aggregates.lock().ok()?.groups.get(session_id).cloned()
+    }
+
+    pub fn group_aggregates(&self) -> BTreeMap<String, TerminalState> {
+        self.aggregates
+            .lock()
+            .map(|aggregates| aggregates.last_groups.clone())
+            .unwrap_or_default()
+    }
+
+    // key か、他と重ならないラベルからグループの key を引く
+    // Finds a group key from the key itself or from a label no other group shares.
+    pub fn group_key(&self, name: &str) -> Option<String> {
+        let name = name.trim();
+        let aggregates = self.aggregates.lock().ok()?;
+        if aggregates.labels.contains_key(name) {
+            return Some(name.to_string());
+        }
+        let mut matches = aggregates
+            .labels
+            .iter()
+            .filter(|(_, label)| label.as_str() == name)
+            .map(|(key, _)| key.clone());
+        match (matches.next(), matches.next()) {
+            (Some(key), None) => Some(key),
+            _ => None,
+        }
+    }
+
+    // 空の名前は全体の集約。グループは key かラベルで指す
+    // An empty name means the overall aggregate; a group is named by its key or label.
+    pub fn scoped_aggregate(&self, group: &str) -> TerminalState {
+        if group.trim().is_empty() {
+            return self.aggregate();
+        }
+        self.group_key(group)
+            .and_then(|key| self.group_aggregates().get(&key).copied())
+            .unwrap_or_default()
+    }
+
+    // SSE の最初に送る現在の集約（全体とグループごと）/ Current aggregates sent first on SSE, overall and per group.
+    pub fn aggregate_events(&self) -> Vec<ControlEvent> {
+        let Ok(aggregates) = self.aggregates.lock() else {
+            return Vec::new();
+        };
+        let mut events = vec![ControlEvent::Aggregate {
+            state: aggregates.last.as_str().to_string(),
+            group: None,
+            label: None,
+        }];
+        events.extend(aggregates.last_groups.iter().map(|(group, state)| {
+            ControlEvent::Aggregate {
+                state: state.as_str().to_string(),
+                group: Some(group.clone()),
+                label: aggregates.labels.get(group).cloned(),
+            }
+        }));
+        events
+    }
+
+    pub fn set_policy(&self, policy: AggregatePolicy, sink: &dyn EventSink) {
+        if let Ok(mut aggregates) = self.aggregates.lock() {
+            if aggregates.policy == policy {
+                return;
+            }
+            aggregates.policy = policy;
+        }
+        self.refresh_aggregates(sink);
+    }
+
+    // None でグループから外す / None takes the session out of any group.
+    pub fn set_group(&self, session_id: &str, group: Option<AggregateGroup>, sink: &dyn EventSink) {
+        if let Ok(mut aggregates) = self.aggregates.lock() {
+            let previous = match group {
+                Some(group) => {
+                    aggregates.labels.insert(group.key.clone(), group.label);
+                    aggregates.groups.insert(session_id.to_string(), group.key)
+                }
+                None => aggregates.groups.remove(session_id),
+            };
+            if previous == aggregates.groups.get(session_id).cloned() {
+                return;
+            }
+        }
+        self.refresh_aggregates(sink);
+    }
+
+    // 遷移を反映し、変化があれば記録して State / Aggregate を流す。許されない遷移は記録して Err
+    // Applies a transition, logging and publishing State and Aggregate on change.
+    // Invalid transitions are logged and returned as Err.
+    pub fn apply(
+        &self,
+        session_id: &str,
+        trigger: StateTrigger,
+        sink: &dyn EventSink,
+    ) -> Result<StateChange, TransitionError> {
+        let (state, previous) = {
+            let Ok(mut guard) = self.per_session.lock() else {
+                return Err(TransitionError {
+                    from: TerminalState::Idle,
+                    trigger,
+                });
+            };
+            let previous = guard.get(session_id).copied();
+            let from = previous.unwrap_or_default();
+            let state = match transition(from, trigger) {
+                Ok(state) => state,
+                Err(err) => {
+                    drop(guard);
+                    sink.log(&format!("state transition rejected: {session_id} {err}"));
+                    return Err(err);
+                }
+            };
+            guard.insert(session_id.to_string(), state);
+            (state, previous)
+        };
+        let mut change = StateChange {
+            state,
+            previous,
+            trigger,
+            aggregate: None,
+        };
+        if change.changed() {
+            sink.log(&format!(
+                "state transition: {session_id} {} -> {state} ({trigger})",
+                previous.map_or("-", TerminalState::as_str)
+            ));
+            sink::publish(sink, || ControlEvent::State {
+                session_id: session_id.to_string(),
+                state: state.as_str().to_string(),
+                previous: previous.map(|state| state.as_str().to_string()),
+            });
+            sink.state_changed(session_id, &change);
+        }
+        change.aggregate = self.refresh_aggregates(sink);
+        Ok(change)
+    }
+
+    // WebView の観測を遷移として反映する / Applies an observation reported by the WebView.
+    pub fn observe(
+        &self,
+        session_id: &str,
+        observed: TerminalState,
+        sink: &dyn EventSink,
+    ) -> Result<StateChange, TransitionError> {
+        let current = self.get(session_id).unwrap_or_default();
+        self.apply(
+            session_id,
+            StateTrigger::from_observation(current, observed),
+            sink,
+        )
+    }
+
+    // 閉じた session を集約とグループから外す / Drops a closed session from the aggregates and its group.
+    pub fn forget(&self, session_id: &str, sink: &dyn EventSink) -> Option<TerminalState> {
+        let had_state = self.per_session.lock().ok()?.remove(session_id).is_some();
+        let had_group = self
+            .aggregates
+            .lock()
+            .ok()?
+            .groups
+            .remove(session_id)
+            .is_some();
+        if !had_state && !had_group {
+            return None;
+        }
+        self.refresh_aggregates(sink)
+    }
+
+    // 全体とグループごとの集約を計算し直し、変わったものを流す。全体が変わったときだけ Some
+    // Recomputes the overall and per-group aggregates and publishes what moved.
+    // Returns Some only when the overall aggregate changed.
+    fn refresh_aggregates(&self, sink: &dyn EventSink) -> Option<TerminalState> {
+        let (overall, group_changes) = {
+            // 集約のロックを取ってから状態を読む。先に読むと、後から来た apply の結果を
+            // 古い状態で上書きして流してしまう
+            // Take the aggregates lock before reading the states; reading first could let a
+            // later apply's result be overwritten and published with stale states.
+            let mut aggregates = self.aggregates.lock().ok()?;
+            let states = self.snapshot();
+            let next = aggregate_observed_state(&states, &aggregates.policy);
+            let overall = (aggregates.last != next).then_some(next);
+            aggregates.last = next;
+
+            let mut members: BTreeMap<String, Vec<TerminalState>> = BTreeMap::new();
+            for (session_id, group) in &aggregates.groups {
+                members
+                    .entry(group.clone())
+                    .or_default()
+                    .push(states.get(session_id).copied().unwrap_or_default());
+            }
+            let mut changes = Vec::new();
+            for (group, list) in members {
+                let next = aggregates.policy.pick(list);
+                if aggregates.last_groups.insert(group.clone(), next) != Some(next) {
+                    let label = aggregates.labels.get(&group).cloned();
+                    changes.push((group, label, next));
+                }
+            }
+            // 空になったグループは idle を流して消す / An emptied group reports idle once and goes away.
+            let gone: Vec<String> = aggregates
+                .last_groups
+                .keys()
+                .filter(|group| !aggregates.groups.values().any(|name| name == *group))
+                .cloned()
+                .collect();
+            for group in gone {
+                let label = aggregates.labels.remove(&group);
+                if aggregates.last_groups.remove(&group) != Some(TerminalState::Idle) {
+                    changes.push((group, label, TerminalState::Idle));
+                }
+            }
+            let Aggregates { groups, labels, .. } = &mut *aggregates;
+            labels.retain(|key, _| groups.values().any(|group| group == key));
+            (overall, changes)
+        };
+        if let Some(state) = overall {
+            sink::publish(sink, || ControlEvent::Aggregate {
+                state: state.as_str().to_string(),
+                group: None,
+                label: None,
+            });
+            sink.aggregate_changed(state.as_str());
+        }
+        for (group, label, state) in group_changes {
+            sink::publish(sink, || ControlEvent::Aggregate {
+                state: state.as_str().to_string(),
+                group: Some(group.clone()),
+                label: label.clone(),
+            });
+            sink.group_aggregate_changed(&group, state.as_str());
+        }
+        overall
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_aliases_and_rejects_unknown_names() {
+        assert_eq!(TerminalState::parse(""), Some(TerminalState::Idle));
+        assert_eq!(
+            TerminalState::parse("AI_Running"),
+            Some(TerminalState::Idle)
+        );
+        assert_eq!(
+            TerminalState::parse("need_input"),
+            Some(TerminalState::NeedInput)
+        );
+        assert_eq!(TerminalState::parse("error"), Some(TerminalState::Fail));
+        assert_eq!(TerminalState::parse("custom"), None);
+        for state in TerminalState::ALL {
+            assert_eq!(TerminalState::parse(state.as_str()), Some(state));
+        }
+    }
+
+    #[test]
+    fn transition_table_rejects_judgements_during_a_subworker() {
+        use StateTrigger::*;
+        use TerminalState::*;
+        assert_eq!(
+            transition(Idle, Hook(HookEventKind::NeedInput)),
+            Ok(NeedInput)
+        );
+        assert_eq!(transition(SubworkerRunning, Exit(2)), Ok(Fail));
+        assert_eq!(transition(Fail, Input), Ok(Idle));
+        assert_eq!(transition(Idle, Judged(Success)), Ok(Success));
+        assert_eq!(transition(Success, SubworkerStarted), Ok(SubworkerRunning));
+        assert_eq!(
+            transition(SubworkerRunning, SubworkerFinished(NeedInput)),
+            Ok(NeedInput)
+        );
+
+        let rejected = [
+            (Idle, SubworkerStarted),
+            (Success, SubworkerFinished(Success)),
+            (SubworkerRunning, SubworkerFinished(SubworkerRunning)),
+            (SubworkerRunning, Judged(Idle)),
+            (Idle, Judged(SubworkerRunning)),
+        ];
+        for (from, trigger) in rejected {
+            assert_eq!(
+                transition(from, trigger),
+                Err(TransitionError { from, trigger })
+            );
+        }
+    }
+
+    #[test]
+    fn observations_map_onto_subworker_triggers() {
+        use TerminalState::*;
+        assert_eq!(
+            StateTrigger::from_observation(Success, SubworkerRunning),
+            StateTrigger::SubworkerStarted
+        );
+        assert_eq!(
+            StateTrigger::from_observation(SubworkerRunning, NeedInput),
+            StateTrigger::SubworkerFinished(NeedInput)
+        );
+        assert_eq!(
+            StateTrigger::from_observation(SubworkerRunning, SubworkerRunning),
+            StateTrigger::SubworkerStarted
+        );
+        assert_eq!(
+            StateTrigger::from_observation(Idle, Fail),
+            StateTrigger::Judged(Fail)
+        );
+    }
+
+    #[test]
+    fn aggregate_prefers_need_input_then_fail() {
+        let policy = AggregatePolicy::default();
+        let mut states = HashMap::new();
+        assert_eq!(
+            aggregate_observed_state(&states, &policy),
+            TerminalState::Idle
+        );
+        states.insert("a".to_string(), TerminalState::SubworkerRunning);
+        assert_eq!(
+            aggregate_observed_state(&states, &policy),
+            TerminalState::SubworkerRunning
+        );
+        states.insert("b".to_string(), TerminalState::Fail);
+        assert_eq!(
+            aggregate_observed_state(&states, &policy),
+            TerminalState::Fail
+        );
+        states.insert("c".to_string(), TerminalState::NeedInput);
+        assert_eq!(
+            aggregate_observed_state(&states, &policy),
+            TerminalState::NeedInput
+        );
+    }
+
+    #[test]
+    fn policy_follows_the_configured_order_and_states() {
+        let policy = AggregatePolicy::from_names(&["fail", "success", "bogus", "failure"]);
+        assert_eq!(policy.names(), ["fail", "success"]);
+        let states = [TerminalState::NeedInput, TerminalState::Success];
+        // need-input は並びに無いので拾わない / need-input is not listed, so it is ignored.
+        assert_eq!(policy.pick(states), TerminalState::Success);
+        assert_eq!(
+            policy.pick([TerminalState::Success, TerminalState::Fail]),
+            TerminalState::Fail
+        );
+        assert_eq!(
+            policy.pick([TerminalState::SubworkerRunning]),
+            TerminalState::Idle
+        );
+        assert_eq!(
+            AggregatePolicy::from_names::<&str>(&[]),
+            AggregatePolicy::default()
+        );
+    }
+
+    #[test]
+    fn groups_are_keyed_by_the_canonical_root_and_labelled_by_its_name() {
+        let base = std::env::temp_dir().join(format!("nagomi-group-{}", std::process::id()));
+        let work_api = base.join("work").join("api");
+        let oss_api = base.join("oss").join("api");
+        std::fs::create_dir_all(work_api.join("src")).unwrap();
+        std::fs::create_dir_all(work_api.join(".git")).unwrap();
+        std::fs::create_dir_all(&oss_api).unwrap();
+        let options = TerminalLaunchOptions {
+            cwd: Some(work_api.join("src").to_string_lossy().to_string()),
+            profile: Some(crate::settings::TerminalProfile {
+                name: " api - claude ".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let root = std::fs::canonicalize(&work_api).unwrap();
+        assert_eq!(
+            aggregate_group(AGGREGATE_GROUP_PROJECT, &options),
+            Some(AggregateGroup {
+                key: root.to_string_lossy().to_string(),
+                label: "api".to_string(),
+            })
+        );
+        assert_eq!(
+            aggregate_group(AGGREGATE_GROUP_PROFILE, &options).map(|group| group.key),
+            Some("api - claude".to_string())
+        );
+        assert_eq!(aggregate_group(AGGREGATE_GROUP_NONE, &options), None);
+
+        // 同じ名前の別ディレクトリは別グループ / Same-named directories stay separate groups.
+        let other = TerminalLaunchOptions {
+            cwd: Some(oss_api.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let other = aggregate_group(AGGREGATE_GROUP_PROJECT, &other).unwrap();
+        assert_eq!(other.label, "api");
+        assert_ne!(other.key, root.to_string_lossy());
+        let missing = TerminalLaunchOptions {
+            cwd: Some(base.join("missing").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert_eq!(aggregate_group(AGGREGATE_GROUP_PROJECT, &missing), None);
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        bus: crate::control::EventBus,
+        aggregates: Mutex<Vec<String>>,
+        logs: Mutex<Vec<String>>,
+        changes: Mutex<Vec<String>>,
+    }
+
+    impl EventSink for RecordingSink {
+        fn bus(&self) -> Option<&crate::control::EventBus> {
+            Some(&self.bus)
+        }
+
+        fn log(&self, message: &str) {
+            self.logs.lock().unwrap().push(message.to_string());
+        }
+
+        fn aggregate_changed(&self, state: &str) {
+            self.aggregates.lock().unwrap().push(state.to_string());
+        }
+
+        fn group_aggregate_changed(&self, group: &str, state: &str) {
+            self.aggregates
+                .lock()
+                .unwrap()
+                .push(format!("{group}={state}"));
+        }
+
+        fn state_changed(&self, session_id: &str, change: &StateChange) {
+            self.changes
+                .lock()
+                .unwrap()
+                .push(format!("{session_id} {}", change.state));
+        }
+    }
+
+    #[test]
+    fn apply_publishes_and_logs_each_transition_once() {
+        let sink = RecordingSink::default();
+        let rx = sink.bus.subscribe(crate::control::EventFilter::default());
+        let states = ObservedStates::default();
+
+        let change = states
+            .apply("a", StateTrigger::Hook(HookEventKind::NeedInput), &sink)
+            .unwrap();
+        assert!(change.entered(TerminalState::NeedInput));
+        assert_eq!(change.aggregate, Some(TerminalState::NeedInput));
+        let repeat = states
+            .observe("a", TerminalState::NeedInput, &sink)
+            .unwrap();
+        assert!(!repeat.entered(TerminalState::NeedInput));
+        assert_eq!(repeat.aggregate, None);
+
+        let kinds: Vec<&str> = rx.try_iter().map(|event| event.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, ["state", "aggregate"]);
+        assert_eq!(*sink.aggregates.lock().unwrap(), ["need-input"]);
+        assert_eq!(*sink.changes.lock().unwrap(), ["a need-input"]);
+        assert_eq!(
+            *sink.logs.lock().unwrap(),
+            ["state transition: a - -> need-input (hook:need_input)"]
+        );
+    }
+
+    #[test]
+    fn rejected_transitions_leave_the_state_alone() {
+        let sink = RecordingSink::default();
+        let states = ObservedStates::default();
+        states.observe("a", TerminalState::Success, &sink).unwrap();
+        states
+            .observe("a", TerminalState::SubworkerRunning, &sink)
+            .unwrap();
+        let err = states
+            .apply("a", StateTrigger::Judged(TerminalState::Idle), &sink)
+            .unwrap_err();
+        assert_eq!(err.from, TerminalState::SubworkerRunning);
+        assert_eq!(states.get("a"), Some(TerminalState::SubworkerRunning));
+        assert_eq!(
+            sink.logs.lock().unwrap().last().map(String::as_str),
+            Some("state transition rejected: a invalid transition from subworker-running on judge:idle")
+        );
+        let finished = states.observe("a", TerminalState::Idle, &sink).unwrap();
+        assert_eq!(
+            finished.trigger,
+            StateTrigger::SubworkerFinished(TerminalState::Idle)
+        );
+    }
+
+    #[test]
+    fn forget_recomputes_aggregate() {
+        let sink = RecordingSink::default();
+        let states = ObservedStates::default();
+        states.apply("a", StateTrigger::Exit(1), &sink).unwrap();
+        states.apply("b", StateTrigger::Input, &sink).unwrap();
+        assert_eq!(states.aggregate(), TerminalState::Fail);
+        assert_eq!(states.forget("a", &sink), Some(TerminalState::Idle));
+        assert_eq!(states.forget("missing", &sink), None);
+        assert_eq!(states.get("b"), Some(TerminalState::Idle));
+    }
+
+    fn group(name: &str) -> Option<AggregateGroup> {
+        Some(AggregateGroup {
+            key: name.to_string(),
+            label: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn groups_aggregate_separately_from_the_whole() {
+        let sink = RecordingSink::default();
+        let states = ObservedStates::default();
+        states.set_group("a1", group("a"), &sink);
+        states.set_group("a2", group("a"), &sink);
+        states.set_group("b1", group("b"), &sink);
+        states
+            .apply("a1", StateTrigger::Hook(HookEventKind::NeedInput), &sink)
+            .unwrap();
+        states.apply("b1", StateTrigger::Exit(0), &sink).unwrap();
+        assert_eq!(states.scoped_aggregate("a"), TerminalState::NeedInput);
+        assert_eq!(states.scoped_aggregate("b"), TerminalState::Idle);
+        assert_eq!(states.scoped_aggregate(""), TerminalState::NeedInput);
+
+        // success も拾うと b だけが success になる / Listing success makes only b report it.
+        states.set_policy(
+            AggregatePolicy::from_names(&["need-input", "success"]),
+            &sink,
+        );
+        assert_eq!(states.scoped_aggregate("b"), TerminalState::Success);
+        states.forget("a1", &sink);
+        assert_eq!(states.scoped_aggregate("a"), TerminalState::Idle);
+        states.forget("a2", &sink);
+        assert!(!states.group_aggregates().contains_key("a"));
+        assert_eq!(
+            *sink.aggregates.lock().unwrap(),
+            [
+                "a=idle",
+                "b=idle",
+                "need-input",
+                "a=need-input",
+                "b=success",
+                "success",
+                "a=idle",
+            ]
+        );
+        let kinds: Vec<String> = states
+            .aggregate_events()
+            .into_iter()
+            .map(|event| serde_json::to_string(&event).unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                r#"{"type":"aggregate","state":"success"}"#,
+                r#"{"type":"aggregate","state":"success","group":"b","label":"b"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn group_labels_resolve_only_when_unique() {
+        let sink = RecordingSink::default();
+        let states = ObservedStates::default();
+        let project = |key: &str| {
+            Some(AggregateGroup {
+                key: key.to_string(),
+                label: "api".to_string(),
+            })
+        };
+        states.set_group("w", project("/work/api"), &sink);
+        states.apply("w", StateTrigger::Exit(1), &sink).unwrap();
+        assert_eq!(states.group_key("api").as_deref(), Some("/work/api"));
+        assert_eq!(states.scoped_aggregate("api"), TerminalState::Fail);
+
+        states.set_group("o", project("/oss/api"), &sink);
+        assert_eq!(states.group_key("api"), None);
+        assert_eq!(states.scoped_aggregate("api"), TerminalState::Idle);
+        assert_eq!(states.scoped_aggregate("/work/api"), TerminalState::Fail);
+        assert_eq!(states.scoped_aggregate("/oss/api"), TerminalState::Idle);
+
+        states.forget("w", &sink);
+        assert_eq!(states.group_key("/work/api"), None);
+        assert_eq!(states.group_key("api").as_deref(), Some("/oss/api"));
+    }
+}
@@ -0,0 +1,460 @@
+// subworker の判定を外部 CLI（codex など）に問い合わせる / Ask an external CLI (codex, ...) for the subworker decision.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubworkerLlmDecision {
+    pub action: String,
+    pub confidence: f32,
+    pub input: String,
+    pub advice_markdown: String,
+    pub reason: String,
+}
+
+pub struct SubworkerToolRunOutput {
+    pub decision: SubworkerLlmDecision,
+    pub codex_thread_id: Option<String>,
+}
+
+pub fn run_tool_subworker_decide(
+    tool: &str,
+    prompt: &str,
+    codex_session_id: Option<&str>,
+) -> Result<SubworkerToolRunOutput> {
+    let tool_key = tool.trim().to_ascii_lowercase();
+    let tool_path = resolve_tool_command(&tool_key);
+    let env_args = std::env::var("NAGOMI_SUBWORKER_TOOL_ARGS")
+        .ok()
+        .unwrap_or_default();
+    let timeout_ms = std::env::var("NAGOMI_SUBWORKER_TOOL_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(45000);
+    let timeout = Duration::from_millis(timeout_ms);
+
+    if env_args.trim().is_empty() && tool_key == "codex" {
+        return run_codex_subworker_decide_with_session(
+            &tool_path,
+            prompt,
+            timeout,
+            codex_session_id,
+        );
+    }
+
+    if env_args.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "subworker tool args missing (set NAGOMI_SUBWORKER_TOOL_ARGS)"
+        ));
+    }
+
+    let args = split_tool_args(&env_args);
+    let output = run_tool_command_stdout(&tool_path, &args, prompt, timeout)?;
+    let decision = parse_subworker_llm_output_relaxed(&output)
+        .ok_or_else(|| anyhow::anyhow!("invalid subworker llm output"))?;
+    Ok(SubworkerToolRunOutput {
+        decision,
+        codex_thread_id: None,
+    })
+}
+
+fn resolve_tool_command(tool_key: &str) -> String {
+    let direct = std::env::var("NAGOMI_TOOL_PATH")
+        .ok()
+        .filter(|value| !value.trim().is_empty());
+    if let Some(value) = direct {
+        return value;
+    }
+    let cli = std::env::var("NAGOMI_TOOL_CLI")
+        .ok()
+        .filter(|value| !value.trim().is_empty());
+    cli.unwrap_or_else(|| tool_key.to_string())
+}
+
+fn split_tool_args(raw: &str) -> Vec<String> {
+    raw.split_whitespace()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+fn build_tool_command(tool_path: &str, args: &[String]) -> Command {
+    #[cfg(windows)]
+    {
+        use std::path::Path;
+        let ext = Path::new(tool_path)
+            .extension()
+            .and_then(|value| value.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if ext == "exe" || ext == "com" {
+            let mut command = Command::new(tool_path);
+            command.args(args);
+            return command;
+        }
+        // codex / npm global installs are often .cmd on Windows. Use cmd.exe to resolve PATHEXT.
+        // codex / npm 縺ｮ繧ｰ繝ｭ繝ｼ繝舌Ν繧､繝ｳ繧ｹ繝医・繝ｫ縺ｯ Windows 縺ｧ縺ｯ .cmd 縺ｫ縺ｪ繧翫′縺｡縺ｪ縺ｮ縺ｧ縲…md.exe 邨檎罰縺ｧ隗｣豎ｺ縺吶ｋ縲・
+        let mut command = Command::new("cmd.exe");
+        command.arg("/c").arg(tool_path).args(args);
+        return command;
+    }
+
+    #[cfg(not(windows))]
+    {
+        let mut command = Command::new(tool_path);
+        command.args(args);
+        command
+    }
+}
+
+fn internal_hooks_dir() -> PathBuf {
+    std::env::temp_dir().join("nagomi-internal-hooks")
+}
+
+fn apply_internal_tool_env(command: &mut Command) {
+    // Prevent internal tool invocations (judge/subworker) from emitting completion-hook events
+    // that loop back into the currently observed Terminal session.
+    // 蜀・Κ繝・・繝ｫ蜻ｼ縺ｳ蜃ｺ縺暦ｼ・udge/subworker・峨′ completion-hook 繧堤匱轣ｫ縺輔○縲・    // 螳溯｡御ｸｭ繧ｻ繝・す繝ｧ繝ｳ縺ｸ騾・ｵ√☆繧九Ν繝ｼ繝励ｒ驕ｿ縺代ｋ縲・
+    command.env("NAGOMI_HOOKS_DIR", internal_hooks_dir());
+}
+
+fn run_tool_command_stdout(
+    tool_path: &str,
+    args: &[String],
+    prompt: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let mut command = build_tool_command(tool_path, args);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(prompt.as_bytes());
+    }
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let mut stdout = String::new();
+    if let Some(mut reader) = child.stdout.take() {
+        let _ = reader.read_to_string(&mut stdout);
+    }
+    let mut stderr = String::new();
+    if let Some(mut reader) = child.stderr.take() {
+        let _ = reader.read_to_string(&mut stderr);
+    }
+    if !status.success() {
+        return Err(anyhow::anyhow!("tool failed: {stderr}"));
+    }
+    Ok(stdout)
+}
+
+fn run_codex_subworker_decide_with_session(
+    tool_path: &str,
+    prompt: &str,
+    timeout: Duration,
+    codex_session_id: Option<&str>,
+) -> Result<SubworkerToolRunOutput> {
+    if let Some(existing_session_id) = codex_session_id.map(str::trim).filter(|v| !v.is_empty()) {
+        return run_codex_subworker_decide_resume(tool_path, existing_session_id, prompt, timeout);
+    }
+    run_codex_subworker_decide_fresh(tool_path, prompt, timeout)
+}
+
+fn run_codex_subworker_decide_fresh(
+    tool_path: &str,
+    prompt: &str,
+    timeout: Duration,
+) -> Result<SubworkerToolRunOutput> {
+    let schema = r#"{"type":"object","properties":{"action":{"type":"string","enum":["delegate_input","show_advice","noop"]},"confidence":{"type":"number","minimum":0,"maximum":0.99},"input":{"type":"string"},"advice_markdown":{"type":"string"},"reason":{"type":"string"}},"required":["action","confidence","input","advice_markdown","reason"],"additionalProperties":false}"#;
+    let schema_path = create_temp_file("nagomi-subworker-schema", "json", schema)?;
+    let output_path = create_temp_path("nagomi-subworker-output", "json");
+    let args = vec![
+        "exec".to_string(),
+        "--json".to_string(),
+        "--output-schema".to_string(),
+        schema_path.to_string_lossy().to_string(),
+        "--output-last-message".to_string(),
+        output_path.to_string_lossy().to_string(),
+        "--color".to_string(),
+        "never".to_string(),
+        "--sandbox".to_string(),
+        "read-only".to_string(),
+        "--skip-git-repo-check".to_string(),
+    ];
+
+    let mut command = build_tool_command(tool_path, &args);
+    apply_internal_tool_env(&mut command);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(prompt.as_bytes());
+    }
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let mut stderr = String::new();
+    if let Some(mut reader) = child.stderr.take() {
+        let _ = reader.read_to_string(&mut stderr);
+    }
+    let mut stdout = String::new();
+    if let Some(mut reader) = child.stdout.take() {
+        let _ = reader.read_to_string(&mut stdout);
+    }
+    let raw = fs::read_to_string(&output_path).unwrap_or_default();
+    let _ = fs::remove_file(&schema_path);
+    let _ = fs::remove_file(&output_path);
+    if !status.success() {
+        return Err(anyhow::anyhow!("tool failed: {stderr}"));
+    }
+    let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(&stdout);
+    let primary_payload = if raw.trim().is_empty() {
+        last_agent_message.unwrap_or_default()
+    } else {
+        raw
+    };
+    if let Some(decision) = parse_subworker_llm_output_relaxed(&primary_payload) {
+        return Ok(SubworkerToolRunOutput {
+            decision,
+            codex_thread_id: thread_id,
+        });
+    }
+    Err(anyhow::anyhow!(
+        "subworker tool output parse failed (fresh): stdout={}",
+        truncate_error_text(&stdout, 300)
+    ))
+}
+
+fn run_codex_subworker_decide_resume(
+    tool_path: &str,
+    session_id: &str,
+    prompt: &str,
+    timeout: Duration,
+) -> Result<SubworkerToolRunOutput> {
+    let args = vec![
+        "exec".to_string(),
+        "resume".to_string(),
+        "--json".to_string(),
+        "--skip-git-repo-check".to_string(),
+        session_id.to_string(),
+        "-".to_string(),
+    ];
+
+    let mut command = build_tool_command(tool_path, &args);
+    apply_internal_tool_env(&mut command);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(prompt.as_bytes());
+    }
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let mut stderr = String::new();
+    if let Some(mut reader) = child.stderr.take() {
+        let _ = reader.read_to_string(&mut stderr);
+    }
+    let mut stdout = String::new();
+    if let Some(mut reader) = child.stdout.take() {
+        let _ = reader.read_to_string(&mut stdout);
+    }
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "codex resume failed: {stderr}; stdout={}",
+            truncate_error_text(&stdout, 260)
+        ));
+    }
+    let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(&stdout);
+    let raw = last_agent_message.ok_or_else(|| {
+        anyhow::anyhow!(
+            "codex resume output missing agent message: stdout={}",
+            truncate_error_text(&stdout, 320)
+        )
+    })?;
+    let decision = parse_subworker_llm_output_relaxed(&raw).ok_or_else(|| {
+        anyhow::anyhow!(
+            "subworker tool output parse failed (resume): message={}",
+            truncate_error_text(&raw, 260)
+        )
+    })?;
+    Ok(SubworkerToolRunOutput {
+        decision,
+        codex_thread_id: thread_id.or_else(|| Some(session_id.to_string())),
+    })
+}
+
+fn parse_codex_exec_jsonl_stdout(stdout: &str) -> (Option<String>, Option<String>) {
+    let mut thread_id: Option<String> = None;
+    let mut last_agent_message: Option<String> = None;
+    for raw_line in stdout.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with('{') {
+            continue;
+        }
+        let parsed: serde_json::Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let event_type = parsed
+            .get("type")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        if event_type == "thread.started" {
+            if let Some(value) = parsed.get("thread_id").and_then(|v| v.as_str()) {
+                let normalized = value.trim();
+                if !normalized.is_empty() {
+                    thread_id = Some(normalized.to_string());
+                }
+            }
+            continue;
+        }
+        if event_type == "item.completed" {
+            let item = match parsed.get("item").and_then(|v| v.as_object()) {
+                Some(item) => item,
+                None => continue,
+            };
+            let item_type = item
+                .get("type")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            if item_type == "agent_message" {
+                if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
+                    let normalized = text.trim();
+                    if !normalized.is_empty() {
+                        last_agent_message = Some(normalized.to_string());
+                    }
+                }
+            }
+        }
+    }
+    (thread_id, last_agent_message)
+}
+
+fn parse_subworker_llm_output(raw: &str) -> Option<SubworkerLlmDecision> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let candidate = trimmed
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or(trimmed);
+    let parsed: serde_json::Value = serde_json::from_str(candidate).ok()?;
+    Some(SubworkerLlmDecision {
+        action: parsed.get("action")?.as_str()?.to_string(),
+        confidence: parsed.get("confidence")?.as_f64()? as f32,
+        input: parsed.get("input")?.as_str()?.to_string(),
+        advice_markdown: parsed.get("advice_markdown")?.as_str()?.to_string(),
+        reason: parsed.get("reason")?.as_str()?.to_string(),
+    })
+}
+
+fn parse_subworker_llm_output_relaxed(raw: &str) -> Option<SubworkerLlmDecision> {
+    if let Some(parsed) = parse_subworker_llm_output(raw) {
+        return Some(parsed);
+    }
+    let trimmed = raw.trim();
+    let start = trimmed.find('{')?;
+    let end = trimmed.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    parse_subworker_llm_output(&trimmed[start..=end])
+}
+
+fn truncate_error_text(text: &str, max_len: usize) -> String {
+    let normalized = text.trim().replace(['\r', '\n'], " ");
+    if normalized.len() <= max_len {
+        return normalized;
+    }
+    let keep = max_len.saturating_sub(1);
+    let mut out = normalized.chars().take(keep).collect::<String>();
+    out.push('.');
+    out
+}
+
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: Duration,
+) -> Result<std::process::ExitStatus> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            return Err(anyhow::anyhow!("tool timeout"));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn create_temp_path(prefix: &str, ext: &str) -> PathBuf {
+    static TOOL_SEQ: AtomicU64 = AtomicU64::new(0);
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_millis(0))
+        .as_millis();
+    let seq = TOOL_SEQ.fetch_add(1, Ordering::Relaxed);
+    let filename = format!("{prefix}-{nonce}-{seq}.{ext}");
+    std::env::temp_dir().join(filename)
+}
+
+fn create_temp_file(prefix: &str, ext: &str, contents: &str) -> Result<PathBuf> {
+    let path = create_temp_path(prefix, ext);
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_codex_exec_jsonl_stdout_extracts_thread_and_latest_agent_message() {
+        let stdout = r#"{"type":"session.configured"}
+{"type":"thread.started","thread_id":"thread_abc123"}
+{"type":"item.completed","item":{"type":"tool_call","text":"ignored"}}
+{"type":"item.completed","item":{"type":"agent_message","text":"first message"}}
+{"type":"item.completed","item":{"type":"agent_message","text":"final message"}}
+"#;
+        let (thread_id, last_agent_message) = parse_codex_exec_jsonl_stdout(stdout);
+        assert_eq!(thread_id.as_deref(), Some("thread_abc123"));
+        assert_eq!(last_agent_message.as_deref(), Some("final message"));
+    }
+
+    #[test]
+    fn parse_subworker_llm_output_relaxed_extracts_embedded_json_object() {
+        let raw = r#"debug prefix: start
+{"action":"show_advice","confidence":0.77,"input":"","advice_markdown":"- try this","reason":"need clarification"}
+debug suffix: end"#;
+        let parsed = parse_subworker_llm_output_relaxed(raw).expect("parsed decision");
+        assert_eq!(parsed.action, "show_advice");
+        assert!((parsed.confidence - 0.77).abs() < f32::EPSILON);
+        assert_eq!(parsed.input, "");
+        assert_eq!(parsed.advice_markdown, "- try this");
+        assert_eq!(parsed.reason, "need clarification");
+    }
+
+    #[test]
+    fn parse_subworker_llm_output_relaxed_returns_none_for_invalid_payload() {
+        let raw = "this is not a json response";
+        assert!(parse_subworker_llm_output_relaxed(raw).is_none());
+    }
+}
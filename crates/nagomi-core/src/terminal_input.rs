@@ -0,0 +1,347 @@
+// terminal への入力から `:ng` 内部コマンドを拾う / Pick `:ng` internal commands out of terminal input.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TerminalInputCaptureMode {
+    #[default]
+    Unknown,
+    SuppressCandidate,
+    PassThrough,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TerminalInputCaptureState {
+    pending: String,
+    mode: TerminalInputCaptureMode,
+    skip_next_lf: bool,
+    echoed_display: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalBuiltinCommand {
+    Ping,
+    Usage,
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalBuiltinInvocation {
+    pub command: TerminalBuiltinCommand,
+    pub line: String,
+}
+
+pub fn parse_terminal_builtin_command(line: &str) -> Option<TerminalBuiltinCommand> {
+    let cleaned = strip_ansi_control_sequences(line);
+    let trimmed = cleaned.trim();
+    let rest = trimmed.strip_prefix(":ng")?;
+    let args = rest.trim();
+    if args.is_empty() {
+        return Some(TerminalBuiltinCommand::Usage);
+    }
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return Some(TerminalBuiltinCommand::Unknown(args.to_string()));
+    }
+    let mut parts = args.split_whitespace();
+    let command = parts.next().unwrap_or_default().to_ascii_lowercase();
+    if command == "ping" && parts.next().is_none() {
+        return Some(TerminalBuiltinCommand::Ping);
+    }
+    Some(TerminalBuiltinCommand::Unknown(args.to_string()))
+}
+
+fn builtin_local_display_text(pending: &str) -> Option<String> {
+    let cleaned = strip_ansi_control_sequences(pending);
+    if cleaned.trim_start().starts_with(":ng") {
+        return Some(cleaned);
+    }
+    None
+}
+
+fn append_local_echo_delta(
+    capture: &mut TerminalInputCaptureState,
+    next_text: Option<String>,
+    out: &mut String,
+) {
+    let previous: Vec<char> = capture.echoed_display.chars().collect();
+    let next = next_text.unwrap_or_default();
+    let next_chars: Vec<char> = next.chars().collect();
+    let mut common = 0usize;
+    while common < previous.len()
+        && common < next_chars.len()
+        && previous[common] == next_chars[common]
+    {
+        common += 1;
+    }
+    for _ in common..previous.len() {
+        out.push('\u{0008}');
+        out.push(' ');
+        out.push('\u{0008}');
+    }
+    for ch in next_chars.iter().skip(common) {
+        out.push(*ch);
+    }
+    capture.echoed_display = next;
+}
+
+pub fn strip_ansi_control_sequences(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = String::new();
+    let mut i = 0usize;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '\u{001b}' {
+            i += 1;
+            if i >= chars.len() {
+                break;
+            }
+            let next = chars[i];
+            if next == '[' {
+                i += 1;
+                while i < chars.len() {
+                    let code = chars[i] as u32;
+                    i += 1;
+                    if (0x40..=0x7e).contains(&code) {
+                        break;
+                    }
+                }
+                continue;
+            }
+            if next == ']' {
+                i += 1;
+                while i < chars.len() {
+                    if chars[i] == '\u{0007}' {
+                        i += 1;
+                        break;
+                    }
+                    if chars[i] == '\u{001b}' && i + 1 < chars.len() && chars[i + 1] == '\\' {
+                        i += 2;
+                        break;
+                    }
+                    i += 1;
+                }
+                continue;
+            }
+            // Other ESC-prefixed controls are ignored as one sequence head.
+            i += 1;
+            continue;
+        }
+        if ch.is_control() && !ch.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        out.push(ch);
+        i += 1;
+    }
+    out
+}
+
+// 戻り値: (shell へ送る入力, 拾った内部コマンド, 画面に返すローカルエコー)
+// Returns (input forwarded to the shell, intercepted internal commands, local echo to display).
+pub fn process_terminal_input_chunk(
+    capture: &mut TerminalInputCaptureState,
+    chunk: &str,
+) -> (String, Vec<TerminalBuiltinInvocation>, String) {
+    let mut forward = String::new();
+    let mut builtins = Vec::new();
+    let mut local_echo = String::new();
+
+    for ch in chunk.chars() {
+        if capture.skip_next_lf {
+            capture.skip_next_lf = false;
+            if ch == '\n' {
+                continue;
+            }
+        }
+        if ch == '\r' || ch == '\n' {
+            let builtin = parse_terminal_builtin_command(&capture.pending);
+            if let Some(command) = builtin {
+                let display_line = strip_ansi_control_sequences(&capture.pending);
+                if capture.echoed_display.is_empty() {
+                    append_local_echo_delta(
+                        capture,
+                        builtin_local_display_text(&capture.pending),
+                        &mut local_echo,
+                    );
+                }
+                local_echo.push('\r');
+                local_echo.push('\n');
+                builtins.push(TerminalBuiltinInvocation {
+                    command,
+                    line: display_line.trim().to_string(),
+                });
+                if ch == '\r' {
+                    capture.skip_next_lf = true;
+                }
+            } else {
+                forward.push_str(&capture.pending);
+                forward.push(ch);
+            }
+            capture.pending.clear();
+            capture.mode = TerminalInputCaptureMode::Unknown;
+            capture.echoed_display.clear();
+            continue;
+        }
+
+        if capture.mode == TerminalInputCaptureMode::PassThrough {
+            forward.push(ch);
+            continue;
+        }
+
+        if ch == '\u{0008}' || ch == '\u{007f}' {
+            let _ = capture.pending.pop();
+            if capture.mode == TerminalInputCaptureMode::SuppressCandidate {
+                append_local_echo_delta(
+                    capture,
+                    builtin_local_display_text(&capture.pending),
+                    &mut local_echo,
+                );
+            }
+            continue;
+        }
+
+        capture.pending.push(ch);
+        if capture.mode == TerminalInputCaptureMode::Unknown {
+            let cleaned_for_mode = strip_ansi_control_sequences(&capture.pending);
+            if let Some(first_non_ws) = cleaned_for_mode.chars().find(|c| !c.is_whitespace()) {
+                if first_non_ws == ':' {
+                    capture.mode = TerminalInputCaptureMode::SuppressCandidate;
+                } else {
+                    capture.mode = TerminalInputCaptureMode::PassThrough;
+                    forward.push_str(&capture.pending);
+                    capture.pending.clear();
+                }
+            }
+        }
+        if capture.mode == TerminalInputCaptureMode::SuppressCandidate {
+            append_local_echo_delta(
+                capture,
+                builtin_local_display_text(&capture.pending),
+                &mut local_echo,
+            );
+        }
+    }
+
+    (forward, builtins, local_echo)
+}
+
+// 内部コマンドの結果として terminal に表示する文字列 / Text shown in the terminal as the command's result.
+pub fn builtin_command_output(command: &TerminalBuiltinCommand) -> String {
+    match command {
+        TerminalBuiltinCommand::Ping => "pong\r\n".to_string(),
+        TerminalBuiltinCommand::Usage => "[nagomi] usage: :ng ping\r\n".to_string(),
+        TerminalBuiltinCommand::Unknown(raw) => format!("[nagomi] unknown :ng command: {raw}\r\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_terminal_builtin_command_supports_ng_ping() {
+        assert_eq!(
+            parse_terminal_builtin_command(":ng ping"),
+            Some(TerminalBuiltinCommand::Ping)
+        );
+        assert_eq!(
+            parse_terminal_builtin_command("  :ng ping  "),
+            Some(TerminalBuiltinCommand::Ping)
+        );
+        assert_eq!(
+            parse_terminal_builtin_command(":ng"),
+            Some(TerminalBuiltinCommand::Usage)
+        );
+        assert_eq!(
+            parse_terminal_builtin_command(":ng unknown arg"),
+            Some(TerminalBuiltinCommand::Unknown("unknown arg".to_string()))
+        );
+        assert_eq!(
+            parse_terminal_builtin_command(":ngping"),
+            Some(TerminalBuiltinCommand::Unknown("ping".to_string()))
+        );
+        assert_eq!(parse_terminal_builtin_command("echo ok"), None);
+    }
+
+    #[test]
+    fn parse_terminal_builtin_command_ignores_ansi_prefix() {
+        let with_csi = "\u{001b}[200~:ng ping";
+        assert_eq!(
+            parse_terminal_builtin_command(with_csi),
+            Some(TerminalBuiltinCommand::Ping)
+        );
+        let with_osc = "\u{001b}]0;title\u{0007}:ng ping";
+        assert_eq!(
+            parse_terminal_builtin_command(with_osc),
+            Some(TerminalBuiltinCommand::Ping)
+        );
+    }
+
+    #[test]
+    fn process_terminal_input_chunk_intercepts_internal_command() {
+        let mut capture = TerminalInputCaptureState::default();
+        let (forward, invocations, local_echo) =
+            process_terminal_input_chunk(&mut capture, ":ng ping\r\n");
+        assert_eq!(forward, "");
+        assert_eq!(
+            invocations,
+            vec![TerminalBuiltinInvocation {
+                command: TerminalBuiltinCommand::Ping,
+                line: ":ng ping".to_string(),
+            }]
+        );
+        assert_eq!(local_echo, ":ng ping\r\n");
+
+        let mut capture = TerminalInputCaptureState::default();
+        let (forward, invocations, local_echo) =
+            process_terminal_input_chunk(&mut capture, "echo ok\r\n");
+        assert_eq!(forward, "echo ok\r\n");
+        assert!(invocations.is_empty());
+        assert_eq!(local_echo, "");
+    }
+
+    #[test]
+    fn process_terminal_input_chunk_supports_chunked_internal_command() {
+        let mut capture = TerminalInputCaptureState::default();
+        let (forward1, invocations1, local_echo1) =
+            process_terminal_input_chunk(&mut capture, ":ng ");
+        let (forward2, invocations2, local_echo2) =
+            process_terminal_input_chunk(&mut capture, "ping\r");
+        let (forward3, invocations3, local_echo3) =
+            process_terminal_input_chunk(&mut capture, "\n");
+        assert_eq!(forward1, "");
+        assert!(invocations1.is_empty());
+        assert_eq!(local_echo1, ":ng ");
+        assert_eq!(forward2, "");
+        assert_eq!(
+            invocations2,
+            vec![TerminalBuiltinInvocation {
+                command: TerminalBuiltinCommand::Ping,
+                line: ":ng ping".to_string(),
+            }]
+        );
+        assert_eq!(local_echo2, "ping\r\n");
+        assert_eq!(forward3, "");
+        assert!(invocations3.is_empty());
+        assert_eq!(local_echo3, "");
+    }
+
+    #[test]
+    fn process_terminal_input_chunk_intercepts_internal_with_ansi_wrapper() {
+        let mut capture = TerminalInputCaptureState::default();
+        let (forward1, invocations1, local_echo1) =
+            process_terminal_input_chunk(&mut capture, "\u{001b}[200~:ng ");
+        let (forward2, invocations2, local_echo2) =
+            process_terminal_input_chunk(&mut capture, "ping\u{001b}[201~\r\n");
+        assert_eq!(forward1, "");
+        assert!(invocations1.is_empty());
+        assert_eq!(local_echo1, ":ng ");
+        assert_eq!(forward2, "");
+        assert_eq!(
+            invocations2,
+            vec![TerminalBuiltinInvocation {
+                command: TerminalBuiltinCommand::Ping,
+                line: ":ng ping".to_string(),
+            }]
+        );
+        assert_eq!(local_echo2, "ping\r\n");
+    }
+}
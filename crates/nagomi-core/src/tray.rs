@@ -0,0 +1,147 @@
+// tray の表示（アイコンの印・tooltip・terminal ごとの項目）/ What the tray shows: icon badge, tooltip and one entry per terminal.
+
+use crate::control::SessionInfo;
+use crate::state::TerminalState;
+
+// 印の直径（アイコンの短辺に対する割合）/ Badge diameter as a share of the icon's shorter side.
+const BADGE_RATIO: f32 = 0.45;
+
+// terminal の tint と同じ色 / Same colors as the terminal tint.
+pub fn state_badge_color(state: TerminalState) -> Option<[u8; 3]> {
+    match state {
+        TerminalState::Idle => None,
+        TerminalState::NeedInput => Some([255, 232, 96]),
+        TerminalState::Fail => Some([220, 80, 80]),
+        TerminalState::SubworkerRunning => Some([72, 184, 94]),
+        TerminalState::Success => Some([40, 40, 40]),
+    }
+}
+
+fn session_state(session: &SessionInfo) -> TerminalState {
+    TerminalState::parse(&session.state).unwrap_or_default()
+}
+
+// 状態ごとの terminal 数（0 は省く）/ Terminal count per state, leaving out zeros.
+pub fn state_counts(sessions: &[SessionInfo]) -> Vec<(TerminalState, usize)> {
+    TerminalState::ALL
+        .into_iter()
+        .map(|state| {
+            let count = sessions
+                .iter()
+                .filter(|session| session_state(session) == state)
+                .count();
+            (state, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+pub fn tray_tooltip(aggregate: TerminalState, sessions: &[SessionInfo]) -> String {
+    let mut tooltip = format!("nagomi: {aggregate}");
+    let counts = state_counts(sessions);
+    if !counts.is_empty() {
+        let counts: Vec<String> = counts
+            .into_iter()
+            .map(|(state, count)| format!("{state} {count}"))
+            .collect();
+        tooltip.push('\n');
+        tooltip.push_str(&counts.join(" / "));
+    }
+    tooltip
+}
+
+pub fn tray_terminal_label(session: &SessionInfo) -> String {
+    let state = session_state(session);
+    let marker = match state {
+        TerminalState::Idle => "○",
+        _ => "●",
+    };
+    format!("{marker} {} — {state}", session.title)
+}
+
+// RGBA のアイコンの右下に状態色の丸を描く。idle はそのまま
+// Draws a state-colored dot in the bottom-right corner of an RGBA icon; idle leaves it as is.
+pub fn badge_icon_rgba(rgba: &[u8], width: u32, height: u32, state: TerminalState) -> Vec<u8> {
+    let mut out = rgba.to_vec();
+    let Some([r, g, b]) = state_badge_color(state) else {
+        return out;
+    };
+    if out.len() < (width as usize) * (height as usize) * 4 {
+        return out;
+    }
+    let diameter = (width.min(height) as f32 * BADGE_RATIO).max(3.0);
+    let radius = diameter / 2.0;
+    let cx = width as f32 - radius;
+    let cy = height as f32 - radius;
+    // 背景に埋もれないよう白い縁を付ける / A white rim keeps the dot readable on any background.
+    let rim = (radius * 0.2).max(1.0);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as f32 + 0.5 - cx;
+            let dy = y as f32 + 0.5 - cy;
+            let distance = (dx * dx + dy * dy).sqrt();
+            if distance > radius {
+                continue;
+            }
+            let color = if distance > radius - rim {
+                [255, 255, 255]
+            } else {
+                [r, g, b]
+            };
+            let index = ((y * width + x) * 4) as usize;
+            out[index..index + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(title: &str, state: &str) -> SessionInfo {
+        SessionInfo {
+            session_id: title.to_string(),
+            title: title.to_string(),
+            state: state.to_string(),
+            cwd: None,
+            pid: None,
+            profile: None,
+            running: true,
+        }
+    }
+
+    #[test]
+    fn tooltip_and_labels_list_states() {
+        let sessions = [
+            session("api", "need-input"),
+            session("web", "idle"),
+            session("docs", "idle"),
+        ];
+        assert_eq!(
+            tray_tooltip(TerminalState::NeedInput, &sessions),
+            "nagomi: need-input\nidle 2 / need-input 1"
+        );
+        assert_eq!(tray_tooltip(TerminalState::Idle, &[]), "nagomi: idle");
+        assert_eq!(tray_terminal_label(&sessions[0]), "● api — need-input");
+        assert_eq!(tray_terminal_label(&sessions[1]), "○ web — idle");
+    }
+
+    #[test]
+    fn badge_paints_only_the_corner() {
+        let (width, height) = (16, 16);
+        let icon = vec![0u8; (width * height * 4) as usize];
+        assert_eq!(
+            badge_icon_rgba(&icon, width, height, TerminalState::Idle),
+            icon
+        );
+        let badged = badge_icon_rgba(&icon, width, height, TerminalState::Fail);
+        let pixel = |x: u32, y: u32| {
+            let index = ((y * width + x) * 4) as usize;
+            badged[index..index + 4].to_vec()
+        };
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(8, 8), [0, 0, 0, 0]);
+        assert_eq!(pixel(12, 12), [220, 80, 80, 255]);
+    }
+}
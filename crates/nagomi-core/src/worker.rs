@@ -11,9 +11,29 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
+// 子プロセスを立てずに worker を演じる実装（テスト用の fake など）
+// A worker that lives in this process instead of a child, e.g. a scripted fake for tests.
+pub trait InProcessWorker: Send {
+    // orchestrator からの 1 メッセージ。返信は `out` へ / One message from the orchestrator; replies go to `out`.
+    fn handle(&mut self, message: Message, out: &mpsc::Sender<Message>);
+
+    fn stop(&mut self) {}
+}
+
+enum Transport {
+    Child {
+        child: Child,
+        stdin: ChildStdin,
+    },
+    InProcess {
+        worker: Box<dyn InProcessWorker>,
+        // stop 後は None。送信側が消えると reader が終わる / None after stop so readers see the disconnect.
+        out: Option<mpsc::Sender<Message>>,
+    },
+}
+
 pub struct WorkerProcess {
-    child: Child,
-    stdin: ChildStdin,
+    transport: Transport,
     rx: Option<mpsc::Receiver<Message>>,
 }
 
@@ -48,16 +68,44 @@ impl WorkerProcess {
             }
         });
         Ok(Self {
-            child,
-            stdin,
+            transport: Transport::Child { child, stdin },
             rx: Some(rx),
         })
     }
 
+    // 同じ API のまま in-process の worker に繋ぐ / Same API, backed by an in-process worker.
+    pub fn in_process(worker: impl InProcessWorker + 'static) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            transport: Transport::InProcess {
+                worker: Box::new(worker),
+                out: Some(tx),
+            },
+            rx: Some(rx),
+        }
+    }
+
+    // 子プロセスの pid。in-process なら None / The child's pid; None for in-process workers.
+    pub fn id(&self) -> Option<u32> {
+        match &self.transport {
+            Transport::Child { child, .. } => Some(child.id()),
+            Transport::InProcess { .. } => None,
+        }
+    }
+
     pub fn send_message(&mut self, message: &Message) -> Result<()> {
         let line = serialize_message(message);
-        self.stdin.write_all(line.as_bytes())?;
-        self.stdin.flush()?;
+        match &mut self.transport {
+            Transport::Child { stdin, .. } => {
+                stdin.write_all(line.as_bytes())?;
+                stdin.flush()?;
+            }
+            // 行形式を往復させて本物と同じ protocol を通す / Round-trip the wire format like a real worker.
+            Transport::InProcess { worker, out } => {
+                let out = out.as_ref().context("worker stopped")?;
+                worker.handle(parse_line(&line), out);
+            }
+        }
         Ok(())
     }
 
@@ -93,17 +141,25 @@ impl WorkerProcess {
     }
 
     pub fn stop(&mut self) -> Result<()> {
-        let _ = self.child.kill();
-        let _ = self.child.wait();
+        match &mut self.transport {
+            Transport::Child { child, .. } => {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            Transport::InProcess { worker, out } => {
+                worker.stop();
+                out.take();
+            }
+        }
         Ok(())
     }
 }
 
-fn workspace_root() -> Option<PathBuf> {
+pub(crate) fn workspace_root() -> Option<PathBuf> {
     let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     manifest_dir
         .ancestors()
-        .nth(3)
+        .nth(2)
         .map(|path| path.to_path_buf())
 }
 
@@ -115,7 +171,6 @@ fn worker_exe_name() -> &'static str {
     }
 }
 
-#[allow(dead_code)]
 pub fn resolve_worker_path() -> Result<PathBuf> {
     if let Ok(exe_path) = std::env::current_exe() {
         if let Some(dir) = exe_path.parent() {
@@ -136,6 +191,7 @@ pub fn resolve_worker_path() -> Result<PathBuf> {
     Ok(PathBuf::from(worker_exe_name()))
 }
 
+// 移設前の書き方のまま残す / Kept as written before the move out of the Tauri app.
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,7 +240,7 @@ mod tests {
     #[test]
     fn worker_spawn_stdio_connect() {
         let mut worker = start_worker().expect("spawn worker");
-        assert!(worker.child.id() > 0);
+        assert!(worker.id().expect("child pid") > 0);
         worker.stop().expect("stop worker");
     }
 
@@ -201,6 +257,7 @@ mod tests {
                 env: None,
                 cols: 120,
                 rows: 30,
+                ..Default::default()
             })
             .expect("start session");
 
@@ -230,10 +287,10 @@ mod tests {
         while Instant::now() < deadline {
             if let Some(message) = wait_for_message(&worker, Duration::from_millis(300)) {
                 match message {
-                    Message::Output(Output { chunk, .. }) => {
-                        if chunk.to_lowercase().contains("ok") {
-                            saw_output = true;
-                        }
+                    Message::Output(Output { chunk, .. })
+                        if chunk.to_lowercase().contains("ok") =>
+                    {
+                        saw_output = true;
                     }
                     Message::Exit(_) => {
                         saw_exit = true;
@@ -272,19 +329,30 @@ mod tests {
     fn recv_output_exit_error() {
         let mut worker = start_worker().expect("spawn worker");
         let session_id = "session-output";
-        let cmd = if cfg!(windows) {
-            "cmd.exe /C echo ok"
+        // argv で渡して `echo ok` を 1 引数のまま sh に届ける（cmd の分割に頼らない）
+        // Pass argv so `echo ok` reaches sh as one argument, without relying on cmd splitting.
+        let (cmd, argv) = if cfg!(windows) {
+            ("cmd.exe /C echo ok", None)
         } else {
-            "sh -c echo ok"
+            (
+                "",
+                Some(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "echo ok".to_string(),
+                ]),
+            )
         };
         worker
             .send_start_session(StartSession {
                 session_id: session_id.to_string(),
                 cmd: cmd.to_string(),
+                argv,
                 cwd: None,
                 env: None,
                 cols: 120,
                 rows: 30,
+                ..Default::default()
             })
             .expect("start session");
 
@@ -294,10 +362,10 @@ mod tests {
         while Instant::now() < deadline {
             if let Some(message) = wait_for_message(&worker, Duration::from_millis(300)) {
                 match message {
-                    Message::Output(Output { chunk, .. }) => {
-                        if chunk.to_lowercase().contains("ok") {
-                            saw_output = true;
-                        }
+                    Message::Output(Output { chunk, .. })
+                        if chunk.to_lowercase().contains("ok") =>
+                    {
+                        saw_output = true;
                     }
                     Message::Exit(_) => {
                         saw_exit = true;
@@ -331,6 +399,7 @@ mod tests {
                 env: None,
                 cols: 120,
                 rows: 30,
+                ..Default::default()
             })
             .expect("start session");
 
@@ -352,10 +421,10 @@ mod tests {
         while Instant::now() < deadline {
             if let Some(message) = wait_for_message(&worker, Duration::from_millis(300)) {
                 match message {
-                    Message::Output(Output { chunk, .. }) => {
-                        if chunk.to_lowercase().contains("ok") {
-                            saw_output = true;
-                        }
+                    Message::Output(Output { chunk, .. })
+                        if chunk.to_lowercase().contains("ok") =>
+                    {
+                        saw_output = true;
                     }
                     Message::Exit(_) => {
                         saw_exit = true;
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const WORKSPACE_FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspaceRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 保存した 1 枚分の terminal / One saved terminal window.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceTerminal {
+    pub order: usize,
+    pub session_id: String,
+    pub title: String,
+    pub profile: String,
+    pub cmd: String,
+    pub cwd: String,
+    pub monitor: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor_area: Option<WorkspaceRect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rect: Option<WorkspaceRect>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Workspace {
+    pub version: u32,
+    pub name: String,
+    pub saved_at_ms: u64,
+    pub terminals: Vec<WorkspaceTerminal>,
+}
+
+impl Workspace {
+    pub fn ordered_terminals(&self) -> Vec<&WorkspaceTerminal> {
+        let mut terminals: Vec<&WorkspaceTerminal> = self.terminals.iter().collect();
+        terminals.sort_by_key(|terminal| terminal.order);
+        terminals
+    }
+}
+
+// 名前からファイル名を作る（大文字小文字は区別しない）
+// Derive the file stem from a workspace name (case-insensitive).
+pub fn workspace_file_stem(name: &str) -> Result<String> {
+    let stem: String = name
+        .trim()
+        .chars()
+        .map(|ch| {
+            if ch.is_alphanumeric() || ch == '-' || ch == '_' {
+                ch.to_lowercase().next().unwrap_or(ch)
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = stem.trim_matches('_').to_string();
+    if stem.is_empty() {
+        return Err(anyhow!("workspace name is empty"));
+    }
+    Ok(stem)
+}
+
+pub fn workspace_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    Ok(dir.join(format!("{}.json", workspace_file_stem(name)?)))
+}
+
+pub fn write_workspace(dir: &Path, workspace: &Workspace) -> Result<PathBuf> {
+    let path = workspace_path(dir, &workspace.name)?;
+    fs::create_dir_all(dir)?;
+    let raw = serde_json::to_string_pretty(workspace)?;
+    // 途中で落ちても既存ファイルを壊さない / Never leave a half-written workspace behind.
+    let tmp = path.with_extension("json.tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(raw.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+pub fn read_workspace(dir: &Path, name: &str) -> Result<Workspace> {
+    let path = workspace_path(dir, name)?;
+    if !path.exists() {
+        return Err(anyhow!("workspace not found: {}", name.trim()));
+    }
+    let raw = fs::read_to_string(&path)?;
+    serde_json::from_str(&raw)
+        .with_context(|| format!("invalid workspace file: {}", path.display()))
+}
+
+pub fn list_workspaces(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+        .filter_map(|path| {
+            let raw = fs::read_to_string(&path).ok()?;
+            let workspace: Workspace = serde_json::from_str(&raw).ok()?;
+            let name = workspace.name.trim().to_string();
+            (!name.is_empty()).then_some(name)
+        })
+        .collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names
+}
+
+// monitor は名前一致 → 保存時の番号 → 先頭の順で選ぶ
+// Pick the monitor by name, then by saved index, then fall back to the first one.
+pub fn pick_monitor(
+    terminal: &WorkspaceTerminal,
+    monitors: &[(Option<String>, WorkspaceRect)],
+) -> usize {
+    if let Some(name) = terminal.monitor_name.as_deref() {
+        if let Some(index) = monitors
+            .iter()
+            .position(|(candidate, _)| candidate.as_deref() == Some(name))
+        {
+            return index;
+        }
+    }
+    if terminal.monitor < monitors.len() {
+        terminal.monitor
+    } else {
+        0
+    }
+}
+
+// 保存時と違う monitor に置く場合は相対位置を保ったまま収める
+// When the monitor changed, keep the relative position and fit the rect inside the new area.
+pub fn place_rect(
+    rect: WorkspaceRect,
+    saved_area: Option<WorkspaceRect>,
+    target_area: WorkspaceRect,
+) -> WorkspaceRect {
+    if saved_area == Some(target_area) {
+        return rect;
+    }
+    let source = saved_area.unwrap_or(target_area);
+    let width = rect.width.clamp(1, target_area.width.max(1));
+    let height = rect.height.clamp(1, target_area.height.max(1));
+    let max_x = target_area.x + target_area.width.saturating_sub(width) as i32;
+    let max_y = target_area.y + target_area.height.saturating_sub(height) as i32;
+    let x = (target_area.x + (rect.x - source.x)).clamp(target_area.x, max_x.max(target_area.x));
+    let y = (target_area.y + (rect.y - source.y)).clamp(target_area.y, max_y.max(target_area.y));
+    WorkspaceRect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(tag: &str) -> PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        std::env::temp_dir().join(format!("nagomi-workspace-{tag}-{nonce}"))
+    }
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> WorkspaceRect {
+        WorkspaceRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn workspace_file_stem_sanitizes_names() {
+        assert_eq!(workspace_file_stem(" Day Setup ").unwrap(), "day_setup");
+        assert_eq!(workspace_file_stem("../etc/passwd").unwrap(), "etc_passwd");
+        assert!(workspace_file_stem(" ./ ").is_err());
+    }
+
+    #[test]
+    fn workspace_roundtrip_and_listing() {
+        let dir = temp_dir("roundtrip");
+        let workspace = Workspace {
+            version: WORKSPACE_FILE_VERSION,
+            name: "Day Setup".to_string(),
+            saved_at_ms: 42,
+            terminals: vec![
+                WorkspaceTerminal {
+                    order: 1,
+                    session_id: "terminal-b".to_string(),
+                    cmd: "claude".to_string(),
+                    rect: Some(area(960, 0, 940, 1000)),
+                    ..WorkspaceTerminal::default()
+                },
+                WorkspaceTerminal {
+                    order: 0,
+                    session_id: "terminal-a".to_string(),
+                    profile: "api".to_string(),
+                    cwd: "/srv/api".to_string(),
+                    monitor_name: Some("DISPLAY1".to_string()),
+                    ..WorkspaceTerminal::default()
+                },
+            ],
+        };
+        let path = write_workspace(&dir, &workspace).expect("write");
+        assert_eq!(path, dir.join("day_setup.json"));
+
+        let loaded = read_workspace(&dir, "day setup").expect("read");
+        assert_eq!(loaded, workspace);
+        let order: Vec<&str> = loaded
+            .ordered_terminals()
+            .iter()
+            .map(|terminal| terminal.session_id.as_str())
+            .collect();
+        assert_eq!(order, vec!["terminal-a", "terminal-b"]);
+
+        assert_eq!(list_workspaces(&dir), vec!["Day Setup".to_string()]);
+        let err = read_workspace(&dir, "other").expect_err("missing");
+        assert!(err.to_string().starts_with("workspace not found"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pick_monitor_prefers_name_then_index() {
+        let monitors = vec![
+            (Some("LEFT".to_string()), area(0, 0, 1920, 1080)),
+            (Some("RIGHT".to_string()), area(1920, 0, 1920, 1080)),
+        ];
+        let by_name = WorkspaceTerminal {
+            monitor: 0,
+            monitor_name: Some("RIGHT".to_string()),
+            ..WorkspaceTerminal::default()
+        };
+        assert_eq!(pick_monitor(&by_name, &monitors), 1);
+        let by_index = WorkspaceTerminal {
+            monitor: 1,
+            monitor_name: Some("GONE".to_string()),
+            ..WorkspaceTerminal::default()
+        };
+        assert_eq!(pick_monitor(&by_index, &monitors), 1);
+        let missing = WorkspaceTerminal {
+            monitor: 5,
+            ..WorkspaceTerminal::default()
+        };
+        assert_eq!(pick_monitor(&missing, &monitors), 0);
+    }
+
+    #[test]
+    fn place_rect_keeps_same_monitor_and_fits_new_one() {
+        let rect = area(2000, 100, 800, 600);
+        let saved = area(1920, 0, 1920, 1080);
+        assert_eq!(place_rect(rect, Some(saved), saved), rect);
+
+        let smaller = area(0, 0, 1280, 720);
+        assert_eq!(
+            place_rect(rect, Some(saved), smaller),
+            area(80, 100, 800, 600)
+        );
+
+        let huge = area(2000, 900, 3000, 2000);
+        assert_eq!(
+            place_rect(huge, Some(saved), smaller),
+            area(0, 0, 1280, 720)
+        );
+    }
+}
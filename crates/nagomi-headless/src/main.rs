@@ -0,0 +1,238 @@
+// nagomi-headless: WebView なしで terminal session・hook・判定・通知を動かす
+// nagomi-headless: runs terminal sessions, hooks, judging and notifications without any WebView.
+//
+// 操作は GUI と同じ制御 API（`nagomi ls/send/tail/attach` や curl）で行う。
+// Drive it through the same control API as the GUI (`nagomi ls/send/tail/attach`, curl, ...).
+mod runtime;
+
+use anyhow::{bail, Context, Result};
+use nagomi_core::control::{
+    self, parse_query_pairs, ControlError, ControlResponse, ControlRoute, HttpRequest,
+    OpenSessionRequest,
+};
+use serde_json::json;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use runtime::{Headless, Options};
+
+// SIGINT/SIGTERM で立つ。accept loop を抜けて shutdown まで進める
+// Set by SIGINT/SIGTERM; ends the accept loop so shutdown runs.
+static STOP: AtomicBool = AtomicBool::new(false);
+// 停止の確認間隔（listener は non-blocking）/ How often the non-blocking listener checks for a stop.
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+struct Args {
+    port: u16,
+    hook_tool: Option<String>,
+    notify_cmd: Option<String>,
+    // 起動時に開く profile（空文字はシェル）/ Profiles opened at startup ("" opens a plain shell).
+    open: Vec<String>,
+}
+
+fn print_help() {
+    println!("nagomi-headless: run nagomi sessions without a GUI");
+    println!(
+        "  --port <port>        Control API port (default: $NAGOMI_ORCH_HEALTH_PORT or 17707)"
+    );
+    println!(
+        "  --open <profile>     Open a session from a terminal profile at startup (repeatable)"
+    );
+    println!("  --shell              Open a plain shell session at startup");
+    println!("  --hook-tool <tool>   Also watch this completion hook (codex|claude|opencode|gemini|aider),");
+    println!("                       on top of the sources enabled in settings");
+    println!("  --notify-cmd <cmd>   Run <cmd> on need-input/fail/success");
+    println!("                       (NAGOMI_NOTIFY_TITLE/BODY/STATE are set)");
+}
+
+fn parse_args() -> Result<Option<Args>> {
+    let mut parsed = Args {
+        port: std::env::var("NAGOMI_ORCH_HEALTH_PORT")
+            .ok()
+            .and_then(|value| value.parse::<u16>().ok())
+            .unwrap_or(17707),
+        hook_tool: None,
+        notify_cmd: None,
+        open: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().with_context(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--port" => {
+                let raw = value("--port")?;
+                parsed.port = raw
+                    .parse()
+                    .with_context(|| format!("invalid port: {raw}"))?;
+            }
+            "--open" => parsed.open.push(value("--open")?),
+            "--shell" => parsed.open.push(String::new()),
+            "--hook-tool" => parsed.hook_tool = Some(value("--hook-tool")?),
+            "--notify-cmd" => parsed.notify_cmd = Some(value("--notify-cmd")?),
+            "--help" | "-h" => {
+                print_help();
+                return Ok(None);
+            }
+            _ => bail!("unknown argument: {arg}"),
+        }
+    }
+    Ok(Some(parsed))
+}
+
+fn install_stop_signals() -> Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        unsafe { signal_hook_registry::register(signal, || STOP.store(true, Ordering::SeqCst)) }
+            .with_context(|| format!("cannot handle signal {signal}"))?;
+    }
+    Ok(())
+}
+
+fn write_json(stream: &mut TcpStream, status: u16, body: serde_json::Value) {
+    let _ = control::write_response(stream, &ControlResponse { status, body });
+}
+
+// 要求を読み、`/health` と認証失敗はその場で返す。処理を続ける要求だけ返す
+// Reads the request and answers `/health` and auth failures in place; returns only the ones to serve.
+fn accept_request(stream: &mut TcpStream, token: &str) -> Option<HttpRequest> {
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(500)));
+    let request = match control::read_request(stream) {
+        Ok(request) => request,
+        Err(err) => {
+            let _ = control::write_response(stream, &err.to_response());
+            return None;
+        }
+    };
+    if request.method == "GET" && request.path == "/health" {
+        write_json(
+            stream,
+            200,
+            json!({ "status": "ok", "pid": std::process::id(), "headless": true }),
+        );
+        return None;
+    }
+    if control::requires_auth(&request.method, &request.path) {
+        if let Err(err) = control::authorize(&request, token) {
+            let _ = control::write_response(stream, &err.to_response());
+            return None;
+        }
+    }
+    Some(request)
+}
+
+fn handle_connection(mut stream: TcpStream, headless: &Arc<Headless>, request: HttpRequest) {
+    match control::route(&request.method, &request.path) {
+        Ok(Some(ControlRoute::Events)) => {
+            control::serve_events(stream, headless.bus(), &request, || {
+                headless.aggregate_events()
+            });
+        }
+        Ok(Some(route)) => {
+            let response = control::handle(headless.as_ref(), route, &request);
+            let _ = control::write_response(&mut stream, &response);
+        }
+        // `nagomi` CLI の既定動作（/open-terminal）だけは受ける
+        // Accept `/open-terminal` so the plain `nagomi` command works against headless too.
+        Ok(None) if request.path == "/open-terminal" => {
+            let query = parse_query_pairs(request.target.split_once('?').map_or("", |(_, q)| q));
+            let pick = |key: &str| {
+                query
+                    .get(key)
+                    .cloned()
+                    .filter(|value| !value.trim().is_empty())
+            };
+            let opened = headless.open(OpenSessionRequest {
+                session_id: pick("session_id"),
+                profile: pick("profile"),
+                cwd: pick("cwd"),
+            });
+            let response = match opened {
+                Ok(session_id) => {
+                    ControlResponse::ok(json!({ "status": "ok", "session_id": session_id }))
+                }
+                Err(err) => err.to_response(),
+            };
+            let _ = control::write_response(&mut stream, &response);
+        }
+        Ok(None) => {
+            let err = ControlError::not_found(format!(
+                "{} is not available in headless mode",
+                request.path
+            ));
+            let _ = control::write_response(&mut stream, &err.to_response());
+        }
+        Err(err) => {
+            let _ = control::write_response(&mut stream, &err.to_response());
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let Some(args) = parse_args()? else {
+        return Ok(());
+    };
+    let config_dir =
+        nagomi_core::paths::app_config_dir().context("cannot resolve the app config directory")?;
+    // GUI と同じトークンと settings を使う / Share the token and settings with the GUI.
+    let token = control::load_or_create_token(&config_dir)?;
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).with_context(|| {
+        format!(
+            "cannot listen on 127.0.0.1:{} (is the GUI orchestrator running?)",
+            args.port
+        )
+    })?;
+    listener
+        .set_nonblocking(true)
+        .context("cannot make the control listener non-blocking")?;
+    install_stop_signals()?;
+    let headless = Headless::new(Options {
+        settings_path: config_dir.join("settings.json"),
+        hook_tool: args.hook_tool,
+        notify_cmd: args.notify_cmd,
+    })?;
+    for profile in &args.open {
+        let request = OpenSessionRequest {
+            profile: (!profile.is_empty()).then(|| profile.clone()),
+            ..Default::default()
+        };
+        if let Err(err) = headless.open(request) {
+            bail!("failed to open session: {}", err.message);
+        }
+    }
+    eprintln!(
+        "[nagomi-headless] listening on 127.0.0.1:{} (config: {})",
+        args.port,
+        config_dir.display()
+    );
+
+    // 読み取りと認証も別 thread で行い、遅い client が `/health` を塞がないようにする
+    // Reads and auth run off this thread too, so a slow client can't stall `/health`.
+    let pending = control::PendingLimit::new(control::MAX_PENDING_CONNECTIONS);
+    while !STOP.load(Ordering::SeqCst) {
+        let Ok((mut stream, _)) = listener.accept() else {
+            thread::sleep(ACCEPT_POLL);
+            continue;
+        };
+        // listener の non-blocking を継ぐ環境がある / Some platforms hand the listener's non-blocking mode on.
+        let _ = stream.set_nonblocking(false);
+        let Some(permit) = pending.try_acquire() else {
+            control::refuse_busy(&mut stream);
+            continue;
+        };
+        let headless = headless.clone();
+        let token = token.clone();
+        thread::spawn(move || {
+            let request = accept_request(&mut stream, &token);
+            drop(permit);
+            if let Some(request) = request {
+                handle_connection(stream, &headless, request);
+            }
+        });
+    }
+    eprintln!("[nagomi-headless] stopping sessions and hook tails");
+    headless.shutdown();
+    Ok(())
+}
@@ -0,0 +1,484 @@
+// WebView なしで terminal session を抱える本体 / Session runtime that needs no WebView.
+//
+// GUI では frontend が出力を見て状態を報告するが、ここでは judge を直接回す。
+// The GUI relies on the frontend to report observed states; here the judge runs in-process.
+use anyhow::{Context, Result};
+use nagomi_core::completion_hook::{
+    hooks_base_dir, CompletionHookManager, HookCallback, HookEvent,
+};
+use nagomi_core::control::{
+    ControlBackend, ControlError, ControlEvent, EventBus, OpenSessionRequest, SessionInfo,
+};
+use nagomi_core::judge::{self, JudgeConfig, JudgeInput, JudgeState};
+use nagomi_core::launch::{
+    build_terminal_launch_plan, generate_terminal_session_id, launch_options, terminal_window_title,
+};
+use nagomi_core::session::{dispatch_hook_event, HookStatePayload, SessionRegistry};
+use nagomi_core::settings::{
+    aggregate_policy, enabled_hook_sources, find_terminal_profile, read_settings, Settings,
+};
+use nagomi_core::sink::EventSink;
+use nagomi_core::state::{aggregate_group, ObservedStates, StateTrigger, TerminalState};
+use nagomi_core::terminal_input::strip_ansi_control_sequences;
+use nagomi_core::worker::{self, WorkerProcess};
+use nagomi_protocol::StartSession;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const JUDGE_INTERVAL: Duration = Duration::from_millis(500);
+// judge に渡す直近の行数 / Recent lines handed to the judge.
+const JUDGE_TAIL_LINES: usize = 40;
+const DEFAULT_COLS: u16 = 120;
+const DEFAULT_ROWS: u16 = 32;
+
+pub struct Options {
+    // GUI と共有する settings.json / The settings.json shared with the GUI.
+    pub settings_path: PathBuf,
+    // settings で有効な source に足して tail する hook / Hook tailed on top of the sources enabled in settings.
+    pub hook_tool: Option<String>,
+    pub notify_cmd: Option<String>,
+}
+
+// 最後の入力以降の行（judge 用）/ Lines since the last input, for the judge.
+#[derive(Default)]
+struct JudgeBuffer {
+    lines: Vec<String>,
+    partial_line: String,
+    last_output_at: Option<SystemTime>,
+    // hook が状態を決めたら次の入力まで judge しない / A hook-decided state holds until the next input.
+    settled_by_hook: bool,
+}
+
+impl JudgeBuffer {
+    fn push_output(&mut self, chunk: &str) {
+        self.last_output_at = Some(SystemTime::now());
+        self.partial_line.push_str(chunk);
+        if let Some(end) = self.partial_line.rfind('\n') {
+            let complete: String = self.partial_line.drain(..=end).collect();
+            self.lines.extend(
+                complete
+                    .lines()
+                    .map(strip_ansi)
+                    .filter(|line| !line.trim().is_empty()),
+            );
+            let overflow = self.lines.len().saturating_sub(JUDGE_TAIL_LINES);
+            self.lines.drain(..overflow);
+        }
+    }
+}
+
+pub struct Headless {
+    registry: SessionRegistry,
+    observed: ObservedStates,
+    buffers: Mutex<BTreeMap<String, JudgeBuffer>>,
+    bus: EventBus,
+    judge: JudgeConfig,
+    hooks: Mutex<CompletionHookManager>,
+    options: Options,
+    worker_path: PathBuf,
+    // reader thread に渡す自分への弱参照 / Weak self handed to reader threads.
+    this: Weak<Self>,
+}
+
+impl Headless {
+    pub fn new(options: Options) -> Result<Arc<Self>> {
+        let worker_path = worker::resolve_worker_path().context("nagomi-worker not found")?;
+        let headless = Arc::new_cyclic(|weak: &Weak<Self>| {
+            let hook_target = weak.clone();
+            let on_event: HookCallback = Arc::new(move |event| {
+                if let Some(headless) = hook_target.upgrade() {
+                    headless.handle_hook_event(event);
+                }
+            });
+            Self {
+                registry: SessionRegistry::default(),
+                observed: ObservedStates::default(),
+                buffers: Mutex::new(BTreeMap::new()),
+                bus: EventBus::default(),
+                judge: JudgeConfig::default(),
+                hooks: Mutex::new(CompletionHookManager::new(on_event)),
+                options,
+                worker_path,
+                this: weak.clone(),
+            }
+        });
+        headless.apply_hook_sources();
+        headless.apply_aggregate_policy();
+        let judge = Arc::downgrade(&headless);
+        thread::spawn(move || loop {
+            thread::sleep(JUDGE_INTERVAL);
+            let Some(headless) = judge.upgrade() else {
+                return;
+            };
+            headless.judge_running_sessions();
+        });
+        Ok(headless)
+    }
+
+    pub fn bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    // 全体とグループごとの現在の集約 / Current aggregates, overall and per group.
+    pub fn aggregate_events(&self) -> Vec<ControlEvent> {
+        self.observed.aggregate_events()
+    }
+
+    pub fn shutdown(&self) {
+        for id in self.session_ids() {
+            let _ = self.stop(&id);
+        }
+        if let Ok(mut hooks) = self.hooks.lock() {
+            hooks.stop();
+        }
+    }
+
+    // 終了済みも stop まで残る / Exited sessions stay listed until stopped.
+    fn session_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .registry
+            .launch
+            .lock()
+            .map(|launch| launch.keys().cloned().collect())
+            .unwrap_or_default();
+        ids.sort();
+        ids
+    }
+
+    fn is_known(&self, session_id: &str) -> bool {
+        self.registry.launch_options(session_id).is_some()
+    }
+
+    fn running_session(&self, session_id: &str) -> Result<(), ControlError> {
+        if self.registry.is_active(session_id) {
+            Ok(())
+        } else if self.is_known(session_id) {
+            Err(ControlError::new(409, "conflict", "session is not running"))
+        } else {
+            Err(ControlError::not_found(format!(
+                "session not found: {session_id}"
+            )))
+        }
+    }
+
+    fn apply_hook_sources(&self) {
+        let settings = self.settings();
+        if let Ok(mut gate) = self.registry.hook_gate.lock() {
+            gate.set_window_ms(settings.hook_dedup_window_ms);
+        }
+        let mut sources: Vec<&str> = enabled_hook_sources(&settings);
+        sources.extend(self.options.hook_tool.as_deref());
+        let base_dir = hooks_base_dir();
+        let _ = std::fs::create_dir_all(&base_dir);
+        if let Ok(mut hooks) = self.hooks.lock() {
+            hooks.set_sources(&sources, &base_dir);
+        }
+    }
+
+    fn apply_aggregate_policy(&self) {
+        self.observed
+            .set_policy(aggregate_policy(&self.settings()), self);
+    }
+
+    // 起動に失敗した settings は既定値で動く / A broken settings file just means defaults.
+    fn settings(&self) -> Settings {
+        read_settings(&self.options.settings_path).unwrap_or_default()
+    }
+
+    pub fn open(&self, request: OpenSessionRequest) -> Result<String, ControlError> {
+        let settings = self.settings();
+        let profile_name = request
+            .profile
+            .as_deref()
+            .filter(|name| !name.trim().is_empty());
+        if let Some(name) = profile_name {
+            if find_terminal_profile(&settings, name).is_none() {
+                return Err(ControlError::not_found(format!(
+                    "terminal profile not found: {name}"
+                )));
+            }
+        }
+        let mut options = launch_options(&settings, profile_name, request.cwd.as_deref())
+            .map_err(ControlError::bad_request)?;
+        // GUI と同じく使用中の id は 409 にする / Same as the GUI: an id in use is a 409.
+        let session_id = match request
+            .session_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+        {
+            Some(id) if self.is_known(&id) => {
+                return Err(ControlError::new(
+                    409,
+                    "conflict",
+                    format!("session already exists: {id}"),
+                ));
+            }
+            Some(id) => id,
+            None => generate_terminal_session_id(),
+        };
+        let plan = build_terminal_launch_plan(&settings, &session_id, &options)
+            .map_err(ControlError::bad_request)?;
+        // 実際に使った cwd を残す / Keep the cwd actually used.
+        options.cwd = plan.cwd.clone();
+
+        let mut process = WorkerProcess::spawn(&self.worker_path)
+            .map_err(|err| ControlError::internal(err.to_string()))?;
+        let rx = process.take_receiver();
+        process
+            .send_start_session(StartSession {
+                session_id: session_id.clone(),
+                cmd: plan.cmd,
+                argv: plan.argv,
+                login_shell: plan.login_shell,
+                cwd: plan.cwd,
+                env: Some(plan.env),
+                cols: DEFAULT_COLS,
+                rows: DEFAULT_ROWS,
+                ..Default::default()
+            })
+            .map_err(|err| ControlError::internal(err.to_string()))?;
+
+        let group = aggregate_group(&settings.aggregate_group_by, &options);
+        self.registry.set_launch_options(&session_id, options);
+        self.observed.set_group(&session_id, group, self);
+        if let Ok(mut buffers) = self.buffers.lock() {
+            buffers.insert(session_id.clone(), JudgeBuffer::default());
+        }
+        self.registry
+            .insert_worker(&session_id, process, self)
+            .map_err(ControlError::internal)?;
+
+        if let (Some(rx), Some(headless)) = (rx, self.this.upgrade()) {
+            thread::spawn(move || headless.registry.run_reader(&rx, headless.as_ref()));
+        }
+        eprintln!("[nagomi-headless] session opened: {session_id}");
+        Ok(session_id)
+    }
+
+    fn judge_running_sessions(&self) {
+        let now = SystemTime::now();
+        let judged: Vec<(String, TerminalState)> = match self.buffers.lock() {
+            Ok(buffers) => buffers
+                .iter()
+                .filter(|(id, buffer)| !buffer.settled_by_hook && self.registry.is_active(id))
+                .filter_map(|(id, buffer)| {
+                    let input = JudgeInput {
+                        exit_code: None,
+                        tail_lines: &buffer.lines,
+                        last_output_at: buffer.last_output_at,
+                        now,
+                    };
+                    let state = match judge::evaluate(&self.judge, &input)? {
+                        JudgeState::NeedInput => TerminalState::NeedInput,
+                        JudgeState::Failure => TerminalState::Fail,
+                        JudgeState::Success => TerminalState::Success,
+                    };
+                    (self.observed.get(id) != Some(state)).then(|| (id.clone(), state))
+                })
+                .collect(),
+            Err(_) => return,
+        };
+        for (session_id, state) in judged {
+            self.set_state(&session_id, StateTrigger::Judged(state));
+        }
+    }
+
+    // 遷移できたときだけ、結果の出た状態への変化を通知する
+    // Notifies only when an accepted transition settles into a result state.
+    fn set_state(&self, session_id: &str, trigger: StateTrigger) {
+        let Ok(change) = self.observed.apply(session_id, trigger, self) else {
+            return;
+        };
+        let settled = matches!(
+            change.state,
+            TerminalState::NeedInput | TerminalState::Fail | TerminalState::Success
+        );
+        if !settled || !change.changed() {
+            return;
+        }
+        let state = change.state.as_str();
+        let title = self
+            .registry
+            .launch_options(session_id)
+            .map(|options| terminal_window_title(session_id, &options))
+            .unwrap_or_else(|| format!("Terminal {session_id}"));
+        let summary = self
+            .buffers
+            .lock()
+            .ok()
+            .and_then(|buffers| {
+                buffers
+                    .get(session_id)
+                    .map(|buffer| judge::summarize_tail(&buffer.lines, 2).join("\n"))
+            })
+            .unwrap_or_default();
+        self.notify(&title, &summary, state);
+    }
+
+    fn handle_hook_event(&self, event: HookEvent) {
+        dispatch_hook_event(&self.registry, None, &event, self);
+    }
+
+    // 通知は stderr に 1 行、指定があれば外部コマンドにも渡す
+    // Notify with one stderr line, plus the external command when configured.
+    fn notify(&self, title: &str, body: &str, state: &str) {
+        let first_line = body.lines().next().unwrap_or_default();
+        eprintln!("[nagomi-headless] {state}: {title} {first_line}");
+        let Some(cmd) = self.options.notify_cmd.as_deref() else {
+            return;
+        };
+        let mut command = if cfg!(windows) {
+            let mut command = std::process::Command::new("cmd");
+            command.arg("/C").arg(cmd);
+            command
+        } else {
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(cmd);
+            command
+        };
+        command
+            .env("NAGOMI_NOTIFY_TITLE", title)
+            .env("NAGOMI_NOTIFY_BODY", body)
+            .env("NAGOMI_NOTIFY_STATE", state);
+        match command.spawn() {
+            // 終了待ちは別 thread で（zombie を残さない）/ Reap in the background so no zombie is left.
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => eprintln!("[nagomi-headless] notify command failed: {err}"),
+        }
+    }
+}
+
+impl EventSink for Headless {
+    fn bus(&self) -> Option<&EventBus> {
+        Some(&self.bus)
+    }
+
+    // 状態遷移や捨てた hook も stderr に残す / State transitions and dropped hooks go to stderr too.
+    fn log(&self, message: &str) {
+        eprintln!("[nagomi-headless] {message}");
+    }
+
+    fn session_output(&self, session_id: &str, _stream: &str, chunk: &str) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if let Some(buffer) = buffers.get_mut(session_id) {
+                buffer.push_output(chunk);
+            }
+        }
+    }
+
+    fn session_exit(&self, session_id: &str, exit_code: i32) {
+        self.set_state(session_id, StateTrigger::Exit(exit_code));
+        eprintln!("[nagomi-headless] session exited: {session_id} (exit code {exit_code})");
+    }
+
+    fn session_error(&self, session_id: &str, message: &str, recoverable: bool) {
+        eprintln!("[nagomi-headless] worker error: {session_id} {message}");
+        if !recoverable {
+            self.set_state(session_id, StateTrigger::WorkerFailed);
+        }
+    }
+
+    fn hook_state(&self, payload: &HookStatePayload) {
+        // terminal に届いた hook はその session の状態になる / A routed hook becomes that session's state.
+        if let Some(session_id) = payload.session_id.as_deref() {
+            if let Ok(mut buffers) = self.buffers.lock() {
+                if let Some(buffer) = buffers.get_mut(session_id) {
+                    buffer.settled_by_hook = true;
+                }
+            }
+            let _ = self
+                .observed
+                .apply(session_id, StateTrigger::Hook(payload.event_kind), self);
+        }
+        self.notify(
+            &payload.source,
+            payload.summary.as_deref().unwrap_or_default(),
+            &payload.state,
+        );
+    }
+}
+
+impl ControlBackend for Headless {
+    fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.session_ids()
+            .iter()
+            .map(|id| self.registry.session_info(id, &self.observed))
+            .collect()
+    }
+
+    fn open_session(&self, request: OpenSessionRequest) -> Result<String, ControlError> {
+        self.open(request)
+    }
+
+    fn send_input(&self, session_id: &str, text: &str) -> Result<(), ControlError> {
+        self.running_session(session_id)?;
+        self.registry
+            .send_input(session_id, text)
+            .map_err(ControlError::internal)?;
+        // 入力したら判定をやり直す / Each input starts a fresh judgement.
+        if let Ok(mut buffers) = self.buffers.lock() {
+            buffers.insert(session_id.to_string(), JudgeBuffer::default());
+        }
+        if self
+            .observed
+            .get(session_id)
+            .is_some_and(|state| state != TerminalState::Idle)
+        {
+            self.set_state(session_id, StateTrigger::Input);
+        }
+        Ok(())
+    }
+
+    fn resize(&self, session_id: &str, cols: u16, rows: u16) -> Result<(), ControlError> {
+        self.running_session(session_id)?;
+        self.registry
+            .resize(session_id, cols, rows)
+            .map_err(ControlError::internal)
+    }
+
+    // 終了済みの session も stop で一覧から消える / Stopping also drops exited sessions from the list.
+    fn stop(&self, session_id: &str) -> Result<(), ControlError> {
+        if !self.is_known(session_id) {
+            return Ok(());
+        }
+        self.registry
+            .stop(session_id, "control-api", self)
+            .map_err(ControlError::internal)?;
+        if let Ok(mut buffers) = self.buffers.lock() {
+            buffers.remove(session_id);
+        }
+        self.observed.forget(session_id, self);
+        Ok(())
+    }
+
+    fn tail(&self, session_id: &str) -> Result<String, ControlError> {
+        if !self.is_known(session_id) {
+            return Err(ControlError::not_found(format!(
+                "session not found: {session_id}"
+            )));
+        }
+        Ok(self.registry.tail(session_id).unwrap_or_default())
+    }
+
+    fn hook_source_enabled(&self, source: &str) -> bool {
+        let base_dir = hooks_base_dir();
+        self.hooks
+            .lock()
+            .is_ok_and(|mut hooks| hooks.accept(source, &base_dir))
+    }
+
+    fn ingest_hook(&self, event: HookEvent) -> Result<(), ControlError> {
+        self.handle_hook_event(event);
+        Ok(())
+    }
+}
+
+// judge 用に制御シーケンスと CR を落とす / Strip control sequences and CRs before judging.
+fn strip_ansi(line: &str) -> String {
+    strip_ansi_control_sequences(line).replace('\r', "")
+}
@@ -2,18 +2,52 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// 子プロセス環境の作り方 / How the child process environment is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvMode {
+    /// worker の環境をそのまま渡す（`env`/`env_unset` は指定不可）/ Pass the worker environment through untouched.
+    Inherit,
+    /// 空の環境に `env` だけを設定する（`PATH` / `HOME` / `USERPROFILE` / `SystemRoot` は残す）
+    /// Start from an empty environment and apply `env` only, keeping `PATH`, `HOME`, `USERPROFILE` and `SystemRoot`.
+    Clean,
+    /// 継承した環境に `env` を重ね、`env_unset` を削除する / Overlay `env` on the inherited environment and drop `env_unset`.
+    Merge,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct StartSession {
     pub session_id: String,
+    /// 1 行のコマンド文字列（shlex 分割）/ Single command line, split with shlex.
+    #[serde(default)]
     pub cmd: String,
+    /// 分割済みの引数列。指定時は `cmd` を空にする / Exact argument vector; `cmd` must be empty when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv: Option<Vec<String>>,
+    /// シェルをログインシェルとして起動する / Launch the program as a login shell.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub login_shell: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cwd: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env: Option<HashMap<String, String>>,
+    /// 未指定時は `merge` と同じ / Defaults to `merge` when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_mode: Option<EnvMode>,
+    /// `merge` 時に削除する変数名 / Variable names removed in `merge` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_unset: Option<Vec<String>>,
     pub cols: u16,
     pub rows: u16,
 }
 
+/// `login_shell` で `-l` を付けて起動できるシェル / Shells that accept `-l` when `login_shell` is set.
+pub const LOGIN_SHELL_PROGRAMS: &[&str] = &["sh", "bash", "zsh", "fish", "nu", "dash", "ksh", "mksh"];
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SendInput {
     pub session_id: String,
@@ -32,6 +66,15 @@ pub struct StopSession {
     pub session_id: String,
 }
 
+/// PTY 上で子プロセスを起動できた / The child process was spawned on the PTY.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionStarted {
+    pub session_id: String,
+    /// OS が pid を返さない環境では省略 / Omitted when the platform cannot report a pid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Output {
     pub session_id: String,
@@ -58,6 +101,7 @@ pub enum Message {
     SendInput(SendInput),
     Resize(Resize),
     StopSession(StopSession),
+    SessionStarted(SessionStarted),
     Output(Output),
     Exit(Exit),
     Error(ErrorMessage),
@@ -97,6 +141,9 @@ pub fn parse_line(line: &str) -> Message {
         "stop_session" => serde_json::from_value::<StopSession>(value.clone())
             .map(Message::StopSession)
             .unwrap_or(Message::Unknown(value)),
+        "session_started" => serde_json::from_value::<SessionStarted>(value.clone())
+            .map(Message::SessionStarted)
+            .unwrap_or(Message::Unknown(value)),
         "output" => serde_json::from_value::<Output>(value.clone())
             .map(Message::Output)
             .unwrap_or(Message::Unknown(value)),
@@ -116,6 +163,7 @@ pub fn serialize_message(message: &Message) -> String {
         Message::SendInput(message) => with_type(serde_json::to_value(message).unwrap(), "send_input"),
         Message::Resize(message) => with_type(serde_json::to_value(message).unwrap(), "resize"),
         Message::StopSession(message) => with_type(serde_json::to_value(message).unwrap(), "stop_session"),
+        Message::SessionStarted(message) => with_type(serde_json::to_value(message).unwrap(), "session_started"),
         Message::Output(message) => with_type(serde_json::to_value(message).unwrap(), "output"),
         Message::Exit(message) => with_type(serde_json::to_value(message).unwrap(), "exit"),
         Message::Error(message) => with_type(serde_json::to_value(message).unwrap(), "error"),
@@ -163,6 +211,10 @@ mod tests {
                     let expected: StopSession = serde_json::from_value(value).unwrap();
                     assert_eq!(parsed, Message::StopSession(expected));
                 }
+                "session_started" => {
+                    let expected: SessionStarted = serde_json::from_value(value).unwrap();
+                    assert_eq!(parsed, Message::SessionStarted(expected));
+                }
                 "output" => {
                     let expected: Output = serde_json::from_value(value).unwrap();
                     assert_eq!(parsed, Message::Output(expected));
@@ -186,10 +238,20 @@ mod tests {
             Message::StartSession(StartSession {
                 session_id: "session".to_string(),
                 cmd: "cmd.exe".to_string(),
-                cwd: None,
-                env: None,
                 cols: 120,
                 rows: 30,
+                ..Default::default()
+            }),
+            Message::StartSession(StartSession {
+                session_id: "session".to_string(),
+                argv: Some(vec!["bash".to_string(), "-c".to_string(), "echo 'a b'".to_string()]),
+                login_shell: true,
+                env: Some(HashMap::from([("KEY".to_string(), "VALUE".to_string())])),
+                env_mode: Some(EnvMode::Merge),
+                env_unset: Some(vec!["SECRET".to_string()]),
+                cols: 80,
+                rows: 24,
+                ..Default::default()
             }),
             Message::SendInput(SendInput {
                 session_id: "session".to_string(),
@@ -203,6 +265,14 @@ mod tests {
             Message::StopSession(StopSession {
                 session_id: "session".to_string(),
             }),
+            Message::SessionStarted(SessionStarted {
+                session_id: "session".to_string(),
+                pid: Some(4242),
+            }),
+            Message::SessionStarted(SessionStarted {
+                session_id: "session".to_string(),
+                pid: None,
+            }),
             Message::Output(Output {
                 session_id: "session".to_string(),
                 stream: "stdout".to_string(),
@@ -226,6 +296,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn start_session_new_fields_are_optional() {
+        let line = r#"{"type":"start_session","session_id":"s","cmd":"sh","cols":80,"rows":24}"#;
+        match parse_line(line) {
+            Message::StartSession(message) => {
+                assert_eq!(message.argv, None);
+                assert!(!message.login_shell);
+                assert_eq!(message.env_mode, None);
+                assert_eq!(message.env_unset, None);
+            }
+            other => panic!("expected start_session, got {other:?}"),
+        }
+
+        let serialized = serialize_message(&parse_line(line));
+        assert!(!serialized.contains("argv"));
+        assert!(!serialized.contains("login_shell"));
+        assert!(!serialized.contains("env_mode"));
+    }
+
     #[test]
     fn unknown_message_type() {
         let line = r#"{"type":"mystery","value":1}"#;
@@ -0,0 +1,399 @@
+// `nagomi attach <id>`: 動いている terminal session を今の端末に映す
+// `nagomi attach <id>`: mirror a live terminal session into the current terminal.
+//
+// 制御 API だけで組み立てる。直近出力を再生 → `/events` の output を流し、
+// キー入力は `/send`、端末サイズの変化は `/resize` へ送る。GUI の window と同時に見てよい。
+// 送信中に溜まったキー入力は次の `/send` 1 回にまとめる。
+// Built purely on the control API: replay the tail, stream `/events` output, forward keys to
+// `/send` and size changes to `/resize`. The GUI window can stay open alongside. Keys typed
+// while a `/send` is in flight go out together in the next one.
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::sessions::{parse_body, session_path, EventStream};
+use crate::{call_json, is_healthy, url_encode};
+
+// Ctrl+] で切り離す（telnet と同じ）/ Ctrl+] detaches, as in telnet.
+const DETACH_BYTE: u8 = 0x1d;
+// orchestrator が保持する直近出力の上限と同じ / Same as the orchestrator's tail capacity.
+const REPLAY_BYTES: usize = 256 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const RESIZE_POLL: Duration = Duration::from_millis(250);
+
+enum AttachEnd {
+    Detached,
+    Closed(Option<i64>),
+    Failed(anyhow::Error),
+}
+
+fn send_text(port: u16, id: &str, text: &str) -> Result<()> {
+    let payload = json!({ "text": text }).to_string();
+    call_json(
+        port,
+        "POST",
+        &session_path(id, "/send"),
+        Some(&payload),
+        REQUEST_TIMEOUT,
+    )?;
+    Ok(())
+}
+
+fn send_resize(port: u16, id: &str, (cols, rows): (u16, u16)) -> Result<()> {
+    let payload = json!({ "cols": cols, "rows": rows }).to_string();
+    call_json(
+        port,
+        "POST",
+        &session_path(id, "/resize"),
+        Some(&payload),
+        REQUEST_TIMEOUT,
+    )?;
+    Ok(())
+}
+
+// 文字の途中で切れたバイトは次回に回す / Hold back a trailing partial UTF-8 sequence.
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(_) => pending.len(),
+    };
+    let rest = pending.split_off(valid);
+    let text = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    text
+}
+
+// 溜まった stdin の読み取りを 1 回の `/send` 分にまとめる。Ctrl+] と EOF で終わり、それ以降は捨てる
+// Folds queued stdin reads into one `/send` payload. Ctrl+] or EOF ends it; later reads are dropped.
+fn batch_input(
+    pending: &mut Vec<u8>,
+    reads: impl IntoIterator<Item = std::io::Result<Vec<u8>>>,
+) -> (String, Option<AttachEnd>) {
+    let mut end = None;
+    for read in reads {
+        match read {
+            Ok(bytes) if bytes.is_empty() => end = Some(AttachEnd::Detached),
+            Ok(bytes) => {
+                let detach_at = bytes.iter().position(|byte| *byte == DETACH_BYTE);
+                pending.extend_from_slice(&bytes[..detach_at.unwrap_or(bytes.len())]);
+                if detach_at.is_some() {
+                    end = Some(AttachEnd::Detached);
+                }
+            }
+            Err(err) => end = Some(AttachEnd::Failed(err.into())),
+        }
+        if end.is_some() {
+            break;
+        }
+    }
+    (take_utf8(pending), end)
+}
+
+pub fn run(port: u16, args: &[String]) -> Result<i32> {
+    let mut id: Option<String> = None;
+    let mut resize = true;
+    for arg in args {
+        match arg.as_str() {
+            "--no-resize" => resize = false,
+            _ if id.is_none() => id = Some(arg.clone()),
+            _ => bail!("unexpected argument: {arg}"),
+        }
+    }
+    let Some(id) = id.filter(|id| !id.trim().is_empty()) else {
+        bail!("usage: nagomi attach <id> [--no-resize]");
+    };
+    if !is_healthy(port) {
+        bail!("orchestrator is not running");
+    }
+    let session = parse_body(&call_json(
+        port,
+        "GET",
+        &session_path(&id, ""),
+        None,
+        REQUEST_TIMEOUT,
+    )?)?;
+    if session["session"]["running"] != true {
+        bail!("session is not running: {id}");
+    }
+
+    // 先に購読してから再生するので、その間の出力を取りこぼさない
+    // Subscribe before replaying so nothing emitted in between is lost.
+    let query = format!("session_id={}&types=output,session-closed", url_encode(&id));
+    let mut events = EventStream::open(port, &query)?;
+    let tail = parse_body(&call_json(
+        port,
+        "GET",
+        &format!("{}?bytes={REPLAY_BYTES}", session_path(&id, "/tail")),
+        None,
+        REQUEST_TIMEOUT,
+    )?)?;
+
+    eprintln!("[nagomi] attached to {id} (detach: Ctrl+])");
+    let raw = term::RawMode::enable()?;
+    let mut last_size = term::size();
+    if resize {
+        if let Some(size) = last_size {
+            send_resize(port, &id, size)?;
+        }
+    }
+    {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(b"\x1b[H\x1b[2J")?;
+        stdout.write_all(tail["data"].as_str().unwrap_or_default().as_bytes())?;
+        stdout.flush()?;
+    }
+
+    let (tx, rx) = mpsc::channel::<AttachEnd>();
+    let output_tx = tx.clone();
+    thread::spawn(move || {
+        let mut stdout = std::io::stdout();
+        loop {
+            let event = match events.next(None) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(err) => {
+                    let _ = output_tx.send(AttachEnd::Failed(err));
+                    return;
+                }
+            };
+            if event["type"] == "session-closed" {
+                let _ = output_tx.send(AttachEnd::Closed(event["exit_code"].as_i64()));
+                return;
+            }
+            let data = event["data"].as_str().unwrap_or_default();
+            if stdout.write_all(data.as_bytes()).and_then(|_| stdout.flush()).is_err() {
+                let _ = output_tx.send(AttachEnd::Detached);
+                return;
+            }
+        }
+    });
+
+    // stdin は読むだけの thread、送信は別 thread。送信中に届いた分は次の 1 回にまとめる
+    // One thread only reads stdin; another sends, folding whatever queued up meanwhile.
+    let (input_tx, input_rx) = mpsc::channel::<std::io::Result<Vec<u8>>>();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = stdin.read(&mut buf).map(|read| buf[..read].to_vec());
+            let done = !matches!(&read, Ok(bytes) if !bytes.is_empty());
+            if input_tx.send(read).is_err() || done {
+                return;
+            }
+        }
+    });
+    let input_id = id.clone();
+    thread::spawn(move || {
+        let mut pending: Vec<u8> = Vec::new();
+        while let Ok(first) = input_rx.recv() {
+            let reads = std::iter::once(first).chain(input_rx.try_iter());
+            let (text, end) = batch_input(&mut pending, reads);
+            if !text.is_empty() {
+                if let Err(err) = send_text(port, &input_id, &text) {
+                    let _ = tx.send(AttachEnd::Failed(err));
+                    return;
+                }
+            }
+            if let Some(end) = end {
+                let _ = tx.send(end);
+                return;
+            }
+        }
+    });
+
+    // 端末サイズは定期的に見て、変わったときだけ送る / Poll the terminal size and send only changes.
+    let end = loop {
+        match rx.recv_timeout(RESIZE_POLL) {
+            Ok(end) => break end,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break AttachEnd::Detached,
+        }
+        let size = term::size();
+        if resize && size.is_some() && size != last_size {
+            last_size = size;
+            if let Some(size) = size {
+                let _ = send_resize(port, &id, size);
+            }
+        }
+    };
+    drop(raw);
+    match end {
+        AttachEnd::Detached => {
+            eprintln!("\r\n[nagomi] detached from {id}");
+            Ok(0)
+        }
+        AttachEnd::Closed(code) => {
+            eprintln!(
+                "\r\n[nagomi] session {id} closed (exit code {})",
+                code.map(|code| code.to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            );
+            Ok(0)
+        }
+        AttachEnd::Failed(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+mod term {
+    use anyhow::{bail, Result};
+
+    // drop で元の termios に戻す / Restores the original termios on drop.
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        pub fn enable() -> Result<Self> {
+            unsafe {
+                let mut termios: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                    bail!("stdin is not a terminal");
+                }
+                let original = termios;
+                libc::cfmakeraw(&mut termios);
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                    bail!("failed to enter raw mode");
+                }
+                Ok(Self { original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+
+    // (cols, rows)
+    pub fn size() -> Option<(u16, u16)> {
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 {
+                return None;
+            }
+            (size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col, size.ws_row))
+        }
+    }
+}
+
+#[cfg(windows)]
+mod term {
+    use anyhow::{bail, Result};
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, SetConsoleMode,
+        CONSOLE_SCREEN_BUFFER_INFO, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+        ENABLE_VIRTUAL_TERMINAL_INPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_INPUT_HANDLE,
+        STD_OUTPUT_HANDLE,
+    };
+
+    // drop で元の console mode に戻す / Restores the original console modes on drop.
+    pub struct RawMode {
+        input_mode: u32,
+        output_mode: u32,
+    }
+
+    impl RawMode {
+        pub fn enable() -> Result<Self> {
+            unsafe {
+                let input = GetStdHandle(STD_INPUT_HANDLE);
+                let output = GetStdHandle(STD_OUTPUT_HANDLE);
+                let mut input_mode = 0u32;
+                let mut output_mode = 0u32;
+                if GetConsoleMode(input, &mut input_mode) == 0
+                    || GetConsoleMode(output, &mut output_mode) == 0
+                {
+                    bail!("stdin is not a console");
+                }
+                let raw_input = (input_mode
+                    & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT))
+                    | ENABLE_VIRTUAL_TERMINAL_INPUT;
+                if SetConsoleMode(input, raw_input) == 0
+                    || SetConsoleMode(output, output_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) == 0
+                {
+                    bail!("failed to enter raw mode");
+                }
+                Ok(Self {
+                    input_mode,
+                    output_mode,
+                })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.input_mode);
+                SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), self.output_mode);
+            }
+        }
+    }
+
+    // (cols, rows)
+    pub fn size() -> Option<(u16, u16)> {
+        unsafe {
+            let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+            if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) == 0 {
+                return None;
+            }
+            let cols = (info.srWindow.Right - info.srWindow.Left + 1).max(0) as u16;
+            let rows = (info.srWindow.Bottom - info.srWindow.Top + 1).max(0) as u16;
+            (cols > 0 && rows > 0).then_some((cols, rows))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_utf8_holds_back_a_split_character() {
+        let bytes = "aあ".as_bytes();
+        let mut pending = bytes[..2].to_vec();
+        assert_eq!(take_utf8(&mut pending), "a");
+        assert_eq!(pending, bytes[1..2]);
+        pending.extend_from_slice(&bytes[2..]);
+        assert_eq!(take_utf8(&mut pending), "あ");
+        assert!(pending.is_empty());
+        // 壊れたバイトは抱え込まず置き換えて流す / Invalid bytes are replaced, not held forever.
+        let mut pending = vec![b'x', 0xff, b'y'];
+        assert_eq!(take_utf8(&mut pending), "x\u{fffd}y");
+    }
+
+    #[test]
+    fn batch_input_joins_reads_and_stops_at_the_detach_key() {
+        let mut pending = Vec::new();
+        let bytes = "é".as_bytes();
+        let (text, end) = batch_input(
+            &mut pending,
+            [Ok(b"ls".to_vec()), Ok(vec![b'\r', bytes[0]])],
+        );
+        assert_eq!(text, "ls\r");
+        assert!(end.is_none());
+        let (text, end) = batch_input(
+            &mut pending,
+            [
+                Ok(vec![bytes[1], b'q', DETACH_BYTE, b'z']),
+                Ok(b"dropped".to_vec()),
+            ],
+        );
+        assert_eq!(text, "éq");
+        assert!(matches!(end, Some(AttachEnd::Detached)));
+
+        let (text, end) = batch_input(&mut Vec::new(), [Ok(b"x".to_vec()), Ok(Vec::new())]);
+        assert_eq!(text, "x");
+        assert!(matches!(end, Some(AttachEnd::Detached)));
+        let failed = std::io::Error::other("closed");
+        let (_, end) = batch_input(&mut Vec::new(), [Err(failed)]);
+        assert!(matches!(end, Some(AttachEnd::Failed(_))));
+    }
+}
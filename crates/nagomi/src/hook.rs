@@ -0,0 +1,288 @@
+// `nagomi hook emit --source <tool> [<json>]`: agent の hook から呼ばれ、event を Orchestrator へ届ける
+// Called from agent hooks; hands one event to the orchestrator.
+// `nagomi hook install|uninstall --tool <tool>`: agent 側の設定へ emit を登録/削除する
+// Adds or removes emit in the agent's own config, printing the diff first.
+//
+// Orchestrator につながらなければ `~/.nagomi/hooks/<source>.jsonl` に追記し、次の起動時に取り込ませる。
+// 応答が返ってきたのに断られた（トークン違い・無効な source など）ときは追記せずエラーにする。
+// When the orchestrator cannot be reached the event is appended to
+// `~/.nagomi/hooks/<source>.jsonl` and picked up on its next start. A response that refuses the
+// event (bad token, disabled source, ...) is an error and is never queued for replay.
+use anyhow::{bail, Context, Result};
+use nagomi_core::completion_hook::{
+    append_hook_payload, hook_payload, hooks_base_dir, load_hook_mappings, normalize_hook_source,
+    now_ms, parse_hook_input, resolve_hook_source, HOOK_SOURCES,
+};
+use nagomi_core::hook_setup::{
+    hook_install_status, plan_hook_install, resolve_cli_path, HookInstallAction,
+};
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{http_request, parse_http_response, url_encode};
+
+// agent の応答を待たせないよう短く / Kept short so the agent is not held up.
+const POST_TIMEOUT: Duration = Duration::from_millis(800);
+
+fn print_usage() {
+    println!("usage: nagomi hook emit --source <source> [<json>]");
+    println!("  <source>: codex, claude, opencode, gemini, aider or a mapping in ~/.nagomi/hooks/sources");
+    println!("  The event JSON is read from the last argument, or from stdin when omitted.");
+    println!("usage: nagomi hook install|uninstall --tool codex|claude|opencode [--project <dir>] [--dry-run]");
+    println!(
+        "  Prints the config diff, backs the old file up as <name>.nagomi.bak (once), then writes it."
+    );
+    println!("usage: nagomi hook sources");
+    println!("  Lists the built-in sources and checks the mapping files.");
+}
+
+pub fn run(port: u16, args: &[String]) -> Result<i32> {
+    match args.first().map(String::as_str) {
+        Some("emit") => {
+            // agent 側を止めないよう失敗しても stderr に出すだけ
+            // Failures are only reported on stderr so the agent keeps going.
+            if let Err(err) = emit(port, &args[1..]) {
+                eprintln!("nagomi hook: {err:#}");
+                return Ok(1);
+            }
+            Ok(0)
+        }
+        Some("install") => install(HookInstallAction::Install, &args[1..]),
+        Some("uninstall") => install(HookInstallAction::Uninstall, &args[1..]),
+        Some("sources") => Ok(list_sources()),
+        _ => {
+            print_usage();
+            Ok(2)
+        }
+    }
+}
+
+// マッピングファイルの誤りもここで見つけられるようにする / Also surfaces broken mapping files.
+fn list_sources() -> i32 {
+    for source in HOOK_SOURCES {
+        println!("{source}\tbuilt-in");
+    }
+    let base_dir = hooks_base_dir();
+    let mut broken = false;
+    for mapping in load_hook_mappings(&base_dir) {
+        match mapping {
+            Ok(mapping) => println!("{}\tmapping ({})", mapping.name, mapping.event_field),
+            Err(err) => {
+                broken = true;
+                eprintln!("nagomi hook: {err}");
+            }
+        }
+    }
+    i32::from(broken)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct InstallArgs {
+    tool: &'static str,
+    project: Option<PathBuf>,
+    dry_run: bool,
+}
+
+fn parse_install_args(args: &[String]) -> Result<InstallArgs> {
+    let mut tool = None;
+    let mut project: Option<PathBuf> = None;
+    let mut dry_run = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--tool" | "-t" => tool = Some(iter.next().context("--tool needs a value")?),
+            "--project" | "-p" => {
+                project = Some(PathBuf::from(iter.next().context("--project needs a dir")?))
+            }
+            "--dry-run" | "-n" => dry_run = true,
+            other => bail!("unknown option: {other}"),
+        }
+    }
+    let raw = tool.context("--tool is required")?;
+    let Some(tool) = normalize_hook_source(raw) else {
+        bail!("unknown hook tool: {raw}");
+    };
+    Ok(InstallArgs {
+        tool,
+        project,
+        dry_run,
+    })
+}
+
+fn install(action: HookInstallAction, args: &[String]) -> Result<i32> {
+    let InstallArgs {
+        tool,
+        project,
+        dry_run,
+    } = parse_install_args(args)?;
+    let cli_path = resolve_cli_path();
+    let edit = match plan_hook_install(tool, project.as_deref(), &cli_path, action) {
+        Ok(edit) => edit,
+        Err(err) => {
+            eprintln!("nagomi hook: {err}");
+            return Ok(1);
+        }
+    };
+    let status = hook_install_status(&edit, action);
+    if edit.is_noop() {
+        println!("{status}: {}", edit.path.display());
+        return Ok(0);
+    }
+    print!("{}", edit.diff());
+    if dry_run {
+        println!("(dry run) would write {}", edit.path.display());
+        return Ok(0);
+    }
+    match edit.apply() {
+        Ok(Some(backup)) => println!(
+            "{status}: {} (backup: {})",
+            edit.path.display(),
+            backup.display()
+        ),
+        Ok(None) => println!("{status}: {}", edit.path.display()),
+        Err(err) => {
+            eprintln!("nagomi hook: {}: {err}", edit.path.display());
+            return Ok(1);
+        }
+    }
+    Ok(0)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct EmitArgs {
+    source: String,
+    event: Option<String>,
+}
+
+fn parse_emit_args(args: &[String]) -> Result<EmitArgs> {
+    let mut source = None;
+    let mut event = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--source" | "-s" => source = Some(iter.next().context("--source needs a tool")?),
+            // codex は JSON をコマンド配列の末尾に付ける / Codex appends the JSON as the last argument.
+            _ => event = Some(arg.clone()),
+        }
+    }
+    Ok(EmitArgs {
+        source: source.context("--source is required")?.clone(),
+        event,
+    })
+}
+
+// POST の結果から次にすることを決める / What to do after the POST.
+#[derive(Debug, PartialEq, Eq)]
+enum EmitOutcome {
+    Delivered,
+    // つながらない・時間切れ・応答が読めない / No connection, a timeout or no readable response.
+    Fallback,
+    Rejected(u16, String),
+}
+
+fn emit_outcome(response: Result<String>) -> EmitOutcome {
+    let Ok(raw) = response else {
+        return EmitOutcome::Fallback;
+    };
+    match parse_http_response(&raw) {
+        (200, _) => EmitOutcome::Delivered,
+        (0, _) => EmitOutcome::Fallback,
+        (status, body) => EmitOutcome::Rejected(status, body.trim().to_string()),
+    }
+}
+
+fn emit(port: u16, args: &[String]) -> Result<()> {
+    let args = parse_emit_args(args)?;
+    let raw = args.source.as_str();
+    // マッピングファイルの source も使える / Mapped sources work too.
+    let base_dir = hooks_base_dir();
+    let Some(source) = resolve_hook_source(raw, &base_dir) else {
+        bail!("unknown hook source: {raw}");
+    };
+    let source = source.name();
+    let text = match args.event {
+        Some(text) => text,
+        None if !std::io::stdin().is_terminal() => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .context("read hook event from stdin")?;
+            text
+        }
+        None => String::new(),
+    };
+    let event = parse_hook_input(&text).map_err(anyhow::Error::msg)?;
+    let session_id = std::env::var("NAGOMI_SESSION_ID").ok();
+    let payload = hook_payload(source, event, session_id.as_deref(), now_ms());
+    let body = payload.to_string();
+    let path = format!("/hooks/{}", url_encode(source));
+    let response = http_request("127.0.0.1", port, "POST", &path, Some(&body), POST_TIMEOUT);
+    match emit_outcome(response) {
+        EmitOutcome::Delivered => Ok(()),
+        EmitOutcome::Fallback => append_hook_payload(&base_dir, source, &payload)
+            .with_context(|| format!("append to {}", base_dir.display())),
+        EmitOutcome::Rejected(status, body) => bail!("{path} refused ({status}): {body}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn emit_args_take_the_last_argument_as_event() {
+        let parsed = parse_emit_args(&args(&["--source", "codex", "{\"a\":1}"])).expect("args");
+        assert_eq!(
+            parsed,
+            EmitArgs {
+                source: "codex".to_string(),
+                event: Some("{\"a\":1}".to_string()),
+            }
+        );
+        let parsed = parse_emit_args(&args(&["-s", "aider"])).expect("stdin event");
+        assert_eq!(parsed.event, None);
+        assert!(parse_emit_args(&args(&["{}"])).is_err());
+        assert!(parse_emit_args(&args(&["--source"])).is_err());
+    }
+
+    #[test]
+    fn install_args_normalize_the_tool() {
+        let parsed =
+            parse_install_args(&args(&["--tool", "claudecode", "-p", "proj", "-n"])).expect("args");
+        assert_eq!(
+            parsed,
+            InstallArgs {
+                tool: "claude",
+                project: Some(PathBuf::from("proj")),
+                dry_run: true,
+            }
+        );
+        assert!(parse_install_args(&args(&["--tool", "vim"])).is_err());
+        assert!(parse_install_args(&args(&["--tool", "codex", "--force"])).is_err());
+        assert!(parse_install_args(&args(&[])).is_err());
+    }
+
+    #[test]
+    fn only_unreachable_orchestrators_fall_back_to_the_file() {
+        let response = |status: &str| Ok(format!("HTTP/1.1 {status}\r\n\r\n{{\"error\":\"x\"}}"));
+        assert_eq!(emit_outcome(response("200 OK")), EmitOutcome::Delivered);
+        assert_eq!(
+            emit_outcome(Err(anyhow::anyhow!("connect 127.0.0.1:1"))),
+            EmitOutcome::Fallback
+        );
+        assert_eq!(emit_outcome(Ok(String::new())), EmitOutcome::Fallback);
+        // トークン違いや無効な source を溜めると、後で古い完了として再生されてしまう
+        // Queuing a bad token or disabled source would replay stale completions later.
+        for status in ["401 Unauthorized", "409 Conflict", "400 Bad Request"] {
+            assert!(
+                matches!(emit_outcome(response(status)), EmitOutcome::Rejected(code, _) if code >= 400),
+                "{status}"
+            );
+        }
+    }
+}
@@ -7,6 +7,10 @@ use std::time::{Duration, Instant};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
+mod attach;
+mod hook;
+mod sessions;
+
 fn env_u16(name: &str, default: u16) -> u16 {
     std::env::var(name)
         .ok()
@@ -14,19 +18,106 @@ fn env_u16(name: &str, default: u16) -> u16 {
         .unwrap_or(default)
 }
 
-fn http_get(host: &str, port: u16, path: &str, timeout: Duration) -> Result<String> {
+// 制御 API 用のトークン（未起動なら無い）/ Control API token (absent until the orchestrator has run).
+fn read_control_token() -> Option<String> {
+    let dir = nagomi_core::paths::app_config_dir()?;
+    let raw = std::fs::read_to_string(dir.join(nagomi_core::control::CONTROL_TOKEN_FILE)).ok()?;
+    let token = raw.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+// リクエストを送ったところで返す（応答は呼び出し側が読む）
+// Connect and send the request; the caller reads the response.
+fn send_request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<TcpStream> {
     let addr = format!("{host}:{port}");
     let mut stream = TcpStream::connect(&addr).with_context(|| format!("connect {addr}"))?;
     let _ = stream.set_read_timeout(Some(timeout));
     let _ = stream.set_write_timeout(Some(timeout));
-    let request = format!("GET {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    let auth = read_control_token()
+        .map(|token| format!("X-Nagomi-Token: {token}\r\n"))
+        .unwrap_or_default();
+    let body = body.unwrap_or_default();
+    let content = if body.is_empty() && method == "GET" {
+        String::new()
+    } else {
+        format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        )
+    };
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {host}\r\n{auth}{content}Connection: close\r\n\r\n{body}"
+    );
     stream.write_all(request.as_bytes())?;
     stream.flush()?;
+    Ok(stream)
+}
+
+fn http_request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<String> {
+    let mut stream = send_request(host, port, method, path, body, timeout)?;
     let mut buf = String::new();
     stream.read_to_string(&mut buf)?;
     Ok(buf)
 }
 
+fn http_get(host: &str, port: u16, path: &str, timeout: Duration) -> Result<String> {
+    http_request(host, port, "GET", path, None, timeout)
+}
+
+// クエリ値用の最小限のエンコード / Minimal percent-encoding for query values.
+fn url_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+// ステータス行と本文を分ける / Split the status code and body of a raw HTTP response.
+fn parse_http_response(raw: &str) -> (u16, &str) {
+    let status = raw
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(0);
+    let body = raw
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body)
+        .unwrap_or_default();
+    (status, body)
+}
+
+// 相対パスを現在のディレクトリ基準で絶対化 / Make a directory absolute against the current directory.
+fn resolve_cwd(dir: &str) -> Result<String> {
+    let path = std::env::current_dir()
+        .context("current directory")?
+        .join(dir);
+    if !path.is_dir() {
+        anyhow::bail!("not a directory: {}", path.display());
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
 fn is_healthy(port: u16) -> bool {
     http_get("127.0.0.1", port, "/health", Duration::from_millis(400))
         .map(|raw| raw.contains(r#""status":"ok""#))
@@ -106,35 +197,151 @@ fn wait_health(port: u16, timeout: Duration) -> Result<()> {
     anyhow::bail!("timeout waiting for orchestrator health on port {port}");
 }
 
+fn ensure_orchestrator(port: u16) -> Result<()> {
+    if !is_healthy(port) {
+        let orchestrator_path = resolve_orchestrator_path()
+            .or_else(|| Some(PathBuf::from(orchestrator_exe_name())))
+            .context("resolve orchestrator path")?;
+        spawn_orchestrator(&orchestrator_path)?;
+        wait_health(port, Duration::from_secs(8))?;
+    }
+    Ok(())
+}
+
+// orchestrator の JSON エンドポイントを呼ぶ / Call an orchestrator JSON endpoint.
+fn request_json(port: u16, path: &str, timeout: Duration) -> Result<String> {
+    call_json(port, "GET", path, None, timeout)
+}
+
+fn call_json(
+    port: u16,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<String> {
+    let raw = http_request("127.0.0.1", port, method, path, body, timeout)?;
+    let (status, body) = parse_http_response(&raw);
+    if status != 200 {
+        let route = path.split('?').next().unwrap_or(path);
+        anyhow::bail!("{route} failed ({status}): {}", body.trim());
+    }
+    Ok(body.trim().to_string())
+}
+
+// `nagomi workspace save|open|list` / Workspace subcommands.
+fn workspace_command(port: u16, args: &[String]) -> Result<()> {
+    let action = args.first().map(String::as_str).unwrap_or_default();
+    let name = args.get(1).map(|name| url_encode(name));
+    let path = match (action, name) {
+        ("list", _) => "/list-workspaces".to_string(),
+        ("save", Some(name)) => format!("/save-workspace?name={name}"),
+        ("open", Some(name)) => format!("/open-workspace?name={name}"),
+        _ => {
+            println!("usage: nagomi workspace save <name> | open <name> | list");
+            return Ok(());
+        }
+    };
+    // 保存は既存の window が必要なので起動はしない / Saving needs open windows, so don't start one.
+    if action == "save" && !is_healthy(port) {
+        anyhow::bail!("orchestrator is not running");
+    }
+    ensure_orchestrator(port)?;
+    // 保存は状態を書くので POST / Saving writes state, so it is a POST.
+    let method = if action == "save" { "POST" } else { "GET" };
+    let body = call_json(port, method, &path, None, Duration::from_secs(10))?;
+    println!("{body}");
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let port = env_u16("NAGOMI_ORCH_HEALTH_PORT", 17707);
 
+    let all_args: Vec<String> = std::env::args().skip(1).collect();
+    if all_args.first().map(String::as_str) == Some("workspace") {
+        return workspace_command(port, &all_args[1..]);
+    }
+    if all_args.first().map(String::as_str) == Some("hook") {
+        let code = hook::run(port, &all_args[1..])?;
+        std::process::exit(code);
+    }
+    if all_args.first().map(String::as_str) == Some("attach") {
+        let code = attach::run(port, &all_args[1..])?;
+        std::process::exit(code);
+    }
+    if let Some(command) = all_args
+        .first()
+        .filter(|command| sessions::COMMANDS.contains(&command.as_str()))
+    {
+        let code = sessions::run(port, command, &all_args[1..])?;
+        std::process::exit(code);
+    }
+
     let mut session_id: Option<String> = None;
-    let mut args = std::env::args().skip(1);
+    let mut workspace: Option<String> = None;
+    let mut profile: Option<String> = None;
+    let mut cwd: Option<String> = None;
+    let mut args = all_args.into_iter();
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "--session-id" => session_id = args.next(),
+            "--profile" | "-p" => profile = args.next(),
+            "--cwd" | "-C" => cwd = args.next(),
+            "--workspace" | "-w" => workspace = args.next(),
             "--help" | "-h" => {
                 println!("nagomi: start orchestrator and open a terminal window");
                 println!("  --session-id <id>  Open terminal with a fixed session id");
+                println!("  --profile <name>   Open terminal from a terminal profile in settings");
+                println!("  --cwd <dir>        Start in <dir> (default: current directory)");
+                println!("  --workspace <name> Restore a saved workspace instead of one terminal");
+                println!("  workspace save <name> | open <name> | list");
+                println!("  ls | send <id> <text> | tail <id> [-f] | stop <id>");
+                println!("  wait <id> --until success|failure|need-input [--timeout <secs>]");
+                println!("  (session commands accept --json)");
+                println!("  attach <id> [--no-resize]  Mirror a session here (detach: Ctrl+])");
+                println!("  hook emit --source <source> [<json>]");
+                println!("                     Deliver an agent hook event (JSON from argv or stdin)");
+                println!("  hook install|uninstall --tool codex|claude|opencode [--project <dir>] [--dry-run]");
+                println!("                     Add or remove nagomi's hook in the agent config");
+                println!("  hook sources       List hook sources, including mapping files");
                 return Ok(());
             }
             _ => {}
         }
     }
 
-    if !is_healthy(port) {
-        let orchestrator_path = resolve_orchestrator_path()
-            .or_else(|| Some(PathBuf::from(orchestrator_exe_name())))
-            .context("resolve orchestrator path")?;
-        spawn_orchestrator(&orchestrator_path)?;
-        wait_health(port, Duration::from_secs(8))?;
+    ensure_orchestrator(port)?;
+    if let Some(name) = workspace {
+        let path = format!("/open-workspace?name={}", url_encode(&name));
+        request_json(port, &path, Duration::from_secs(10))?;
+        return Ok(());
     }
 
-    let path = match session_id {
-        Some(id) => format!("/open-terminal?session_id={id}"),
-        None => "/open-terminal".to_string(),
+    // 明示がなければ現在のディレクトリ。profile 指定時は profile の cwd を優先
+    // Default to the current directory, unless a profile is given (its own cwd wins).
+    let cwd = match cwd {
+        Some(dir) => Some(resolve_cwd(&dir)?),
+        None if profile.is_none() => std::env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().to_string()),
+        None => None,
+    };
+
+    let mut query: Vec<String> = Vec::new();
+    if let Some(id) = session_id {
+        query.push(format!("session_id={}", url_encode(&id)));
+    }
+    if let Some(name) = profile {
+        query.push(format!("profile={}", url_encode(&name)));
+    }
+    if let Some(dir) = cwd {
+        query.push(format!("cwd={}", url_encode(&dir)));
+    }
+    let path = if query.is_empty() {
+        "/open-terminal".to_string()
+    } else {
+        format!("/open-terminal?{}", query.join("&"))
     };
-    let _ = http_get("127.0.0.1", port, &path, Duration::from_millis(800))?;
+    request_json(port, &path, Duration::from_millis(800))?;
     Ok(())
 }
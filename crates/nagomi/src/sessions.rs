@@ -0,0 +1,439 @@
+// `nagomi ls|send|tail|stop|wait`: 制御 API（`/sessions`・`/events`）を叩くサブコマンド
+// Session subcommands on top of the control API (`/sessions` and `/events`).
+//
+// どれも Orchestrator は起動しない（動いていなければエラー）。`--json` で機械向け出力。
+// None of these start the orchestrator; `--json` switches to machine-readable output.
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::{call_json, is_healthy, parse_http_response, send_request, url_encode};
+
+pub const COMMANDS: &[&str] = &["ls", "send", "tail", "stop", "wait"];
+
+// `wait` がタイムアウトしたときの終了コード（GNU timeout と同じ）
+// Exit code when `wait` times out (same as GNU timeout).
+const EXIT_TIMEOUT: i32 = 124;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// keepalive は 15 秒ごとに届くので、それより長く黙れば切れたとみなす
+// Keepalives arrive every 15s, so a longer silence means the stream is gone.
+const EVENT_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+
+#[derive(Debug, Default)]
+struct SessionArgs {
+    positional: Vec<String>,
+    json: bool,
+    follow: bool,
+    no_enter: bool,
+    lines: Option<usize>,
+    until: Vec<String>,
+    timeout: Option<Duration>,
+}
+
+fn parse_args(args: &[String]) -> Result<SessionArgs> {
+    let mut parsed = SessionArgs::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            "-f" | "--follow" => parsed.follow = true,
+            "--no-enter" => parsed.no_enter = true,
+            "-n" | "--lines" => {
+                let value = iter.next().context("--lines needs a number")?;
+                parsed.lines = Some(value.parse().context("--lines must be a number")?);
+            }
+            "--until" => {
+                let value = iter.next().context("--until needs a state")?;
+                for state in value.split(',').filter(|state| !state.trim().is_empty()) {
+                    parsed.until.push(wait_target(state)?.to_string());
+                }
+            }
+            "--timeout" => {
+                let value = iter.next().context("--timeout needs seconds")?;
+                let secs: f64 = value.parse().context("--timeout must be seconds")?;
+                // 負・NaN・桁あふれは from_secs_f64 だと panic するので弾く
+                // Negative, NaN and overflowing values would panic in from_secs_f64.
+                let Ok(timeout) = Duration::try_from_secs_f64(secs) else {
+                    bail!("--timeout must be a non-negative number of seconds");
+                };
+                parsed.timeout = Some(timeout);
+            }
+            "--" => parsed.positional.extend(iter.by_ref().cloned()),
+            _ => parsed.positional.push(arg.clone()),
+        }
+    }
+    Ok(parsed)
+}
+
+// `--until` の名前を観測状態に揃える。`idle` は入力直後にも現れ、コマンドの終わりを表さないので受けない
+// Map `--until` names onto observed states. `idle` is left out: input itself moves a session to
+// idle, so it cannot tell that a sent command has finished.
+fn wait_target(raw: &str) -> Result<&'static str> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "success" => Ok("success"),
+        "failure" | "fail" => Ok("fail"),
+        "need-input" => Ok("need-input"),
+        other => bail!("unknown --until state: {other} (success|failure|need-input)"),
+    }
+}
+
+fn session_id(args: &SessionArgs, usage: &str) -> Result<String> {
+    match args.positional.first() {
+        Some(id) if !id.trim().is_empty() => Ok(id.clone()),
+        _ => bail!("usage: {usage}"),
+    }
+}
+
+pub(crate) fn session_path(id: &str, action: &str) -> String {
+    format!("/sessions/{}{action}", url_encode(id))
+}
+
+pub(crate) fn parse_body(body: &str) -> Result<Value> {
+    serde_json::from_str(body).context("invalid response from orchestrator")
+}
+
+// 戻り値はプロセスの終了コード / Returns the process exit code.
+pub fn run(port: u16, command: &str, args: &[String]) -> Result<i32> {
+    let args = parse_args(args)?;
+    if !is_healthy(port) {
+        bail!("orchestrator is not running");
+    }
+    match command {
+        "ls" => list(port, &args),
+        "send" => send(port, &args),
+        "tail" => tail(port, &args),
+        "stop" => stop(port, &args),
+        "wait" => wait(port, &args),
+        _ => bail!("unknown command: {command}"),
+    }
+}
+
+fn list(port: u16, args: &SessionArgs) -> Result<i32> {
+    let body = parse_body(&call_json(port, "GET", "/sessions", None, REQUEST_TIMEOUT)?)?;
+    let sessions = body["sessions"].as_array().cloned().unwrap_or_default();
+    if args.json {
+        println!("{}", Value::Array(sessions));
+        return Ok(0);
+    }
+    let text = |value: &Value| value.as_str().unwrap_or("-").to_string();
+    let rows: Vec<[String; 5]> = sessions
+        .iter()
+        .map(|session| {
+            [
+                text(&session["session_id"]),
+                text(&session["state"]),
+                session["pid"]
+                    .as_u64()
+                    .map(|pid| pid.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                text(&session["cwd"]),
+                text(&session["title"]),
+            ]
+        })
+        .collect();
+    let header = ["SESSION", "STATE", "PID", "CWD", "TITLE"].map(str::to_string);
+    let mut widths = header.clone().map(|cell| cell.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    Ok(0)
+}
+
+fn send(port: u16, args: &SessionArgs) -> Result<i32> {
+    let usage = "nagomi send <id> <text> [--no-enter]";
+    let id = session_id(args, usage)?;
+    let text = args.positional[1..].join(" ");
+    if text.is_empty() && args.no_enter {
+        bail!("usage: {usage}");
+    }
+    let payload = json!({ "text": text, "enter": !args.no_enter }).to_string();
+    let path = session_path(&id, "/send");
+    let body = call_json(port, "POST", &path, Some(&payload), REQUEST_TIMEOUT)?;
+    if args.json {
+        println!("{body}");
+    }
+    Ok(0)
+}
+
+fn stop(port: u16, args: &SessionArgs) -> Result<i32> {
+    let id = session_id(args, "nagomi stop <id>")?;
+    let path = session_path(&id, "/stop");
+    let body = call_json(port, "POST", &path, None, REQUEST_TIMEOUT)?;
+    if args.json {
+        println!("{body}");
+    }
+    Ok(0)
+}
+
+fn tail(port: u16, args: &SessionArgs) -> Result<i32> {
+    let id = session_id(args, "nagomi tail <id> [-f] [-n <lines>]")?;
+    // 先に購読してから末尾を読むので、その間の出力を取りこぼさない
+    // Subscribe before reading the tail so nothing emitted in between is lost.
+    let mut events = if args.follow {
+        let query = format!("session_id={}&types=output,session-closed", url_encode(&id));
+        Some(EventStream::open(port, &query)?)
+    } else {
+        None
+    };
+    let mut path = session_path(&id, "/tail");
+    if let Some(lines) = args.lines {
+        path.push_str(&format!("?lines={lines}"));
+    }
+    let body = parse_body(&call_json(port, "GET", &path, None, REQUEST_TIMEOUT)?)?;
+    let data = body["data"].as_str().unwrap_or_default();
+    let mut stdout = std::io::stdout().lock();
+    if args.json {
+        writeln!(stdout, "{}", json!({ "session_id": id, "data": data }))?;
+    } else {
+        stdout.write_all(data.as_bytes())?;
+    }
+    stdout.flush()?;
+    let Some(events) = events.as_mut() else {
+        return Ok(0);
+    };
+    loop {
+        let Some(event) = events.next(None)? else {
+            continue;
+        };
+        if args.json {
+            writeln!(stdout, "{event}")?;
+        } else if event["type"] == "output" {
+            stdout.write_all(event["data"].as_str().unwrap_or_default().as_bytes())?;
+        }
+        stdout.flush()?;
+        if event["type"] == "session-closed" {
+            return Ok(0);
+        }
+    }
+}
+
+fn report_wait(args: &SessionArgs, id: &str, status: &str, state: &str) {
+    if args.json {
+        println!(
+            "{}",
+            json!({ "session_id": id, "status": status, "state": state })
+        );
+    } else {
+        println!("{id}: {status} ({state})");
+    }
+}
+
+fn wait(port: u16, args: &SessionArgs) -> Result<i32> {
+    let usage = "nagomi wait <id> --until success|failure|need-input [--timeout <secs>]";
+    let id = session_id(args, usage)?;
+    if args.until.is_empty() {
+        bail!("usage: {usage}");
+    }
+    // 遠すぎて表せない deadline は無期限と同じ / A deadline too far to represent means no deadline.
+    let deadline = args
+        .timeout
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+    let query = format!("session_id={}&types=state,session-closed", url_encode(&id));
+    let mut events = EventStream::open(port, &query)?;
+    let body = parse_body(&call_json(
+        port,
+        "GET",
+        &session_path(&id, ""),
+        None,
+        REQUEST_TIMEOUT,
+    )?)?;
+    let mut state = body["session"]["state"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    loop {
+        if args.until.contains(&state) {
+            report_wait(args, &id, "matched", &state);
+            return Ok(0);
+        }
+        let Some(event) = events.next(deadline)? else {
+            report_wait(args, &id, "timeout", &state);
+            return Ok(EXIT_TIMEOUT);
+        };
+        match event["type"].as_str() {
+            Some("state") => {
+                state = event["state"].as_str().unwrap_or_default().to_string();
+            }
+            Some("session-closed") => {
+                report_wait(args, &id, "closed", &state);
+                return Ok(1);
+            }
+            _ => {}
+        }
+    }
+}
+
+// `GET /events` の SSE を 1 イベントずつ読む / Read the `GET /events` SSE stream one event at a time.
+pub(crate) struct EventStream {
+    reader: BufReader<TcpStream>,
+    line: Vec<u8>,
+    parser: SseParser,
+}
+
+// SSE の行を組み立てて `data:` の JSON を 1 イベントずつ返す
+// Assembles SSE lines and yields each event's `data:` JSON.
+#[derive(Debug, Default)]
+struct SseParser {
+    data: String,
+}
+
+impl SseParser {
+    // 空行でイベントが閉じる。コメント（`:`）や `event:` は読み飛ばす
+    // A blank line ends an event; comments (`:`) and `event:` lines are skipped.
+    fn feed(&mut self, line: &str) -> Result<Option<Value>> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(data) = line.strip_prefix("data:") {
+            if !self.data.is_empty() {
+                self.data.push('\n');
+            }
+            self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+        } else if line.is_empty() && !self.data.is_empty() {
+            let data = std::mem::take(&mut self.data);
+            return Ok(Some(parse_body(&data)?));
+        }
+        Ok(None)
+    }
+}
+
+impl EventStream {
+    pub(crate) fn open(port: u16, query: &str) -> Result<Self> {
+        let path = format!("/events?{query}");
+        let stream = send_request("127.0.0.1", port, "GET", &path, None, REQUEST_TIMEOUT)?;
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            head.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let (status, _) = parse_http_response(&head);
+        if status != 200 {
+            let mut rest = String::new();
+            let _ = std::io::Read::read_to_string(&mut reader, &mut rest);
+            bail!("/events failed ({status}): {}", rest.trim());
+        }
+        Ok(Self {
+            reader,
+            line: Vec::new(),
+            parser: SseParser::default(),
+        })
+    }
+
+    // None は deadline 到達 / None means the deadline passed.
+    pub(crate) fn next(&mut self, deadline: Option<Instant>) -> Result<Option<Value>> {
+        let mut idle_since = Instant::now();
+        loop {
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Ok(None);
+            }
+            if now.duration_since(idle_since) >= EVENT_IDLE_TIMEOUT {
+                bail!("event stream stalled");
+            }
+            let wait = deadline
+                .map(|deadline| deadline.saturating_duration_since(now))
+                .unwrap_or(EVENT_IDLE_TIMEOUT)
+                .clamp(Duration::from_millis(10), EVENT_IDLE_TIMEOUT);
+            let _ = self.reader.get_ref().set_read_timeout(Some(wait));
+            // 途中で時間切れになっても読んだ分は self.line に残す
+            // Bytes read before a timeout stay in self.line for the next attempt.
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => bail!("event stream closed"),
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            if !self.line.ends_with(b"\n") {
+                continue;
+            }
+            idle_since = Instant::now();
+            let line = String::from_utf8_lossy(&self.line).to_string();
+            self.line.clear();
+            if let Some(event) = self.parser.feed(&line)? {
+                return Ok(Some(event));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args_reads_flags_and_positionals() {
+        let parsed = parse_args(&args(&[
+            "term-1",
+            "--until",
+            "success,fail",
+            "--timeout",
+            "1.5",
+            "--json",
+            "-n",
+            "20",
+            "--",
+            "--no-enter",
+        ]))
+        .expect("valid args");
+        assert_eq!(parsed.positional, vec!["term-1", "--no-enter"]);
+        assert_eq!(parsed.until, vec!["success", "fail"]);
+        assert_eq!(parsed.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(parsed.lines, Some(20));
+        assert!(parsed.json);
+        assert!(!parsed.no_enter);
+
+        for bad in ["-1", "NaN", "inf", "1e30", "soon"] {
+            assert!(parse_args(&args(&["--timeout", bad])).is_err(), "{bad}");
+        }
+        assert!(parse_args(&args(&["--lines"])).is_err());
+        assert!(parse_args(&args(&["--until", "done"])).is_err());
+    }
+
+    #[test]
+    fn wait_target_maps_names_and_rejects_idle() {
+        assert_eq!(wait_target("Failure").ok(), Some("fail"));
+        assert_eq!(wait_target(" need-input ").ok(), Some("need-input"));
+        // 入力だけで idle になるので、待つ対象にはならない / Input alone means idle, so it can't be waited on.
+        assert!(wait_target("idle").is_err());
+        assert!(wait_target("running").is_err());
+    }
+
+    #[test]
+    fn sse_parser_joins_data_lines_until_blank() {
+        let mut parser = SseParser::default();
+        assert_eq!(parser.feed(": keepalive\n").expect("comment"), None);
+        assert_eq!(parser.feed("\r\n").expect("empty event"), None);
+        assert_eq!(parser.feed("event: state\n").expect("event name"), None);
+        assert_eq!(parser.feed("data: {\"type\":\n").expect("first"), None);
+        assert_eq!(parser.feed("data:\"state\"}\r\n").expect("second"), None);
+        assert_eq!(
+            parser.feed("\n").expect("dispatch"),
+            Some(json!({ "type": "state" }))
+        );
+        assert_eq!(parser.feed("data: not json\n").expect("bad"), None);
+        assert!(parser.feed("\n").is_err());
+    }
+}
@@ -5,8 +5,9 @@ use std::sync::{mpsc, Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
-use nagomi_protocol::{parse_line, serialize_message, Message, Output};
+use nagomi_protocol::{parse_line, serialize_message, Message, Output, LOGIN_SHELL_PROGRAMS};
 
+#[cfg(test)]
 fn spawn_command_with_args(
     command: &str,
     args: &[&str],
@@ -15,30 +16,16 @@ fn spawn_command_with_args(
     cwd: Option<&str>,
     env: Option<&std::collections::HashMap<String, String>>,
 ) -> Result<(Box<dyn MasterPty + Send>, Box<dyn Child + Send + Sync>)> {
-    let pty_system = native_pty_system();
-    let pty_pair = pty_system.openpty(PtySize {
-        rows,
-        cols,
-        pixel_width: 0,
-        pixel_height: 0,
-    })?;
-
-    let mut cmd = CommandBuilder::new(command);
-    for arg in args {
-        cmd.arg(arg);
-    }
-    if let Some(path) = cwd {
-        cmd.cwd(path);
-    }
-    if let Some(env_map) = env {
-        for (key, value) in env_map {
-            cmd.env(key, value);
-        }
-    }
-
-    let child = pty_pair.slave.spawn_command(cmd)?;
-    drop(pty_pair.slave);
-    Ok((pty_pair.master, child))
+    let spec = LaunchSpec {
+        program: command.to_string(),
+        args: args.iter().map(ToString::to_string).collect(),
+        env_clear: false,
+        env_set: env
+            .map(|env| env.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default(),
+        env_remove: Vec::new(),
+    };
+    spawn_launch(&spec, cols, rows, cwd)
 }
 
 #[cfg(test)]
@@ -201,38 +188,175 @@ fn send_error(stdout_tx: &mpsc::Sender<String>, session_id: &str, message: &str)
     send_message(stdout_tx, &error)
 }
 
-fn spawn_from_cmd(
-    cmd: &str,
+// clean でも残す変数（コマンド探索とホームの解決に要る）/ Variables clean mode keeps, needed to find commands and resolve the home directory.
+const CLEAN_ENV_KEEP: &[&str] = &["PATH", "HOME", "USERPROFILE", "SystemRoot"];
+
+/// StartSession を検証して得る起動内容 / Validated launch parameters derived from StartSession.
+#[derive(Debug, PartialEq)]
+struct LaunchSpec {
+    program: String,
+    args: Vec<String>,
+    env_clear: bool,
+    env_set: Vec<(String, String)>,
+    env_remove: Vec<String>,
+}
+
+fn program_basename(program: &str) -> &str {
+    program
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(program)
+        .trim_end_matches(".exe")
+}
+
+fn resolve_launch(message: &nagomi_protocol::StartSession) -> Result<LaunchSpec> {
+    use nagomi_protocol::EnvMode;
+
+    // argv と cmd はどちらか一方のみ / Exactly one of argv and cmd may be used.
+    let parts = match &message.argv {
+        Some(argv) => {
+            if !message.cmd.trim().is_empty() {
+                bail!("cmd and argv are mutually exclusive; send argv with an empty cmd");
+            }
+            if argv.first().map(|program| program.is_empty()).unwrap_or(true) {
+                bail!("argv is empty or has an empty program");
+            }
+            argv.clone()
+        }
+        None => shlex::split(&message.cmd).unwrap_or_else(|| {
+            message
+                .cmd
+                .split_whitespace()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        }),
+    };
+    if parts.is_empty() {
+        bail!("cmd is empty");
+    }
+    let (program, args) = parts.split_first().expect("cmd parts");
+    let mut args = args.to_vec();
+
+    if message.login_shell {
+        if cfg!(windows) {
+            bail!("login_shell is not supported on Windows");
+        }
+        let name = program_basename(program);
+        if !LOGIN_SHELL_PROGRAMS.contains(&name) {
+            bail!(
+                "login_shell requires a shell program (one of {}), got {name}",
+                LOGIN_SHELL_PROGRAMS.join(", ")
+            );
+        }
+        if args.iter().any(|arg| arg == "-l" || arg == "--login") {
+            bail!("login_shell is set but args already contain -l/--login");
+        }
+        args.insert(0, "-l".to_string());
+    }
+
+    let mut env_set: Vec<(String, String)> = message
+        .env
+        .as_ref()
+        .map(|env| env.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default();
+    env_set.sort();
+    let env_remove = message.env_unset.clone().unwrap_or_default();
+    let mode = message.env_mode.unwrap_or(EnvMode::Merge);
+    match mode {
+        EnvMode::Inherit => {
+            if !env_set.is_empty() {
+                bail!("env_mode inherit does not accept env; use merge to add variables");
+            }
+            if !env_remove.is_empty() {
+                bail!("env_mode inherit does not accept env_unset; use merge to remove variables");
+            }
+        }
+        EnvMode::Clean => {
+            if !env_remove.is_empty() {
+                bail!("env_mode clean does not accept env_unset; the environment is already empty");
+            }
+            for key in CLEAN_ENV_KEEP {
+                if env_set.iter().any(|(set_key, _)| set_key == key) {
+                    continue;
+                }
+                if let Ok(value) = std::env::var(key) {
+                    env_set.push((key.to_string(), value));
+                }
+            }
+            env_set.sort();
+        }
+        EnvMode::Merge => {
+            if let Some(key) = env_remove
+                .iter()
+                .find(|key| env_set.iter().any(|(set_key, _)| set_key == *key))
+            {
+                bail!("env variable {key} is both set in env and listed in env_unset");
+            }
+        }
+    }
+
+    Ok(LaunchSpec {
+        program: program.clone(),
+        args,
+        env_clear: mode == EnvMode::Clean,
+        env_set,
+        env_remove,
+    })
+}
+
+fn spawn_launch(
+    spec: &LaunchSpec,
     cols: u16,
     rows: u16,
     cwd: Option<&str>,
-    env: Option<&std::collections::HashMap<String, String>>,
 ) -> Result<(Box<dyn MasterPty + Send>, Box<dyn Child + Send + Sync>)> {
-    let parts = shlex::split(cmd)
-        .unwrap_or_else(|| cmd.split_whitespace().map(ToString::to_string).collect::<Vec<_>>());
-    if parts.is_empty() {
-        bail!("cmd is empty");
+    let pty_system = native_pty_system();
+    let pty_pair = pty_system.openpty(PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+
+    let mut cmd = CommandBuilder::new(&spec.program);
+    cmd.args(&spec.args);
+    if let Some(path) = cwd {
+        cmd.cwd(path);
     }
-    let (program, args) = parts.split_first().expect("cmd parts");
-    let args_ref: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
-    spawn_command_with_args(program, &args_ref, cols, rows, cwd, env)
+    if spec.env_clear {
+        cmd.env_clear();
+    }
+    for (key, value) in &spec.env_set {
+        cmd.env(key, value);
+    }
+    for key in &spec.env_remove {
+        cmd.env_remove(key);
+    }
+
+    let child = pty_pair.slave.spawn_command(cmd)?;
+    drop(pty_pair.slave);
+    Ok((pty_pair.master, child))
 }
 
 fn start_session(
     message: &nagomi_protocol::StartSession,
     stdout_tx: &mpsc::Sender<String>,
 ) -> Result<WorkerSession> {
-    let (master, child) = spawn_from_cmd(
-        &message.cmd,
-        message.cols,
-        message.rows,
-        message.cwd.as_deref(),
-        message.env.as_ref(),
-    )?;
+    let spec = resolve_launch(message)?;
+    let (master, child) = spawn_launch(&spec, message.cols, message.rows, message.cwd.as_deref())?;
     let writer = master.take_writer()?;
+    let pid = child.process_id();
     let child = Arc::new(Mutex::new(child));
     let exit_sent = Arc::new(AtomicBool::new(false));
     let session_id = message.session_id.clone();
+    // 出力より先に pid を通知する / Report the pid before any output.
+    let _ = send_message(
+        stdout_tx,
+        &Message::SessionStarted(nagomi_protocol::SessionStarted {
+            session_id: session_id.clone(),
+            pid,
+        }),
+    );
 
     let reader = master.try_clone_reader()?;
     let stdout_clone = stdout_tx.clone();
@@ -707,10 +831,116 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn cleanup() {
         assert!(true);
     }
 
+    fn start_message() -> nagomi_protocol::StartSession {
+        nagomi_protocol::StartSession {
+            session_id: "session".to_string(),
+            cols: 80,
+            rows: 24,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn launch_argv_is_passed_verbatim() {
+        let mut message = start_message();
+        message.argv = Some(vec![
+            "printf".to_string(),
+            "%s|".to_string(),
+            "a  b".to_string(),
+            "\"q\"".to_string(),
+        ]);
+        let spec = resolve_launch(&message).expect("resolve");
+        assert_eq!(spec.program, "printf");
+        assert_eq!(spec.args, vec!["%s|", "a  b", "\"q\""]);
+    }
+
+    #[test]
+    fn launch_rejects_ambiguous_combinations() {
+        let mut both = start_message();
+        both.cmd = "sh".to_string();
+        both.argv = Some(vec!["bash".to_string()]);
+        assert!(resolve_launch(&both).unwrap_err().to_string().contains("mutually exclusive"));
+
+        let mut empty_argv = start_message();
+        empty_argv.argv = Some(Vec::new());
+        assert!(resolve_launch(&empty_argv).is_err());
+
+        let mut inherit_with_env = start_message();
+        inherit_with_env.cmd = "sh".to_string();
+        inherit_with_env.env_mode = Some(nagomi_protocol::EnvMode::Inherit);
+        inherit_with_env.env = Some([("A".to_string(), "1".to_string())].into());
+        assert!(resolve_launch(&inherit_with_env).unwrap_err().to_string().contains("inherit"));
+
+        let mut clean_with_unset = start_message();
+        clean_with_unset.cmd = "sh".to_string();
+        clean_with_unset.env_mode = Some(nagomi_protocol::EnvMode::Clean);
+        clean_with_unset.env_unset = Some(vec!["A".to_string()]);
+        assert!(resolve_launch(&clean_with_unset).unwrap_err().to_string().contains("clean"));
+
+        let mut set_and_unset = start_message();
+        set_and_unset.cmd = "sh".to_string();
+        set_and_unset.env = Some([("A".to_string(), "1".to_string())].into());
+        set_and_unset.env_unset = Some(vec!["A".to_string()]);
+        assert!(resolve_launch(&set_and_unset).unwrap_err().to_string().contains("both"));
+    }
+
+    #[test]
+    fn launch_login_shell_adds_flag() {
+        let mut message = start_message();
+        message.argv = Some(vec!["/bin/bash".to_string(), "-i".to_string()]);
+        message.login_shell = true;
+        if cfg!(windows) {
+            assert!(resolve_launch(&message).is_err());
+            return;
+        }
+        let spec = resolve_launch(&message).expect("resolve");
+        assert_eq!(spec.args, vec!["-l", "-i"]);
+
+        message.argv = Some(vec!["bash".to_string(), "--login".to_string()]);
+        assert!(resolve_launch(&message).is_err());
+
+        message.argv = Some(vec!["python3".to_string()]);
+        assert!(resolve_launch(&message).unwrap_err().to_string().contains("shell program"));
+    }
+
+    #[test]
+    fn launch_env_modes_apply() {
+        if cfg!(windows) {
+            return;
+        }
+        let _guard = conpty_lock().lock().expect("conpty lock");
+        std::env::set_var("NAGOMI_WORKER_TEST_INHERITED", "inherited");
+
+        let mut clean = start_message();
+        clean.argv = Some(vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            "echo \"[$NAGOMI_WORKER_TEST_INHERITED][$ONLY][${PATH:+path}]\"".to_string(),
+        ]);
+        clean.env_mode = Some(nagomi_protocol::EnvMode::Clean);
+        clean.env = Some([("ONLY".to_string(), "set".to_string())].into());
+        let spec = resolve_launch(&clean).expect("resolve clean");
+        let (master, mut child) = spawn_launch(&spec, 80, 24, None).expect("spawn clean");
+        let output = read_output_with_timeout(master.as_ref(), child.as_mut(), Duration::from_secs(5))
+            .expect("read clean");
+        assert!(output.contains("[][set][path]"), "clean output: {output}");
+
+        let mut merge = clean.clone();
+        merge.env_mode = Some(nagomi_protocol::EnvMode::Merge);
+        merge.env = None;
+        merge.env_unset = Some(vec!["NAGOMI_WORKER_TEST_INHERITED".to_string()]);
+        let spec = resolve_launch(&merge).expect("resolve merge");
+        let (master, mut child) = spawn_launch(&spec, 80, 24, None).expect("spawn merge");
+        let output = read_output_with_timeout(master.as_ref(), child.as_mut(), Duration::from_secs(5))
+            .expect("read merge");
+        assert!(output.contains("[][]"), "merge output: {output}");
+    }
+
     #[test]
     fn cmd_parser_supports_quoted_args() {
         let parts = shlex::split("wsl.exe -d \"Ubuntu 24.04\"").expect("parse cmd");
@@ -4,6 +4,7 @@ const http = require("node:http");
 const path = require("node:path");
 const { spawn, spawnSync } = require("node:child_process");
 const os = require("node:os");
+const { CONTROL_TOKEN_FILE, resolveAppConfigDir } = require("./paths");
 
 const DEFAULT_HEALTH_PORT = 17707;
 const DEFAULT_HEALTH_TIMEOUT_MS = 400;
@@ -29,22 +30,6 @@ function orchestratorExeName() {
   return isWindows() ? "nagomi-orchestrator.exe" : "nagomi-orchestrator";
 }
 
-function resolveAppConfigDir() {
-  if (isWindows()) {
-    const base = process.env.APPDATA || "";
-    if (!base) return null;
-    return path.join(base, "com.kitfactory.nagomi");
-  }
-  if (process.platform === "darwin") {
-    return path.join(os.homedir(), "Library", "Application Support", "com.kitfactory.nagomi");
-  }
-  const xdg = process.env.XDG_CONFIG_HOME;
-  if (xdg) {
-    return path.join(xdg, "com.kitfactory.nagomi");
-  }
-  return path.join(os.homedir(), ".config", "com.kitfactory.nagomi");
-}
-
 function formatLocalTimeFromTsMs(tsMs) {
   const num = Number(tsMs);
   if (!Number.isFinite(num) || num <= 0) return "-";
@@ -251,9 +236,25 @@ function stopOrchestratorProcesses() {
   spawnSync("pkill", ["-f", name], { encoding: "utf8" });
 }
 
+// orchestrator が app config dir に置く制御トークン / Control token the orchestrator keeps in the app config dir.
+function readControlToken() {
+  const configDir = resolveAppConfigDir();
+  if (!configDir) return "";
+  try {
+    return fs.readFileSync(path.join(configDir, CONTROL_TOKEN_FILE), "utf8").trim();
+  } catch {
+    return "";
+  }
+}
+
+function controlHeaders() {
+  const token = readControlToken();
+  return token ? { "X-Nagomi-Token": token } : {};
+}
+
 function httpGet(url) {
   return new Promise((resolve, reject) => {
-    const req = http.get(url, { agent: false }, (res) => {
+    const req = http.get(url, { agent: false, headers: controlHeaders() }, (res) => {
       res.resume();
       resolve(res.statusCode || 0);
     });
@@ -266,7 +267,7 @@ function httpGet(url) {
 
 function httpGetBody(url) {
   return new Promise((resolve, reject) => {
-    const req = http.get(url, { agent: false }, (res) => {
+    const req = http.get(url, { agent: false, headers: controlHeaders() }, (res) => {
       const chunks = [];
       res.on("data", (chunk) => chunks.push(chunk));
       res.on("end", () => {
@@ -281,6 +282,38 @@ function httpGetBody(url) {
   });
 }
 
+// 状態を書く要求は POST で送る / Requests that write state go out as POST.
+function httpPostBody(url, payload = "") {
+  return new Promise((resolve, reject) => {
+    const body = Buffer.from(payload, "utf8");
+    const req = http.request(
+      url,
+      {
+        method: "POST",
+        agent: false,
+        headers: {
+          ...controlHeaders(),
+          "Content-Type": "application/json",
+          "Content-Length": body.length,
+        },
+      },
+      (res) => {
+        const chunks = [];
+        res.on("data", (chunk) => chunks.push(chunk));
+        res.on("end", () => {
+          const text = Buffer.concat(chunks).toString("utf8");
+          resolve({ status: res.statusCode || 0, body: text });
+        });
+      }
+    );
+    req.on("error", reject);
+    req.setTimeout(DEFAULT_HEALTH_TIMEOUT_MS * 5, () => {
+      req.destroy(new Error("timeout"));
+    });
+    req.end(body);
+  });
+}
+
 function windowsDesktopDir() {
   return path.join(os.homedir(), "Desktop");
 }
@@ -432,23 +465,91 @@ function createWindowsShortcut({ path: shortcutPath, target, args, workingDir })
   }
 }
 
-function openTerminalUrl(sessionId) {
+function isDirectory(dirPath) {
+  try {
+    return fs.statSync(dirPath).isDirectory();
+  } catch {
+    return false;
+  }
+}
+
+function openTerminalUrl({ sessionId, profile, cwd } = {}) {
   const port = resolveHealthPort();
   const url = new URL(`http://127.0.0.1:${port}/open-terminal`);
   if (sessionId) {
     url.searchParams.set("session_id", sessionId);
   }
+  if (profile) {
+    url.searchParams.set("profile", profile);
+  }
+  if (cwd) {
+    url.searchParams.set("cwd", cwd);
+  }
   return url.toString();
 }
 
-async function openTerminal(sessionId) {
-  const { status, body } = await httpGetBody(openTerminalUrl(sessionId));
+async function openTerminal(options) {
+  const { status, body } = await httpGetBody(openTerminalUrl(options));
   if (status !== 200) {
     throw new Error(body || `open-terminal failed: ${status}`);
   }
   return body;
 }
 
+function workspaceUrl(action, name) {
+  const port = resolveHealthPort();
+  const route = action === "list" ? "list-workspaces" : `${action}-workspace`;
+  const url = new URL(`http://127.0.0.1:${port}/${route}`);
+  if (name) {
+    url.searchParams.set("name", name);
+  }
+  return url.toString();
+}
+
+async function openWorkspace(name) {
+  const { status, body } = await httpGetBody(workspaceUrl("open", name));
+  if (status !== 200) {
+    throw new Error(body || `open-workspace failed: ${status}`);
+  }
+  return body;
+}
+
+function printWorkspaceUsage() {
+  console.error(
+    [
+      "usage:",
+      "  nagomi workspace save <name>",
+      "  nagomi workspace open <name>",
+      "  nagomi workspace list",
+    ].join("\n")
+  );
+}
+
+async function workspaceCli(args) {
+  const [action, name] = args;
+  if (action !== "list" && !((action === "save" || action === "open") && name)) {
+    printWorkspaceUsage();
+    process.exitCode = 2;
+    return;
+  }
+  try {
+    // 保存は状態を書くので POST / Saving writes state, so it is a POST.
+    const url = workspaceUrl(action, name);
+    const { status, body } = action === "save" ? await httpPostBody(url) : await httpGetBody(url);
+    if (status !== 200) {
+      console.error(body || `request failed: ${status}`);
+      process.exitCode = 1;
+      return;
+    }
+    if (body) {
+      console.log(body.trim());
+    }
+  } catch (err) {
+    console.error(err && err.message ? err.message : String(err));
+    process.exitCode = 1;
+  }
+}
+
 function parseTerminalSendArgs(args) {
   let sessionId = "";
   let text = "";
@@ -624,6 +725,9 @@ function shortcutCli(args) {
 
 function parseLauncherArgs(args) {
   let sessionId = "";
+  let profile = "";
+  let cwd = "";
+  let workspace = "";
   let showHelp = false;
   let restart = false;
   let showStatus = false;
@@ -636,6 +740,21 @@ function parseLauncherArgs(args) {
       i += 1;
       continue;
     }
+    if (arg === "--profile" || arg === "-p") {
+      profile = args[i + 1] || "";
+      i += 1;
+      continue;
+    }
+    if (arg === "--cwd" || arg === "-C") {
+      cwd = args[i + 1] || "";
+      i += 1;
+      continue;
+    }
+    if (arg === "--workspace" || arg === "-w") {
+      workspace = args[i + 1] || "";
+      i += 1;
+      continue;
+    }
     if (arg === "--help" || arg === "-h") {
       showHelp = true;
       continue;
@@ -654,7 +773,17 @@ function parseLauncherArgs(args) {
     }
     unknown.push(arg);
   }
-  return { sessionId, showHelp, restart, showStatus, showDebugPaths, unknown };
+  return {
+    sessionId,
+    profile,
+    cwd,
+    workspace,
+    showHelp,
+    restart,
+    showStatus,
+    showDebugPaths,
+    unknown,
+  };
 }
 
 function printLauncherUsage() {
@@ -665,6 +794,11 @@ function printLauncherUsage() {
       "  nagomi --restart",
       "  nagomi --session-id <id>",
       "  nagomi --restart --session-id <id>",
+      "  nagomi --profile <name>",
+      "  nagomi --cwd <dir>",
+      "  nagomi --workspace <name>",
+      "  nagomi workspace save|open <name>",
+      "  nagomi workspace list",
       "  nagomi --status",
       "  nagomi --debug-paths",
       "  nagomi debug-tail [status|watcher|subworker|subworker-io] [--n <count>]",
@@ -1353,11 +1487,25 @@ async function main() {
     await terminalSendCli(args.slice(1));
     return;
   }
+  if (subcommand === "workspace") {
+    await workspaceCli(args.slice(1));
+    return;
+  }
   if (subcommand === "shortcut") {
     shortcutCli(args.slice(1));
     return;
   }
-  const { sessionId, showHelp, restart, showStatus, showDebugPaths, unknown } = parseLauncherArgs(args);
+  const {
+    sessionId,
+    profile,
+    cwd,
+    workspace,
+    showHelp,
+    restart,
+    showStatus,
+    showDebugPaths,
+    unknown,
+  } = parseLauncherArgs(args);
   if (showHelp) {
     printLauncherUsage();
     return;
@@ -1372,6 +1520,15 @@ async function main() {
     printDebugPaths(collectDebugPaths());
     return;
   }
+  // Default to the current directory unless a profile brings its own cwd.
+  const launchCwd = cwd ? path.resolve(cwd) : profile ? "" : process.cwd();
+  const openLaunchTarget = () =>
+    workspace ? openWorkspace(workspace) : openTerminal({ sessionId, profile, cwd: launchCwd });
+  if (cwd && !isDirectory(launchCwd)) {
+    console.error(`not a directory: ${launchCwd}`);
+    process.exitCode = 2;
+    return;
+  }
 
   const orchestratorPath = resolveOrchestratorPath();
   if (!orchestratorPath) {
@@ -1401,7 +1558,7 @@ async function main() {
     const healthy = await waitForHealth(DEFAULT_HEALTH_RETRIES);
     if (healthy) {
       try {
-        await openTerminal(sessionId);
+        await openLaunchTarget();
       } catch (err) {
         console.error(err && err.message ? err.message : String(err));
         process.exitCode = 1;
@@ -1426,7 +1583,7 @@ async function main() {
     return;
   }
   try {
-    await openTerminal(sessionId);
+    await openLaunchTarget();
   } catch (err) {
     console.error(err && err.message ? err.message : String(err));
     process.exitCode = 1;
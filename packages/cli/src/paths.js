@@ -0,0 +1,32 @@
+// Tauri の app_config_dir と同じ場所を求める（nagomi_core::paths と揃える）
+// Resolve the same directory as Tauri's app_config_dir; kept in step with nagomi_core::paths.
+const os = require("node:os");
+const path = require("node:path");
+
+const APP_IDENTIFIER = "com.kitfactory.nagomi";
+const CONTROL_TOKEN_FILE = "control_token";
+
+// `NAGOMI_APP_CONFIG_DIR` が最優先 / `NAGOMI_APP_CONFIG_DIR` always wins.
+function resolveAppConfigDir() {
+  const override = String(process.env.NAGOMI_APP_CONFIG_DIR || "").trim();
+  if (override) return override;
+  if (process.platform === "win32") {
+    const base = process.env.APPDATA || "";
+    if (!base) return null;
+    return path.join(base, APP_IDENTIFIER);
+  }
+  if (process.platform === "darwin") {
+    return path.join(os.homedir(), "Library", "Application Support", APP_IDENTIFIER);
+  }
+  const xdg = process.env.XDG_CONFIG_HOME;
+  if (xdg) {
+    return path.join(xdg, APP_IDENTIFIER);
+  }
+  return path.join(os.homedir(), ".config", APP_IDENTIFIER);
+}
+
+module.exports = {
+  APP_IDENTIFIER,
+  CONTROL_TOKEN_FILE,
+  resolveAppConfigDir,
+};
@@ -5,7 +5,7 @@ const path = require('node:path');
 const test = require('node:test');
 const assert = require('node:assert/strict');
 
-const { parseLine, serializeMessage } = require('./src/index.js');
+const { isSessionStarted, parseLine, serializeMessage } = require('./src/index.js');
 
 const fixturesPath = path.join(__dirname, '..', '..', 'testdata', 'protocol_fixtures.json');
 const fixtures = JSON.parse(fs.readFileSync(fixturesPath, 'utf8'));
@@ -24,3 +24,10 @@ test('unknown message type', () => {
   assert.equal(parsed.type, 'unknown');
   assert.deepStrictEqual(parsed.raw, { type: 'mystery', value: 1 });
 });
+
+test('session_started guard', () => {
+  assert.equal(isSessionStarted({ type: 'session_started', session_id: 's', pid: 42 }), true);
+  assert.equal(isSessionStarted({ type: 'session_started', session_id: 's' }), true);
+  assert.equal(isSessionStarted({ type: 'session_started', session_id: 's', pid: '42' }), false);
+  assert.equal(isSessionStarted({ type: 'output', session_id: 's' }), false);
+});
@@ -2,8 +2,12 @@ export type StartSession = {
   type: 'start_session';
   session_id: string;
   cmd: string;
+  argv?: string[];
+  login_shell?: boolean;
   cwd?: string | null;
   env?: Record<string, string>;
+  env_mode?: 'inherit' | 'clean' | 'merge';
+  env_unset?: string[];
   cols: number;
   rows: number;
 };
@@ -26,6 +30,12 @@ export type StopSession = {
   session_id: string;
 };
 
+export type SessionStarted = {
+  type: 'session_started';
+  session_id: string;
+  pid?: number | null;
+};
+
 export type Output = {
   type: 'output';
   session_id: string;
@@ -56,6 +66,7 @@ export type Message =
   | SendInput
   | Resize
   | StopSession
+  | SessionStarted
   | Output
   | Exit
   | ErrorMessage
@@ -63,6 +74,8 @@ export type Message =
 
 export declare const KNOWN_TYPES: Set<string>;
 
+export declare function isSessionStarted(value: unknown): value is SessionStarted;
+
 export declare function parseLine(line: string): Message;
 
 export declare function serializeMessage(message: Message): string;
@@ -5,11 +5,14 @@ const KNOWN_TYPES = new Set([
   'send_input',
   'resize',
   'stop_session',
+  'session_started',
   'output',
   'exit',
   'error',
 ]);
 
+const ENV_MODES = new Set(['inherit', 'clean', 'merge']);
+
 function isObject(value) {
   return value !== null && typeof value === 'object' && !Array.isArray(value);
 }
@@ -35,6 +38,12 @@ function isStartSession(value) {
   if (!isNumber(value.rows)) return false;
   if (value.cwd !== undefined && value.cwd !== null && !isString(value.cwd)) return false;
   if (value.env !== undefined && !isObject(value.env)) return false;
+  if (value.argv !== undefined && !(Array.isArray(value.argv) && value.argv.every(isString))) return false;
+  if (value.login_shell !== undefined && !isBoolean(value.login_shell)) return false;
+  if (value.env_mode !== undefined && !ENV_MODES.has(value.env_mode)) return false;
+  if (value.env_unset !== undefined && !(Array.isArray(value.env_unset) && value.env_unset.every(isString))) {
+    return false;
+  }
   return true;
 }
 
@@ -62,6 +71,14 @@ function isStopSession(value) {
   return true;
 }
 
+function isSessionStarted(value) {
+  if (!isObject(value)) return false;
+  if (value.type !== 'session_started') return false;
+  if (!isString(value.session_id)) return false;
+  if (value.pid !== undefined && value.pid !== null && !isNumber(value.pid)) return false;
+  return true;
+}
+
 function isOutput(value) {
   if (!isObject(value)) return false;
   if (value.type !== 'output') return false;
@@ -117,6 +134,8 @@ function parseLine(line) {
       return isResize(parsed) ? parsed : { type: 'unknown', raw: parsed };
     case 'stop_session':
       return isStopSession(parsed) ? parsed : { type: 'unknown', raw: parsed };
+    case 'session_started':
+      return isSessionStarted(parsed) ? parsed : { type: 'unknown', raw: parsed };
     case 'output':
       return isOutput(parsed) ? parsed : { type: 'unknown', raw: parsed };
     case 'exit':
@@ -147,6 +166,7 @@ function serializeMessage(message) {
     send_input: isSendInput,
     resize: isResize,
     stop_session: isStopSession,
+    session_started: isSessionStarted,
     output: isOutput,
     exit: isExit,
     error: isErrorMessage,
@@ -161,6 +181,7 @@ function serializeMessage(message) {
 
 module.exports = {
   KNOWN_TYPES,
+  isSessionStarted,
   parseLine,
   serializeMessage,
 };